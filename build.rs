@@ -0,0 +1,38 @@
+/// Regenerates `include/aibook.h` from the C interface in `src/ffi.rs` with
+/// `cargo build --features c-header`; plain builds use the header checked into the repository
+/// and never write to the source tree
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "c-header")]
+    header::generate();
+}
+
+#[cfg(feature = "c-header")]
+mod header {
+    use std::env;
+    use std::path::Path;
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let mut config = cbindgen::Config::default();
+        // `AIBOOK_STATE_RUNNING` rather than a bare `Running` in C's global namespace
+        config.enumeration.prefix_with_name = true;
+        config.enumeration.rename_variants = cbindgen::RenameRule::ScreamingSnakeCase;
+        let header = cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(Path::new(&crate_dir).join("src/ffi.rs"))
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("AIBOOK_H")
+            .with_no_includes()
+            .with_sys_include("stdint.h")
+            .with_header("/* Generated by cbindgen from src/ffi.rs; do not edit. */")
+            .generate();
+        match header {
+            Ok(header) => {
+                header.write_to_file(Path::new(&crate_dir).join("include/aibook.h"));
+            }
+            Err(e) => println!("cargo:warning=could not generate include/aibook.h: {}", e),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#ifndef AIBOOK_H
+#define AIBOOK_H
+
+#include <stdint.h>
+
+/**
+ * State of a job
+ */
+typedef enum AibookState {
+  AIBOOK_STATE_RUNNING = 0,
+  AIBOOK_STATE_SUCCEEDED = 1,
+  AIBOOK_STATE_FAILED = 2,
+  AIBOOK_STATE_CANCELLED = 3,
+} AibookState;
+
+/**
+ * A book to summarize, opened with `aibook_open`
+ */
+typedef struct AibookBook AibookBook;
+
+/**
+ * A summary being made by an `aibook-cli` process, started with `aibook_start`
+ */
+typedef struct AibookJob AibookJob;
+
+/**
+ * Progress of a job as reported by `aibook_poll`
+ */
+typedef struct AibookProgress {
+  enum AibookState state;
+  uint64_t chapters_done;
+  uint64_t chapters_total;
+} AibookProgress;
+
+/**
+ * Message of the last error on this thread, or NULL; valid until the next call that fails
+ */
+const char *aibook_last_error(void);
+
+/**
+ * Opens a book (any input aibook-cli reads), returning NULL when it does not exist
+ *
+ * # Safety
+ *
+ * `path` must be a valid NUL-terminated string.
+ */
+struct AibookBook *aibook_open(const char *path);
+
+/**
+ * Releases a book; jobs started from it keep running
+ *
+ * # Safety
+ *
+ * `book` must come from `aibook_open` and not be used afterwards.
+ */
+void aibook_book_free(struct AibookBook *book);
+
+/**
+ * Starts summarizing a book, with `options_json` (or NULL for the defaults) holding
+ * `executable`, `output_dir` and extra command-line `args`; returns NULL when it cannot start
+ *
+ * The API key is taken from the environment, as for aibook-cli.
+ *
+ * # Safety
+ *
+ * `book` must come from `aibook_open`; `options_json` must be NULL or a valid NUL-terminated
+ * string.
+ */
+struct AibookJob *aibook_start(const struct AibookBook *book, const char *options_json);
+
+/**
+ * Fills `progress` with the job's state and chapter counts; returns 0, or -1 on error
+ *
+ * # Safety
+ *
+ * `job` must come from `aibook_start` and `progress` must point to writable memory.
+ */
+int aibook_poll(const struct AibookJob *job, struct AibookProgress *progress);
+
+/**
+ * The `summary.json` of a succeeded job, or NULL; free it with `aibook_string_free`
+ *
+ * # Safety
+ *
+ * `job` must come from `aibook_start`.
+ */
+char *aibook_result_json(const struct AibookJob *job);
+
+/**
+ * Stops a running job; returns 0, or -1 on error
+ *
+ * # Safety
+ *
+ * `job` must come from `aibook_start`.
+ */
+int aibook_cancel(const struct AibookJob *job);
+
+/**
+ * Releases a job, cancelling it when it still runs; its outputs stay on disk
+ *
+ * # Safety
+ *
+ * `job` must come from `aibook_start` and not be used afterwards.
+ */
+void aibook_job_free(struct AibookJob *job);
+
+/**
+ * Releases a string returned by aibook
+ *
+ * # Safety
+ *
+ * `text` must come from aibook and not be used afterwards.
+ */
+void aibook_string_free(char *text);
+
+#endif  /* AIBOOK_H */
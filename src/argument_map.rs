@@ -0,0 +1,214 @@
+use crate::schema;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Longest node label in the Mermaid graph; the full text stays in the nested list
+const MERMAID_LABEL_CHARS: usize = 60;
+
+/// An objection to a claim and the author's answer, if any
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Counterargument {
+    pub point: String,
+    pub addressed: bool,
+    pub response: String,
+}
+
+/// A claim with the evidence for it and the objections against it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Claim {
+    pub claim: String,
+    pub evidence: Vec<String>,
+    pub counterarguments: Vec<Counterargument>,
+}
+
+/// Claims of one chapter, collected from all of its sections
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChapterArguments {
+    pub title: String,
+    pub claims: Vec<Claim>,
+}
+
+impl ChapterArguments {
+    pub fn new(title: String) -> Self {
+        ChapterArguments {
+            title,
+            ..Default::default()
+        }
+    }
+
+    /// Adds one section's claims, skipping malformed entries
+    pub fn merge_section(&mut self, value: &Value) {
+        let Some(claims) = value.get("claims").and_then(Value::as_array) else {
+            return;
+        };
+        let text = |value: &Value, name: &str| {
+            value
+                .get(name)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        for item in claims {
+            let claim = text(item, "claim");
+            if claim.is_empty() {
+                continue;
+            }
+            let evidence = item
+                .get("evidence")
+                .and_then(Value::as_array)
+                .map(|evidence| {
+                    evidence
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|e| e.trim().to_string())
+                        .filter(|e| !e.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let counterarguments = item
+                .get("counterarguments")
+                .and_then(Value::as_array)
+                .map(|counters| {
+                    counters
+                        .iter()
+                        .map(|counter| Counterargument {
+                            point: text(counter, "point"),
+                            addressed: counter
+                                .get("addressed")
+                                .and_then(Value::as_bool)
+                                .unwrap_or_default(),
+                            response: text(counter, "response"),
+                        })
+                        .filter(|counter| !counter.point.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.claims.push(Claim {
+                claim,
+                evidence,
+                counterarguments,
+            });
+        }
+    }
+}
+
+/// Writes `argument_map.json` and `argument_map.md` (a nested list and a Mermaid graph per
+/// chapter), returning the Markdown path
+pub fn write_map(output_dir: &Path, title: &str, chapters: &[ChapterArguments]) -> Result<PathBuf> {
+    schema::write_json(
+        &output_dir.join("argument_map.json"),
+        &json!({ "chapters": chapters }),
+    )?;
+
+    let mut doc = format!("# Argument Map: {}\n\n", title);
+    let argued: Vec<&ChapterArguments> = chapters.iter().filter(|c| !c.claims.is_empty()).collect();
+    if argued.is_empty() {
+        doc.push_str("No arguments were found.\n");
+    }
+
+    for chapter in argued {
+        doc.push_str(&format!("## {}\n\n", chapter.title));
+        for claim in &chapter.claims {
+            doc.push_str(&format!("- **Claim:** {}\n", claim.claim));
+            for evidence in &claim.evidence {
+                doc.push_str(&format!("  - Evidence: {}\n", evidence));
+            }
+            for counter in &claim.counterarguments {
+                if counter.addressed {
+                    doc.push_str(&format!("  - Objection: {}\n", counter.point));
+                    if !counter.response.is_empty() {
+                        doc.push_str(&format!("    - Author's response: {}\n", counter.response));
+                    }
+                } else {
+                    doc.push_str(&format!("  - Unanswered objection: {}\n", counter.point));
+                }
+            }
+        }
+        doc.push_str(&format!("\n```mermaid\n{}```\n\n", mermaid(chapter)));
+    }
+
+    let path = output_dir.join("argument_map.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
+
+/// Evidence points to the claim it supports; objections point at it with a dotted line,
+/// red when the author leaves them unanswered
+fn mermaid(chapter: &ChapterArguments) -> String {
+    let mut graph = String::from("flowchart LR\n");
+    let mut unanswered = Vec::new();
+    for (c, claim) in chapter.claims.iter().enumerate() {
+        graph.push_str(&format!(
+            "    C{}[\"{}\"]\n",
+            c,
+            mermaid_label(&claim.claim)
+        ));
+        for (e, evidence) in claim.evidence.iter().enumerate() {
+            graph.push_str(&format!(
+                "    E{c}_{e}(\"{}\") -->|supports| C{c}\n",
+                mermaid_label(evidence)
+            ));
+        }
+        for (k, counter) in claim.counterarguments.iter().enumerate() {
+            graph.push_str(&format!(
+                "    K{c}_{k}{{\"{}\"}} -.->|challenges| C{c}\n",
+                mermaid_label(&counter.point)
+            ));
+            if !counter.addressed {
+                unanswered.push(format!("K{}_{}", c, k));
+            }
+        }
+    }
+    if !unanswered.is_empty() {
+        graph.push_str("    classDef open stroke:#c0392b,stroke-width:2px\n");
+        graph.push_str(&format!("    class {} open\n", unanswered.join(",")));
+    }
+    graph
+}
+
+/// Shortens a label to one line and escapes what Mermaid would read as syntax
+fn mermaid_label(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut label = String::new();
+    for c in text.chars().take(MERMAID_LABEL_CHARS) {
+        match c {
+            '"' => label.push_str("#quot;"),
+            '#' | '[' | ']' | '(' | ')' | '{' | '}' | '|' | ';' | '<' | '>' => {
+                label.push_str(&format!("#{};", c as u32))
+            }
+            _ => label.push(c),
+        }
+    }
+    if text.chars().count() > MERMAID_LABEL_CHARS {
+        label.push('…');
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_cannot_break_out_of_their_node() {
+        let label = mermaid_label("Taxes \"must\" fall [a]\n(b) | c; #1 {d} <e>");
+        assert_eq!(
+            label,
+            "Taxes #quot;must#quot; fall #91;a#93; #40;b#41; #124; c#59; #35;1 #123;d#125; #60;e#62;"
+        );
+    }
+
+    #[test]
+    fn long_labels_are_cut_before_escaping() {
+        let text = format!("{}\"tail", "x".repeat(MERMAID_LABEL_CHARS - 1));
+        let label = mermaid_label(&text);
+        assert_eq!(
+            label,
+            format!("{}#quot;…", "x".repeat(MERMAID_LABEL_CHARS - 1))
+        );
+    }
+}
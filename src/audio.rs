@@ -0,0 +1,465 @@
+use crate::encryption::{self, CacheKey};
+use crate::images::ImageOptions;
+use crate::manifest::sha256_hex;
+use crate::metadata::BookMetadata;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// File extensions transcribed instead of read as books
+const AUDIO_EXTENSIONS: [&str; 9] = [
+    "mp3", "m4a", "m4b", "mp4", "wav", "ogg", "opus", "flac", "webm",
+];
+/// Longest piece sent to the transcriber at once; hosted APIs reject uploads over 25 MB
+const MAX_PIECE_SECS: f64 = 600.0;
+/// Silence-detected chapters shorter than this are merged into the previous one
+const MIN_CHAPTER_SECS: f64 = 120.0;
+
+/// Speech-to-text backend for audio inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transcriber {
+    /// OpenAI's hosted Whisper API
+    OpenAi,
+    /// Groq's hosted Whisper API
+    Groq,
+    /// A local whisper.cpp build (`whisper-cli`), given a ggml model file
+    WhisperCpp,
+}
+
+impl Transcriber {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "openai" => Ok(Transcriber::OpenAi),
+            "groq" => Ok(Transcriber::Groq),
+            "whisper-cpp" => Ok(Transcriber::WhisperCpp),
+            other => Err(anyhow!(
+                "Unknown transcriber '{}' (expected openai, groq or whisper-cpp)",
+                other
+            )),
+        }
+    }
+
+    fn endpoint(&self) -> &'static str {
+        match self {
+            Transcriber::OpenAi => "https://api.openai.com/v1/audio/transcriptions",
+            Transcriber::Groq => "https://api.groq.com/openai/v1/audio/transcriptions",
+            Transcriber::WhisperCpp => "",
+        }
+    }
+
+    fn api_key_env(&self) -> &'static str {
+        match self {
+            Transcriber::OpenAi => "OPENAI_API_KEY",
+            Transcriber::Groq => "GROQ_API_KEY",
+            Transcriber::WhisperCpp => "",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            Transcriber::OpenAi => "whisper-1",
+            Transcriber::Groq => "whisper-large-v3",
+            Transcriber::WhisperCpp => "ggml-base.en.bin",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscriptionOptions {
+    pub transcriber: Transcriber,
+    /// Model name for hosted APIs, or the path of the ggml model for whisper.cpp
+    pub model: Option<String>,
+    /// Pauses at least this long (in seconds) split chapters when the file has no chapter markers
+    pub silence_gap: f64,
+    /// Transcripts are cached here so re-running a book does not transcribe it again
+    pub cache_dir: PathBuf,
+    /// Encrypts cached transcripts (`--encrypt-cache`)
+    pub cache_key: Option<CacheKey>,
+    /// Client for the hosted APIs, with the run's proxy and TLS settings
+    pub client: reqwest::Client,
+}
+
+impl TranscriptionOptions {
+    fn model(&self) -> String {
+        self.model
+            .clone()
+            .unwrap_or_else(|| self.transcriber.default_model().to_string())
+    }
+}
+
+/// Whether the input is an audio or video file to transcribe
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// A span of the recording summarized as one chapter
+#[derive(Debug, Clone)]
+struct Segment {
+    title: String,
+    start: f64,
+    end: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TranscribedChapter {
+    title: String,
+    text: String,
+}
+
+/// An audiobook, lecture or talk turned into chapters of transcribed text
+pub struct Transcript {
+    metadata: BookMetadata,
+    chapters: Vec<TranscribedChapter>,
+}
+
+/// Transcribes an audio file chapter by chapter
+///
+/// Chapters come from the file's chapter markers (as in M4B audiobooks) or, without markers, from
+/// pauses of at least `silence_gap` seconds. Needs `ffmpeg` and `ffprobe` on the `PATH`.
+pub async fn transcribe(path: &Path, options: &TranscriptionOptions) -> Result<Transcript> {
+    let probe = ffprobe(path).await?;
+    let metadata = probe_metadata(&probe, path);
+
+    let cache_path = cache_path(path, options)?;
+    if let Some(chapters) = read_cache(&cache_path, options.cache_key.as_ref()) {
+        info!("Using cached transcript '{}'", cache_path.display());
+        if options.cache_key.is_some() {
+            // A transcript cached before --encrypt-cache is not left in plain text
+            write_cache(&cache_path, &chapters, options.cache_key.as_ref())?;
+        }
+        return Ok(Transcript { metadata, chapters });
+    }
+
+    let duration = probe
+        .pointer("/format/duration")
+        .and_then(Value::as_str)
+        .and_then(|d| d.parse::<f64>().ok())
+        .ok_or_else(|| anyhow!("could not read the duration of '{}'", path.display()))?;
+    let mut segments = chapter_markers(&probe);
+    if segments.len() < 2 {
+        segments = silence_segments(path, duration, options.silence_gap).await?;
+    }
+    info!(
+        "Transcribing '{}' ({:.0} minutes) in {} chapter(s)",
+        path.display(),
+        duration / 60.0,
+        segments.len()
+    );
+
+    let work_dir = cache_path.with_extension("parts");
+    fs::create_dir_all(&work_dir)?;
+    let mut chapters = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        let mut pieces = Vec::new();
+        let mut start = segment.start;
+        while start < segment.end {
+            let end = (start + MAX_PIECE_SECS).min(segment.end);
+            let piece = work_dir.join(format!("{:04}-{:.0}", index, start));
+            pieces.push(transcribe_piece(path, &piece, start, end, options).await?);
+            start = end;
+        }
+        chapters.push(TranscribedChapter {
+            title: segment.title.clone(),
+            text: pieces.join("\n\n"),
+        });
+    }
+    fs::remove_dir_all(&work_dir).ok();
+
+    write_cache(&cache_path, &chapters, options.cache_key.as_ref())?;
+    Ok(Transcript { metadata, chapters })
+}
+
+/// A cached transcript; an encrypted one can only be read with `key`
+fn read_cache(path: &Path, key: Option<&CacheKey>) -> Option<Vec<TranscribedChapter>> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    match (encryption::sealed(&value), key) {
+        (Some(sealed), Some(key)) => key.open(&sealed).ok(),
+        (Some(_), None) => None,
+        (None, _) => serde_json::from_value(value).ok(),
+    }
+}
+
+fn write_cache(path: &Path, chapters: &[TranscribedChapter], key: Option<&CacheKey>) -> Result<()> {
+    let content = match key {
+        Some(key) => serde_json::to_string_pretty(&key.seal(&chapters)?)?,
+        None => serde_json::to_string_pretty(&chapters)?,
+    };
+    fs::write(path, content)?;
+    Ok(())
+}
+
+impl EbookReader for Transcript {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|chapter| {
+            Ok(Chapter {
+                title: Some(chapter.title.clone()),
+                text: chapter.text.clone(),
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters.iter().map(|c| c.title.clone()).collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Recordings have no images
+    fn images(&mut self, _images_dir: &Path, _options: &ImageOptions) -> Result<()> {
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+}
+
+/// Transcript cache entry, keyed by the file's path, size and modification time and the model
+fn cache_path(path: &Path, options: &TranscriptionOptions) -> Result<PathBuf> {
+    let file = fs::metadata(path).with_context(|| format!("cannot read '{}'", path.display()))?;
+    let modified = file
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let key = format!(
+        "{}|{}|{}|{:?}|{}|{}",
+        fs::canonicalize(path)?.display(),
+        file.len(),
+        modified.as_secs(),
+        options.transcriber,
+        options.model(),
+        options.silence_gap
+    );
+    let dir = options.cache_dir.join("transcripts");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.json", &sha256_hex(key.as_bytes())[..16])))
+}
+
+async fn ffprobe(path: &Path) -> Result<Value> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json"])
+        .args(["-show_format", "-show_chapters"])
+        .arg(path)
+        .output()
+        .await
+        .context("ffprobe is required to read audio inputs")?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe could not read '{}': {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Title, author and language from the container's tags, falling back to the file name
+fn probe_metadata(probe: &Value, path: &Path) -> BookMetadata {
+    let tag = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            probe
+                .pointer(&format!("/format/tags/{}", name))
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        })
+    };
+
+    BookMetadata {
+        title: tag(&["album", "title"]).or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+        }),
+        creators: tag(&["album_artist", "artist", "composer"])
+            .into_iter()
+            .collect(),
+        language: tag(&["language"]),
+        description: tag(&["description", "comment"]),
+        date: tag(&["date"]),
+        ..Default::default()
+    }
+}
+
+fn chapter_markers(probe: &Value) -> Vec<Segment> {
+    let Some(chapters) = probe.get("chapters").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    let time = |chapter: &Value, name: &str| {
+        chapter
+            .get(name)
+            .and_then(Value::as_str)
+            .and_then(|t| t.parse::<f64>().ok())
+    };
+
+    chapters
+        .iter()
+        .enumerate()
+        .filter_map(|(index, chapter)| {
+            Some(Segment {
+                title: chapter
+                    .pointer("/tags/title")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("Chapter {}", index + 1)),
+                start: time(chapter, "start_time")?,
+                end: time(chapter, "end_time")?,
+            })
+        })
+        .collect()
+}
+
+/// Splits the recording at pauses of at least `gap` seconds, merging chapters that are too short
+async fn silence_segments(path: &Path, duration: f64, gap: f64) -> Result<Vec<Segment>> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-af", &format!("silencedetect=noise=-35dB:d={}", gap)])
+        .args(["-f", "null", "-"])
+        .output()
+        .await
+        .context("ffmpeg is required to read audio inputs")?;
+    if !output.status.success() {
+        bail!("ffmpeg could not analyse '{}'", path.display());
+    }
+
+    // silencedetect reports on stderr, e.g. "silence_end: 1234.5 | silence_duration: 3.2"
+    let log = String::from_utf8_lossy(&output.stderr);
+    let mut cuts: Vec<f64> = log
+        .lines()
+        .filter_map(|line| line.split("silence_start: ").nth(1))
+        .filter_map(|rest| rest.split_whitespace().next()?.parse().ok())
+        .collect();
+    cuts.push(duration);
+
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut start = 0.0;
+    for cut in cuts {
+        if cut - start < MIN_CHAPTER_SECS && cut < duration {
+            continue;
+        }
+        match segments.last_mut() {
+            // A short tail joins the previous chapter instead of standing alone
+            Some(last) if cut - start < MIN_CHAPTER_SECS => last.end = cut,
+            _ => segments.push(Segment {
+                title: format!("Part {}", segments.len() + 1),
+                start,
+                end: cut,
+            }),
+        }
+        start = cut;
+    }
+    Ok(segments)
+}
+
+/// Cuts `start..end` out of the recording and transcribes it
+async fn transcribe_piece(
+    path: &Path,
+    piece: &Path,
+    start: f64,
+    end: f64,
+    options: &TranscriptionOptions,
+) -> Result<String> {
+    // whisper.cpp reads 16 kHz WAV; hosted APIs get a small mono MP3 to stay under upload limits
+    let (extension, codec): (&str, &[&str]) = match options.transcriber {
+        Transcriber::WhisperCpp => ("wav", &["-c:a", "pcm_s16le"]),
+        _ => ("mp3", &["-c:a", "libmp3lame", "-b:a", "32k"]),
+    };
+    let audio = piece.with_extension(extension);
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-ss", &start.to_string()])
+        .args(["-t", &(end - start).to_string(), "-i"])
+        .arg(path)
+        .args(["-vn", "-ac", "1", "-ar", "16000"])
+        .args(codec)
+        .arg(&audio)
+        .status()
+        .await
+        .context("ffmpeg is required to read audio inputs")?;
+    if !status.success() {
+        bail!(
+            "ffmpeg could not extract {:.0}s-{:.0}s of '{}'",
+            start,
+            end,
+            path.display()
+        );
+    }
+    info!(
+        "Transcribing {:.0}s-{:.0}s of '{}'",
+        start,
+        end,
+        path.display()
+    );
+
+    let text = match options.transcriber {
+        Transcriber::WhisperCpp => whisper_cpp(&audio, piece, &options.model()).await?,
+        _ => whisper_api(&audio, options).await?,
+    };
+    fs::remove_file(&audio).ok();
+    Ok(text.trim().to_string())
+}
+
+async fn whisper_cpp(audio: &Path, piece: &Path, model: &str) -> Result<String> {
+    let output = Command::new("whisper-cli")
+        .args(["-m", model, "-nt", "-otxt", "-of"])
+        .arg(piece)
+        .arg("-f")
+        .arg(audio)
+        .output()
+        .await
+        .context("whisper-cli (whisper.cpp) is required for --transcriber whisper-cpp")?;
+    if !output.status.success() {
+        bail!(
+            "whisper.cpp failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let text_path = piece.with_extension("txt");
+    let text = fs::read_to_string(&text_path)?;
+    fs::remove_file(&text_path).ok();
+    Ok(text)
+}
+
+/// Uploads a piece to an OpenAI-compatible transcription endpoint
+async fn whisper_api(audio: &Path, options: &TranscriptionOptions) -> Result<String> {
+    let transcriber = &options.transcriber;
+    let api_key = std::env::var(transcriber.api_key_env())
+        .map_err(|_| anyhow!("{} is not set", transcriber.api_key_env()))?;
+
+    let file = Part::bytes(fs::read(audio)?)
+        .file_name("piece.mp3")
+        .mime_str("audio/mpeg")?;
+    let form = Form::new()
+        .text("model", options.model())
+        .text("response_format", "text")
+        .part("file", file);
+
+    let response = options
+        .client
+        .post(transcriber.endpoint())
+        .timeout(Duration::from_secs(600))
+        .bearer_auth(api_key)
+        .multipart(form)
+        .send()
+        .await?;
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        warn!("Transcription request failed: {}", text);
+        bail!("transcription failed with HTTP {}", status);
+    }
+    Ok(text)
+}
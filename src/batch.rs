@@ -0,0 +1,159 @@
+use crate::language;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings one book of a batch run uses instead of the run's
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Output language, resolved like `--language` ("auto" included)
+    pub language: Option<String>,
+    pub model: Option<String>,
+    pub detail_level: Option<String>,
+}
+
+/// An input of the run with its overrides
+#[derive(Debug, Clone)]
+pub struct BookInput {
+    pub path: PathBuf,
+    pub overrides: Overrides,
+}
+
+/// Entry of a `--batch` file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchEntry {
+    input: PathBuf,
+    language: Option<String>,
+    model: Option<String>,
+    detail_level: Option<String>,
+}
+
+impl BookInput {
+    /// Reads an `--input` value, which may end in overrides: `book.epub:lang=de,model=x,detail=long`
+    ///
+    /// The suffix is only split off when every part is a known `key=value` pair, so drive letters
+    /// and URLs are left alone.
+    pub fn parse(value: &Path) -> Result<Self> {
+        let text = value.to_string_lossy();
+        let Some((path, suffix)) = text.rsplit_once(':') else {
+            return Ok(BookInput::plain(value));
+        };
+        let pairs: Option<Vec<(&str, &str)>> = suffix
+            .split(',')
+            .map(|pair| {
+                pair.split_once('=').filter(|(key, _)| {
+                    matches!(key.trim(), "lang" | "language" | "model" | "detail")
+                })
+            })
+            .collect();
+        let Some(pairs) = pairs.filter(|_| !path.is_empty()) else {
+            return Ok(BookInput::plain(value));
+        };
+
+        let mut overrides = Overrides::default();
+        for (key, value) in pairs {
+            let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+            match key.trim() {
+                "lang" | "language" => overrides.language = value,
+                "model" => overrides.model = value,
+                _ => overrides.detail_level = value,
+            }
+        }
+        BookInput {
+            path: PathBuf::from(path),
+            overrides,
+        }
+        .validated()
+    }
+
+    pub fn plain(path: &Path) -> Self {
+        BookInput {
+            path: path.to_path_buf(),
+            overrides: Overrides::default(),
+        }
+    }
+
+    /// Resolves the language override now, so a typo fails the run before any book is summarized
+    fn validated(mut self) -> Result<Self> {
+        if let Some(value) = &self.overrides.language {
+            let resolved = if value.trim().eq_ignore_ascii_case("auto") {
+                "auto".to_string()
+            } else {
+                language::resolve(value)
+                    .with_context(|| format!("in the overrides of '{}'", self.path.display()))?
+            };
+            self.overrides.language = Some(resolved);
+        }
+        Ok(self)
+    }
+}
+
+/// Reads a `--batch` file: a JSON array of `{"input": ..., "language": ..., "model": ...,
+/// "detail_level": ...}` objects, with relative inputs taken from the file's directory
+pub fn load(path: &Path) -> Result<Vec<BookInput>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("could not read batch file '{}'", path.display()))?;
+    let entries: Vec<BatchEntry> = serde_json::from_str(&content)
+        .map_err(|e| anyhow!("invalid batch file '{}': {}", path.display(), e))?;
+    if entries.is_empty() {
+        bail!("batch file '{}' lists no inputs", path.display());
+    }
+    let base = path.parent().unwrap_or(Path::new(""));
+    entries
+        .into_iter()
+        .map(|entry| {
+            let is_url = entry.input.to_string_lossy().contains("://");
+            BookInput {
+                path: if is_url || entry.input.is_absolute() {
+                    entry.input
+                } else {
+                    base.join(entry.input)
+                },
+                overrides: Overrides {
+                    language: entry.language,
+                    model: entry.model,
+                    detail_level: entry.detail_level,
+                },
+            }
+            .validated()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_are_split_off_the_input() {
+        let input = BookInput::parse(Path::new(
+            "books/dune.epub:lang=de, model=gpt-4o,detail=long",
+        ))
+        .unwrap();
+        assert_eq!(input.path, PathBuf::from("books/dune.epub"));
+        assert_eq!(input.overrides.language.as_deref(), Some("de"));
+        assert_eq!(input.overrides.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(input.overrides.detail_level.as_deref(), Some("long"));
+    }
+
+    #[test]
+    fn paths_without_overrides_are_left_alone() {
+        for value in [
+            "C:\\books\\dune.epub",
+            "https://example.com/article",
+            "dune.epub:draft=1",
+            ":lang=de",
+        ] {
+            let input = BookInput::parse(Path::new(value)).unwrap();
+            assert_eq!(input.path, PathBuf::from(value));
+            assert!(input.overrides.language.is_none());
+        }
+    }
+
+    #[test]
+    fn unknown_override_languages_fail() {
+        assert!(BookInput::parse(Path::new("dune.epub:lang=not a language")).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::evaluation;
+use crate::llm::{LLMClient, Usage};
+use crate::paths::AppDirs;
+use crate::summarizer::Summarizer;
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::time::{Duration, Instant};
+
+/// Public-domain texts every model is benchmarked on, short enough for one section each
+const CHAPTERS: &[(&str, &str)] = &[
+    (
+        "The Gettysburg Address",
+        include_str!("../bench/gettysburg_address.txt"),
+    ),
+    (
+        "The Tell-Tale Heart (opening)",
+        include_str!("../bench/tell_tale_heart.txt"),
+    ),
+    (
+        "Pride and Prejudice, Chapter 1",
+        include_str!("../bench/pride_and_prejudice_ch1.txt"),
+    ),
+];
+
+/// How section summaries are asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The regular summary prompt
+    Plain,
+    /// Key points backed by source quotes, as with `--grounding`
+    Grounded,
+}
+
+impl Strategy {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "plain" => Ok(Strategy::Plain),
+            "grounded" => Ok(Strategy::Grounded),
+            other => Err(anyhow!(
+                "Unknown strategy '{}' (expected plain or grounded)",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Plain => "plain",
+            Strategy::Grounded => "grounded",
+        }
+    }
+}
+
+/// One model and strategy over all the bundled chapters
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub model: String,
+    pub strategy: Strategy,
+    pub chapters: usize,
+    pub failures: usize,
+    /// Summarizing time of the chapters that succeeded, judging excluded
+    pub latency: Duration,
+    /// Tokens and cost of the summaries, judging excluded
+    pub usage: Usage,
+    /// Mean judge score from 1 to 5 over the chapters the judge scored
+    pub score: Option<f32>,
+}
+
+impl BenchResult {
+    fn mean_latency(&self) -> Option<Duration> {
+        let succeeded = self.chapters - self.failures;
+        (succeeded > 0).then(|| self.latency / succeeded as u32)
+    }
+}
+
+/// Summarizes every bundled chapter with each model and strategy and has `judge` score the
+/// summaries, one request at a time so latencies are comparable
+pub async fn run(
+    judge: &LLMClient,
+    models: &[String],
+    strategies: &[Strategy],
+    dirs: &AppDirs,
+    language: &str,
+    detail_level: &str,
+) -> Result<Vec<BenchResult>> {
+    let mut results = Vec::new();
+    for model in models {
+        for &strategy in strategies {
+            println!("Benchmarking {} ({})...", model, strategy.name());
+            let summarizer = Summarizer::new(
+                judge.clone(),
+                language.to_string(),
+                detail_level.to_string(),
+                dirs,
+            )
+            .with_map_client(Some(judge.with_model(model)))
+            .with_grounding(strategy == Strategy::Grounded);
+
+            let mut result = BenchResult {
+                model: model.clone(),
+                strategy,
+                chapters: CHAPTERS.len(),
+                failures: 0,
+                latency: Duration::ZERO,
+                usage: Usage::default(),
+                score: None,
+            };
+            let mut scores = Vec::new();
+            for (title, text) in CHAPTERS {
+                // Clients share one usage counter, so it is cleared around each summary to
+                // keep the judge's tokens out of the model's
+                judge.take_usage();
+                let started = Instant::now();
+                let summary = summarizer.summarize_with_plan(text, "").await;
+                let elapsed = started.elapsed();
+                result.usage.add(judge.take_usage());
+                let summary = match summary {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        warn!("{} failed on '{}': {}", model, title, e);
+                        result.failures += 1;
+                        continue;
+                    }
+                };
+                result.latency += elapsed;
+                let evaluation = evaluation::evaluate_chapter(
+                    &summarizer,
+                    title,
+                    &[text.to_string()],
+                    &[summary],
+                )
+                .await;
+                scores.extend(evaluation.overall());
+            }
+            judge.take_usage();
+            result.score =
+                (!scores.is_empty()).then(|| scores.iter().sum::<f32>() / scores.len() as f32);
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+/// Comparison table of the results, best score first
+pub fn format_results(results: &[BenchResult]) -> String {
+    let mut sorted: Vec<&BenchResult> = results.iter().collect();
+    sorted.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+
+    let mut table = format!(
+        "{:<36}  {:<8}  {:>5}  {:>9}  {:>9}  {:>9}  {:>9}  {:>6}\n",
+        "Model", "Strategy", "Score", "Latency", "Prompt", "Output", "Cost", "Failed"
+    );
+    for result in sorted {
+        table.push_str(&format!(
+            "{:<36}  {:<8}  {:>5}  {:>9}  {:>9}  {:>9}  {:>9}  {:>6}\n",
+            result.model,
+            result.strategy.name(),
+            result
+                .score
+                .map_or("-".to_string(), |score| format!("{:.2}", score)),
+            result
+                .mean_latency()
+                .map_or("-".to_string(), |latency| format!(
+                    "{:.1}s",
+                    latency.as_secs_f32()
+                )),
+            result.usage.prompt_tokens,
+            result.usage.completion_tokens,
+            format!("${:.4}", result.usage.cost),
+            format!("{}/{}", result.failures, result.chapters)
+        ));
+    }
+    table.push_str(&format!(
+        "\nScores are the judge's mean of coverage, faithfulness and clarity (1-5) over {} \
+         bundled public-domain texts; latency is per text, tokens and cost are totals.\n",
+        CHAPTERS.len()
+    ));
+    table
+}
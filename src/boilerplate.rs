@@ -0,0 +1,87 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// A block must appear in at least this many spine documents to count as boilerplate
+const MIN_DOCUMENTS: usize = 3;
+/// Shorter blocks (scene breaks, "Notes" headings) are structure rather than boilerplate
+const MIN_CHARS: usize = 20;
+
+/// Counts in how many documents each paragraph-sized block of text occurs
+#[derive(Debug, Default)]
+pub struct BoilerplateDetector {
+    counts: HashMap<u64, usize>,
+}
+
+impl BoilerplateDetector {
+    pub fn add_document(&mut self, text: &str) {
+        let blocks: HashSet<u64> = blocks(text).filter_map(|(_, hash)| hash).collect();
+        for hash in blocks {
+            *self.counts.entry(hash).or_default() += 1;
+        }
+    }
+
+    pub fn finish(self) -> Boilerplate {
+        Boilerplate {
+            blocks: self
+                .counts
+                .into_iter()
+                .filter(|(_, documents)| *documents >= MIN_DOCUMENTS)
+                .map(|(hash, _)| hash)
+                .collect(),
+        }
+    }
+}
+
+/// Blocks repeated across chapters, such as running headers, author bios and promo text
+#[derive(Debug, Clone, Default)]
+pub struct Boilerplate {
+    blocks: HashSet<u64>,
+}
+
+impl Boilerplate {
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Removes the repeated blocks from a chapter's text
+    pub fn strip(&self, text: &str) -> String {
+        if self.blocks.is_empty() {
+            return text.to_string();
+        }
+        blocks(text)
+            .filter(|(_, hash)| !hash.is_some_and(|hash| self.blocks.contains(&hash)))
+            .map(|(block, _)| block)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// Splits text into blank-line separated blocks with the hash of their normalized words
+///
+/// Fenced code is never hashed, so repeated snippets stay in every chapter.
+fn blocks(text: &str) -> impl Iterator<Item = (&str, Option<u64>)> {
+    let mut in_fence = false;
+    text.split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .map(move |block| {
+            let fenced = in_fence || block.trim_start().starts_with("```");
+            in_fence ^= block.matches("```").count() % 2 == 1;
+            (block, (!fenced).then(|| hash(block)).flatten())
+        })
+}
+
+fn hash(block: &str) -> Option<u64> {
+    let normalized = block
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if normalized.chars().count() < MIN_CHARS {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    normalized.hash(&mut hasher);
+    Some(hasher.finish())
+}
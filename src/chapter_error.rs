@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+
+/// What happens when a chapter's sections cannot be summarized (`--on-chapter-error`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChapterErrorPolicy {
+    /// Stop the run
+    Abort,
+    /// Leave the chapter out, marked as not summarized in the outputs and the run report
+    Skip,
+    /// Summarize the chapter again up to this many times, then skip it
+    Retry(usize),
+}
+
+impl ChapterErrorPolicy {
+    pub fn parse(value: &str) -> Result<Self> {
+        let retries = value
+            .strip_prefix("retry:")
+            .and_then(|count| count.parse().ok());
+        match (value, retries) {
+            ("abort", _) => Ok(ChapterErrorPolicy::Abort),
+            ("skip", _) => Ok(ChapterErrorPolicy::Skip),
+            (_, Some(retries)) => Ok(ChapterErrorPolicy::Retry(retries)),
+            (other, None) => Err(anyhow!(
+                "Unknown chapter error policy '{}' (expected skip, retry:N or abort)",
+                other
+            )),
+        }
+    }
+
+    /// Whether a chapter that has failed `attempts` times is summarized once more
+    pub fn retries(&self, attempts: usize) -> bool {
+        matches!(self, ChapterErrorPolicy::Retry(max) if attempts <= *max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_parse() {
+        assert_eq!(
+            ChapterErrorPolicy::parse("abort").unwrap(),
+            ChapterErrorPolicy::Abort
+        );
+        assert_eq!(
+            ChapterErrorPolicy::parse("skip").unwrap(),
+            ChapterErrorPolicy::Skip
+        );
+        assert_eq!(
+            ChapterErrorPolicy::parse("retry:2").unwrap(),
+            ChapterErrorPolicy::Retry(2)
+        );
+        for value in ["retry", "retry:", "retry:x", "retry:-1", "Skip"] {
+            assert!(ChapterErrorPolicy::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn retry_stops_after_its_count() {
+        let policy = ChapterErrorPolicy::Retry(2);
+        assert!(policy.retries(1));
+        assert!(policy.retries(2));
+        assert!(!policy.retries(3));
+        assert!(!ChapterErrorPolicy::Skip.retries(1));
+    }
+}
@@ -0,0 +1,402 @@
+use async_trait::async_trait;
+use http::Extensions;
+use log::{info, warn};
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use reqwest::{Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest cool-down the circuit backs off to
+const MAX_COOLDOWN: Duration = Duration::from_secs(600);
+
+/// How often requests held back by a half-open circuit check whether its probe has finished
+const PROBE_POLL: Duration = Duration::from_millis(250);
+
+/// Stops sending requests to a provider that keeps failing with 5xx or 429 responses
+///
+/// After `threshold` consecutive failures the circuit opens: requests wait out a jittered
+/// cool-down, then a single probe is let through while the others keep waiting. A successful
+/// probe closes the circuit; a failed one reopens it with the cool-down doubled.
+///
+/// 429 responses are counted per API key, so one rate-limited key pauses only the requests sent
+/// with it while the other keys keep working. Server errors and transport failures count against
+/// the provider as a whole.
+pub struct CircuitBreaker {
+    threshold: u32,
+    base_cooldown: Duration,
+    state: Mutex<Circuits>,
+}
+
+#[derive(Debug, Default)]
+struct Circuits {
+    provider: CircuitState,
+    /// Rate-limit circuits by a hash of the API key the request was sent with
+    keys: HashMap<u64, CircuitState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    cooldown: Duration,
+    /// Whether the probe of a half-open circuit is in flight
+    probing: bool,
+}
+
+/// What a request must do before it may be sent
+enum Admission {
+    Wait(Duration),
+    Send(Probes),
+}
+
+/// Which half-open circuits a request was let through to probe
+#[derive(Debug, Default, Clone, Copy)]
+struct Probes {
+    provider: bool,
+    key: bool,
+}
+
+impl CircuitState {
+    fn closed(cooldown: Duration) -> Self {
+        CircuitState {
+            cooldown,
+            ..CircuitState::default()
+        }
+    }
+
+    /// Time left before the circuit half-opens, `Some(ZERO)` while another request probes it,
+    /// or `None` when this request may go ahead
+    fn wait_time(&self, now: Instant) -> Option<Duration> {
+        let open_until = self.open_until?;
+        match open_until.checked_duration_since(now) {
+            Some(remaining) if !remaining.is_zero() => Some(remaining),
+            _ if self.probing => Some(Duration::ZERO),
+            _ => None,
+        }
+    }
+
+    fn is_half_open(&self) -> bool {
+        self.open_until.is_some()
+    }
+
+    /// Counts a failure, returning the cool-down when it opened the circuit
+    fn record_failure(
+        &mut self,
+        threshold: u32,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        self.consecutive_failures += 1;
+
+        // A failed probe reopens immediately; otherwise the threshold must be reached first
+        if !self.is_half_open() && self.consecutive_failures < threshold {
+            return None;
+        }
+
+        let cooldown = jittered(self.cooldown).max(retry_after.unwrap_or_default());
+        self.open_until = Some(Instant::now() + cooldown);
+        self.cooldown = (self.cooldown * 2).min(MAX_COOLDOWN);
+        self.probing = false;
+        Some(cooldown)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold: threshold.max(1),
+            base_cooldown: cooldown,
+            state: Mutex::new(Circuits {
+                provider: CircuitState::closed(cooldown),
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Human-readable description while a circuit is open, for the progress UI
+    pub fn status(&self) -> Option<String> {
+        let state = self.state.lock().ok()?;
+        let now = Instant::now();
+        if let Some(open_until) = state.provider.open_until {
+            return Some(match open_until.checked_duration_since(now) {
+                Some(remaining) => format!(
+                    "API failing, circuit open: pausing {}s before retrying",
+                    remaining.as_secs() + 1
+                ),
+                None => "API failing, circuit half-open: probing".to_string(),
+            });
+        }
+        let limited = state
+            .keys
+            .values()
+            .filter(|key| key.open_until.is_some())
+            .count();
+        (limited > 0).then(|| format!("API rate limited: {} of the keys in use paused", limited))
+    }
+
+    /// Lets the request through, claiming the probe of any half-open circuit it passes, or tells
+    /// it how long to wait
+    fn admit(&self, key: Option<u64>) -> Admission {
+        let Ok(mut state) = self.state.lock() else {
+            return Admission::Send(Probes::default());
+        };
+        let now = Instant::now();
+        let key_wait = key
+            .and_then(|key| state.keys.get(&key))
+            .and_then(|circuit| circuit.wait_time(now));
+        if let Some(wait) = state.provider.wait_time(now).max(key_wait) {
+            return Admission::Wait(wait);
+        }
+
+        let mut probes = Probes::default();
+        if state.provider.is_half_open() {
+            state.provider.probing = true;
+            probes.provider = true;
+        }
+        if let Some(circuit) = key.and_then(|key| state.keys.get_mut(&key)) {
+            if circuit.is_half_open() {
+                circuit.probing = true;
+                probes.key = true;
+            }
+        }
+        Admission::Send(probes)
+    }
+
+    /// Gives up the probes of a request that ended without an outcome, e.g. when it was dropped
+    fn release(&self, key: Option<u64>, probes: Probes) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if probes.provider {
+            state.provider.probing = false;
+        }
+        if let Some(circuit) = key
+            .filter(|_| probes.key)
+            .and_then(|key| state.keys.get_mut(&key))
+        {
+            circuit.probing = false;
+        }
+    }
+
+    fn record_success(&self, key: Option<u64>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.provider.is_half_open() {
+            info!("Provider recovered, circuit closed");
+        }
+        state.provider = CircuitState::closed(self.base_cooldown);
+        if let Some(key) = key {
+            state.keys.remove(&key);
+        }
+    }
+
+    /// Counts a rate-limited response against the API key it was sent with
+    fn record_rate_limit(&self, key: Option<u64>, retry_after: Option<Duration>) {
+        let Some(key) = key else {
+            return self.record_failure("HTTP 429 Too Many Requests", retry_after);
+        };
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let base_cooldown = self.base_cooldown;
+        let circuit = state
+            .keys
+            .entry(key)
+            .or_insert_with(|| CircuitState::closed(base_cooldown));
+        if let Some(cooldown) = circuit.record_failure(self.threshold, retry_after) {
+            warn!(
+                "API key rate limited {} times in a row, pausing its requests for {}s",
+                circuit.consecutive_failures,
+                cooldown.as_secs()
+            );
+        }
+    }
+
+    fn record_failure(&self, reason: &str, retry_after: Option<Duration>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if let Some(cooldown) = state.provider.record_failure(self.threshold, retry_after) {
+            warn!(
+                "Provider failed {} times in a row ({}), circuit open for {}s",
+                state.provider.consecutive_failures,
+                reason,
+                cooldown.as_secs()
+            );
+        }
+    }
+}
+
+/// Releases a request's probes if it is dropped before its outcome is recorded
+struct ProbeGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    key: Option<u64>,
+    probes: Probes,
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        if self.probes.provider || self.probes.key {
+            self.breaker.release(self.key, self.probes);
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for CircuitBreaker {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let key = key_id(req.headers());
+        let mut announced = false;
+        let probes = loop {
+            match self.admit(key) {
+                Admission::Send(probes) => break probes,
+                Admission::Wait(wait) if wait.is_zero() => tokio::time::sleep(PROBE_POLL).await,
+                Admission::Wait(wait) => {
+                    if !announced {
+                        info!("Circuit open, waiting {}s before probing", wait.as_secs());
+                        announced = true;
+                    }
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        };
+        let _guard = ProbeGuard {
+            breaker: self,
+            key,
+            probes,
+        };
+
+        match next.run(req, extensions).await {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                self.record_rate_limit(key, retry_after(&response));
+                Ok(response)
+            }
+            Ok(response) if response.status().is_server_error() => {
+                self.record_failure(
+                    &format!("HTTP {}", response.status()),
+                    retry_after(&response),
+                );
+                Ok(response)
+            }
+            Ok(response) => {
+                self.record_success(key);
+                Ok(response)
+            }
+            Err(e) => {
+                self.record_failure(&e.to_string(), None);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Identifies the API key a request is sent with, without keeping the key itself
+fn key_id(headers: &HeaderMap) -> Option<u64> {
+    let value = headers
+        .get(AUTHORIZATION)
+        .or_else(|| headers.get("x-api-key"))
+        .or_else(|| headers.get("api-key"))?;
+    let mut hasher = DefaultHasher::new();
+    value.as_bytes().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Reads a `Retry-After` header given in seconds
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Spreads the cool-down by ±25% so parallel clients do not re-probe in lockstep
+fn jittered(cooldown: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let factor = 0.75 + (nanos % 1000) as f64 / 2000.0;
+    cooldown.mul_f64(factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_open(breaker: &CircuitBreaker, key: Option<u64>) -> bool {
+        matches!(breaker.admit(key), Admission::Wait(_))
+    }
+
+    #[test]
+    fn opens_after_threshold_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        breaker.record_failure("HTTP 500", None);
+        breaker.record_failure("HTTP 500", None);
+        assert!(!is_open(&breaker, None));
+        breaker.record_failure("HTTP 500", None);
+        assert!(is_open(&breaker, None));
+        assert!(breaker.status().unwrap().contains("circuit open"));
+    }
+
+    #[test]
+    fn half_open_circuit_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        breaker.record_failure("HTTP 503", None);
+
+        // The cool-down has passed, so the first request probes and the next one waits for it
+        let Admission::Send(probes) = breaker.admit(None) else {
+            panic!("the probe was held back");
+        };
+        assert!(probes.provider);
+        assert!(matches!(breaker.admit(None), Admission::Wait(wait) if wait.is_zero()));
+
+        // A failed probe reopens the circuit with the cool-down doubled from zero
+        breaker.record_failure("HTTP 503", None);
+        assert!(matches!(breaker.admit(None), Admission::Send(probes) if probes.provider));
+
+        // A dropped probe hands the probe on to the next request
+        breaker.release(None, probes);
+        assert!(matches!(breaker.admit(None), Admission::Send(probes) if probes.provider));
+    }
+
+    #[test]
+    fn success_closes_the_circuit() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+        breaker.record_failure("HTTP 502", None);
+        breaker.record_failure("HTTP 502", None);
+        assert!(matches!(breaker.admit(None), Admission::Send(probes) if probes.provider));
+        breaker.record_success(None);
+        assert!(breaker.status().is_none());
+
+        // The failure count starts over
+        breaker.record_failure("HTTP 502", None);
+        assert!(matches!(breaker.admit(None), Admission::Send(probes) if !probes.provider));
+    }
+
+    #[test]
+    fn rate_limits_pause_only_their_key() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_rate_limit(Some(1), None);
+        assert!(is_open(&breaker, Some(1)));
+        assert!(!is_open(&breaker, Some(2)));
+        assert!(!is_open(&breaker, None));
+        assert_eq!(
+            breaker.status().as_deref(),
+            Some("API rate limited: 1 of the keys in use paused")
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::schema;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How strongly a sensitive topic is present in a chapter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Mild,
+    Moderate,
+    Severe,
+}
+
+impl Severity {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "mild" | "low" => Some(Severity::Mild),
+            "moderate" | "medium" => Some(Severity::Moderate),
+            "severe" | "high" => Some(Severity::Severe),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::Mild => "mild",
+            Severity::Moderate => "moderate",
+            Severity::Severe => "severe",
+        }
+    }
+}
+
+/// A single flagged category within a chapter
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentFlag {
+    pub category: String,
+    pub severity: Severity,
+    pub note: String,
+}
+
+/// Flags for one chapter, merged from all of its sections
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChapterWarnings {
+    pub title: String,
+    pub flags: Vec<ContentFlag>,
+    pub min_age: Option<u32>,
+    /// Sections whose analysis failed, so the flags may be incomplete
+    pub unchecked_sections: usize,
+}
+
+impl ChapterWarnings {
+    pub fn new(title: String) -> Self {
+        ChapterWarnings {
+            title,
+            ..Default::default()
+        }
+    }
+
+    /// Records a section that could not be analyzed
+    pub fn mark_unchecked(&mut self) {
+        self.unchecked_sections += 1;
+    }
+
+    /// Merges one section's analysis, keeping the highest severity per category
+    pub fn merge_section(&mut self, value: &Value) {
+        if let Some(age) = value.get("min_age").and_then(Value::as_u64) {
+            let age = age as u32;
+            self.min_age = Some(self.min_age.map_or(age, |current| current.max(age)));
+        }
+
+        let Some(flags) = value.get("flags").and_then(Value::as_array) else {
+            return;
+        };
+        for flag in flags {
+            let category = flag
+                .get("category")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase();
+            let Some(severity) = flag
+                .get("severity")
+                .and_then(Value::as_str)
+                .and_then(Severity::parse)
+            else {
+                continue;
+            };
+            if category.is_empty() {
+                continue;
+            }
+            let note = flag
+                .get("note")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+
+            match self.flags.iter_mut().find(|f| f.category == category) {
+                Some(existing) if severity > existing.severity => {
+                    existing.severity = severity;
+                    existing.note = note;
+                }
+                Some(_) => (),
+                None => self.flags.push(ContentFlag {
+                    category,
+                    severity,
+                    note,
+                }),
+            }
+        }
+    }
+}
+
+/// Content warnings for the whole book
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContentReport {
+    pub min_age: Option<u32>,
+    pub chapters: Vec<ChapterWarnings>,
+}
+
+impl ContentReport {
+    pub fn push(&mut self, chapter: ChapterWarnings) {
+        if let Some(age) = chapter.min_age {
+            self.min_age = Some(self.min_age.map_or(age, |current| current.max(age)));
+        }
+        self.chapters.push(chapter);
+    }
+
+    /// Highest severity found for each category across the book
+    fn overall(&self) -> Vec<(String, Severity)> {
+        let mut overall: Vec<(String, Severity)> = Vec::new();
+        for flag in self.chapters.iter().flat_map(|c| &c.flags) {
+            match overall.iter_mut().find(|(c, _)| *c == flag.category) {
+                Some((_, severity)) => *severity = (*severity).max(flag.severity),
+                None => overall.push((flag.category.clone(), flag.severity)),
+            }
+        }
+        overall
+    }
+}
+
+/// Writes `content_warnings.json` and a readable `content_warnings.md`, returning the Markdown path
+pub fn write_report(output_dir: &Path, title: &str, report: &ContentReport) -> Result<PathBuf> {
+    schema::write_json(&output_dir.join("content_warnings.json"), report)?;
+
+    let mut doc = format!("# Content Warnings: {}\n\n", title);
+    if let Some(age) = report.min_age {
+        doc.push_str(&format!("**Suggested minimum age:** {}+\n\n", age));
+    }
+
+    let overall = report.overall();
+    let unchecked: Vec<&ChapterWarnings> = report
+        .chapters
+        .iter()
+        .filter(|c| c.unchecked_sections > 0)
+        .collect();
+    if overall.is_empty() && unchecked.is_empty() {
+        doc.push_str("No sensitive content was flagged.\n");
+    } else if overall.is_empty() {
+        doc.push_str("No sensitive content was flagged in the chapters that were checked.\n\n");
+    } else {
+        doc.push_str("## Overview\n\n");
+        for (category, severity) in &overall {
+            doc.push_str(&format!("- {}: {}\n", category, severity.label()));
+        }
+        doc.push('\n');
+    }
+
+    for chapter in report.chapters.iter().filter(|c| !c.flags.is_empty()) {
+        doc.push_str(&format!(
+            "## {}\n\n| Category | Severity | Note |\n|---|---|---|\n",
+            chapter.title
+        ));
+        for flag in &chapter.flags {
+            doc.push_str(&format!(
+                "| {} | {} | {} |\n",
+                flag.category,
+                flag.severity.label(),
+                flag.note.replace('|', "\\|")
+            ));
+        }
+        doc.push('\n');
+    }
+
+    if !unchecked.is_empty() {
+        doc.push_str(
+            "## Not Checked\n\nThe analysis failed for these chapters, so they may hold \
+             content that is not flagged above:\n\n",
+        );
+        for chapter in unchecked {
+            doc.push_str(&format!(
+                "- {} ({} section(s))\n",
+                chapter.title, chapter.unchecked_sections
+            ));
+        }
+    }
+
+    let path = output_dir.join("content_warnings.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
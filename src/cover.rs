@@ -0,0 +1,153 @@
+use crate::text::escape_html;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 1600;
+const HEIGHT: u32 = 2560;
+/// Approximate number of title characters that fit on one line at the title font size
+const TITLE_LINE_CHARS: usize = 18;
+
+/// Which cover the summary document gets (`--cover`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverMode {
+    None,
+    /// A typographic cover rendered from the title and author
+    Generated,
+    /// The book's own cover image
+    Original,
+}
+
+impl CoverMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "none" => Ok(CoverMode::None),
+            "generated" => Ok(CoverMode::Generated),
+            "original" => Ok(CoverMode::Original),
+            other => Err(anyhow!(
+                "Unknown cover '{}' (expected none, generated or original)",
+                other
+            )),
+        }
+    }
+}
+
+/// Renders a typographic SVG cover with the original title, a "Summary" banner and the author
+pub fn render_typographic_cover(title: &str, author: Option<&str>) -> String {
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = WIDTH,
+        h = HEIGHT
+    );
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"#1f2a44\"/>\n");
+    svg.push_str(&format!(
+        "<rect x=\"80\" y=\"80\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#e0b04b\" stroke-width=\"8\"/>\n",
+        WIDTH - 160,
+        HEIGHT - 160
+    ));
+
+    let lines = wrap_words(title, TITLE_LINE_CHARS);
+    let line_height = 150;
+    let title_top = 700u32.saturating_sub(lines.len() as u32 * line_height / 2) + 120;
+    for (index, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-family=\"Georgia, serif\" font-size=\"120\" font-weight=\"bold\" fill=\"#ffffff\" text-anchor=\"middle\">{}</text>\n",
+            WIDTH / 2,
+            title_top + index as u32 * line_height,
+            escape_html(line)
+        ));
+    }
+
+    svg.push_str(&summary_banner(1500));
+
+    if let Some(author) = author.filter(|a| !a.trim().is_empty()) {
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"2150\" font-family=\"Georgia, serif\" font-size=\"80\" fill=\"#e0b04b\" text-anchor=\"middle\">{}</text>\n",
+            WIDTH / 2,
+            escape_html(author)
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Draws the "Summary" banner band at the given vertical position
+fn summary_banner(y: u32) -> String {
+    format!(
+        "<rect x=\"0\" y=\"{}\" width=\"{}\" height=\"200\" fill=\"#e0b04b\"/>\n<text x=\"{}\" y=\"{}\" font-family=\"Helvetica, Arial, sans-serif\" font-size=\"110\" font-weight=\"bold\" letter-spacing=\"20\" fill=\"#1f2a44\" text-anchor=\"middle\">SUMMARY</text>\n",
+        y,
+        WIDTH,
+        WIDTH / 2,
+        y + 140
+    )
+}
+
+/// Writes the generated cover as `cover.svg` in the output directory
+pub fn write_generated_cover(
+    output_dir: &Path,
+    title: &str,
+    author: Option<&str>,
+) -> Result<PathBuf> {
+    let path = output_dir.join("cover.svg");
+    fs::write(&path, render_typographic_cover(title, author))?;
+    Ok(path)
+}
+
+/// Writes `cover.svg` showing the original cover image with a "Summary of" banner over it
+pub fn write_overlay_cover(output_dir: &Path, image_file: &str, title: &str) -> Result<PathBuf> {
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = WIDTH,
+        h = HEIGHT
+    );
+    svg.push_str(&format!(
+        "<image x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"xMidYMid slice\" xlink:href=\"{}\"/>\n",
+        WIDTH,
+        HEIGHT,
+        escape_html(image_file)
+    ));
+    svg.push_str(&format!(
+        "<rect x=\"0\" y=\"{}\" width=\"{}\" height=\"360\" fill=\"#1f2a44\" fill-opacity=\"0.85\"/>\n",
+        HEIGHT - 520,
+        WIDTH
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"Helvetica, Arial, sans-serif\" font-size=\"80\" letter-spacing=\"12\" fill=\"#e0b04b\" text-anchor=\"middle\">SUMMARY OF</text>\n",
+        WIDTH / 2,
+        HEIGHT - 400
+    ));
+    let title_line = wrap_words(title, TITLE_LINE_CHARS * 2)
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"Georgia, serif\" font-size=\"90\" font-weight=\"bold\" fill=\"#ffffff\" text-anchor=\"middle\">{}</text>\n",
+        WIDTH / 2,
+        HEIGHT - 260,
+        escape_html(&title_line)
+    ));
+    svg.push_str("</svg>\n");
+
+    let path = output_dir.join("cover.svg");
+    fs::write(&path, svg)?;
+    Ok(path)
+}
+
+fn wrap_words(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
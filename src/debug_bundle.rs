@@ -0,0 +1,265 @@
+use crate::http_log::Exchange;
+use crate::redact::Redactor;
+use crate::summarizer::{self, PromptVersion};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Written to each run's log directory, describing the run for `debug-bundle`
+pub const RUN_FILE: &str = "run.json";
+/// Files of a book's output directory that report on its run
+const BOOK_REPORTS: &[&str] = &[
+    "run_manifest.json",
+    "run_report.json",
+    "manifest.json",
+    "metadata.json",
+    "evaluation.json",
+    "extraction_report.md",
+];
+/// Settings read from the environment; their values go into `run.json` redacted
+const SETTINGS: &[&str] = &[
+    "MODEL_NAME",
+    "OUTPUT_LANGUAGE",
+    "OUTPUT_DIR",
+    "PRE_CHAPTER_HOOK",
+    "POST_CHAPTER_HOOK",
+    "POST_BOOK_HOOK",
+    "SMTP_HOST",
+    "SMTP_PORT",
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_SERVICE_NAME",
+];
+/// Settings only reported as set or not, since they hold credentials
+const SECRET_SETTINGS: &[&str] = &[
+    "OPENROUTER_API_KEY",
+    "GROQ_API_KEY",
+    "MISTRAL_API_KEY",
+    "OPENAI_API_KEY",
+    "SMTP_PASSWORD",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "OTEL_EXPORTER_OTLP_HEADERS",
+];
+/// How many failed responses the bundle quotes at most, and how much of each
+const MAX_FAILURES: usize = 20;
+const SNIPPET_CHARS: usize = 1500;
+
+/// What a run was started with and which books it wrote, kept next to its logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub aibook_version: String,
+    pub started_at: String,
+    pub command_line: String,
+    pub settings: BTreeMap<String, String>,
+    pub prompts: Vec<PromptVersion>,
+    /// Output directories of the books processed so far
+    pub books: Vec<PathBuf>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl RunInfo {
+    pub fn new(run_id: &str, log_dir: &Path, prompt_dirs: &[PathBuf], redactor: &Redactor) -> Self {
+        let command_line: Vec<String> = env::args().collect();
+        let mut settings = BTreeMap::new();
+        for &name in SETTINGS {
+            if let Ok(value) = env::var(name) {
+                settings.insert(name.to_string(), redactor.redact(&value));
+            }
+        }
+        for &name in SECRET_SETTINGS {
+            if env::var(name).is_ok_and(|value| !value.is_empty()) {
+                settings.insert(name.to_string(), "[set]".to_string());
+            }
+        }
+        RunInfo {
+            run_id: run_id.to_string(),
+            aibook_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: Utc::now().to_rfc3339(),
+            command_line: redactor.redact(&command_line.join(" ")),
+            settings,
+            prompts: summarizer::prompt_versions(prompt_dirs),
+            books: Vec::new(),
+            path: log_dir.join(RUN_FILE),
+        }
+    }
+
+    pub fn add_book(&mut self, output_dir: &Path) {
+        self.books.push(output_dir.to_path_buf());
+        self.save();
+    }
+
+    /// Like the other logs, a failed write is reported rather than stopping the run
+    pub fn save(&self) {
+        let written = serde_json::to_string_pretty(self)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&self.path, json)?));
+        if let Err(e) = written {
+            warn!("Could not write '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Zips what a bug report needs about a run into `output`, all redacted again with `redactor`
+/// in case the logs predate a `--redact` pattern: the run's settings and prompt versions, its
+/// logs, the reports of its books and the responses that failed
+///
+/// `run` is the run's log directory or its run ID.
+pub fn create(
+    run: &str,
+    log_dir: &Path,
+    output: Option<PathBuf>,
+    redactor: &Redactor,
+) -> Result<PathBuf> {
+    let run_dir = if Path::new(run).is_dir() {
+        PathBuf::from(run)
+    } else {
+        log_dir.join(run)
+    };
+    if !run_dir.is_dir() {
+        bail!(
+            "No run '{}' (expected a run's log directory, or a run ID under '{}')",
+            run,
+            log_dir.display()
+        );
+    }
+    let run_info: Option<RunInfo> = fs::read_to_string(run_dir.join(RUN_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    let run_id = run_info
+        .as_ref()
+        .map(|info| info.run_id.clone())
+        .unwrap_or_else(|| {
+            run_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+    let path = output.unwrap_or_else(|| PathBuf::from(format!("debug-bundle-{}.zip", run_id)));
+
+    let mut zip = ZipWriter::new(
+        File::create(&path).with_context(|| format!("could not create '{}'", path.display()))?,
+    );
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut failures = Vec::new();
+
+    let mut logs: Vec<PathBuf> = fs::read_dir(&run_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    logs.sort();
+    for log in logs {
+        let name = log
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let content = String::from_utf8_lossy(&fs::read(&log)?).into_owned();
+        let redacted = if name.starts_with("http.log") {
+            failures.extend(http_failures(&content, redactor));
+            content
+                .lines()
+                .map(|line| redactor.redact_payload(line) + "\n")
+                .collect()
+        } else if name == RUN_FILE {
+            redact_json_text(&content, redactor)
+        } else {
+            if name.starts_with("llm_") {
+                failures.extend(llm_failures(&name, &content, redactor));
+            }
+            redactor.redact(&content)
+        };
+        zip.start_file(format!("logs/{}", name), options)?;
+        zip.write_all(redacted.as_bytes())?;
+    }
+
+    for book in run_info.iter().flat_map(|info| &info.books) {
+        let book_name = book.file_name().unwrap_or_default().to_string_lossy();
+        for &report in BOOK_REPORTS {
+            let Ok(content) = fs::read_to_string(book.join(report)) else {
+                continue;
+            };
+            let redacted = if report.ends_with(".json") {
+                redact_json_text(&content, redactor)
+            } else {
+                redactor.redact(&content)
+            };
+            zip.start_file(format!("books/{}/{}", book_name, report), options)?;
+            zip.write_all(redacted.as_bytes())?;
+        }
+    }
+
+    failures.reverse();
+    failures.truncate(MAX_FAILURES);
+    zip.start_file("failures.md", options)?;
+    if failures.is_empty() {
+        zip.write_all(b"No failed responses were logged.\n")?;
+    } else {
+        zip.write_all(format!("# Failed responses of run {}\n\n", run_id).as_bytes())?;
+        zip.write_all(failures.join("\n").as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(path)
+}
+
+/// Exchanges of `http.log` that got an error status, as Markdown snippets
+fn http_failures(log: &str, redactor: &Redactor) -> Vec<String> {
+    log.lines()
+        .filter_map(|line| serde_json::from_str::<Exchange>(line).ok())
+        .filter(|exchange| exchange.response.status >= 400)
+        .map(|exchange| {
+            format!(
+                "## HTTP {} from {} at {}\n\n```\n{}\n```\n",
+                exchange.response.status,
+                redactor.redact(&exchange.request.url),
+                exchange.timestamp,
+                snippet(&redactor.redact_payload(&exchange.response.body))
+            )
+        })
+        .collect()
+}
+
+/// Entries of an `llm_*.log`, which only holds responses that could not be parsed
+fn llm_failures(name: &str, log: &str, redactor: &Redactor) -> Vec<String> {
+    log.split("\n\n[")
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            format!(
+                "## {}\n\n```\n{}\n```\n",
+                name,
+                snippet(&redactor.redact(&format!(
+                    "[{}",
+                    entry.strip_prefix('[').unwrap_or(entry).trim_end()
+                )))
+            )
+        })
+        .collect()
+}
+
+fn redact_json_text(content: &str, redactor: &Redactor) -> String {
+    match serde_json::from_str::<Value>(content) {
+        Ok(mut value) => {
+            redactor.redact_json(&mut value);
+            serde_json::to_string_pretty(&value).unwrap_or_default()
+        }
+        Err(_) => redactor.redact(content),
+    }
+}
+
+fn snippet(text: &str) -> String {
+    match text.char_indices().nth(SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A chapter section read back from a `summary.md`
+#[derive(Debug, Clone)]
+struct Section {
+    title: String,
+    body: String,
+}
+
+/// Compares the summaries of two output directories and writes a Markdown diff report
+///
+/// Each directory is either a book directory holding `summary.md` or a run directory whose
+/// book subdirectories are compared pairwise by name.
+pub fn write_report(old: &Path, new: &Path, report_path: &Path) -> Result<PathBuf> {
+    let pairs = summary_pairs(old, new)?;
+    if pairs.is_empty() {
+        bail!(
+            "No summary.md found to compare in '{}' and '{}'",
+            old.display(),
+            new.display()
+        );
+    }
+
+    let mut report = format!(
+        "# Summary Diff\n\n- Old: `{}`\n- New: `{}`\n\nRemoved text is ~~struck through~~, added text is **bold**.\n\n",
+        old.display(),
+        new.display()
+    );
+    for (name, old_summary, new_summary) in pairs {
+        if !name.is_empty() {
+            report.push_str(&format!("## Book: {}\n\n", name));
+        }
+        report.push_str(&book_report(
+            &read_sections(&old_summary)?,
+            &read_sections(&new_summary)?,
+        ));
+    }
+
+    fs::write(report_path, report)?;
+    Ok(report_path.to_path_buf())
+}
+
+/// Finds the `summary.md` files to compare, named by book when whole runs are compared
+fn summary_pairs(old: &Path, new: &Path) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+    let (old_summary, new_summary) = (old.join("summary.md"), new.join("summary.md"));
+    if old_summary.is_file() && new_summary.is_file() {
+        return Ok(vec![(String::new(), old_summary, new_summary)]);
+    }
+
+    let mut pairs = Vec::new();
+    let mut books: Vec<_> = fs::read_dir(old)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("summary.md").is_file())
+        .collect();
+    books.sort_by_key(|entry| entry.file_name());
+    for book in books {
+        let new_summary = new.join(book.file_name()).join("summary.md");
+        if new_summary.is_file() {
+            pairs.push((
+                book.file_name().to_string_lossy().to_string(),
+                book.path().join("summary.md"),
+                new_summary,
+            ));
+        }
+    }
+    Ok(pairs)
+}
+
+/// Splits a summary document into its `## ` chapter sections, skipping the vocabulary appendix
+fn read_sections(path: &Path) -> Result<Vec<Section>> {
+    let text = fs::read_to_string(path)?;
+    let mut sections: Vec<Section> = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        match line.strip_prefix("## ") {
+            Some(title) if !in_fence => sections.push(Section {
+                // Right-to-left summaries mark their headings with U+200F
+                title: title.trim().trim_start_matches('\u{200f}').to_string(),
+                body: String::new(),
+            }),
+            _ => {
+                if let Some(section) = sections.last_mut() {
+                    // Chapter subheadings move one level down, below the report's chapter headings
+                    if !in_fence && line.starts_with("### ") {
+                        section.body.push('#');
+                    }
+                    section.body.push_str(line);
+                    section.body.push('\n');
+                }
+            }
+        }
+    }
+
+    sections.retain(|s| s.title != "Vocabulary");
+    Ok(sections)
+}
+
+fn book_report(old: &[Section], new: &[Section]) -> String {
+    let old_titles: Vec<String> = old.iter().map(|s| normalize_title(&s.title)).collect();
+    let new_titles: Vec<String> = new.iter().map(|s| normalize_title(&s.title)).collect();
+
+    let mut report = String::new();
+    let mut changed = 0;
+    let mut similarity_total = 0.0;
+    let mut matched = 0;
+    let mut body = String::new();
+
+    for op in diff(&old_titles, &new_titles) {
+        match op {
+            Op::Same(i, j) => {
+                let (old_words, new_words) = (words(&old[i].body), words(&new[j].body));
+                let ops = diff(&old_words, &new_words);
+                let similarity = similarity(&ops, &old_words, &new_words);
+                similarity_total += similarity;
+                matched += 1;
+
+                if old_words == new_words {
+                    body.push_str(&format!("### {}\n\n*Unchanged.*\n\n", new[j].title));
+                    continue;
+                }
+                changed += 1;
+                body.push_str(&format!(
+                    "### {} ({:.0}% similar)\n\n{}\n\n",
+                    new[j].title,
+                    similarity * 100.0,
+                    highlight(&ops, &old_words, &new_words)
+                ));
+            }
+            Op::Removed(i) => body.push_str(&format!(
+                "### ~~{}~~ (removed)\n\n{}\n\n",
+                old[i].title,
+                old[i].body.trim()
+            )),
+            Op::Added(j) => body.push_str(&format!(
+                "### **{}** (added)\n\n{}\n\n",
+                new[j].title,
+                new[j].body.trim()
+            )),
+        }
+    }
+
+    report.push_str(&format!(
+        "Chapters: {} old, {} new, {} matched, {} changed. Average similarity of matched chapters: {:.0}%.\n\n",
+        old.len(),
+        new.len(),
+        matched,
+        changed,
+        if matched > 0 {
+            similarity_total / matched as f64 * 100.0
+        } else {
+            0.0
+        }
+    ));
+    report.push_str(&body);
+    report
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Token marking the end of a line, so the diff keeps the document's line structure
+const LINE_BREAK: &str = "\n";
+
+fn words(text: &str) -> Vec<String> {
+    text.trim()
+        .lines()
+        .flat_map(|line| {
+            line.split_whitespace()
+                .map(str::to_string)
+                .chain(std::iter::once(LINE_BREAK.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Longest-common-subsequence diff of two token sequences
+fn diff(old: &[String], new: &[String]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Removed(i));
+            i += 1;
+        } else {
+            ops.push(Op::Added(j));
+            j += 1;
+        }
+    }
+    ops.extend((i..n).map(Op::Removed));
+    ops.extend((j..m).map(Op::Added));
+    ops
+}
+
+/// Share of words common to both texts (1.0 when identical)
+fn similarity(ops: &[Op], old: &[String], new: &[String]) -> f64 {
+    let count = |tokens: &[String]| tokens.iter().filter(|t| *t != LINE_BREAK).count();
+    let total = count(old) + count(new);
+    if total == 0 {
+        return 1.0;
+    }
+    let same = ops
+        .iter()
+        .filter(|op| matches!(op, Op::Same(_, j) if new[*j] != LINE_BREAK))
+        .count();
+    2.0 * same as f64 / total as f64
+}
+
+/// Renders a word diff with runs of removed words struck through and added words in bold
+///
+/// Line breaks of the new text are kept so headings and lists still render.
+fn highlight(ops: &[Op], old: &[String], new: &[String]) -> String {
+    let mut lines: Vec<Vec<String>> = vec![Vec::new()];
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+
+    let flush = |line: &mut Vec<String>, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        if !removed.is_empty() {
+            line.push(format!("~~{}~~", removed.join(" ")));
+            removed.clear();
+        }
+        if !added.is_empty() {
+            line.push(format!("**{}**", added.join(" ")));
+            added.clear();
+        }
+    };
+
+    for op in ops {
+        let line = lines.last_mut().expect("at least one line");
+        match *op {
+            Op::Same(_, j) | Op::Added(j) if new[j] == LINE_BREAK => {
+                flush(line, &mut removed, &mut added);
+                lines.push(Vec::new());
+            }
+            Op::Removed(i) if old[i] == LINE_BREAK => {}
+            Op::Same(_, j) => {
+                flush(line, &mut removed, &mut added);
+                line.push(new[j].clone());
+            }
+            Op::Removed(i) => removed.push(&old[i]),
+            Op::Added(j) => added.push(&new[j]),
+        }
+    }
+    flush(
+        lines.last_mut().expect("at least one line"),
+        &mut removed,
+        &mut added,
+    );
+
+    lines
+        .iter()
+        .map(|line| line.join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,111 @@
+use crate::output::{self, ChapterSummary};
+use crate::summarizer::Summarizer;
+use anyhow::Result;
+use log::warn;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An open-ended question with talking points for book clubs
+#[derive(Debug, Clone, Default)]
+pub struct DiscussionQuestion {
+    pub question: String,
+    pub talking_points: Vec<String>,
+}
+
+/// Generates 8–12 questions for the whole book and, optionally, 3–5 for each chapter
+pub async fn generate(
+    summarizer: &Summarizer,
+    chapters: &[ChapterSummary],
+    per_chapter: bool,
+) -> Result<(
+    Vec<DiscussionQuestion>,
+    Vec<(String, Vec<DiscussionQuestion>)>,
+)> {
+    let full_summary = output::full_summary(chapters);
+    let book_questions = parse_questions(
+        &summarizer
+            .generate_discussion_questions(&full_summary, "8 to 12")
+            .await?,
+    );
+
+    let mut chapter_questions = Vec::new();
+    if per_chapter {
+        for chapter in chapters {
+            match summarizer
+                .generate_discussion_questions(&chapter.summary, "3 to 5")
+                .await
+            {
+                Ok(value) => {
+                    chapter_questions.push((chapter.title.clone(), parse_questions(&value)))
+                }
+                Err(e) => warn!("Discussion questions failed for '{}': {}", chapter.title, e),
+            }
+        }
+    }
+
+    Ok((book_questions, chapter_questions))
+}
+
+/// Parses the JSON array returned by the discussion questions prompt
+pub fn parse_questions(value: &Value) -> Vec<DiscussionQuestion> {
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let question = item.get("question")?.as_str()?.trim().to_string();
+            let talking_points = item
+                .get("talking_points")
+                .and_then(Value::as_array)
+                .map(|points| {
+                    points
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|p| p.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(DiscussionQuestion {
+                question,
+                talking_points,
+            })
+        })
+        .collect()
+}
+
+/// Writes the questions for the whole book (and optionally each chapter) to a standalone Markdown file
+pub fn write_questions(
+    output_dir: &Path,
+    title: &str,
+    book_questions: &[DiscussionQuestion],
+    chapter_questions: &[(String, Vec<DiscussionQuestion>)],
+) -> Result<PathBuf> {
+    let mut doc = format!("# Discussion Questions: {}\n\n", title);
+
+    push_questions(&mut doc, book_questions);
+
+    for (chapter_title, questions) in chapter_questions {
+        if questions.is_empty() {
+            continue;
+        }
+        doc.push_str(&format!("## {}\n\n", chapter_title));
+        push_questions(&mut doc, questions);
+    }
+
+    let path = output_dir.join("discussion_questions.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
+
+fn push_questions(doc: &mut String, questions: &[DiscussionQuestion]) {
+    for (index, question) in questions.iter().enumerate() {
+        doc.push_str(&format!("{}. **{}**\n", index + 1, question.question));
+        for point in &question.talking_points {
+            doc.push_str(&format!("   - {}\n", point));
+        }
+        doc.push('\n');
+    }
+}
@@ -0,0 +1,221 @@
+use crate::images::ImageOptions;
+use crate::metadata::BookMetadata;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::path::Path;
+use std::process::Command;
+
+/// Pages per chapter for documents without an outline
+const PAGES_PER_CHAPTER: usize = 20;
+
+/// A DjVu document read through its text layer
+///
+/// Chapters follow the document outline (bookmarks) when it has one, otherwise fixed runs of
+/// pages. Scanned documents without a text layer cannot be read. Needs DjVuLibre's `djvutxt`
+/// and `djvused` on the `PATH`.
+pub struct Djvu {
+    metadata: BookMetadata,
+    chapters: Vec<(String, String)>,
+}
+
+pub fn open_djvu(path: &Path) -> Result<Djvu> {
+    let text = run("djvutxt", path, &[])?;
+    // djvutxt separates pages with form feeds
+    let pages: Vec<&str> = text.split('\u{c}').collect();
+    if pages.iter().all(|page| page.trim().is_empty()) {
+        bail!("the DjVu file has no text layer (it needs OCR first)");
+    }
+
+    let outline = run("djvused", path, &["-e", "print-outline"]).unwrap_or_default();
+    let mut bookmarks = top_level_bookmarks(&outline);
+    bookmarks.retain(|(_, page)| *page >= 1 && *page <= pages.len());
+    bookmarks.sort_by_key(|(_, page)| *page);
+    bookmarks.dedup_by_key(|(_, page)| *page);
+
+    let chapters: Vec<(String, String)> = if bookmarks.is_empty() {
+        pages
+            .chunks(PAGES_PER_CHAPTER)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let first = index * PAGES_PER_CHAPTER + 1;
+                let title = format!("Pages {}–{}", first, first + chunk.len() - 1);
+                (title, join_pages(chunk))
+            })
+            .collect()
+    } else {
+        let mut chapters = Vec::new();
+        // Front matter before the first bookmark
+        if bookmarks[0].1 > 1 {
+            chapters.push((
+                "Front Matter".to_string(),
+                join_pages(&pages[..bookmarks[0].1 - 1]),
+            ));
+        }
+        for (index, (title, page)) in bookmarks.iter().enumerate() {
+            let end = bookmarks
+                .get(index + 1)
+                .map_or(pages.len(), |(_, next)| next - 1);
+            chapters.push((title.clone(), join_pages(&pages[page - 1..end])));
+        }
+        chapters
+    };
+    info!("Total chapters: {}", chapters.len());
+
+    let meta = run("djvused", path, &["-e", "print-meta"]).unwrap_or_default();
+    let field = |name: &str| {
+        meta.lines()
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| unquote(value.trim()))
+            .filter(|value| !value.is_empty())
+    };
+
+    Ok(Djvu {
+        metadata: BookMetadata {
+            title: field("title").or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+            }),
+            creators: field("author").into_iter().collect(),
+            publisher: field("publisher"),
+            date: field("year"),
+            isbn: field("isbn"),
+            ..Default::default()
+        },
+        chapters: chapters
+            .into_iter()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .collect(),
+    })
+}
+
+impl EbookReader for Djvu {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|(title, text)| {
+            Ok(Chapter {
+                title: Some(title.clone()),
+                text: text.clone(),
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Page images of a DjVu are scans, not figures, so none are extracted
+    fn images(&mut self, _images_dir: &Path, _options: &ImageOptions) -> Result<()> {
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+}
+
+fn run(program: &str, path: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .arg(path)
+        .args(args)
+        .output()
+        .with_context(|| format!("{} (DjVuLibre) is required to read DjVu files", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} could not read the file: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Joins page texts, re-flowing the hard line breaks of each page into paragraphs
+fn join_pages(pages: &[&str]) -> String {
+    let mut text = String::new();
+    for page in pages {
+        let mut paragraph = String::new();
+        for line in page.lines().map(str::trim) {
+            if line.is_empty() {
+                if !paragraph.is_empty() {
+                    text.push_str(&paragraph);
+                    text.push_str("\n\n");
+                    paragraph.clear();
+                }
+                continue;
+            }
+            // Words hyphenated across lines are joined back together
+            match paragraph.strip_suffix('-') {
+                Some(stripped) if line.starts_with(char::is_lowercase) => {
+                    paragraph = stripped.to_string();
+                }
+                _ if !paragraph.is_empty() => paragraph.push(' '),
+                _ => (),
+            }
+            paragraph.push_str(line);
+        }
+        if !paragraph.is_empty() {
+            text.push_str(&paragraph);
+            text.push_str("\n\n");
+        }
+    }
+    text
+}
+
+/// Titles and page numbers of the outermost entries of a `print-outline` S-expression, e.g.
+/// `(bookmarks ("Chapter 1" "#5" ("Section" "#6")) ("Chapter 2" "#12"))`
+fn top_level_bookmarks(outline: &str) -> Vec<(String, usize)> {
+    let mut bookmarks = Vec::new();
+    let mut depth = 0;
+    let mut strings: Vec<String> = Vec::new();
+    let mut chars = outline.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                depth += 1;
+                strings.clear();
+            }
+            ')' => depth -= 1,
+            '"' => {
+                let mut value = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+                // Entries of the bookmarks list sit at depth 2
+                if depth == 2 {
+                    strings.push(value);
+                    if let [title, target] = strings.as_slice() {
+                        if let Some(page) =
+                            target.strip_prefix('#').and_then(|page| page.parse().ok())
+                        {
+                            bookmarks.push((title.clone(), page));
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    bookmarks
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .replace("\\\"", "\"")
+}
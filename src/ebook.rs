@@ -1,66 +1,342 @@
-use anyhow::Result;
+use crate::boilerplate::{Boilerplate, BoilerplateDetector};
+use crate::extract::{self, ExtractionOptions};
+use crate::images::{self, ImageMode, ImageOptions};
+use crate::math;
+use crate::metadata::BookMetadata;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{anyhow, bail, Context, Result};
 use epub::doc::EpubDoc;
-use log::{error, info};
-use sanitize_filename::sanitize;
-use std::collections::HashMap;
+use log::info;
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
 use std::fs::{create_dir_all, File};
-use std::io::{BufReader, Write};
+use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Spine documents with more words than this are split at their headings
+const GIANT_CHAPTER_WORDS: usize = 12_000;
+
+/// Any markup tag, stripped to count and sample words
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+/// The algorithm of an `EncryptionMethod` in `META-INF/encryption.xml`
+static ENCRYPTION_ALGORITHM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"EncryptionMethod[^>]*Algorithm\s*=\s*["']([^"']+)["']"#).unwrap()
+});
+/// An `<h1>`–`<h3>` heading with its level and content
+static HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<h([1-3])\b[^>]*>(.*?)</h[1-3]\s*>").unwrap());
+
+/// An opened EPUB whose chapters are extracted lazily, one spine document at a time
+pub struct Ebook {
+    doc: EpubDoc<BufReader<File>>,
+    metadata: BookMetadata,
+    options: ExtractionOptions,
+    notes: extract::Notes,
+    /// Image paths referenced from the spine documents
+    referenced_images: HashSet<PathBuf>,
+    /// Text blocks repeated across spine documents, stripped from every chapter
+    boilerplate: Boilerplate,
+}
+
+/// Opens the e-book and gathers its notes, image references and repeated boilerplate
+///
+/// Chapter texts are not extracted here; iterate [`EbookReader::chapters`] to convert them one
+/// spine document at a time so huge books never sit in memory all at once.
+pub fn open_ebook<P: AsRef<Path>>(path: P, options: &ExtractionOptions) -> Result<Ebook> {
+    check_container(path.as_ref())?;
 
-/// Reads the e-book, extracts chapter texts, and saves images to the specified folder
-pub fn read_ebook<P: AsRef<Path>>(
-    path: P,
-    images_dir: &Path,
-) -> Result<(
-    EpubDoc<BufReader<File>>,
-    Vec<String>,
-    Vec<Vec<String>>,
-    HashMap<String, String>,
-)> {
     let file = File::open(&path)?;
     let buf_reader = BufReader::new(file);
 
-    let mut doc = EpubDoc::from_reader(buf_reader)?;
-
-    let mut chapters_content = Vec::new();
-    let mut chapters_images = Vec::new();
-    let total_chapters = doc.get_num_pages();
-    info!("Total chapters: {}", total_chapters);
-
-    // Extract and save images
-    let image_map = extract_images(&mut doc, images_dir)?;
-
-    // Reset to the beginning of the document
-    doc.set_current_page(0);
-
-    for chapter_index in 0..total_chapters {
-        if let Some((chapter_content, _mime)) = doc.get_current_str() {
-            // Convert HTML content to plain text
-            let text = html2text::from_read(chapter_content.as_bytes(), usize::MAX)?;
-            chapters_content.push(text);
-
-            // Get images associated with this chapter
-            let chapter_images = image_map.get(&chapter_index).cloned().unwrap_or_default();
-            chapters_images.push(chapter_images);
-        } else {
-            error!(
-                "Error getting content of chapter {}",
-                doc.get_current_page()
-            );
-            chapters_images.push(Vec::new());
+    let mut doc = EpubDoc::from_reader(buf_reader).map_err(|e| {
+        anyhow!(
+            "malformed EPUB, the package document could not be read: {}",
+            e
+        )
+    })?;
+    info!("Total chapters: {}", doc.get_num_chapters());
+
+    // Notes are gathered across the whole book since endnotes live in their own document
+    let mut notes = extract::Notes::new();
+    let mut referenced = HashSet::new();
+    let mut detector = BoilerplateDetector::default();
+    doc.set_current_chapter(0);
+    loop {
+        if let Some((html, _mime)) = doc.get_current_str() {
+            let document_path = doc.get_current_path().unwrap_or_default();
+            extract::collect_notes(&mut notes, &document_path, &html);
+            referenced.extend(images::referenced_images(&html, &document_path));
+            if options.strip_boilerplate {
+                if let Ok(text) = extract::render_text(&html, options) {
+                    detector.add_document(&text);
+                }
+            }
+        }
+        if !doc.go_next() {
+            break;
         }
-        doc.go_next();
     }
 
-    let metadata = get_ebook_metadata(&doc);
+    let boilerplate = detector.finish();
+    if boilerplate.len() > 0 {
+        info!(
+            "Stripping {} text block(s) repeated across chapters",
+            boilerplate.len()
+        );
+    }
+
+    let metadata = BookMetadata::from_epub(&doc);
 
-    Ok((doc, chapters_content, chapters_images, metadata))
+    Ok(Ebook {
+        doc,
+        metadata,
+        options: options.clone(),
+        notes,
+        referenced_images: referenced,
+        boilerplate,
+    })
+}
+
+impl EbookReader for Ebook {
+    /// Iterates the chapters in reading order, extracting each spine document when it is reached
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(Chapters {
+            ebook: self,
+            spine_index: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn toc(&self) -> Vec<String> {
+        extract_table_of_contents(&self.doc)
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Writes the images to disk one at a time as they are read
+    fn images(&mut self, images_dir: &Path, options: &ImageOptions) -> Result<()> {
+        if options.mode == ImageMode::Skip {
+            return Ok(());
+        }
+        let only = (options.mode == ImageMode::Referenced).then_some(&self.referenced_images);
+        extract_images(&mut self.doc, images_dir, options, only)
+    }
+
+    /// Number of spine documents; split giant documents can yield more chapters than this
+    fn chapter_count_hint(&self) -> usize {
+        self.doc.get_num_chapters()
+    }
+
+    fn cover(&mut self) -> Option<(Vec<u8>, String)> {
+        self.doc.get_cover()
+    }
+
+    /// Tag-stripped text of the first spine documents, skipping the full extraction pipeline
+    fn text_sample(&mut self, max_chars: usize) -> String {
+        let mut sample = String::new();
+
+        for index in 0..self.doc.get_num_chapters() {
+            if sample.len() >= max_chars {
+                break;
+            }
+            self.doc.set_current_chapter(index);
+            if let Some((html, _mime)) = self.doc.get_current_str() {
+                let body = html.find("<body").map_or(html.as_str(), |at| &html[at..]);
+                let text = TAG.replace_all(body, " ");
+                sample.push_str(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+                sample.push('\n');
+            }
+        }
+
+        sample
+    }
+}
+
+/// Lazy iterator over the chapters of an [`Ebook`]
+struct Chapters<'a> {
+    ebook: &'a mut Ebook,
+    spine_index: usize,
+    /// Remaining parts of a giant spine document that was split at its headings
+    pending: VecDeque<Chapter>,
+}
+
+impl Iterator for Chapters<'_> {
+    type Item = Result<Chapter>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chapter) = self.pending.pop_front() {
+                return Some(Ok(chapter));
+            }
+            if self.spine_index >= self.ebook.doc.get_num_chapters() {
+                return None;
+            }
+
+            let chapter_index = self.spine_index;
+            self.spine_index += 1;
+            self.ebook.doc.set_current_chapter(chapter_index);
+
+            // An unreadable document is still a chapter, so positions stay aligned with the
+            // table of contents and the chapter error policy decides what happens to it
+            let Some((chapter_html, _mime)) = self.ebook.doc.get_current_str() else {
+                let id = self
+                    .ebook
+                    .doc
+                    .get_current_id()
+                    .unwrap_or_else(|| chapter_index.to_string());
+                return Some(Err(anyhow!("spine item {} could not be read", id)));
+            };
+
+            let document_path = self.ebook.doc.get_current_path().unwrap_or_default();
+            for (title, part_html) in split_by_headings(&chapter_html) {
+                match html_to_text(
+                    &part_html,
+                    &document_path,
+                    &self.ebook.options,
+                    &self.ebook.notes,
+                ) {
+                    Ok(text) => self.pending.push_back(Chapter {
+                        title,
+                        text: self.ebook.boilerplate.strip(&text),
+                        images: images::image_references(&part_html, &document_path),
+                    }),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+    }
+}
+
+/// Font obfuscation algorithms allowed in `encryption.xml`; anything else means the content is encrypted
+const FONT_OBFUSCATION: [&str; 2] = [
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+/// Inspects the ZIP container before parsing so DRM and corruption get a specific error message
+fn check_container(path: &Path) -> Result<()> {
+    let file = File::open(path).context("could not open file")?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| {
+        anyhow!(
+            "corrupted EPUB, the file is not a valid ZIP container ({})",
+            e
+        )
+    })?;
+
+    if archive.index_for_name("META-INF/container.xml").is_none() {
+        bail!("malformed EPUB, META-INF/container.xml is missing");
+    }
+    if archive.index_for_name("META-INF/sinf.xml").is_some() {
+        bail!("the book is protected by Apple FairPlay DRM and cannot be read");
+    }
+    if archive.index_for_name("META-INF/rights.xml").is_some() {
+        bail!("the book is protected by Adobe ADEPT DRM and cannot be read");
+    }
+
+    let Ok(mut entry) = archive.by_name("META-INF/encryption.xml") else {
+        return Ok(());
+    };
+    let mut encryption = String::new();
+    entry.read_to_string(&mut encryption).map_err(|e| {
+        anyhow!(
+            "corrupted EPUB, META-INF/encryption.xml is unreadable ({})",
+            e
+        )
+    })?;
+
+    if encryption.contains("ns.adobe.com/adept") {
+        bail!("the book is protected by Adobe ADEPT DRM and cannot be read");
+    }
+    if encryption.to_lowercase().contains("fairplay") {
+        bail!("the book is protected by Apple FairPlay DRM and cannot be read");
+    }
+
+    let encrypted_content = ENCRYPTION_ALGORITHM
+        .captures_iter(&encryption)
+        .any(|caps| !FONT_OBFUSCATION.contains(&&caps[1]));
+    if encrypted_content {
+        bail!("the book's content is encrypted (DRM) and cannot be read");
+    }
+
+    Ok(())
+}
+
+/// Runs the extraction pipeline on one (X)HTML document, the one at `document_path` inside the
+/// book, and converts it to plain text
+pub fn html_to_text(
+    html: &str,
+    document_path: &Path,
+    options: &ExtractionOptions,
+    notes: &extract::Notes,
+) -> Result<String> {
+    let (html, chapter_notes) =
+        extract::apply_footnotes(html, document_path, options.footnotes, notes)?;
+    let html = math::mathml_to_latex(&html);
+    let html = extract::describe_images(&html)?;
+    // Code is fenced first so the tables' own <pre> output is not mistaken for code
+    let html = extract::fence_code_blocks(&html)?;
+    let html = extract::tables_to_markdown(&html)?;
+
+    // Convert HTML content to plain text
+    let mut text = extract::render_text(&html, options)?;
+    extract::append_notes(&mut text, &chapter_notes);
+    Ok(text)
+}
+
+/// Splits a giant spine document at its top-level `<h1>`–`<h3>` headings
+///
+/// Some EPUBs put the whole book in a single spine item; splitting keeps plan sections and
+/// summaries aligned with the real chapters. Regular documents are returned whole, untitled.
+pub fn split_by_headings(html: &str) -> Vec<(Option<String>, String)> {
+    let whole = || vec![(None, html.to_string())];
+
+    if TAG.replace_all(html, " ").split_whitespace().count() <= GIANT_CHAPTER_WORDS {
+        return whole();
+    }
+
+    let headings: Vec<(usize, u8, String)> = HEADING
+        .captures_iter(html)
+        .map(|caps| {
+            let level = caps[1].parse().unwrap_or(3);
+            let title = TAG.replace_all(&caps[2], " ");
+            let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+            (caps.get(0).unwrap().start(), level, title)
+        })
+        .collect();
+
+    let Some(top_level) = headings.iter().map(|(_, level, _)| *level).min() else {
+        return whole();
+    };
+    let splits: Vec<&(usize, u8, String)> = headings
+        .iter()
+        .filter(|(_, level, _)| *level == top_level)
+        .collect();
+    if splits.len() < 2 {
+        return whole();
+    }
+
+    info!(
+        "Splitting a large document into {} chapters at <h{}> headings",
+        splits.len(),
+        top_level
+    );
+
+    let mut parts = Vec::new();
+    for (index, (start, _, title)) in splits.iter().enumerate() {
+        // Content before the first heading belongs to the first part
+        let start = if index == 0 { 0 } else { *start };
+        let end = splits.get(index + 1).map_or(html.len(), |next| next.0);
+        let title = (!title.is_empty()).then(|| title.clone());
+        parts.push((title, html[start..end].to_string()));
+    }
+    parts
 }
 
 /// Extracts the table of contents from the e-book
-pub fn extract_table_of_contents<R: std::io::Read + std::io::Seek>(
-    doc: &EpubDoc<R>,
-) -> Vec<String> {
+fn extract_table_of_contents<R: std::io::Read + std::io::Seek>(doc: &EpubDoc<R>) -> Vec<String> {
     let mut toc = Vec::new();
 
     for nav_point in &doc.toc {
@@ -73,42 +349,39 @@ pub fn extract_table_of_contents<R: std::io::Read + std::io::Seek>(
 }
 
 /// Extracts images from the e-book and saves them to the specified folder
+///
+/// With `only` set, images whose path is not in the set are skipped. Images below the minimum
+/// size are dropped and larger ones optionally downscaled, see [`images::optimize`].
 fn extract_images<R: std::io::Read + std::io::Seek>(
     doc: &mut EpubDoc<R>,
     images_dir: &Path,
-) -> Result<HashMap<usize, Vec<String>>> {
-    let mut image_map: HashMap<usize, Vec<String>> = HashMap::new();
-
+    options: &ImageOptions,
+    only: Option<&HashSet<PathBuf>>,
+) -> Result<()> {
     // Collect image resources
     let image_resources: Vec<(String, PathBuf)> = doc
         .resources
         .iter()
-        .filter_map(|(id, (path, mime))| {
-            if mime.starts_with("image/") {
-                Some((id.clone(), path.clone()))
+        .filter_map(|(id, item)| {
+            if item.mime.starts_with("image/") {
+                Some((id.clone(), item.path.clone()))
             } else {
                 None
             }
         })
+        .filter(|(_, path)| only.is_none_or(|only| only.contains(&images::normalize(path))))
         .collect();
 
+    let mut dropped = 0;
     for (resource_id, resource_path) in image_resources {
         // Get the image content
         if let Some((data, mime)) = doc.get_resource(&resource_id) {
-            // Determine file extension based on MIME type
-            let extension = match mime.as_str() {
-                "image/jpeg" => "jpg",
-                "image/png" => "png",
-                "image/gif" => "gif",
-                "image/svg+xml" => "svg",
-                _ => "bin", // default binary extension
+            let Some(data) = images::optimize(data, &mime, options) else {
+                dropped += 1;
+                continue;
             };
 
-            // Convert PathBuf to String for filename
-            let resource_path_str = resource_path.to_string_lossy();
-            // Create a safe filename
-            let filename = format!("{}.{}", sanitize(&resource_path_str), extension);
-            let image_path = images_dir.join(&filename);
+            let image_path = images_dir.join(images::saved_file_name(&resource_path, &mime));
 
             // Create directory if it doesn't exist
             if let Some(parent) = image_path.parent() {
@@ -118,32 +391,15 @@ fn extract_images<R: std::io::Read + std::io::Seek>(
             // Save the image to disk
             let mut file = File::create(&image_path)?;
             file.write_all(&data)?;
-
-            // Map image to chapter (simplified mapping)
-            let chapter_index = doc.get_current_page();
-            image_map
-                .entry(chapter_index)
-                .or_insert_with(Vec::new)
-                .push(filename);
         }
     }
 
-    Ok(image_map)
-}
-
-// Add a function to get metadata from the e-book
-pub fn get_ebook_metadata<R: std::io::Read + std::io::Seek>(
-    doc: &EpubDoc<R>,
-) -> HashMap<String, String> {
-    let mut metadata = HashMap::new();
-    if let Some(title) = doc.mdata("title") {
-        metadata.insert("title".to_string(), title);
-    }
-    if let Some(author) = doc.mdata("creator") {
-        metadata.insert("author".to_string(), author);
+    if dropped > 0 {
+        info!(
+            "Dropped {} images smaller than {}px",
+            dropped, options.min_size
+        );
     }
-    if let Some(language) = doc.mdata("language") {
-        metadata.insert("language".to_string(), language);
-    }
-    metadata
+
+    Ok(())
 }
@@ -0,0 +1,238 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::info;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Environment variable holding the cache key as base64, used instead of the OS keyring
+pub const KEY_ENV: &str = "AIBOOK_CACHE_KEY";
+/// Service and account the key is stored under in the OS keyring
+const KEYRING_SERVICE: &str = "aibook";
+const KEYRING_ACCOUNT: &str = "cache-key";
+const KEY_LEN: usize = 32;
+const ALGORITHM: &str = "aes-256-gcm";
+
+/// Key encrypting the files aibook keeps with book text in them (`--encrypt-cache`): the
+/// section summaries in `state.json` and cached audio transcripts
+#[derive(Clone)]
+pub struct CacheKey([u8; KEY_LEN]);
+
+impl fmt::Debug for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CacheKey([hidden])")
+    }
+}
+
+/// An encrypted value as stored on disk, in place of the plain JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    /// The cipher, which also marks the file as encrypted
+    pub encrypted: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl CacheKey {
+    /// The key from `AIBOOK_CACHE_KEY` or the OS keyring, generating and storing one on first use
+    ///
+    /// The keyring is reached through `security` on macOS and `secret-tool` (libsecret) on
+    /// Linux; elsewhere the key must be given in the environment variable.
+    pub fn load_or_create() -> Result<Self> {
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return Self::decode(encoded.trim())
+                .with_context(|| format!("{} is not a base64 encoded 32-byte key", KEY_ENV));
+        }
+        if let Some(encoded) = keyring::lookup()? {
+            return Self::decode(encoded.trim())
+                .context("the cache key in the OS keyring is not a base64 encoded 32-byte key");
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("could not generate a cache key"))?;
+        keyring::store(&STANDARD.encode(key))?;
+        info!("Stored a new cache encryption key in the OS keyring");
+        Ok(CacheKey(key))
+    }
+
+    fn decode(encoded: &str) -> Result<Self> {
+        let bytes = STANDARD.decode(encoded)?;
+        let key: [u8; KEY_LEN] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("the key must be {} bytes", KEY_LEN))?;
+        Ok(CacheKey(key))
+    }
+
+    fn cipher(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &self.0).expect("the key has the right length"),
+        )
+    }
+
+    /// Encrypts `value` serialized as JSON
+    pub fn seal<T: Serialize>(&self, value: &T) -> Result<Sealed> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("could not generate a nonce"))?;
+        let mut data = serde_json::to_vec(value)?;
+        self.cipher()
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| anyhow!("encryption failed"))?;
+        Ok(Sealed {
+            encrypted: ALGORITHM.to_string(),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(data),
+        })
+    }
+
+    /// Decrypts a value sealed with this key
+    pub fn open<T: DeserializeOwned>(&self, sealed: &Sealed) -> Result<T> {
+        if sealed.encrypted != ALGORITHM {
+            bail!("unsupported encryption '{}'", sealed.encrypted);
+        }
+        let nonce: [u8; NONCE_LEN] = STANDARD
+            .decode(&sealed.nonce)?
+            .try_into()
+            .map_err(|_| anyhow!("invalid nonce"))?;
+        let mut data = STANDARD.decode(&sealed.ciphertext)?;
+        let plain = self
+            .cipher()
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| {
+                anyhow!("could not decrypt it with the cache key (wrong key or damaged file)")
+            })?;
+        Ok(serde_json::from_slice(plain)?)
+    }
+}
+
+/// The sealed envelope, if `value` is an encrypted file rather than plain JSON
+pub fn sealed(value: &Value) -> Option<Sealed> {
+    value.get("encrypted")?;
+    serde_json::from_value(value.clone()).ok()
+}
+
+mod keyring {
+    use super::*;
+
+    /// `security` exit status for an item that does not exist
+    const MACOS_NOT_FOUND: i32 = 44;
+
+    /// The stored key, or `None` when the keyring has none yet
+    ///
+    /// Any other failure, such as a locked keyring or a denied access prompt, is an error: taking
+    /// it for a missing key would replace the key and leave the existing cache unreadable.
+    pub fn lookup() -> Result<Option<String>> {
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command
+                .args(["find-generic-password", "-s", KEYRING_SERVICE])
+                .args(["-a", KEYRING_ACCOUNT, "-w"]);
+            command
+        } else if cfg!(unix) {
+            let mut command = Command::new("secret-tool");
+            command
+                .args(["lookup", "service", KEYRING_SERVICE])
+                .args(["account", KEYRING_ACCOUNT]);
+            command
+        } else {
+            bail!(unsupported());
+        };
+        let output = command.output().with_context(unsupported)?;
+        let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let not_found = if cfg!(target_os = "macos") {
+            output.status.code() == Some(MACOS_NOT_FOUND)
+        } else {
+            // secret-tool reports a missing item as a plain failure without output
+            output.status.code() == Some(1) && key.is_empty()
+        };
+        if not_found {
+            return Ok(None);
+        }
+        if !output.status.success() || key.is_empty() {
+            bail!(
+                "could not read the cache key from the OS keyring ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(Some(key))
+    }
+
+    /// Stores `key`, passing it on stdin so it never shows in the process list
+    pub fn store(key: &str) -> Result<()> {
+        let (mut command, input) = if cfg!(target_os = "macos") {
+            // In interactive mode `security` reads its commands from stdin
+            let mut command = Command::new("security");
+            command.arg("-i");
+            let input = format!(
+                "add-generic-password -U -s {} -a {} -w {}\n",
+                KEYRING_SERVICE, KEYRING_ACCOUNT, key
+            );
+            (command, input)
+        } else {
+            let mut command = Command::new("secret-tool");
+            command
+                .args(["store", "--label", "aibook cache key"])
+                .args(["service", KEYRING_SERVICE])
+                .args(["account", KEYRING_ACCOUNT]);
+            (command, key.to_string())
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(unsupported)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            bail!(
+                "could not store the cache key in the OS keyring; {}",
+                unsupported()
+            );
+        }
+        Ok(())
+    }
+
+    fn unsupported() -> String {
+        format!(
+            "the OS keyring is not available (it needs `security` on macOS or `secret-tool` on \
+             Linux); set {} to a base64 encoded 32-byte key instead",
+            KEY_ENV
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn sealed_values_open_with_their_key_only() {
+        let key = CacheKey([7; KEY_LEN]);
+        let value = json!({"summary": "The harvest failed."});
+        let sealed = key.seal(&value).unwrap();
+        assert!(!sealed.ciphertext.contains("harvest"));
+        assert_eq!(key.open::<Value>(&sealed).unwrap(), value);
+        assert!(CacheKey([8; KEY_LEN]).open::<Value>(&sealed).is_err());
+    }
+
+    #[test]
+    fn sealed_envelopes_are_told_from_plain_json() {
+        let key = CacheKey([7; KEY_LEN]);
+        let envelope = serde_json::to_value(key.seal(&json!([1, 2])).unwrap()).unwrap();
+        assert!(sealed(&envelope).is_some());
+        assert!(sealed(&json!({"schema_version": 1})).is_none());
+    }
+}
@@ -0,0 +1,673 @@
+use anyhow::{Context, Result};
+use log::{error, warn};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::{CompressionMethod, ZipArchive};
+
+const OPF_NS: &str = "http://www.idpf.org/2007/opf";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const EPUB_NS: &str = "http://www.idpf.org/2007/ops";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Reading systems may refuse the book or lose content
+    Error,
+    /// The book opens, but something is missing or inconsistent
+    Warning,
+}
+
+/// One problem found in an EPUB, with the file it was found in
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Checks the structure of an EPUB: the mimetype entry, container, package document, manifest,
+/// spine and navigation document, that every content document is well-formed XML, and that
+/// every local link and resource it references is packaged
+///
+/// Only an unreadable file is an `Err`; everything else is reported as problems.
+pub fn validate(path: &Path) -> Result<Vec<Problem>> {
+    let file = File::open(path).with_context(|| format!("could not open '{}'", path.display()))?;
+    let mut checker = Checker::default();
+    match ZipArchive::new(file) {
+        Ok(mut zip) => checker.check(&mut zip),
+        Err(e) => checker.error("", format!("not a ZIP archive: {}", e)),
+    }
+    Ok(checker.problems)
+}
+
+/// Validates an EPUB, logging each problem, and returns whether it is free of errors
+///
+/// An EPUB that cannot be opened is logged and counts as invalid, so one unreadable output does
+/// not stop the batch.
+pub fn report(path: &Path) -> bool {
+    let problems = match validate(path) {
+        Ok(problems) => problems,
+        Err(e) => {
+            error!("Could not validate '{}': {:#}", path.display(), e);
+            return false;
+        }
+    };
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    for problem in &problems {
+        match problem.severity {
+            Severity::Error => error!("{}", problem),
+            Severity::Warning => warn!("{}", problem),
+        }
+    }
+    if problems.is_empty() {
+        println!("'{}' passed validation", path.display());
+    } else {
+        println!(
+            "'{}' has {} error(s) and {} warning(s)",
+            path.display(),
+            errors,
+            problems.len() - errors
+        );
+    }
+    errors == 0
+}
+
+#[derive(Default)]
+struct Checker {
+    problems: Vec<Problem>,
+}
+
+/// A manifest entry
+struct Item {
+    href: String,
+    media_type: String,
+    properties: String,
+}
+
+impl Checker {
+    fn error(&mut self, location: &str, message: String) {
+        self.push(Severity::Error, location, message);
+    }
+
+    fn warning(&mut self, location: &str, message: String) {
+        self.push(Severity::Warning, location, message);
+    }
+
+    fn push(&mut self, severity: Severity, location: &str, message: String) {
+        self.problems.push(Problem {
+            severity,
+            location: location.to_string(),
+            message,
+        });
+    }
+
+    fn check(&mut self, zip: &mut ZipArchive<File>) {
+        self.check_mimetype(zip);
+        let names: BTreeSet<String> = zip.file_names().map(str::to_string).collect();
+
+        let Some(container) = self.read(zip, "META-INF/container.xml") else {
+            self.error("META-INF/container.xml", "missing".to_string());
+            return;
+        };
+        let Some(opf_path) = self.rootfile(&container) else {
+            return;
+        };
+        let Some(opf) = self.read(zip, &opf_path) else {
+            self.error(
+                &opf_path,
+                "package document listed in container.xml is missing".to_string(),
+            );
+            return;
+        };
+        let document = match Document::parse(&opf) {
+            Ok(document) => document,
+            Err(e) => {
+                self.error(&opf_path, format!("not well-formed XML: {}", e));
+                return;
+            }
+        };
+        let opf_dir = parent(&opf_path);
+        let package = document.root_element();
+        self.check_metadata(&opf_path, package);
+        let items = self.check_manifest(&opf_path, &opf_dir, package, &names);
+        let spine = self.check_spine(&opf_path, package, &items);
+
+        let mut listed: HashSet<&str> = items.values().map(|item| item.href.as_str()).collect();
+        listed.insert(opf_path.as_str());
+        for name in &names {
+            if name != "mimetype"
+                && !name.starts_with("META-INF/")
+                && !name.ends_with('/')
+                && !listed.contains(name.as_str())
+            {
+                self.warning(name, "packaged but not listed in the manifest".to_string());
+            }
+        }
+
+        // Content documents are parsed once: their ids are link targets for the others
+        let mut contents: BTreeMap<String, String> = BTreeMap::new();
+        for item in items.values() {
+            if item.media_type == "application/xhtml+xml" && names.contains(&item.href) {
+                if let Some(text) = self.read(zip, &item.href) {
+                    contents.insert(item.href.clone(), text);
+                }
+            }
+        }
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        let mut documents: BTreeMap<&str, Document> = BTreeMap::new();
+        for (href, text) in &contents {
+            match Document::parse_with_options(text, options) {
+                Ok(document) => {
+                    documents.insert(href, document);
+                }
+                Err(e) => self.error(href, format!("not well-formed XML: {}", e)),
+            }
+        }
+        let ids: HashMap<&str, HashSet<&str>> = documents
+            .iter()
+            .map(|(href, document)| {
+                let ids = document
+                    .descendants()
+                    .filter_map(|node| node.attribute("id"))
+                    .collect();
+                (*href, ids)
+            })
+            .collect();
+
+        let manifest_hrefs: HashSet<&str> = items.values().map(|item| item.href.as_str()).collect();
+        for (href, document) in &documents {
+            self.check_references(href, document, &manifest_hrefs, &ids);
+        }
+
+        match items.values().find(|item| has_property(item, "nav")) {
+            Some(nav) => {
+                if let Some(document) = documents.get(nav.href.as_str()) {
+                    self.check_nav(&nav.href, document, &spine);
+                }
+            }
+            None => self.error(
+                &opf_path,
+                "no manifest item has the \"nav\" property".to_string(),
+            ),
+        }
+    }
+
+    fn check_mimetype(&mut self, zip: &mut ZipArchive<File>) {
+        let Ok(mut first) = zip.by_index(0) else {
+            self.error("", "the archive is empty".to_string());
+            return;
+        };
+        if first.name() != "mimetype" {
+            self.error(
+                "mimetype",
+                format!("must be the first entry, found '{}'", first.name()),
+            );
+            return;
+        }
+        if first.compression() != CompressionMethod::Stored {
+            self.error("mimetype", "must be stored uncompressed".to_string());
+        }
+        if first.extra_data().is_some_and(|extra| !extra.is_empty()) {
+            self.warning("mimetype", "has an extra field".to_string());
+        }
+        let mut content = String::new();
+        if first.read_to_string(&mut content).is_err() || content != "application/epub+zip" {
+            self.error(
+                "mimetype",
+                "must contain exactly \"application/epub+zip\"".to_string(),
+            );
+        }
+    }
+
+    fn read(&mut self, zip: &mut ZipArchive<File>, name: &str) -> Option<String> {
+        let mut file = zip.by_name(name).ok()?;
+        let mut text = String::new();
+        match file.read_to_string(&mut text) {
+            Ok(_) => Some(text),
+            Err(e) => {
+                self.error(name, format!("unreadable: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Path of the package document named by container.xml
+    fn rootfile(&mut self, container: &str) -> Option<String> {
+        let location = "META-INF/container.xml";
+        let document = match Document::parse(container) {
+            Ok(document) => document,
+            Err(e) => {
+                self.error(location, format!("not well-formed XML: {}", e));
+                return None;
+            }
+        };
+        let rootfile = document.descendants().find(|node| {
+            node.has_tag_name("rootfile")
+                && node.attribute("media-type") == Some("application/oebps-package+xml")
+        });
+        match rootfile.and_then(|node| node.attribute("full-path")) {
+            Some(path) => Some(path.to_string()),
+            None => {
+                self.error(location, "no package document rootfile".to_string());
+                None
+            }
+        }
+    }
+
+    fn check_metadata(&mut self, location: &str, package: Node) {
+        if package.attribute("version") != Some("3.0") {
+            self.warning(
+                location,
+                format!(
+                    "package version is {}, not 3.0",
+                    package.attribute("version").unwrap_or("missing")
+                ),
+            );
+        }
+        let Some(metadata) = child(package, OPF_NS, "metadata") else {
+            self.error(location, "no <metadata> element".to_string());
+            return;
+        };
+
+        let unique_id = package.attribute("unique-identifier").unwrap_or_default();
+        let identifier = metadata.children().find(|node| {
+            node.tag_name().namespace() == Some(DC_NS)
+                && node.has_tag_name("identifier")
+                && node.attribute("id") == Some(unique_id)
+        });
+        match identifier.and_then(|node| node.text()) {
+            Some(text) if !text.trim().is_empty() => (),
+            _ => self.error(
+                location,
+                format!(
+                    "unique-identifier '{}' does not name a non-empty <dc:identifier>",
+                    unique_id
+                ),
+            ),
+        }
+
+        for element in ["title", "language"] {
+            let text = metadata
+                .children()
+                .find(|node| {
+                    node.tag_name().namespace() == Some(DC_NS) && node.has_tag_name(element)
+                })
+                .and_then(|node| node.text());
+            if text.is_none_or(|text| text.trim().is_empty()) {
+                self.error(location, format!("missing <dc:{}>", element));
+            }
+        }
+
+        let modified = metadata
+            .children()
+            .find(|node| node.attribute("property") == Some("dcterms:modified"))
+            .and_then(|node| node.text());
+        match modified {
+            Some(text)
+                if chrono::NaiveDateTime::parse_from_str(text.trim(), "%Y-%m-%dT%H:%M:%SZ")
+                    .is_ok() => {}
+            Some(text) => self.error(
+                location,
+                format!("dcterms:modified '{}' is not CCYY-MM-DDThh:mm:ssZ", text),
+            ),
+            None => self.error(location, "missing dcterms:modified".to_string()),
+        }
+    }
+
+    /// Manifest items by id, their hrefs resolved to archive paths
+    fn check_manifest(
+        &mut self,
+        location: &str,
+        opf_dir: &str,
+        package: Node,
+        names: &BTreeSet<String>,
+    ) -> HashMap<String, Item> {
+        let mut items = HashMap::new();
+        let Some(manifest) = child(package, OPF_NS, "manifest") else {
+            self.error(location, "no <manifest> element".to_string());
+            return items;
+        };
+        for node in manifest.children().filter(|node| node.has_tag_name("item")) {
+            let (Some(id), Some(href)) = (node.attribute("id"), node.attribute("href")) else {
+                self.error(location, "manifest item without id or href".to_string());
+                continue;
+            };
+            let href = resolve(opf_dir, href);
+            if !names.contains(&href) {
+                self.error(
+                    location,
+                    format!("manifest item '{}' is missing: {}", id, href),
+                );
+            }
+            let media_type = node.attribute("media-type").unwrap_or_default().to_string();
+            if media_type.is_empty() {
+                self.error(
+                    location,
+                    format!("manifest item '{}' has no media-type", id),
+                );
+            }
+            let item = Item {
+                href,
+                media_type,
+                properties: node.attribute("properties").unwrap_or_default().to_string(),
+            };
+            if has_property(&item, "cover-image") && !item.media_type.starts_with("image/") {
+                self.error(
+                    location,
+                    format!("cover image '{}' is {}, not an image", id, item.media_type),
+                );
+            }
+            if items.insert(id.to_string(), item).is_some() {
+                self.error(location, format!("duplicate manifest id '{}'", id));
+            }
+        }
+        items
+    }
+
+    /// Archive paths of the spine documents in reading order
+    fn check_spine(
+        &mut self,
+        location: &str,
+        package: Node,
+        items: &HashMap<String, Item>,
+    ) -> Vec<String> {
+        let mut spine = Vec::new();
+        let Some(element) = child(package, OPF_NS, "spine") else {
+            self.error(location, "no <spine> element".to_string());
+            return spine;
+        };
+        for node in element
+            .children()
+            .filter(|node| node.has_tag_name("itemref"))
+        {
+            let idref = node.attribute("idref").unwrap_or_default();
+            match items.get(idref) {
+                Some(item) => {
+                    if !matches!(
+                        item.media_type.as_str(),
+                        "application/xhtml+xml" | "image/svg+xml"
+                    ) {
+                        self.error(
+                            location,
+                            format!(
+                                "spine item '{}' is {}, not a content document",
+                                idref, item.media_type
+                            ),
+                        );
+                    }
+                    if spine.contains(&item.href) {
+                        self.error(location, format!("'{}' is in the spine twice", idref));
+                    }
+                    spine.push(item.href.clone());
+                }
+                None => self.error(
+                    location,
+                    format!("spine refers to unknown manifest id '{}'", idref),
+                ),
+            }
+        }
+        if spine.is_empty() {
+            self.error(location, "the spine is empty".to_string());
+        }
+        spine
+    }
+
+    /// Links, images and stylesheets must point at manifest items, and fragments at ids there
+    fn check_references(
+        &mut self,
+        href: &str,
+        document: &Document,
+        manifest: &HashSet<&str>,
+        ids: &HashMap<&str, HashSet<&str>>,
+    ) {
+        let dir = parent(href);
+        let mut seen_ids: HashSet<&str> = HashSet::new();
+        for node in document.descendants().filter(Node::is_element) {
+            if let Some(id) = node.attribute("id") {
+                if !seen_ids.insert(id) {
+                    self.error(href, format!("duplicate id '{}'", id));
+                }
+            }
+            let reference = match node.tag_name().name() {
+                "a" | "link" | "area" => node.attribute("href"),
+                "img" | "audio" | "video" | "source" | "script" | "iframe" | "embed" => {
+                    node.attribute("src")
+                }
+                "image" | "use" => node
+                    .attribute(("http://www.w3.org/1999/xlink", "href"))
+                    .or_else(|| node.attribute("href")),
+                _ => None,
+            };
+            let Some(reference) = reference.filter(|r| !is_remote(r)) else {
+                continue;
+            };
+            let (path, fragment) = match reference.split_once('#') {
+                Some((path, fragment)) => (path, Some(fragment)),
+                None => (reference, None),
+            };
+            let target = if path.is_empty() {
+                href.to_string()
+            } else {
+                resolve(&dir, path)
+            };
+            if !manifest.contains(target.as_str()) {
+                self.error(
+                    href,
+                    format!(
+                        "<{}> refers to '{}', which is not in the manifest",
+                        node.tag_name().name(),
+                        reference
+                    ),
+                );
+                continue;
+            }
+            if let (Some(fragment), Some(target_ids)) = (fragment, ids.get(target.as_str())) {
+                if !fragment.is_empty() && !target_ids.contains(fragment) {
+                    self.warning(href, format!("link '{}' points at a missing id", reference));
+                }
+            }
+        }
+    }
+
+    fn check_nav(&mut self, href: &str, document: &Document, spine: &[String]) {
+        let toc = document.descendants().find(|node| {
+            node.has_tag_name("nav")
+                && node
+                    .attribute((EPUB_NS, "type"))
+                    .is_some_and(|types| types.split_whitespace().any(|t| t == "toc"))
+        });
+        let Some(toc) = toc else {
+            self.error(href, "no <nav epub:type=\"toc\">".to_string());
+            return;
+        };
+        let dir = parent(href);
+        let mut entries = 0;
+        for link in toc.descendants().filter(|node| node.has_tag_name("a")) {
+            entries += 1;
+            let Some(target) = link.attribute("href") else {
+                continue;
+            };
+            let path = target.split('#').next().unwrap_or_default();
+            if !path.is_empty() && !spine.contains(&resolve(&dir, path)) {
+                self.warning(
+                    href,
+                    format!("contents entry '{}' is not in the spine", target),
+                );
+            }
+        }
+        if entries == 0 {
+            self.error(href, "the table of contents has no entries".to_string());
+        }
+    }
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.tag_name().namespace() == Some(namespace) && child.has_tag_name(name))
+}
+
+fn has_property(item: &Item, property: &str) -> bool {
+    item.properties.split_whitespace().any(|p| p == property)
+}
+
+fn is_remote(reference: &str) -> bool {
+    reference.contains("://") || reference.starts_with("mailto:") || reference.starts_with("data:")
+}
+
+/// Directory of an archive path, with a trailing slash unless it is the root
+fn parent(path: &str) -> String {
+    match path.rfind('/') {
+        Some(index) => path[..=index].to_string(),
+        None => String::new(),
+    }
+}
+
+/// Resolves a relative, percent-encoded reference against an archive directory
+fn resolve(dir: &str, reference: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for part in format!("{}{}", dir, percent_decode(reference)).split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let (b'%', Some(byte)) = (bytes[index], escaped) {
+            decoded.push(byte);
+            index += 3;
+            continue;
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+    const OPF: &str = r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:identifier id="id">urn:uuid:1</dc:identifier><dc:title>Book</dc:title><dc:language>en</dc:language><meta property="dcterms:modified">2024-01-01T00:00:00Z</meta></metadata><manifest><item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/><item id="c1" href="text/c1.xhtml" media-type="application/xhtml+xml"/></manifest><spine><itemref idref="c1"/></spine></package>"#;
+    const NAV: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><body><nav epub:type="toc"><ol><li><a href="text/c1.xhtml">One</a></li></ol></nav></body></html>"#;
+
+    fn chapter(body: &str) -> String {
+        format!(
+            r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>{}</body></html>"#,
+            body
+        )
+    }
+
+    /// Writes an EPUB with a stored `mimetype` first and the other entries after it
+    fn write_epub(entries: &[(&str, &str)]) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut zip = ZipWriter::new(file.reopen().unwrap());
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        for (name, content) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        file
+    }
+
+    fn problems(body: &str) -> Vec<Problem> {
+        let chapter = chapter(body);
+        let epub = write_epub(&[
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", OPF),
+            ("OEBPS/nav.xhtml", NAV),
+            ("OEBPS/text/c1.xhtml", &chapter),
+        ]);
+        validate(epub.path()).unwrap()
+    }
+
+    #[test]
+    fn a_complete_epub_has_no_problems() {
+        let found = problems(r##"<p id="a">Text <a href="#a">here</a></p>"##);
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    #[test]
+    fn links_to_files_outside_the_manifest_are_errors() {
+        let found = problems(r#"<p><img src="../images/missing.png" alt=""/></p>"#);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].severity, Severity::Error);
+        assert_eq!(found[0].location, "OEBPS/text/c1.xhtml");
+    }
+
+    #[test]
+    fn links_to_missing_ids_are_warnings() {
+        let found = problems(r##"<p><a href="c1.xhtml#nowhere">link</a></p>"##);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn malformed_content_documents_are_errors() {
+        let found = problems("<p>unclosed");
+        assert!(found
+            .iter()
+            .any(|p| p.severity == Severity::Error && p.message.starts_with("not well-formed")));
+    }
+
+    #[test]
+    fn a_missing_container_stops_the_check() {
+        let epub = write_epub(&[("OEBPS/content.opf", OPF)]);
+        let found = validate(epub.path()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].location, "META-INF/container.xml");
+    }
+
+    #[test]
+    fn references_resolve_against_their_directory() {
+        assert_eq!(
+            resolve("OEBPS/text/", "../images/a%20b.png"),
+            "OEBPS/images/a b.png"
+        );
+        assert_eq!(resolve("", "./nav.xhtml"), "nav.xhtml");
+        assert_eq!(parent("OEBPS/text/c1.xhtml"), "OEBPS/text/");
+    }
+}
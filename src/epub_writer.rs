@@ -0,0 +1,518 @@
+use crate::images::{self, ImageMode, ImageOptions};
+use crate::language;
+use crate::metadata::BookMetadata;
+use crate::output::{self, ChapterSummary, DocumentOptions};
+use crate::stats::ReadingStats;
+use crate::text::escape_html;
+use crate::theme;
+use anyhow::{bail, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Paragraphs of a chapter's opening used as its excerpt when no key point quotes it
+const OPENING_PARAGRAPHS: usize = 3;
+
+/// How the EPUB package is kept small for e-ink devices and e-mail limits
+#[derive(Debug, Clone)]
+pub struct PackageOptions {
+    /// Deflate level of the zip entries, 0 (stored) to 9; `None` uses the zip default
+    pub compression_level: Option<i64>,
+    /// Longest side in pixels that packaged images are downscaled to (0 keeps them as they are)
+    pub max_image_size: u32,
+    /// JPEG quality used when a downscaled photo is re-encoded
+    pub image_quality: u8,
+    /// Package identical images once and leave out files no document or stylesheet refers to
+    pub strip_unused: bool,
+}
+
+impl PackageOptions {
+    pub fn new(
+        compression_level: u32,
+        max_image_size: u32,
+        image_quality: u8,
+        strip_unused: bool,
+    ) -> Result<Self> {
+        if compression_level > 9 {
+            bail!(
+                "Invalid EPUB compression level {} (expected 0 to 9)",
+                compression_level
+            );
+        }
+        Ok(PackageOptions {
+            compression_level: Some(compression_level.into()),
+            max_image_size,
+            image_quality: image_quality.clamp(1, 100),
+            strip_unused,
+        })
+    }
+}
+
+impl Default for PackageOptions {
+    fn default() -> Self {
+        PackageOptions {
+            compression_level: None,
+            max_image_size: 0,
+            image_quality: 80,
+            strip_unused: false,
+        }
+    }
+}
+
+/// A file packaged alongside the content documents
+struct Resource {
+    id: String,
+    /// Path of the file relative to `OEBPS/`
+    path: String,
+    /// The path as documents link to it
+    href: String,
+    media_type: &'static str,
+    properties: Option<&'static str>,
+    data: Vec<u8>,
+}
+
+/// Which book the EPUB writer builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edition {
+    /// The summary alone, as `summary.epub`
+    Summary,
+    /// Each chapter's summary followed by its original text (or the quoted excerpts), as
+    /// `study_edition.epub`
+    Study,
+}
+
+/// Packages the summary as an EPUB 3 book, one XHTML file per chapter, and returns its path
+///
+/// `cover` is a file name inside `output_dir`, and chapter figures are packaged from its
+/// `images/` directory. The summary language is declared so e-readers pick the right
+/// dictionaries and hyphenation.
+pub fn write_epub(
+    output_dir: &Path,
+    metadata: &BookMetadata,
+    stats: &ReadingStats,
+    chapters: &[ChapterSummary],
+    cover: Option<&str>,
+    options: &DocumentOptions,
+    edition: Edition,
+) -> Result<PathBuf> {
+    let language = options.language.as_str();
+    let title = output::document_title(metadata);
+    let path = output_dir.join(match edition {
+        Edition::Summary => "summary.epub",
+        Edition::Study => "study_edition.epub",
+    });
+    let package = &options.package;
+    let mut zip = ZipWriter::new(File::create(&path)?);
+    let deflated = match package.compression_level {
+        Some(0) => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        level => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(level),
+    };
+
+    // The mimetype entry must come first and stay uncompressed
+    zip.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+
+    // (file name, heading, `epub:type` of the body) of every content document in reading order
+    let mut documents: Vec<(String, String, &str)> = Vec::new();
+    let mut manifest = String::new();
+    let mut resources: Vec<Resource> = Vec::new();
+
+    let cover_item = match cover {
+        Some(cover) if output_dir.join(cover).is_file() => {
+            let extension = cover.rsplit('.').next().unwrap_or_default();
+            let href = format!("cover.{}", extension);
+            resources.push(Resource {
+                id: "cover-image".to_string(),
+                path: href.clone(),
+                href: href.clone(),
+                media_type: media_type(extension),
+                properties: Some("cover-image"),
+                data: shrink_image(fs::read(output_dir.join(cover))?, extension, package),
+            });
+            Some(href)
+        }
+        _ => None,
+    };
+
+    let mut title_page = String::new();
+    if let Some(href) = &cover_item {
+        title_page.push_str(&format!(
+            "<p class=\"cover\"><img src=\"{}\" alt=\"Cover of {}\"/></p>\n",
+            href,
+            escape_html(&title)
+        ));
+    }
+    title_page.push_str(&format!("<h1>{}</h1>\n", escape_html(&title)));
+    if let Some(author) = metadata.author() {
+        title_page.push_str(&format!("<p><em>{}</em></p>\n", escape_html(author)));
+    }
+    title_page.push_str(&format!(
+        "<p class=\"reading-stats\">{}</p>\n",
+        escape_html(&stats.summary_line())
+    ));
+    documents.push((
+        "title.xhtml".to_string(),
+        title.clone(),
+        "frontmatter titlepage",
+    ));
+    let mut bodies = vec![title_page];
+
+    for (index, chapter) in chapters.iter().enumerate() {
+        documents.push((
+            format!("chapter_{:03}.xhtml", index + 1),
+            chapter.title.clone(),
+            "bodymatter chapter",
+        ));
+        bodies.push(output::chapter_html(index, chapter, options));
+        if edition == Edition::Study {
+            documents.push((
+                format!("original_{:03}.xhtml", index + 1),
+                format!("{}: Original Text", chapter.title),
+                "bodymatter",
+            ));
+            bodies.push(original_html(index, chapter, options.study_excerpts));
+        }
+    }
+    let vocabulary = output::vocabulary_html(chapters);
+    if !vocabulary.is_empty() {
+        documents.push((
+            "vocabulary.xhtml".to_string(),
+            "Vocabulary".to_string(),
+            "backmatter glossary",
+        ));
+        bodies.push(vocabulary);
+    }
+    if options.index {
+        let index = output::index_html(chapters, |number| {
+            format!("chapter_{:03}.xhtml#chapter-{}", number + 1, number + 1)
+        });
+        if !index.is_empty() {
+            documents.push(("index.xhtml".to_string(), "Index".to_string(), "backmatter"));
+            bodies.push(index);
+        }
+    }
+
+    if let Some(font) = &options.font {
+        let extension = font.rsplit('.').next().unwrap_or_default().to_lowercase();
+        resources.push(Resource {
+            id: "font".to_string(),
+            path: format!("fonts/{}", font),
+            href: format!("fonts/{}", font),
+            media_type: theme::font_media_type(&extension).unwrap_or("application/octet-stream"),
+            properties: None,
+            data: fs::read(output_dir.join("fonts").join(font))?,
+        });
+    }
+    // Each figure's image is packaged once, at the path the chapters link to
+    let mut packaged: Vec<&str> = Vec::new();
+    for figure in chapters.iter().flat_map(|chapter| &chapter.figures) {
+        let source = output_dir.join("images").join(&figure.file);
+        if packaged.contains(&figure.file.as_str()) || !source.is_file() {
+            continue;
+        }
+        packaged.push(&figure.file);
+        let extension = figure.file.rsplit('.').next().unwrap_or_default();
+        resources.push(Resource {
+            id: format!("image-{}", packaged.len()),
+            path: format!("images/{}", figure.file),
+            href: figure.href(),
+            media_type: media_type(extension),
+            properties: None,
+            data: shrink_image(fs::read(&source)?, extension, package),
+        });
+    }
+    if package.strip_unused {
+        strip_unused(&mut resources, &mut bodies, &options.stylesheet);
+    }
+    for resource in &resources {
+        zip.start_file(format!("OEBPS/{}", resource.path), deflated)?;
+        zip.write_all(&resource.data)?;
+        manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"{}\"{}/>\n",
+            resource.id,
+            escape_html(&resource.href),
+            resource.media_type,
+            resource
+                .properties
+                .map(|properties| format!(" properties=\"{}\"", properties))
+                .unwrap_or_default()
+        ));
+    }
+
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(options.stylesheet.as_bytes())?;
+    manifest.push_str("<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n");
+
+    let mut spine = String::new();
+    for ((file, heading, epub_type), body) in documents.iter().zip(&bodies) {
+        let id = file.trim_end_matches(".xhtml");
+        zip.start_file(format!("OEBPS/{}", file), deflated)?;
+        zip.write_all(xhtml(heading, body, language, epub_type).as_bytes())?;
+        // EPUB 3 requires documents with MathML equations to declare it
+        let properties = if body.contains("<math ") {
+            " properties=\"mathml\""
+        } else {
+            ""
+        };
+        manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>\n",
+            id, file, properties
+        ));
+        spine.push_str(&format!("<itemref idref=\"{}\"/>\n", id));
+    }
+
+    let nav_items: String = documents
+        .iter()
+        .map(|(file, heading, _)| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                file,
+                escape_html(heading)
+            )
+        })
+        .collect();
+    // Landmarks let reading systems jump to the start of the text, the contents or the index
+    let mut landmarks = String::from(
+        "<li><a epub:type=\"titlepage\" href=\"title.xhtml\">Title Page</a></li>\n<li><a epub:type=\"toc\" href=\"nav.xhtml#toc\">Contents</a></li>\n",
+    );
+    for (file, heading, epub_type) in &documents {
+        let landmark = match *epub_type {
+            "bodymatter chapter" if file == "chapter_001.xhtml" => "bodymatter",
+            "backmatter glossary" => "glossary",
+            _ if file == "index.xhtml" => "index",
+            _ => continue,
+        };
+        landmarks.push_str(&format!(
+            "<li><a epub:type=\"{}\" href=\"{}\">{}</a></li>\n",
+            landmark,
+            file,
+            escape_html(heading)
+        ));
+    }
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(
+        xhtml(
+            "Contents",
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{}</ol>\n</nav>\n<nav epub:type=\"landmarks\" id=\"landmarks\" hidden=\"hidden\">\n<h2>Landmarks</h2>\n<ol>\n{}</ol>\n</nav>\n",
+                nav_items, landmarks
+            ),
+            language,
+            "frontmatter",
+        )
+        .as_bytes(),
+    )?;
+    manifest.push_str(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+
+    let mut package_metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title{}>{}</dc:title>\n<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n",
+        identifier(metadata, &title, language),
+        text_direction(&title, language),
+        escape_html(&format!("{} (Summary)", title)),
+        escape_html(language),
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    for creator in &metadata.creators {
+        package_metadata.push_str(&format!(
+            "<dc:creator{}>{}</dc:creator>\n",
+            text_direction(creator, language),
+            escape_html(creator)
+        ));
+    }
+    if cover_item.is_some() {
+        // EPUB 2 readers, Kindle's converter among them, only find the cover through this
+        package_metadata.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+    }
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n<manifest>\n{}</manifest>\n<spine{}>\n{}</spine>\n</package>\n",
+            package_metadata,
+            manifest,
+            // Pages turn from right to left in right-to-left languages
+            if language::is_right_to_left(language) {
+                " page-progression-direction=\"rtl\""
+            } else {
+                ""
+            },
+            spine
+        )
+        .as_bytes(),
+    )?;
+
+    zip.finish()?;
+    Ok(path)
+}
+
+/// The `dir` attribute of a title or name whose direction differs from the package language's,
+/// such as a Hebrew title in an English summary; without it readers show it in the wrong order
+fn text_direction(text: &str, language: &str) -> &'static str {
+    match (
+        language::starts_right_to_left(text),
+        language::is_right_to_left(language),
+    ) {
+        (true, false) => " dir=\"rtl\"",
+        (false, true) => " dir=\"ltr\"",
+        _ => "",
+    }
+}
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n<rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n</container>\n";
+
+/// The source chapter after its summary in the study edition: its whole text, or with
+/// `excerpts` only the passages the key points quote (the opening paragraphs when none do)
+fn original_html(number: usize, chapter: &ChapterSummary, excerpts: bool) -> String {
+    let mut doc = format!(
+        "<section class=\"original\" aria-labelledby=\"original-{0}\">\n<h2 id=\"original-{0}\">{1}</h2>\n",
+        number + 1,
+        escape_html(&chapter.title)
+    );
+    let text = chapter.original.as_deref().unwrap_or_default();
+    if !excerpts {
+        doc.push_str("<p class=\"edition-note\"><em>Original text</em></p>\n");
+        doc.push_str(&text_paragraphs(text.split("\n\n")));
+    } else {
+        doc.push_str("<p class=\"edition-note\"><em>Excerpts from the original</em></p>\n");
+        let quotes: Vec<&str> = chapter
+            .key_points
+            .iter()
+            .map(|key_point| key_point.quote.trim())
+            .filter(|quote| !quote.is_empty())
+            .collect();
+        if quotes.is_empty() {
+            let opening = text
+                .split("\n\n")
+                .filter(|paragraph| !paragraph.trim().is_empty())
+                .take(OPENING_PARAGRAPHS);
+            doc.push_str(&text_paragraphs(opening));
+        }
+        for quote in quotes {
+            doc.push_str(&format!(
+                "<blockquote><p>{}</p></blockquote>\n",
+                escape_html(quote)
+            ));
+        }
+    }
+    doc.push_str("</section>\n");
+    doc
+}
+
+/// Source text as escaped `<p>` paragraphs, so lines that look like Markdown stay plain text
+fn text_paragraphs<'a>(paragraphs: impl Iterator<Item = &'a str>) -> String {
+    paragraphs
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| format!("<p>{}</p>\n", escape_html(paragraph)))
+        .collect()
+}
+
+fn xhtml(title: &str, body: &str, language: &str, epub_type: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{0}\" lang=\"{0}\"{4}>\n<head>\n<meta charset=\"utf-8\"/>\n<title>{1}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body epub:type=\"{3}\">\n{2}</body>\n</html>\n",
+        escape_html(language),
+        escape_html(title),
+        body,
+        epub_type,
+        output::direction(language)
+    )
+}
+
+/// A name-based UUID URN that stays the same across runs, so readers replace an earlier copy of
+/// the summary, derived from the source book's ISBN (or title) and the summary language so
+/// translations are distinct books
+fn identifier(metadata: &BookMetadata, title: &str, language: &str) -> String {
+    let key = match &metadata.isbn {
+        Some(isbn) => format!("isbn:{}", isbn),
+        None => format!("title:{}", title),
+    };
+    let hex = crate::manifest::sha256_hex(format!("{}|{}", key, language).as_bytes());
+    // Version 5 and RFC 4122 variant bits, as for a name-based UUID
+    let variant = ["8", "9", "a", "b"][usize::from_str_radix(&hex[16..17], 16).unwrap_or(0) % 4];
+    format!(
+        "urn:uuid:{}-{}-5{}-{}{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[13..16],
+        variant,
+        &hex[17..20],
+        &hex[20..32]
+    )
+}
+
+/// Downscales a raster image when `max_image_size` asks for it, keeping its format
+fn shrink_image(data: Vec<u8>, extension: &str, package: &PackageOptions) -> Vec<u8> {
+    if package.max_image_size == 0 {
+        return data;
+    }
+    let options = ImageOptions {
+        mode: ImageMode::All,
+        min_size: 0,
+        max_size: package.max_image_size,
+        jpeg_quality: package.image_quality,
+    };
+    let original = data.clone();
+    images::optimize(data, media_type(extension), &options).unwrap_or(original)
+}
+
+/// Packages byte-identical images once, pointing the documents at the first copy, and drops
+/// resources that neither a document nor the stylesheet refers to
+fn strip_unused(resources: &mut Vec<Resource>, bodies: &mut [String], stylesheet: &str) {
+    let mut kept: Vec<Resource> = Vec::new();
+    for resource in resources.drain(..) {
+        let duplicate_of = kept.iter().find(|other| {
+            other.media_type.starts_with("image/")
+                && other.media_type == resource.media_type
+                && other.data == resource.data
+        });
+        match duplicate_of {
+            Some(original) if resource.properties.is_none() => {
+                let from = format!("src=\"{}\"", escape_html(&resource.href));
+                let to = format!("src=\"{}\"", escape_html(&original.href));
+                for body in bodies.iter_mut() {
+                    *body = body.replace(&from, &to);
+                }
+            }
+            _ => kept.push(resource),
+        }
+    }
+
+    let attribute = Regex::new(r#"(?:src|href)="([^"]*)""#).unwrap();
+    let url = Regex::new(r#"url\(\s*["']?([^"')]+)["']?\s*\)"#).unwrap();
+    let referenced: HashSet<String> = bodies
+        .iter()
+        .flat_map(|body| attribute.captures_iter(body))
+        .map(|caps| caps[1].to_string())
+        .chain(
+            url.captures_iter(stylesheet)
+                .map(|caps| caps[1].to_string()),
+        )
+        .collect();
+    kept.retain(|resource| {
+        referenced.contains(&escape_html(&resource.href)) || referenced.contains(&resource.href)
+    });
+    *resources = kept;
+}
+
+fn media_type(extension: &str) -> &'static str {
+    match extension.to_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,184 @@
+use crate::schema;
+use crate::summarizer::Summarizer;
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Judge scores from 1 (poor) to 5 (excellent)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Scores {
+    /// How much of the source's key content the summary covers
+    pub coverage: f32,
+    /// Whether every statement in the summary is supported by the source
+    pub faithfulness: f32,
+    pub clarity: f32,
+}
+
+impl Scores {
+    fn parse(value: &Value) -> Option<Self> {
+        let score = |name: &str| {
+            value
+                .get(name)
+                .and_then(Value::as_f64)
+                .map(|s| s.clamp(1.0, 5.0) as f32)
+        };
+        Some(Scores {
+            coverage: score("coverage")?,
+            faithfulness: score("faithfulness")?,
+            clarity: score("clarity")?,
+        })
+    }
+
+    pub fn overall(&self) -> f32 {
+        (self.coverage + self.faithfulness + self.clarity) / 3.0
+    }
+}
+
+/// Scores of one chapter, averaged over its sections
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChapterEvaluation {
+    pub title: String,
+    /// `None` when the judge gave no usable answer for any section
+    pub scores: Option<Scores>,
+    pub issues: Vec<String>,
+    /// Set when the chapter scored below `--min-score` and was summarized again
+    pub resummarized: bool,
+}
+
+impl ChapterEvaluation {
+    pub fn overall(&self) -> Option<f32> {
+        self.scores.as_ref().map(Scores::overall)
+    }
+}
+
+/// Asks the judge to score every section summary against the section it was written from
+pub async fn evaluate_chapter(
+    summarizer: &Summarizer,
+    title: &str,
+    sections: &[String],
+    section_summaries: &[Value],
+) -> ChapterEvaluation {
+    let mut scored = Vec::new();
+    let mut issues = Vec::new();
+
+    for (section, summary) in sections.iter().zip(section_summaries) {
+        let summary = summary
+            .get("summary")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        match summarizer.evaluate_summary(section, summary).await {
+            Ok(value) => {
+                match Scores::parse(&value) {
+                    Some(scores) => scored.push(scores),
+                    None => warn!("Evaluation of '{}' returned no scores", title),
+                }
+                issues.extend(
+                    value
+                        .get("issues")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(Value::as_str)
+                        .map(|issue| issue.trim().to_string())
+                        .filter(|issue| !issue.is_empty()),
+                );
+            }
+            Err(e) => warn!("Evaluation of '{}' failed: {}", title, e),
+        }
+    }
+
+    let scores = (!scored.is_empty()).then(|| {
+        let count = scored.len() as f32;
+        Scores {
+            coverage: scored.iter().map(|s| s.coverage).sum::<f32>() / count,
+            faithfulness: scored.iter().map(|s| s.faithfulness).sum::<f32>() / count,
+            clarity: scored.iter().map(|s| s.clarity).sum::<f32>() / count,
+        }
+    });
+
+    ChapterEvaluation {
+        title: title.to_string(),
+        scores,
+        issues,
+        resummarized: false,
+    }
+}
+
+/// Quality scores for the whole book
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EvaluationReport {
+    pub average: Option<f32>,
+    pub chapters: Vec<ChapterEvaluation>,
+}
+
+impl EvaluationReport {
+    pub fn push(&mut self, chapter: ChapterEvaluation) {
+        self.chapters.push(chapter);
+        let overall: Vec<f32> = self
+            .chapters
+            .iter()
+            .filter_map(ChapterEvaluation::overall)
+            .collect();
+        self.average =
+            (!overall.is_empty()).then(|| overall.iter().sum::<f32>() / overall.len() as f32);
+    }
+}
+
+/// Writes `evaluation.json` and a readable `evaluation.md`, returning the Markdown path
+pub fn write_report(
+    output_dir: &Path,
+    title: &str,
+    report: &EvaluationReport,
+    min_score: f32,
+) -> Result<PathBuf> {
+    schema::write_json(&output_dir.join("evaluation.json"), report)?;
+
+    let mut doc = format!("# Summary Evaluation: {}\n\n", title);
+    if let Some(average) = report.average {
+        doc.push_str(&format!("**Average score:** {:.1} / 5\n\n", average));
+    }
+    doc.push_str(
+        "| Chapter | Coverage | Faithfulness | Clarity | Overall |\n|---|---|---|---|---|\n",
+    );
+    for chapter in &report.chapters {
+        let mut name = chapter.title.replace('|', "\\|");
+        if chapter.resummarized {
+            name.push_str(" (re-summarized)");
+        }
+        match &chapter.scores {
+            Some(scores) => {
+                let flag = if min_score > 0.0 && scores.overall() < min_score {
+                    " ⚠"
+                } else {
+                    ""
+                };
+                doc.push_str(&format!(
+                    "| {} | {:.1} | {:.1} | {:.1} | {:.1}{} |\n",
+                    name,
+                    scores.coverage,
+                    scores.faithfulness,
+                    scores.clarity,
+                    scores.overall(),
+                    flag
+                ));
+            }
+            None => doc.push_str(&format!("| {} | – | – | – | – |\n", name)),
+        }
+    }
+    doc.push('\n');
+
+    for chapter in report.chapters.iter().filter(|c| !c.issues.is_empty()) {
+        doc.push_str(&format!("## {}\n\n", chapter.title));
+        for issue in &chapter.issues {
+            doc.push_str(&format!("- {}\n", issue));
+        }
+        doc.push('\n');
+    }
+
+    let path = output_dir.join("evaluation.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
@@ -0,0 +1,667 @@
+use crate::images;
+use crate::text::{decode_entities, escape_html};
+use anyhow::{anyhow, Result};
+use html2text::render::{TaggedLine, TextDecorator};
+use log::warn;
+use lol_html::html_content::ContentType;
+use lol_html::{element, end_tag, rewrite_str, text, RewriteStrSettings};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Elements holding the body of a footnote or endnote
+const NOTE_SELECTOR: &str = r#"[epub\:type~="footnote"], [epub\:type~="endnote"], [epub\:type~="rearnote"], [role="doc-footnote"], [role="doc-endnote"]"#;
+/// Links pointing at a note
+const NOTEREF_SELECTOR: &str = r#"a[epub\:type~="noteref"], a[role="doc-noteref"]"#;
+
+/// How footnotes and endnotes are carried into the extracted text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FootnoteMode {
+    /// Drop note references and note bodies
+    Strip,
+    /// Replace each reference with the note text in parentheses
+    Inline,
+    /// Number the references and append the notes at the end of the chapter
+    Collect,
+}
+
+impl FootnoteMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "strip" => Ok(FootnoteMode::Strip),
+            "inline" => Ok(FootnoteMode::Inline),
+            "collect" => Ok(FootnoteMode::Collect),
+            other => Err(anyhow!(
+                "Unknown footnote mode '{}' (expected strip, inline or collect)",
+                other
+            )),
+        }
+    }
+}
+
+/// How hyperlinks are rendered in the extracted text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Keep external URLs inline after the link text
+    Keep,
+    /// Number external links and list their URLs at the end of the chapter
+    Footnote,
+    /// Keep only the link text
+    Strip,
+}
+
+impl LinkMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "keep" => Ok(LinkMode::Keep),
+            "footnote" => Ok(LinkMode::Footnote),
+            "strip" => Ok(LinkMode::Strip),
+            other => Err(anyhow!(
+                "Unknown link mode '{}' (expected keep, footnote or strip)",
+                other
+            )),
+        }
+    }
+}
+
+/// How list items are marked in the extracted text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListStyle {
+    /// `* item` and `1. item`
+    Markdown,
+    /// Items without any marker
+    Plain,
+}
+
+impl ListStyle {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "markdown" => Ok(ListStyle::Markdown),
+            "plain" => Ok(ListStyle::Plain),
+            other => Err(anyhow!(
+                "Unknown list style '{}' (expected markdown or plain)",
+                other
+            )),
+        }
+    }
+}
+
+/// Options controlling how chapter HTML is turned into text for the LLM
+#[derive(Debug, Clone)]
+pub struct ExtractionOptions {
+    /// Line width for wrapping, 0 for no wrapping
+    pub width: usize,
+    /// Emit Markdown-style headings, emphasis and inline code
+    pub markdown: bool,
+    pub links: LinkMode,
+    pub lists: ListStyle,
+    pub footnotes: FootnoteMode,
+    /// Remove blocks repeated across chapters (running headers, author bios, promo text)
+    pub strip_boilerplate: bool,
+}
+
+impl Default for ExtractionOptions {
+    fn default() -> Self {
+        ExtractionOptions {
+            width: 0,
+            markdown: true,
+            links: LinkMode::Footnote,
+            lists: ListStyle::Markdown,
+            footnotes: FootnoteMode::Collect,
+            strip_boilerplate: true,
+        }
+    }
+}
+
+/// Converts HTML to text with html2text according to the extraction options
+pub fn render_text(html: &str, options: &ExtractionOptions) -> Result<String> {
+    let width = if options.width == 0 {
+        usize::MAX
+    } else {
+        options.width
+    };
+    let decorator = ExtractionDecorator::new(options);
+    Ok(html2text::config::with_decorator(decorator).string_from_read(html.as_bytes(), width)?)
+}
+
+/// html2text decorator implementing the configurable markup, link and list rendering
+#[derive(Clone)]
+struct ExtractionDecorator {
+    markdown: bool,
+    links: LinkMode,
+    lists: ListStyle,
+    /// URLs of the links currently open, innermost last
+    open_links: Vec<Option<String>>,
+    /// External URLs numbered in footnote mode, shared with sub-block decorators
+    footnotes: Rc<RefCell<Vec<String>>>,
+}
+
+impl ExtractionDecorator {
+    fn new(options: &ExtractionOptions) -> Self {
+        ExtractionDecorator {
+            markdown: options.markdown,
+            links: options.links,
+            lists: options.lists,
+            open_links: Vec::new(),
+            footnotes: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    fn markup(&self, text: &str) -> String {
+        if self.markdown {
+            text.to_string()
+        } else {
+            String::new()
+        }
+    }
+}
+
+impl TextDecorator for ExtractionDecorator {
+    type Annotation = ();
+
+    fn decorate_link_start(&mut self, url: &str) -> (String, Self::Annotation) {
+        // Links inside the book (other chapters, anchors) only add noise
+        let external =
+            url.starts_with("http://") || url.starts_with("https://") || url.starts_with("mailto:");
+        self.open_links.push(external.then(|| url.to_string()));
+        (String::new(), ())
+    }
+
+    fn decorate_link_end(&mut self) -> String {
+        let Some(Some(url)) = self.open_links.pop() else {
+            return String::new();
+        };
+        match self.links {
+            LinkMode::Keep => format!(" ({})", url),
+            LinkMode::Footnote => {
+                let mut footnotes = self.footnotes.borrow_mut();
+                footnotes.push(url);
+                format!("[{}]", footnotes.len())
+            }
+            LinkMode::Strip => String::new(),
+        }
+    }
+
+    fn decorate_em_start(&self) -> (String, Self::Annotation) {
+        (self.markup("*"), ())
+    }
+
+    fn decorate_em_end(&self) -> String {
+        self.markup("*")
+    }
+
+    fn decorate_strong_start(&self) -> (String, Self::Annotation) {
+        (self.markup("**"), ())
+    }
+
+    fn decorate_strong_end(&self) -> String {
+        self.markup("**")
+    }
+
+    fn decorate_strikeout_start(&self) -> (String, Self::Annotation) {
+        (String::new(), ())
+    }
+
+    fn decorate_strikeout_end(&self) -> String {
+        String::new()
+    }
+
+    fn decorate_code_start(&self) -> (String, Self::Annotation) {
+        (self.markup("`"), ())
+    }
+
+    fn decorate_code_end(&self) -> String {
+        self.markup("`")
+    }
+
+    fn decorate_preformat_first(&self) -> Self::Annotation {}
+    fn decorate_preformat_cont(&self) -> Self::Annotation {}
+
+    fn decorate_image(&mut self, _src: &str, title: &str) -> (String, Self::Annotation) {
+        if self.markdown {
+            (format!("[{}]", title), ())
+        } else {
+            (title.to_string(), ())
+        }
+    }
+
+    fn header_prefix(&self, level: usize) -> String {
+        self.markup(&("#".repeat(level) + " "))
+    }
+
+    fn quote_prefix(&self) -> String {
+        self.markup("> ")
+    }
+
+    fn unordered_item_prefix(&self) -> String {
+        match self.lists {
+            ListStyle::Markdown => "* ".to_string(),
+            ListStyle::Plain => String::new(),
+        }
+    }
+
+    fn ordered_item_prefix(&self, i: i64) -> String {
+        match self.lists {
+            ListStyle::Markdown => format!("{}. ", i),
+            ListStyle::Plain => String::new(),
+        }
+    }
+
+    fn make_subblock_decorator(&self) -> Self {
+        ExtractionDecorator {
+            open_links: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    fn finalise(&mut self, _links: Vec<String>) -> Vec<TaggedLine<()>> {
+        self.footnotes
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(index, url)| TaggedLine::from_string(format!("[{}]: {}", index + 1, url), &()))
+            .collect()
+    }
+}
+
+/// Note texts keyed by the path of the document holding them and their element id
+///
+/// The same id (such as `fn1`) is often reused by every chapter of a book, so the id alone
+/// does not identify a note.
+pub type Notes = HashMap<(PathBuf, String), String>;
+
+/// Collects the text of every note body in `html`, the document at `document_path`
+///
+/// Endnotes usually live in a separate document, so notes are gathered across the whole book.
+pub fn collect_notes(notes: &mut Notes, document_path: &Path, html: &str) {
+    let found: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    let current: RefCell<Option<String>> = RefCell::new(None);
+
+    let result = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!(NOTE_SELECTOR, |el| {
+                    *current.borrow_mut() = el.get_attribute("id");
+                    Ok(())
+                }),
+                text!(NOTE_SELECTOR, |chunk| {
+                    if let Some(id) = current.borrow().as_ref() {
+                        found
+                            .borrow_mut()
+                            .entry(id.clone())
+                            .or_default()
+                            .push_str(chunk.as_str());
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    if let Err(e) = result {
+        warn!("Could not scan document for notes: {}", e);
+    }
+
+    let document_path = images::normalize(document_path);
+    for (id, text) in found.into_inner() {
+        let text = clean_note_text(&decode_entities(&text));
+        if !text.is_empty() {
+            notes.insert((document_path.clone(), id), text);
+        }
+    }
+}
+
+/// The document and id a note reference points at, with its href resolved against the
+/// document holding the reference
+fn note_target(document_path: &Path, href: &str) -> Option<(PathBuf, String)> {
+    let (target, id) = href.rsplit_once('#')?;
+    let document_path = images::normalize(document_path);
+    if target.is_empty() {
+        return Some((document_path, id.to_string()));
+    }
+    let base = document_path.parent().unwrap_or(Path::new(""));
+    let target = images::normalize(&base.join(images::percent_decode(target)));
+    Some((target, id.to_string()))
+}
+
+/// Rewrites note references and removes note bodies according to the footnote mode
+///
+/// `document_path` is the path of the document `html` comes from, against which the references
+/// are resolved. Returns the rewritten HTML and, in collect mode, the numbered notes to append
+/// after the text.
+pub fn apply_footnotes(
+    html: &str,
+    document_path: &Path,
+    mode: FootnoteMode,
+    notes: &Notes,
+) -> Result<(String, Vec<String>)> {
+    let collected: RefCell<Vec<String>> = RefCell::new(Vec::new());
+
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!(NOTE_SELECTOR, |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                element!(NOTEREF_SELECTOR, |el| {
+                    let note = el
+                        .get_attribute("href")
+                        .and_then(|href| note_target(document_path, &href))
+                        .and_then(|target| notes.get(&target));
+
+                    match (mode, note) {
+                        (FootnoteMode::Inline, Some(note)) => {
+                            el.replace(&format!(" (Note: {})", note), ContentType::Text)
+                        }
+                        (FootnoteMode::Collect, Some(note)) => {
+                            let mut collected = collected.borrow_mut();
+                            collected.push(note.clone());
+                            el.replace(&format!("[{}]", collected.len()), ContentType::Text);
+                        }
+                        _ => el.remove(),
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok((rewritten, collected.into_inner()))
+}
+
+/// Appends collected notes to the chapter text as a numbered "Notes" section
+pub fn append_notes(text: &mut String, notes: &[String]) {
+    if notes.is_empty() {
+        return;
+    }
+    text.push_str("\n\nNotes:\n");
+    for (index, note) in notes.iter().enumerate() {
+        text.push_str(&format!("[{}] {}\n", index + 1, note));
+    }
+}
+
+fn clean_note_text(text: &str) -> String {
+    let text = text.replace('↩', "");
+    let words: Vec<&str> = text.split_whitespace().collect();
+    // Drop the leading note number that usually links back to the reference
+    let start = match words.first() {
+        Some(first)
+            if first
+                .trim_end_matches(['.', ')', ']'])
+                .trim_start_matches('[')
+                .chars()
+                .all(|c| c.is_ascii_digit()) =>
+        {
+            1
+        }
+        _ => 0,
+    };
+    words[start..].join(" ")
+}
+
+/// Accumulates the cells of the table currently being rewritten
+#[derive(Default)]
+struct TableBuilder {
+    caption: String,
+    rows: Vec<Vec<String>>,
+    in_caption: bool,
+}
+
+impl TableBuilder {
+    fn push_text(&mut self, text: &str) {
+        if self.in_caption {
+            self.caption.push_str(text);
+        } else if let Some(cell) = self.rows.last_mut().and_then(|row| row.last_mut()) {
+            cell.push_str(text);
+        }
+    }
+
+    /// Renders the collected rows as a Markdown table, using the first row as header
+    fn to_markdown(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|cell| markdown_cell(cell)).collect())
+            .filter(|row: &Vec<String>| row.iter().any(|cell| !cell.is_empty()))
+            .collect();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+
+        let mut markdown = String::new();
+        let caption = self
+            .caption
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !caption.is_empty() {
+            markdown.push_str(&format!("Table: {}\n\n", caption));
+        }
+        for (index, row) in rows.iter().enumerate() {
+            let mut cells = row.clone();
+            cells.resize(columns, String::new());
+            markdown.push_str(&format!("| {} |\n", cells.join(" | ")));
+            if index == 0 {
+                markdown.push_str(&format!("|{}\n", "---|".repeat(columns)));
+            }
+        }
+        markdown
+    }
+}
+
+/// Replaces every `<table>` with a Markdown table so tabular facts survive text conversion
+///
+/// The Markdown is emitted inside `<pre>` so html2text keeps its line structure.
+pub fn tables_to_markdown(html: &str) -> Result<String> {
+    let table = Rc::new(RefCell::new(TableBuilder::default()));
+
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("table", |el| {
+                    *table.borrow_mut() = TableBuilder::default();
+                    el.set_tag_name("div")?;
+                    let table = table.clone();
+                    el.on_end_tag(end_tag!(move |end| {
+                        let markdown = table.borrow().to_markdown();
+                        if !markdown.is_empty() {
+                            end.before(
+                                &format!("<pre>{}</pre>", escape_html(&markdown)),
+                                ContentType::Html,
+                            );
+                        }
+                        Ok(())
+                    }))
+                }),
+                element!("table caption", |el| {
+                    table.borrow_mut().in_caption = true;
+                    let table = table.clone();
+                    el.remove_and_keep_content();
+                    el.on_end_tag(end_tag!(move |_| {
+                        table.borrow_mut().in_caption = false;
+                        Ok(())
+                    }))
+                }),
+                element!("table tr", |el| {
+                    table.borrow_mut().rows.push(Vec::new());
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+                element!("table td, table th", |el| {
+                    let mut table = table.borrow_mut();
+                    if table.rows.is_empty() {
+                        table.rows.push(Vec::new());
+                    }
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(String::new());
+                    }
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+                element!("table br", |el| {
+                    table.borrow_mut().push_text(" ");
+                    el.remove();
+                    Ok(())
+                }),
+                text!("table", |chunk| {
+                    table
+                        .borrow_mut()
+                        .push_text(&decode_entities(chunk.as_str()));
+                    chunk.remove();
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok(rewritten)
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+/// Replaces images with their alt text and labels figure captions, so figures contribute to the
+/// summary without a vision model; images without alt text are treated as decorative and dropped
+pub fn describe_images(html: &str) -> Result<String> {
+    let describe = |label: &str, el: &mut lol_html::html_content::Element| {
+        let alt = el
+            .get_attribute("alt")
+            .map(|alt| {
+                decode_entities(&alt)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default();
+        if alt.is_empty() {
+            el.remove();
+        } else {
+            el.replace(
+                &format!("<p>[{}: {}]</p>", label, escape_html(&alt)),
+                ContentType::Html,
+            );
+        }
+    };
+
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("figure img", |el| {
+                    describe("Figure", el);
+                    Ok(())
+                }),
+                element!("img", |el| {
+                    if !el.removed() {
+                        describe("Image", el);
+                    }
+                    Ok(())
+                }),
+                element!("figcaption", |el| {
+                    el.prepend("Caption: ", ContentType::Text);
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok(rewritten)
+}
+
+/// Wraps `<pre>` blocks in Markdown code fences so code survives text conversion verbatim
+pub fn fence_code_blocks(html: &str) -> Result<String> {
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("pre", |el| {
+                    let language =
+                        code_language(el.get_attribute("data-language"), el.get_attribute("class"));
+                    el.prepend(&format!("```{}\n", language), ContentType::Text);
+                    el.append("\n```", ContentType::Text);
+                    Ok(())
+                }),
+                // html2text already backticks <code>, which would leak into the fenced block
+                element!("pre code", |el| {
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok(rewritten)
+}
+
+/// Reads the language from `data-language` or a `language-*`/`lang-*` class
+fn code_language(data_language: Option<String>, class: Option<String>) -> String {
+    data_language
+        .or_else(|| {
+            class?.split_whitespace().find_map(|c| {
+                c.strip_prefix("language-")
+                    .or_else(|| c.strip_prefix("lang-"))
+                    .map(str::to_string)
+            })
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(text: &str, note: &str) -> String {
+        format!(
+            r##"<p>{}<a epub:type="noteref" href="#fn1">1</a><a epub:type="noteref" href="../notes/end.xhtml#fn1">2</a></p><aside epub:type="footnote" id="fn1"><p>{}</p></aside>"##,
+            text, note
+        )
+    }
+
+    #[test]
+    fn notes_with_the_same_id_stay_in_their_own_document() {
+        let one = chapter("One", "First note");
+        let two = chapter("Two", "Second note");
+        let end = r#"<aside epub:type="endnote" id="fn1"><p>Endnote</p></aside>"#;
+        let mut notes = Notes::new();
+        collect_notes(&mut notes, Path::new("text/one.xhtml"), &one);
+        collect_notes(&mut notes, Path::new("text/two.xhtml"), &two);
+        collect_notes(&mut notes, Path::new("notes/end.xhtml"), end);
+        assert_eq!(notes.len(), 3);
+
+        let (_, collected) = apply_footnotes(
+            &two,
+            Path::new("text/two.xhtml"),
+            FootnoteMode::Collect,
+            &notes,
+        )
+        .unwrap();
+        assert_eq!(collected, ["Second note", "Endnote"]);
+    }
+
+    #[test]
+    fn unknown_references_are_removed() {
+        let (html, collected) = apply_footnotes(
+            &chapter("One", "Note"),
+            Path::new("one.xhtml"),
+            FootnoteMode::Inline,
+            &Notes::new(),
+        )
+        .unwrap();
+        assert!(collected.is_empty());
+        assert!(!html.contains("noteref"));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::mock;
+use crate::summarizer;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use http::Extensions;
+use regex::Regex;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// Share of a sentence's rank passed on to the sentences similar to it (TextRank's damping)
+const DAMPING: f64 = 0.85;
+/// Power iterations; rankings settle well before this on chapter-sized text
+const ITERATIONS: usize = 30;
+/// Shorter words ("a", "of", "is") are too common to tie sentences together
+const MIN_WORD_CHARS: usize = 3;
+/// Share of a section's prose an extractive summary keeps
+const SUMMARY_RATIO: f32 = 0.25;
+/// Sentences of a section given as key points with their quotes
+const KEY_POINTS: usize = 3;
+/// Shortest word counted as a keyword, leaving out most function words in European languages
+const KEYWORD_CHARS: usize = 5;
+
+/// Sentence-ending punctuation, with any closing quotes and brackets, and the space after it
+static SENTENCE_END: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"[.!?…]+["'”’)\]]*\s+"#).unwrap());
+
+/// How section summaries are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// By the model
+    Llm,
+    /// From the book's own highest-ranked sentences, offline (`--provider none`)
+    Extractive,
+}
+
+impl Strategy {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "llm" => Ok(Strategy::Llm),
+            "extractive" => Ok(Strategy::Extractive),
+            other => Err(anyhow!(
+                "Unknown strategy '{}' (expected llm or extractive)",
+                other
+            )),
+        }
+    }
+}
+
+/// Answers chat-completion requests in-process for `--provider none --strategy extractive`, so
+/// a book is summarized offline and for free from its own sentences
+///
+/// Section summaries are the section's highest-ranked sentences in reading order, with its
+/// most frequent words as keywords and its top sentences as key points quoting themselves;
+/// plans and chapter titles are filled in from templates. Prompts that need a model, such as
+/// evaluation or the book-level extras, get an empty object.
+pub struct ExtractiveProvider;
+
+#[async_trait]
+impl Middleware for ExtractiveProvider {
+    async fn handle(
+        &self,
+        req: Request,
+        _extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        mock::respond(&req, answer)
+    }
+}
+
+fn answer(prompt: &str) -> Value {
+    if let Some(answer) = mock::template_answer(prompt) {
+        return answer;
+    }
+    let Some((_, text)) = prompt.rsplit_once("\nText:\n") else {
+        return json!({});
+    };
+    if !prompt.contains("\"summary\"") {
+        return json!({});
+    }
+
+    let prose = mock::section_prose(text);
+    let mut answer = json!({
+        "summary": top_sentences(&prose, SUMMARY_RATIO).join(" "),
+        "keywords": keywords(&prose, 5),
+        "glossary": [],
+        "references": [],
+        "additional_resources": []
+    });
+    if prompt.contains("\"key_points\"") {
+        let blocks = summarizer::text_blocks(&prose);
+        let sentences = prose_sentences(&blocks);
+        let mut order = ranked(&sentences);
+        order.truncate(KEY_POINTS);
+        order.sort_unstable();
+        let key_points: Vec<Value> = order
+            .into_iter()
+            .map(|index| json!({ "point": sentences[index], "quote": sentences[index] }))
+            .collect();
+        answer["key_points"] = json!(key_points);
+    }
+    answer
+}
+
+/// Keeps the most salient sentences of `text`, about `ratio` of its prose by length, ranked
+/// with TextRank
+///
+/// Headings, fenced code blocks, tables and image references are kept as they are, and the
+/// kept sentences stay in their original order, lines and paragraphs.
+pub fn condense(text: &str, ratio: f32) -> String {
+    if ratio >= 1.0 {
+        return text.to_string();
+    }
+    let blocks = summarizer::text_blocks(text);
+    let sentences = prose_sentences(&blocks);
+    let mut keep = select(&sentences, ratio).into_iter();
+
+    let mut kept = Vec::new();
+    for block in &blocks {
+        if !is_prose(block) {
+            kept.push(block.clone());
+            continue;
+        }
+        let lines: Vec<String> = block
+            .lines()
+            .map(|line| {
+                split_sentences(line)
+                    .into_iter()
+                    .filter(|_| keep.next().unwrap_or(true))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|line| !line.is_empty())
+            .collect();
+        if !lines.is_empty() {
+            kept.push(lines.join("\n"));
+        }
+    }
+    kept.join("\n\n")
+}
+
+/// The highest-ranked sentences of the prose in `text`, about `ratio` of it by length and at
+/// least one, in their original order
+pub fn top_sentences(text: &str, ratio: f32) -> Vec<String> {
+    let blocks = summarizer::text_blocks(text);
+    let sentences = prose_sentences(&blocks);
+    let keep = select(&sentences, ratio);
+    sentences
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(sentence, _)| sentence.to_string())
+        .collect()
+}
+
+/// The `count` words of five or more letters used most often in `text`, with the casing of
+/// their first use
+pub fn keywords(text: &str, count: usize) -> Vec<String> {
+    let mut counts: HashMap<String, (usize, usize, &str)> = HashMap::new();
+    for (position, word) in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= KEYWORD_CHARS)
+        .enumerate()
+    {
+        counts
+            .entry(word.to_lowercase())
+            .or_insert((0, position, word))
+            .0 += 1;
+    }
+    let mut words: Vec<(usize, usize, &str)> = counts.into_values().collect();
+    words.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    words
+        .into_iter()
+        .take(count)
+        .map(|(_, _, word)| word.to_string())
+        .collect()
+}
+
+fn prose_sentences(blocks: &[String]) -> Vec<&str> {
+    blocks
+        .iter()
+        .filter(|block| is_prose(block))
+        .flat_map(|block| block.lines().flat_map(split_sentences))
+        .collect()
+}
+
+/// Whether a block is running text rather than structure to keep whole
+fn is_prose(block: &str) -> bool {
+    let block = block.trim_start();
+    !["#", "```", "![", "|"]
+        .iter()
+        .any(|marker| block.starts_with(marker))
+}
+
+/// Splits a line after sentence-ending punctuation followed by a space
+fn split_sentences(line: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for end in SENTENCE_END.find_iter(line).map(|m| m.end()) {
+        sentences.push(line[start..end].trim());
+        start = end;
+    }
+    sentences.push(line[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+/// Marks the highest-ranked sentences until they add up to `ratio` of the text's length
+fn select(sentences: &[&str], ratio: f32) -> Vec<bool> {
+    let total: usize = sentences.iter().map(|sentence| sentence.len()).sum();
+    let budget = (total as f64 * ratio.max(0.0) as f64).ceil() as usize;
+    let mut keep = vec![false; sentences.len()];
+    let mut used = 0;
+    for index in ranked(sentences) {
+        if used >= budget {
+            break;
+        }
+        keep[index] = true;
+        used += sentences[index].len();
+    }
+    keep
+}
+
+/// Sentence positions from the highest-ranked down, earlier sentences first on a tie
+fn ranked(sentences: &[&str]) -> Vec<usize> {
+    let scores = rank(sentences);
+    let mut order: Vec<usize> = (0..sentences.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]).then(a.cmp(&b)));
+    order
+}
+
+/// TextRank scores: sentences sharing words with many well-ranked sentences rank higher
+fn rank(sentences: &[&str]) -> Vec<f64> {
+    let words: Vec<HashSet<String>> = sentences.iter().map(|sentence| words(sentence)).collect();
+    let count = sentences.len();
+    let mut weights = vec![vec![0.0; count]; count];
+    for i in 0..count {
+        for j in i + 1..count {
+            let weight = similarity(&words[i], &words[j]);
+            weights[i][j] = weight;
+            weights[j][i] = weight;
+        }
+    }
+    let totals: Vec<f64> = weights.iter().map(|row| row.iter().sum()).collect();
+
+    let mut scores = vec![1.0; count];
+    for _ in 0..ITERATIONS {
+        scores = (0..count)
+            .map(|i| {
+                let votes: f64 = (0..count)
+                    .filter(|&j| totals[j] > 0.0)
+                    .map(|j| weights[j][i] / totals[j] * scores[j])
+                    .sum();
+                (1.0 - DAMPING) + DAMPING * votes
+            })
+            .collect();
+    }
+    scores
+}
+
+fn words(sentence: &str) -> HashSet<String> {
+    sentence
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_WORD_CHARS)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Shared words, normalized by sentence length as in the TextRank paper
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let shared = a.intersection(b).count();
+    let norm = (a.len() as f64).ln() + (b.len() as f64).ln();
+    if shared == 0 || norm <= 0.0 {
+        0.0
+    } else {
+        shared as f64 / norm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn condense_keeps_structure_and_sentence_order() {
+        let text = "# Bread\n\n\
+                    Bread needs flour and water. The weather was mild. Flour and water make \
+                    dough for bread. Dough rises with yeast and becomes bread.\n\n\
+                    ```\nknead(dough)\n```";
+        let condensed = condense(text, 0.5);
+        assert!(condensed.starts_with("# Bread\n\n"));
+        assert!(condensed.ends_with("```\nknead(dough)\n```"));
+        assert!(!condensed.contains("The weather was mild."));
+
+        let prose = summarizer::text_blocks(&condensed)[1].clone();
+        let kept = split_sentences(&prose);
+        let blocks = summarizer::text_blocks(text);
+        let original = split_sentences(&blocks[1]);
+        let positions: Vec<usize> = kept
+            .iter()
+            .map(|sentence| original.iter().position(|s| s == sentence).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn condense_at_full_ratio_returns_the_text() {
+        assert_eq!(condense("One. Two.", 1.0), "One. Two.");
+    }
+}
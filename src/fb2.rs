@@ -0,0 +1,422 @@
+use crate::ebook::html_to_text;
+use crate::extract::{self, ExtractionOptions};
+use crate::images::{self, ImageMode, ImageOptions};
+use crate::metadata::{BookMetadata, Identifier};
+use crate::paths::safe_file_name;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use crate::text::escape_html;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use log::info;
+use roxmltree::{Document, Node};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// An embedded image: its MIME type and decoded bytes
+type Binary = (String, Vec<u8>);
+
+/// A FictionBook (FB2) document, plain or zipped as `.fb2.zip`
+///
+/// Each top-level `<section>` of the main body is a chapter; its FB2 markup is translated to
+/// XHTML so chapters go through the same extraction pipeline as EPUB documents.
+pub struct FictionBook {
+    metadata: BookMetadata,
+    /// Chapter titles and XHTML, in reading order
+    chapters: Vec<(Option<String>, String)>,
+    /// Notes of the single document, so their paths are empty
+    notes: extract::Notes,
+    binaries: HashMap<String, Binary>,
+    referenced_images: HashSet<String>,
+    cover: Option<String>,
+    options: ExtractionOptions,
+}
+
+pub fn open_fb2(path: &Path, options: &ExtractionOptions) -> Result<FictionBook> {
+    let xml = read_xml(path)?;
+    let document = Document::parse(&xml).map_err(|e| anyhow!("malformed FB2: {}", e))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "FictionBook" {
+        bail!("malformed FB2, the root element is not <FictionBook>");
+    }
+
+    let bodies: Vec<Node> = children(root, "body").collect();
+    let main_body = bodies
+        .iter()
+        .find(|body| body.attribute("name").is_none())
+        .or(bodies.first())
+        .ok_or_else(|| anyhow!("malformed FB2, the book has no <body>"))?;
+
+    // Footnotes and comments live in extra bodies named "notes" or "comments"
+    let mut notes = extract::Notes::new();
+    for body in bodies
+        .iter()
+        .filter(|body| body.attribute("name").is_some())
+    {
+        for section in body.descendants().filter(|n| n.has_tag_name("section")) {
+            if let Some(id) = section.attribute("id") {
+                let text = children(section, "p")
+                    .map(text_of)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                notes.insert((PathBuf::new(), id.to_string()), text);
+            }
+        }
+    }
+
+    let mut referenced_images = HashSet::new();
+    let chapters = chapter_sections(*main_body)
+        .into_iter()
+        .map(|section| {
+            let title = children(section, "title")
+                .next()
+                .map(text_of)
+                .filter(|title| !title.is_empty());
+            let mut html = String::from("<html><body>");
+            to_html(section, 1, &mut html, &mut referenced_images);
+            html.push_str("</body></html>");
+            (title, html)
+        })
+        .collect::<Vec<_>>();
+    info!("Total chapters: {}", chapters.len());
+
+    let binaries = children(root, "binary")
+        .filter_map(|binary| {
+            let id = binary.attribute("id")?;
+            let mime = binary.attribute("content-type").unwrap_or("image/jpeg");
+            let data: String = binary.text()?.split_whitespace().collect();
+            let data = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .ok()?;
+            Some((id.to_string(), (mime.to_string(), data)))
+        })
+        .collect();
+
+    let title_info = root.descendants().find(|n| n.has_tag_name("title-info"));
+    let cover = title_info
+        .and_then(|info| info.descendants().find(|n| n.has_tag_name("coverpage")))
+        .and_then(|coverpage| coverpage.descendants().find(|n| n.has_tag_name("image")))
+        .and_then(href);
+
+    Ok(FictionBook {
+        metadata: fb2_metadata(root),
+        chapters,
+        notes,
+        binaries,
+        referenced_images,
+        cover,
+        options: options.clone(),
+    })
+}
+
+impl EbookReader for FictionBook {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|(title, html)| {
+            Ok(Chapter {
+                title: title.clone(),
+                text: html_to_text(html, Path::new(""), &self.options, &self.notes)?,
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .filter_map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Decodes the `<binary>` images, named after their ids
+    fn images(&mut self, images_dir: &Path, options: &ImageOptions) -> Result<()> {
+        if options.mode == ImageMode::Skip {
+            return Ok(());
+        }
+        for (id, (mime, data)) in &self.binaries {
+            if options.mode == ImageMode::Referenced && !self.referenced_images.contains(id) {
+                continue;
+            }
+            let Some(data) = images::optimize(data.clone(), mime, options) else {
+                continue;
+            };
+            let filename = format!("{}.{}", safe_file_name(id), images::extension(mime));
+            fs::write(images_dir.join(filename), data)?;
+        }
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn cover(&mut self) -> Option<(Vec<u8>, String)> {
+        let (mime, data) = self.binaries.get(self.cover.as_ref()?)?;
+        Some((data.clone(), mime.clone()))
+    }
+}
+
+/// Reads the XML of a `.fb2` file, or of the first `.fb2` entry of a `.fb2.zip` archive
+fn read_xml(path: &Path) -> Result<String> {
+    let mut bytes = Vec::new();
+    // Zipped books are told apart by content, as they are not always named .fb2.zip
+    let mut magic = [0u8; 4];
+    let is_zip = File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == b"PK\x03\x04";
+    if is_zip {
+        let file = File::open(path).context("could not open file")?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))
+            .map_err(|e| anyhow!("corrupted FB2 archive ({})", e))?;
+        let name = archive
+            .file_names()
+            .find(|name| name.to_lowercase().ends_with(".fb2"))
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("the archive contains no .fb2 file"))?;
+        archive.by_name(&name)?.read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(path).context("could not open file")?;
+    }
+
+    // Many FB2 files from Russian-language libraries are still in Windows-1251
+    match String::from_utf8(bytes) {
+        Ok(xml) => Ok(xml),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let head = String::from_utf8_lossy(&bytes[..bytes.len().min(200)]).to_lowercase();
+            if head.contains("windows-1251") {
+                Ok(bytes.iter().map(|&b| cp1251(b)).collect())
+            } else {
+                bail!("the FB2 file is neither UTF-8 nor Windows-1251")
+            }
+        }
+    }
+}
+
+/// Decodes one Windows-1251 byte
+fn cp1251(byte: u8) -> char {
+    const HIGH: &str =
+        "ЂЃ‚ѓ„…†‡€‰Љ‹ЊЌЋЏђ‘’“”•–—\u{98}™љ›њќћџ\u{a0}ЎўЈ¤Ґ¦§Ё©Є«¬\u{ad}®Ї°±Ііґµ¶·ё№є»јЅѕї";
+    match byte {
+        0..=0x7f => byte as char,
+        0x80..=0xbf => HIGH.chars().nth(byte as usize - 0x80).unwrap_or('?'),
+        // А..я are contiguous in both encodings
+        _ => char::from_u32(0x410 + (byte as u32 - 0xc0)).unwrap_or('?'),
+    }
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+/// Whitespace-normalized text of a node and its descendants, one space between paragraphs
+fn text_of(node: Node) -> String {
+    let paragraphs: Vec<Node> = children(node, "p").collect();
+    let text = if paragraphs.is_empty() {
+        node.descendants()
+            .filter_map(|n| n.is_text().then(|| n.text()).flatten())
+            .collect::<String>()
+    } else {
+        paragraphs
+            .into_iter()
+            .map(text_of)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The target id of an `l:href="#id"` (or `xlink:href`) link
+fn href(node: Node) -> Option<String> {
+    node.attributes()
+        .find(|attribute| attribute.name() == "href")
+        .map(|attribute| attribute.value().trim_start_matches('#').to_string())
+}
+
+/// The sections read as chapters: the body's top-level sections, or their subsections when the
+/// book is wrapped in a single part
+fn chapter_sections<'a, 'input>(body: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+    let sections: Vec<Node> = children(body, "section").collect();
+    match sections.as_slice() {
+        [only] if children(*only, "section").count() > 1 => children(*only, "section").collect(),
+        [] => vec![body],
+        _ => sections,
+    }
+}
+
+/// Translates FB2 markup to the XHTML the extraction pipeline understands
+fn to_html(node: Node, depth: usize, html: &mut String, images: &mut HashSet<String>) {
+    for child in node.children() {
+        if child.is_text() {
+            html.push_str(&escape_html(child.text().unwrap_or_default()));
+            continue;
+        }
+        if !child.is_element() {
+            continue;
+        }
+        let wrap = |html: &mut String, images: &mut HashSet<String>, tag: &str| {
+            html.push_str(&format!("<{}>", tag));
+            to_html(child, depth, html, images);
+            html.push_str(&format!("</{}>", tag.split(' ').next().unwrap_or(tag)));
+        };
+        match child.tag_name().name() {
+            // Multi-line titles ("Part One" / "The Beginning") become a single heading
+            "title" => html.push_str(&format!(
+                "<h{level}>{}</h{level}>",
+                escape_html(&text_of(child)),
+                level = depth.min(6)
+            )),
+            "subtitle" => wrap(html, images, &format!("h{}", (depth + 1).min(6))),
+            "section" => {
+                html.push_str("<section>");
+                to_html(child, depth + 1, html, images);
+                html.push_str("</section>");
+            }
+            "p" | "v" | "text-author" => wrap(html, images, "p"),
+            "emphasis" => wrap(html, images, "em"),
+            "strong" => wrap(html, images, "strong"),
+            "strikethrough" => wrap(html, images, "del"),
+            "sub" | "sup" | "code" | "table" | "tr" | "th" | "td" => {
+                wrap(html, images, child.tag_name().name())
+            }
+            "epigraph" | "cite" => wrap(html, images, "blockquote"),
+            "poem" | "stanza" | "annotation" => wrap(html, images, "div"),
+            "empty-line" => html.push_str("<br/>"),
+            "image" => {
+                if let Some(id) = href(child) {
+                    let alt = child.attribute("alt").unwrap_or_default();
+                    html.push_str(&format!(
+                        r#"<img src="{}" alt="{}"/>"#,
+                        escape_html(&id),
+                        escape_html(alt)
+                    ));
+                    images.insert(id);
+                }
+            }
+            "a" => {
+                let target = href(child).unwrap_or_default();
+                let tag = if child.attribute("type") == Some("note") {
+                    format!(r##"a role="doc-noteref" href="#{}""##, escape_html(&target))
+                } else {
+                    format!(r##"a href="#{}""##, escape_html(&target))
+                };
+                wrap(html, images, &tag);
+            }
+            _ => to_html(child, depth, html, images),
+        }
+    }
+}
+
+/// Title, authors, language, annotation, date, publisher and ISBN from the FB2 description
+fn fb2_metadata(root: Node) -> BookMetadata {
+    let find = |parent: &str, name: &str| -> Option<Node> {
+        root.descendants()
+            .find(|n| n.has_tag_name(parent))?
+            .descendants()
+            .find(|n| n.has_tag_name(name))
+    };
+    let text = |parent: &str, name: &str| {
+        find(parent, name)
+            .map(text_of)
+            .filter(|text| !text.is_empty())
+    };
+    let people = |role: &'static str| -> Vec<String> {
+        root.descendants()
+            .find(|n| n.has_tag_name("title-info"))
+            .into_iter()
+            .flat_map(|info| children(info, role))
+            .map(|person| {
+                ["first-name", "middle-name", "last-name", "nickname"]
+                    .iter()
+                    .filter_map(|part| children(person, part).next().map(text_of))
+                    .filter(|part| !part.is_empty())
+                    .take(3)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|name| !name.is_empty())
+            .collect()
+    };
+
+    let isbn = text("publish-info", "isbn");
+    BookMetadata {
+        title: text("title-info", "book-title"),
+        creators: people("author"),
+        contributors: people("translator"),
+        language: text("title-info", "lang"),
+        publisher: text("publish-info", "publisher"),
+        description: text("title-info", "annotation"),
+        date: find("title-info", "date")
+            .and_then(|date| date.attribute("value").map(str::to_string))
+            .or_else(|| text("title-info", "date")),
+        subjects: root
+            .descendants()
+            .filter(|n| n.has_tag_name("genre"))
+            .map(text_of)
+            .collect(),
+        identifiers: isbn
+            .iter()
+            .map(|isbn| Identifier {
+                scheme: Some("ISBN".to_string()),
+                value: isbn.clone(),
+            })
+            .collect(),
+        isbn,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    const BOOK: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description><title-info>
+    <author><first-name>Anna</first-name><last-name>Petrova</last-name></author>
+    <book-title>The Harvest</book-title><lang>ru</lang>
+  </title-info></description>
+  <body>
+    <section>
+      <section><title><p>Part One</p><p>Rain</p></title><p>The rain came <emphasis>late</emphasis>.<a l:href="#n1" type="note">1</a></p></section>
+      <section><title><p>Winter</p></title><p>Snow &amp; ice.</p></section>
+    </section>
+  </body>
+  <body name="notes"><section id="n1"><p>A note.</p></section></body>
+</FictionBook>"##;
+
+    #[test]
+    fn sections_become_chapters() {
+        let mut file = tempfile::Builder::new().suffix(".fb2").tempfile().unwrap();
+        file.write_all(BOOK.as_bytes()).unwrap();
+        let mut book = open_fb2(file.path(), &ExtractionOptions::default()).unwrap();
+
+        assert_eq!(book.metadata().title.as_deref(), Some("The Harvest"));
+        assert_eq!(book.metadata().creators, ["Anna Petrova"]);
+        assert_eq!(book.toc(), ["Part One Rain", "Winter"]);
+        assert_eq!(book.notes.len(), 1);
+
+        let chapters: Vec<Chapter> = book.chapters().collect::<Result<_>>().unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert!(chapters[0].text.contains("The rain came *late*."));
+        assert!(chapters[1].text.contains("Snow & ice."));
+    }
+
+    #[test]
+    fn windows_1251_is_decoded() {
+        assert_eq!(cp1251(b'A'), 'A');
+        assert_eq!(cp1251(0xc0), 'А');
+        assert_eq!(cp1251(0xff), 'я');
+        assert_eq!(cp1251(0xa8), 'Ё');
+    }
+}
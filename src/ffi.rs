@@ -0,0 +1,473 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+static NEXT_JOB: AtomicUsize = AtomicUsize::new(1);
+
+/// Options `aibook_start` passes itself, or that would add books other than the opened one,
+/// which `args` may not repeat
+const FIXED_OPTIONS: [&str; 7] = [
+    "--input",
+    "-i",
+    "--batch",
+    "--output-dir",
+    "-o",
+    "--output-format",
+    "--progress-file",
+];
+
+/// Name of the aibook-cli executable on this platform
+#[cfg(windows)]
+const CLI_NAME: &str = "aibook-cli.exe";
+#[cfg(not(windows))]
+const CLI_NAME: &str = "aibook-cli";
+
+/// A book to summarize, opened with `aibook_open`
+pub struct AibookBook {
+    path: PathBuf,
+}
+
+/// A summary being made by an `aibook-cli` process, started with `aibook_start`
+pub struct AibookJob {
+    child: Mutex<Child>,
+    output_dir: PathBuf,
+    progress_path: PathBuf,
+    cancelled: AtomicBool,
+}
+
+/// State of a job
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AibookState {
+    Running = 0,
+    Succeeded = 1,
+    Failed = 2,
+    Cancelled = 3,
+}
+
+/// Progress of a job as reported by `aibook_poll`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AibookProgress {
+    pub state: AibookState,
+    pub chapters_done: u64,
+    pub chapters_total: u64,
+}
+
+/// Settings of a job, passed to `aibook_start` as JSON
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JobOptions {
+    /// aibook-cli executable; `AIBOOK_CLI`, the one next to the program, or the one on the PATH
+    /// by default
+    executable: Option<PathBuf>,
+    /// Where outputs go; a new directory under the system temp directory by default
+    output_dir: Option<PathBuf>,
+    /// Further command-line options, such as `["--language", "de"]`
+    args: Vec<String>,
+}
+
+/// Message of the last error on this thread, or NULL; valid until the next call that fails
+#[no_mangle]
+pub extern "C" fn aibook_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| {
+        error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Opens a book (any input aibook-cli reads), returning NULL when it does not exist
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_open(path: *const c_char) -> *mut AibookBook {
+    let result = string_argument(path, "path").and_then(|path| {
+        let path = PathBuf::from(path);
+        if path.is_file() {
+            Ok(AibookBook { path })
+        } else {
+            Err(format!("'{}' does not exist", path.display()))
+        }
+    });
+    into_handle(result)
+}
+
+/// Releases a book; jobs started from it keep running
+///
+/// # Safety
+///
+/// `book` must come from `aibook_open` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_book_free(book: *mut AibookBook) {
+    if !book.is_null() {
+        drop(Box::from_raw(book));
+    }
+}
+
+/// Starts summarizing a book, with `options_json` (or NULL for the defaults) holding
+/// `executable`, `output_dir` and extra command-line `args`; returns NULL when it cannot start
+///
+/// The API key is taken from the environment, as for aibook-cli.
+///
+/// # Safety
+///
+/// `book` must come from `aibook_open`; `options_json` must be NULL or a valid NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_start(
+    book: *const AibookBook,
+    options_json: *const c_char,
+) -> *mut AibookJob {
+    let Some(book) = book.as_ref() else {
+        set_error("book is NULL".to_string());
+        return ptr::null_mut();
+    };
+    let options = if options_json.is_null() {
+        Ok(JobOptions::default())
+    } else {
+        string_argument(options_json, "options_json").and_then(|json| {
+            serde_json::from_str(&json).map_err(|e| format!("invalid options: {}", e))
+        })
+    };
+    into_handle(options.and_then(|options| start(book, options)))
+}
+
+/// Fills `progress` with the job's state and chapter counts; returns 0, or -1 on error
+///
+/// # Safety
+///
+/// `job` must come from `aibook_start` and `progress` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_poll(
+    job: *const AibookJob,
+    progress: *mut AibookProgress,
+) -> c_int {
+    let (Some(job), false) = (job.as_ref(), progress.is_null()) else {
+        set_error("job or progress is NULL".to_string());
+        return -1;
+    };
+    match poll(job) {
+        Ok(polled) => {
+            *progress = polled;
+            0
+        }
+        Err(message) => {
+            set_error(message);
+            -1
+        }
+    }
+}
+
+/// The `summary.json` of a succeeded job, or NULL; free it with `aibook_string_free`
+///
+/// # Safety
+///
+/// `job` must come from `aibook_start`.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_result_json(job: *const AibookJob) -> *mut c_char {
+    let Some(job) = job.as_ref() else {
+        set_error("job is NULL".to_string());
+        return ptr::null_mut();
+    };
+    let result = poll(job).and_then(|progress| match progress.state {
+        AibookState::Succeeded => {
+            let summary = book_output_dir(job).join("summary.json");
+            fs::read_to_string(&summary)
+                .map_err(|e| format!("no summary at '{}': {}", summary.display(), e))
+        }
+        AibookState::Failed => Err(last_error(&job.output_dir.join("stderr.log"))),
+        state => Err(format!("the job has not succeeded ({:?})", state)),
+    });
+    match result.and_then(|json| CString::new(json).map_err(|e| e.to_string())) {
+        Ok(json) => json.into_raw(),
+        Err(message) => {
+            set_error(message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Stops a running job; returns 0, or -1 on error
+///
+/// # Safety
+///
+/// `job` must come from `aibook_start`.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_cancel(job: *const AibookJob) -> c_int {
+    let Some(job) = job.as_ref() else {
+        set_error("job is NULL".to_string());
+        return -1;
+    };
+    match cancel(job) {
+        Ok(()) => 0,
+        Err(message) => {
+            set_error(message);
+            -1
+        }
+    }
+}
+
+/// Releases a job, cancelling it when it still runs; its outputs stay on disk
+///
+/// # Safety
+///
+/// `job` must come from `aibook_start` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_job_free(job: *mut AibookJob) {
+    if job.is_null() {
+        return;
+    }
+    let job = Box::from_raw(job);
+    if matches!(poll(&job), Ok(progress) if progress.state == AibookState::Running) {
+        let _ = cancel(&job);
+    }
+}
+
+/// Releases a string returned by aibook
+///
+/// # Safety
+///
+/// `text` must come from aibook and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aibook_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+fn start(book: &AibookBook, options: JobOptions) -> Result<AibookJob, String> {
+    if let Some(fixed) = options.args.iter().find_map(|arg| fixed_option(arg)) {
+        return Err(format!(
+            "'{}' is set by aibook_start and cannot be passed in args (open the book with aibook_open and use output_dir for the output directory)",
+            fixed
+        ));
+    }
+    let output_dir = options.output_dir.unwrap_or_else(|| {
+        std::env::temp_dir().join(format!(
+            "aibook-{}-{}",
+            std::process::id(),
+            NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+        ))
+    });
+    fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+    let progress_path = output_dir.join("progress.json");
+    let _ = fs::remove_file(&progress_path);
+    // Errors aibook-cli prints end up here, so a failed job can say why
+    let stderr = File::create(output_dir.join("stderr.log")).map_err(|e| e.to_string())?;
+    let executable = find_executable(options.executable)?;
+
+    let child = Command::new(&executable)
+        .arg("--input")
+        .arg(&book.path)
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .args(["--output-format", "json", "--progress-file"])
+        .arg(&progress_path)
+        .args(&options.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| format!("could not run '{}': {}", executable.display(), e))?;
+    Ok(AibookJob {
+        child: Mutex::new(child),
+        output_dir,
+        progress_path,
+        cancelled: AtomicBool::new(false),
+    })
+}
+
+/// The fixed option `arg` sets, whether given as `--flag value`, `--flag=value`, `-ovalue` or
+/// after other short flags as in `-vvo`
+fn fixed_option(arg: &str) -> Option<&'static str> {
+    if let Some(flags) = arg
+        .strip_prefix('-')
+        .filter(|flags| !flags.starts_with('-'))
+    {
+        // `-v` is the only short flag without a value, so the first other letter is the option
+        let short = flags.trim_start_matches('v').chars().next()?;
+        return FIXED_OPTIONS
+            .into_iter()
+            .find(|option| option.len() == 2 && option.ends_with(short));
+    }
+    FIXED_OPTIONS.into_iter().find(|option| {
+        arg == *option
+            || arg
+                .strip_prefix(option)
+                .is_some_and(|rest| rest.starts_with('='))
+    })
+}
+
+/// The aibook-cli to run: the one from the options or `AIBOOK_CLI`, else the one installed next
+/// to the program embedding aibook, else the first on the PATH
+fn find_executable(configured: Option<PathBuf>) -> Result<PathBuf, String> {
+    if let Some(path) = configured.or_else(|| std::env::var_os("AIBOOK_CLI").map(PathBuf::from)) {
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(format!("aibook-cli not found at '{}'", path.display()))
+        };
+    }
+    let beside_program = std::env::current_exe()
+        .ok()
+        .and_then(|program| Some(program.parent()?.join(CLI_NAME)));
+    let on_path = std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(CLI_NAME))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    beside_program
+        .into_iter()
+        .chain(on_path)
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            format!(
+                "{} not found next to the program or on the PATH; set executable in the options or AIBOOK_CLI",
+                CLI_NAME
+            )
+        })
+}
+
+fn poll(job: &AibookJob) -> Result<AibookProgress, String> {
+    let status = job
+        .child
+        .lock()
+        .map_err(|_| "job lock poisoned".to_string())?
+        .try_wait()
+        .map_err(|e| e.to_string())?;
+    let progress = read_progress(&job.progress_path);
+    let count = |field: &str| progress.get(field).and_then(Value::as_u64).unwrap_or(0);
+    let state = match status {
+        None => AibookState::Running,
+        Some(_) if job.cancelled.load(Ordering::Relaxed) => AibookState::Cancelled,
+        Some(status) if status.success() && progress["stage"] == "done" => AibookState::Succeeded,
+        Some(_) => {
+            set_error(last_error(&job.output_dir.join("stderr.log")));
+            AibookState::Failed
+        }
+    };
+    Ok(AibookProgress {
+        state,
+        chapters_done: count("chapters_done"),
+        chapters_total: count("chapters_total"),
+    })
+}
+
+fn cancel(job: &AibookJob) -> Result<(), String> {
+    let mut child = job
+        .child
+        .lock()
+        .map_err(|_| "job lock poisoned".to_string())?;
+    if child.try_wait().map_err(|e| e.to_string())?.is_some() {
+        return Ok(());
+    }
+    job.cancelled.store(true, Ordering::Relaxed);
+    child.kill().map_err(|e| e.to_string())?;
+    child.wait().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The book's directory inside the job's output directory, as reported in the progress file
+fn book_output_dir(job: &AibookJob) -> PathBuf {
+    read_progress(&job.progress_path)["output_dir"]
+        .as_str()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| job.output_dir.clone())
+}
+
+fn read_progress(path: &Path) -> Value {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// The error aibook-cli printed last, or else its last line of output
+fn last_error(path: &Path) -> String {
+    let log = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().filter(|line| !line.trim().is_empty()).collect();
+    lines
+        .iter()
+        .rev()
+        .find(|line| line.to_lowercase().starts_with("error"))
+        .or(lines.last())
+        .map(|line| line.to_string())
+        .unwrap_or_else(|| "aibook-cli failed".to_string())
+}
+
+unsafe fn string_argument(text: *const c_char, name: &str) -> Result<String, String> {
+    if text.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map(str::to_string)
+        .map_err(|_| format!("{} is not UTF-8", name))
+}
+
+fn into_handle<T>(result: Result<T, String>) -> *mut T {
+    match result {
+        Ok(value) => Box::into_raw(Box::new(value)),
+        Err(message) => {
+            set_error(message);
+            ptr::null_mut()
+        }
+    }
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_options_are_found_in_every_form() {
+        for (arg, option) in [
+            ("--input", Some("--input")),
+            ("--input=other.epub", Some("--input")),
+            ("-i", Some("-i")),
+            ("-iother.epub", Some("-i")),
+            ("-vvo", Some("-o")),
+            ("--batch=books.json", Some("--batch")),
+            ("--output-format=html", Some("--output-format")),
+            ("--inputs", None),
+            ("--language", None),
+            ("-l", None),
+            ("-vv", None),
+            ("de", None),
+        ] {
+            assert_eq!(fixed_option(arg), option, "{}", arg);
+        }
+    }
+
+    #[test]
+    fn a_configured_executable_must_exist() {
+        let missing = std::env::temp_dir().join("aibook-missing").join(CLI_NAME);
+        let error = find_executable(Some(missing)).unwrap_err();
+        assert!(error.starts_with("aibook-cli not found at"), "{}", error);
+
+        let exists = std::env::current_exe().unwrap();
+        assert_eq!(find_executable(Some(exists.clone())), Ok(exists));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::text::normalize_words;
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What happens to key points whose supporting quote cannot be found in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroundingMode {
+    Off,
+    Flag,
+    Drop,
+}
+
+impl GroundingMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "off" => Ok(GroundingMode::Off),
+            "flag" => Ok(GroundingMode::Flag),
+            "drop" => Ok(GroundingMode::Drop),
+            other => Err(anyhow!(
+                "Unknown grounding mode '{}' (expected off, flag or drop)",
+                other
+            )),
+        }
+    }
+}
+
+/// A claim from the summary together with the source quote that supports it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyPoint {
+    pub point: String,
+    pub quote: String,
+    pub supported: bool,
+}
+
+/// Checks every key point of a section summary against the section text
+///
+/// A point is supported when its quote occurs in the source, ignoring case, punctuation and
+/// whitespace; quotes shortened with an ellipsis must match piece by piece. Unsupported points
+/// are removed (`Drop`) or marked with `"supported": false` (`Flag`).
+pub fn verify(summary: &mut Value, source: &str, mode: GroundingMode) {
+    if mode == GroundingMode::Off {
+        return;
+    }
+    let Some(points) = summary.get_mut("key_points").and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    let source = normalize_words(source);
+    for point in points.iter_mut() {
+        let quote = point
+            .get("quote")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let supported = is_quoted(quote, &source);
+        if let Some(point) = point.as_object_mut() {
+            point.insert("supported".to_string(), Value::Bool(supported));
+        }
+    }
+
+    let is_supported = |point: &Value| point.get("supported") == Some(&Value::Bool(true));
+    let unsupported = points.iter().filter(|point| !is_supported(point)).count();
+    if unsupported == 0 {
+        return;
+    }
+    if mode == GroundingMode::Drop {
+        points.retain(is_supported);
+        info!(
+            "Dropped {} key point(s) without a source quote",
+            unsupported
+        );
+    } else {
+        info!(
+            "Flagged {} key point(s) without a source quote",
+            unsupported
+        );
+    }
+}
+
+/// Parses the verified key points of a section summary, skipping malformed entries
+pub fn parse_key_points(value: &Value) -> Vec<KeyPoint> {
+    let Some(items) = value.get("key_points").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let field = |name: &str| {
+                item.get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            };
+            let point = field("point");
+            if point.is_empty() {
+                return None;
+            }
+            Some(KeyPoint {
+                point,
+                quote: field("quote"),
+                supported: item
+                    .get("supported")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(true),
+            })
+        })
+        .collect()
+}
+
+fn is_quoted(quote: &str, normalized_source: &str) -> bool {
+    let pieces: Vec<String> = quote
+        .split('…')
+        .flat_map(|piece| piece.split("..."))
+        .map(normalize_words)
+        .filter(|piece| !piece.is_empty())
+        .collect();
+    !pieces.is_empty()
+        && pieces
+            .iter()
+            .all(|piece| normalized_source.contains(piece.as_str()))
+}
@@ -0,0 +1,133 @@
+use crate::history::truncate;
+use anyhow::{bail, Result};
+use log::info;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Catalog search API over Project Gutenberg's metadata
+const SEARCH_URL: &str = "https://gutendex.com/books/";
+const EBOOK_URL: &str = "https://www.gutenberg.org/ebooks";
+
+/// A Project Gutenberg book as listed by a search
+#[derive(Debug, Clone, Deserialize)]
+pub struct GutenbergBook {
+    pub id: u64,
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<GutenbergAuthor>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GutenbergAuthor {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    results: Vec<GutenbergBook>,
+}
+
+/// Limit for one catalog or download request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Path of a book's EPUB in the cache, downloading it on first use
+///
+/// The EPUB3 edition with images is preferred; books only published as EPUB2 fall back to it.
+pub async fn download(id: u64, cache_dir: &Path, client: &reqwest::Client) -> Result<PathBuf> {
+    let dir = cache_dir.join("gutenberg");
+    let path = dir.join(format!("pg{}.epub", id));
+    if path.exists() {
+        info!(
+            "Using cached Project Gutenberg #{} at '{}'",
+            id,
+            path.display()
+        );
+        return Ok(path);
+    }
+
+    let mut last_error = None;
+    for edition in ["epub3.images", "epub.images"] {
+        let url = format!("{}/{}.{}", EBOOK_URL, id, edition);
+        let response = client.get(&url).timeout(REQUEST_TIMEOUT).send().await?;
+        let is_epub = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/epub"));
+        if !response.status().is_success() || !is_epub {
+            last_error = Some(format!("'{}' returned HTTP {}", url, response.status()));
+            continue;
+        }
+
+        let bytes = response.bytes().await?;
+        fs::create_dir_all(&dir)?;
+        // Written under a temporary name so an interrupted download is never mistaken for a book
+        let partial = path.with_extension("epub.part");
+        fs::write(&partial, &bytes)?;
+        fs::rename(&partial, &path)?;
+        info!(
+            "Downloaded Project Gutenberg #{} ({} bytes) to '{}'",
+            id,
+            bytes.len(),
+            path.display()
+        );
+        return Ok(path);
+    }
+
+    bail!(
+        "No EPUB found for Project Gutenberg #{}: {}",
+        id,
+        last_error.unwrap_or_default()
+    )
+}
+
+/// Searches the catalog by words of the title or author, most downloaded first
+pub async fn search(
+    query: &str,
+    limit: usize,
+    client: &reqwest::Client,
+) -> Result<Vec<GutenbergBook>> {
+    let response = client
+        .get(SEARCH_URL)
+        .query(&[("search", query)])
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!(
+            "Project Gutenberg search failed with HTTP {}",
+            response.status()
+        );
+    }
+    let mut books = response.json::<SearchResponse>().await?.results;
+    books.truncate(limit);
+    Ok(books)
+}
+
+/// Formats search results as a table for the terminal
+pub fn format_books(books: &[GutenbergBook]) -> String {
+    if books.is_empty() {
+        return "No books found.\n".to_string();
+    }
+    let mut table = format!(
+        "{:>6}  {:<50}  {:<30}  {:<5}\n",
+        "ID", "Title", "Author", "Lang"
+    );
+    for book in books {
+        let authors: Vec<_> = book.authors.iter().map(|a| a.name.as_str()).collect();
+        // Catalog titles often carry their subtitle on a second line
+        let title = book.title.split_whitespace().collect::<Vec<_>>().join(" ");
+        table.push_str(&format!(
+            "{:>6}  {:<50}  {:<30}  {:<5}\n",
+            book.id,
+            truncate(&title, 50),
+            truncate(&authors.join("; "), 30),
+            book.languages.join(",")
+        ));
+    }
+    table
+}
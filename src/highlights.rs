@@ -0,0 +1,133 @@
+use crate::text::normalize_words;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Separator line between entries of a Kindle `My Clippings.txt`
+const CLIPPING_SEPARATOR: &str = "==========";
+/// Leading words compared when a highlight does not appear whole in one chapter
+const MATCH_WORDS: usize = 8;
+/// Highlights shorter than this many words are too common to place in a chapter
+const MIN_MATCH_WORDS: usize = 3;
+
+/// A passage the reader marked, with the note they attached to it
+#[derive(Debug, Clone, Default)]
+pub struct Highlight {
+    pub text: String,
+    pub note: Option<String>,
+}
+
+/// Loads the reader's highlights for one book
+///
+/// Kindle's `My Clippings.txt` holds the clippings of every book on the device, so only the
+/// entries whose title matches `title` are kept (all of them when the book has no title).
+/// Any other file is read as exported highlights, one passage per paragraph, with Markdown
+/// list and quote markers stripped.
+pub fn load(path: &Path, title: Option<&str>) -> Result<Vec<Highlight>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("cannot read highlights from '{}'", path.display()))?;
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+
+    if content.contains(CLIPPING_SEPARATOR) {
+        Ok(parse_clippings(&content, title))
+    } else {
+        Ok(content
+            .split("\n\n")
+            .map(|paragraph| {
+                paragraph
+                    .lines()
+                    .map(|line| line.trim().trim_start_matches(['-', '*', '>']).trim())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|text| !text.is_empty())
+            .map(|text| Highlight { text, note: None })
+            .collect())
+    }
+}
+
+/// Entries are a `Title (Author)` line, a `- Your Highlight on page 4 | Location 50-52 | ...`
+/// line, a blank line and the clipped text. Notes follow the highlight they belong to.
+fn parse_clippings(content: &str, title: Option<&str>) -> Vec<Highlight> {
+    let wanted = title.map(normalize_words).filter(|title| !title.is_empty());
+    let mut highlights: Vec<Highlight> = Vec::new();
+
+    for entry in content.split(CLIPPING_SEPARATOR) {
+        let mut lines = entry.lines().map(str::trim).filter(|line| !line.is_empty());
+        let (Some(book), Some(kind)) = (lines.next(), lines.next()) else {
+            continue;
+        };
+        let text = lines.collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(wanted) = &wanted {
+            // The author is appended in parentheses and titles are often truncated on the device
+            let book = normalize_words(book.rsplit_once(" (").map_or(book, |(title, _)| title));
+            if book.is_empty() || !(book.starts_with(wanted.as_str()) || wanted.starts_with(&book))
+            {
+                continue;
+            }
+        }
+
+        let kind = kind.to_lowercase();
+        if kind.contains("note") {
+            match highlights.last_mut() {
+                Some(last) if last.note.is_none() => last.note = Some(text),
+                _ => highlights.push(Highlight { text, note: None }),
+            }
+        } else if kind.contains("highlight") {
+            highlights.push(Highlight { text, note: None });
+        }
+    }
+    highlights
+}
+
+/// Indices of the highlights that fall in `text`, matched on whole words so punctuation and
+/// line breaks introduced by extraction do not matter
+///
+/// Highlights of fewer than `MIN_MATCH_WORDS` words are never matched, as they would turn up in
+/// almost every chapter.
+pub fn matching(highlights: &[Highlight], text: &str) -> Vec<usize> {
+    // Padded with spaces so a passage only matches at word boundaries
+    let text = format!(" {} ", normalize_words(text));
+    highlights
+        .iter()
+        .enumerate()
+        .filter(|(_, highlight)| {
+            let words: Vec<String> = normalize_words(&highlight.text)
+                .split(' ')
+                .map(str::to_string)
+                .collect();
+            if words.len() < MIN_MATCH_WORDS {
+                return false;
+            }
+            let passage = format!(" {} ", words.join(" "));
+            let opening = format!(" {} ", words[..words.len().min(MATCH_WORDS)].join(" "));
+            text.contains(&passage) || text.contains(&opening)
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Instructions asking the summarizer to anchor the chapter summary on the highlights at
+/// `matched`
+pub fn instructions(highlights: &[Highlight], matched: &[usize]) -> Option<String> {
+    if matched.is_empty() {
+        return None;
+    }
+    let passages = matched
+        .iter()
+        .map(|&index| &highlights[index])
+        .map(|highlight| match &highlight.note {
+            Some(note) => format!("- \"{}\" (reader's note: {})", highlight.text, note),
+            None => format!("- \"{}\"", highlight.text),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!(
+        "The reader highlighted these passages as important. When they appear in the text, give \
+         them extra weight: cover the ideas they express and quote them where it helps.\n{}",
+        passages
+    ))
+}
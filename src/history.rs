@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, Row};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DATABASE_FILE: &str = "history.sqlite";
+
+/// One processed book as stored in the history database
+#[derive(Debug, Clone, Default)]
+pub struct RunRecord {
+    pub id: i64,
+    pub processed_at: String,
+    pub input: String,
+    pub title: String,
+    pub author: String,
+    pub language: String,
+    pub provider: String,
+    pub model: String,
+    pub output_language: String,
+    pub detail_level: String,
+    pub chapters: i64,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// In USD, when the provider reports it
+    pub cost: f64,
+    pub summary_path: String,
+}
+
+/// Local database of every processed book, kept in the data directory
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    pub fn open(data_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(data_dir)?;
+        let connection = Connection::open(data_dir.join(DATABASE_FILE))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                processed_at TEXT NOT NULL,
+                input TEXT NOT NULL,
+                title TEXT NOT NULL,
+                author TEXT NOT NULL,
+                language TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                model TEXT NOT NULL,
+                output_language TEXT NOT NULL,
+                detail_level TEXT NOT NULL,
+                chapters INTEGER NOT NULL,
+                requests INTEGER NOT NULL,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                cost REAL NOT NULL,
+                summary_path TEXT NOT NULL
+            );",
+        )?;
+        Ok(History { connection })
+    }
+
+    /// Stores a run, stamping it with the current time, and returns its id
+    pub fn record(&self, run: &RunRecord) -> Result<i64> {
+        self.connection.execute(
+            "INSERT INTO runs (processed_at, input, title, author, language, provider, model,
+                output_language, detail_level, chapters, requests, prompt_tokens,
+                completion_tokens, cost, summary_path)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                Utc::now().to_rfc3339(),
+                run.input,
+                run.title,
+                run.author,
+                run.language,
+                run.provider,
+                run.model,
+                run.output_language,
+                run.detail_level,
+                run.chapters,
+                run.requests,
+                run.prompt_tokens,
+                run.completion_tokens,
+                run.cost,
+                run.summary_path,
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Runs matching `query` in title, author or input path, newest first
+    pub fn runs(&self, query: Option<&str>, limit: usize) -> Result<Vec<RunRecord>> {
+        self.select(
+            "SELECT * FROM runs
+             WHERE title LIKE ?1 OR author LIKE ?1 OR input LIKE ?1
+             ORDER BY id DESC LIMIT ?2",
+            query,
+            limit as i64,
+        )
+    }
+
+    /// The latest run of every distinct book, by title and author
+    pub fn bookshelf(&self, query: Option<&str>) -> Result<Vec<RunRecord>> {
+        self.select(
+            "SELECT * FROM runs
+             WHERE id IN (SELECT MAX(id) FROM runs GROUP BY title, author)
+               AND (title LIKE ?1 OR author LIKE ?1 OR input LIKE ?1)
+             ORDER BY title COLLATE NOCASE LIMIT ?2",
+            query,
+            -1,
+        )
+    }
+
+    pub fn get(&self, id: i64) -> Result<RunRecord> {
+        self.connection
+            .query_row("SELECT * FROM runs WHERE id = ?1", [id], run_from_row)
+            .map_err(|_| anyhow!("No run with id {} in the history", id))
+    }
+
+    /// Runs the query with `?1` bound to a LIKE pattern and `?2` to the limit (-1 for none)
+    fn select(&self, sql: &str, query: Option<&str>, limit: i64) -> Result<Vec<RunRecord>> {
+        let pattern = format!("%{}%", query.unwrap_or_default());
+        let mut statement = self.connection.prepare(sql)?;
+        let runs = statement
+            .query_map(params![pattern, limit], run_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(runs)
+    }
+}
+
+fn run_from_row(row: &Row) -> rusqlite::Result<RunRecord> {
+    Ok(RunRecord {
+        id: row.get("id")?,
+        processed_at: row.get("processed_at")?,
+        input: row.get("input")?,
+        title: row.get("title")?,
+        author: row.get("author")?,
+        language: row.get("language")?,
+        provider: row.get("provider")?,
+        model: row.get("model")?,
+        output_language: row.get("output_language")?,
+        detail_level: row.get("detail_level")?,
+        chapters: row.get("chapters")?,
+        requests: row.get("requests")?,
+        prompt_tokens: row.get("prompt_tokens")?,
+        completion_tokens: row.get("completion_tokens")?,
+        cost: row.get("cost")?,
+        summary_path: row.get("summary_path")?,
+    })
+}
+
+/// Formats runs as a table for the terminal
+pub fn format_runs(runs: &[RunRecord]) -> String {
+    if runs.is_empty() {
+        return "No books found.\n".to_string();
+    }
+    let mut table = format!(
+        "{:>4}  {:<10}  {:<40}  {:<24}  {:<28}  {:>9}\n",
+        "ID", "Date", "Title", "Author", "Model", "Cost"
+    );
+    for run in runs {
+        table.push_str(&format!(
+            "{:>4}  {:<10}  {:<40}  {:<24}  {:<28}  {:>9}\n",
+            run.id,
+            run.processed_at.get(..10).unwrap_or_default(),
+            truncate(&run.title, 40),
+            truncate(&run.author, 24),
+            truncate(&run.model, 28),
+            format!("${:.4}", run.cost)
+        ));
+    }
+    table
+}
+
+/// Opens a past summary with the system's default application
+pub fn open_summary(run: &RunRecord) -> Result<PathBuf> {
+    let path = PathBuf::from(&run.summary_path);
+    if !path.exists() {
+        return Err(anyhow!(
+            "The summary of '{}' is no longer at '{}'",
+            run.title,
+            path.display()
+        ));
+    }
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(&path).spawn()?;
+    Ok(path)
+}
+
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width - 1).collect();
+    truncated.push('…');
+    truncated
+}
@@ -0,0 +1,151 @@
+use crate::output::ChapterSummary;
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// User commands run while a book is processed, for custom filters, reformatting or uploads
+///
+/// Each hook is a shell command that gets a JSON object on stdin. `pre_chapter` and
+/// `post_chapter` may print a modified copy of it to stdout (printing nothing keeps it as is);
+/// what `post_book` prints is ignored. A hook that exits with an error stops the book, which is
+/// then listed as failed while the batch goes on with the next one.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub pre_chapter: Option<String>,
+    pub post_chapter: Option<String>,
+    pub post_book: Option<String>,
+}
+
+/// The book a hook runs for, sent along with every payload
+#[derive(Debug, Clone, Serialize)]
+pub struct BookContext<'a> {
+    pub input: &'a Path,
+    pub title: &'a str,
+    pub output_dir: &'a Path,
+}
+
+impl Hooks {
+    /// Lets `pre_chapter` rewrite a chapter's title and text before it is summarized
+    pub async fn pre_chapter(
+        &self,
+        book: &BookContext<'_>,
+        number: usize,
+        title: &mut String,
+        text: &mut String,
+    ) -> Result<()> {
+        let Some(command) = &self.pre_chapter else {
+            return Ok(());
+        };
+        let payload = json!({
+            "hook": "pre_chapter",
+            "book": book,
+            "chapter": { "number": number, "title": title, "text": text },
+        });
+        let Some(output) = run("pre_chapter", command, &payload).await? else {
+            return Ok(());
+        };
+        let chapter = &output["chapter"];
+        if let Some(new_title) = chapter["title"].as_str() {
+            *title = new_title.to_string();
+        }
+        if let Some(new_text) = chapter["text"].as_str() {
+            *text = new_text.to_string();
+        }
+        Ok(())
+    }
+
+    /// Lets `post_chapter` rewrite a chapter's summary before it goes into the outputs
+    pub async fn post_chapter(
+        &self,
+        book: &BookContext<'_>,
+        number: usize,
+        summary: ChapterSummary,
+    ) -> Result<ChapterSummary> {
+        let Some(command) = &self.post_chapter else {
+            return Ok(summary);
+        };
+        let payload = json!({
+            "hook": "post_chapter",
+            "book": book,
+            "number": number,
+            "chapter": summary,
+        });
+        match run("post_chapter", command, &payload).await? {
+            Some(output) => serde_json::from_value(output["chapter"].clone())
+                .context("post_chapter hook printed a chapter aibook cannot read"),
+            None => Ok(summary),
+        }
+    }
+
+    /// Runs `post_book` once every output of the book is written
+    pub async fn post_book(
+        &self,
+        book: &BookContext<'_>,
+        summary_path: &Path,
+        chapters: &[ChapterSummary],
+    ) -> Result<()> {
+        let Some(command) = &self.post_book else {
+            return Ok(());
+        };
+        let payload = json!({
+            "hook": "post_book",
+            "book": book,
+            "summary_path": summary_path,
+            "chapters": chapters,
+        });
+        run("post_book", command, &payload).await?;
+        Ok(())
+    }
+}
+
+/// Runs a hook through the shell with `payload` on stdin, returning the JSON it printed
+async fn run(name: &str, command: &str, payload: &Value) -> Result<Option<Value>> {
+    info!("Running {} hook '{}'", name, command);
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("could not start {} hook '{}'", name, command))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .with_context(|| format!("could not open the stdin of {} hook", name))?;
+    // Written while stdout is read, so a hook streaming its output cannot fill both pipes; a
+    // hook may also exit without reading its input, so a closed pipe is not an error
+    let input = serde_json::to_vec(payload)?;
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+    let output = child.wait_with_output().await?;
+    writer.await?;
+    if !output.status.success() {
+        bail!("{} hook '{}' failed ({})", name, command, output.status);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&stdout)
+        .map(Some)
+        .with_context(|| format!("{} hook '{}' did not print JSON", name, command))
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(target_os = "windows") {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
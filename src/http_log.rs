@@ -0,0 +1,276 @@
+use crate::encryption::{self, CacheKey};
+use crate::logs::LogPolicy;
+use crate::redact::{Redactor, REDACTED};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::Utc;
+use http::Extensions;
+use log::{info, warn};
+use reqwest::header::HeaderMap;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Headers whose values never reach the logs
+const SECRET_HEADERS: [&str; 4] = [
+    "authorization",
+    "x-api-key",
+    "api-key",
+    "proxy-authorization",
+];
+
+/// One HTTP request/response pair as written to the log and to recordings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub timestamp: String,
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+/// Logs every exchange with secrets redacted and optionally records it for later replay
+pub struct HttpLogger {
+    log_path: PathBuf,
+    policy: LogPolicy,
+    redactor: Redactor,
+    record_dir: Option<PathBuf>,
+    /// Encrypts the recordings (`--encrypt-cache`), which hold the book's text
+    cache_key: Option<CacheKey>,
+    /// Serializes writes and numbers the recordings
+    state: Mutex<usize>,
+}
+
+impl HttpLogger {
+    pub fn new(
+        log_dir: &Path,
+        policy: LogPolicy,
+        redactor: Redactor,
+        record_dir: Option<PathBuf>,
+        cache_key: Option<CacheKey>,
+    ) -> anyhow::Result<Self> {
+        fs::create_dir_all(log_dir)?;
+        let mut recorded = 0;
+        if let Some(dir) = &record_dir {
+            fs::create_dir_all(dir)?;
+            recorded = recording_files(dir)?.len();
+        }
+        Ok(HttpLogger {
+            log_path: log_dir.join("http.log"),
+            policy,
+            redactor,
+            record_dir,
+            cache_key,
+            state: Mutex::new(recorded),
+        })
+    }
+
+    fn write(&self, exchange: &Exchange) -> anyhow::Result<()> {
+        let mut recorded = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("HTTP log lock poisoned"))?;
+
+        self.policy.append(
+            &self.log_path,
+            &format!("{}\n", serde_json::to_string(exchange)?),
+        )?;
+
+        if let Some(dir) = &self.record_dir {
+            *recorded += 1;
+            let path = dir.join(format!("{:05}.json", *recorded));
+            let content = match &self.cache_key {
+                Some(key) => serde_json::to_string_pretty(&key.seal(exchange)?)?,
+                None => serde_json::to_string_pretty(exchange)?,
+            };
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Middleware for HttpLogger {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let request = RecordedRequest {
+            method: req.method().to_string(),
+            url: self.redactor.redact(req.url().as_str()),
+            headers: redacted_headers(req.headers(), &self.redactor),
+            body: self.redactor.redact_payload(&request_body(&req)),
+        };
+
+        let response = next.run(req, extensions).await?;
+
+        // The body can only be read once, so the response is rebuilt from the buffered bytes
+        let status = response.status();
+        let headers = response.headers().clone();
+        let version = response.version();
+        let bytes = response.bytes().await?;
+
+        let exchange = Exchange {
+            timestamp: Utc::now().to_rfc3339(),
+            request,
+            response: RecordedResponse {
+                status: status.as_u16(),
+                headers: redacted_headers(&headers, &self.redactor),
+                body: self
+                    .redactor
+                    .redact_payload(&String::from_utf8_lossy(&bytes)),
+            },
+        };
+        if let Err(e) = self.write(&exchange) {
+            warn!("Could not write HTTP log: {}", e);
+        }
+
+        let mut rebuilt = http::Response::builder().status(status).version(version);
+        if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+            *rebuilt_headers = headers;
+        }
+        let rebuilt = rebuilt.body(bytes).map_err(anyhow::Error::from)?;
+        Ok(Response::from(rebuilt))
+    }
+}
+
+/// Answers requests from recorded exchanges instead of the network
+///
+/// A request is matched to the first unused recording with the same method, URL and body, so a
+/// run with unchanged inputs and options replays exactly what was recorded. Recordings made
+/// with `--encrypt-cache` need the cache key to be read.
+pub struct Replayer {
+    exchanges: Mutex<Vec<Option<Exchange>>>,
+    redactor: Redactor,
+}
+
+impl Replayer {
+    pub fn load(
+        dir: &Path,
+        redactor: Redactor,
+        cache_key: Option<&CacheKey>,
+    ) -> anyhow::Result<Self> {
+        let mut exchanges = Vec::new();
+        for path in recording_files(dir)? {
+            let exchange = serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| match (encryption::sealed(&value), cache_key) {
+                    (Some(sealed), Some(key)) => key.open(&sealed),
+                    (Some(_), None) => Err(anyhow!("it is encrypted, run with --encrypt-cache")),
+                    (None, _) => Ok(serde_json::from_value::<Exchange>(value)?),
+                })
+                .map_err(|e| anyhow!("invalid recording '{}': {}", path.display(), e))?;
+            exchanges.push(Some(exchange));
+        }
+        info!(
+            "Loaded {} recorded exchanges from '{}'",
+            exchanges.len(),
+            dir.display()
+        );
+        Ok(Replayer {
+            exchanges: Mutex::new(exchanges),
+            redactor,
+        })
+    }
+}
+
+#[async_trait]
+impl Middleware for Replayer {
+    async fn handle(
+        &self,
+        req: Request,
+        _extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> Result<Response> {
+        let (method, url, body) = (
+            req.method().to_string(),
+            req.url().to_string(),
+            request_body(&req),
+        );
+        // Recordings hold redacted requests, so the request is compared as it was recorded
+        let (redacted_url, redacted_body) = (
+            self.redactor.redact(&url),
+            self.redactor.redact_payload(&body),
+        );
+
+        let mut exchanges = self
+            .exchanges
+            .lock()
+            .map_err(|_| anyhow!("replay lock poisoned"))?;
+        let recorded = exchanges
+            .iter_mut()
+            .find(|slot| {
+                slot.as_ref().is_some_and(|e| {
+                    e.request.method == method
+                        && (e.request.url == url || e.request.url == redacted_url)
+                        && (e.request.body == body || e.request.body == redacted_body)
+                })
+            })
+            .and_then(Option::take)
+            .ok_or_else(|| anyhow!("no recorded response matches {} {}", method, url))?;
+
+        // The recorded headers are given back, as callers read rate limits and usage from them
+        let mut response = http::Response::builder().status(recorded.response.status);
+        for (name, value) in &recorded.response.headers {
+            if name != "content-length" && value != REDACTED {
+                response = response.header(name, value);
+            }
+        }
+        if !recorded.response.headers.contains_key("content-type") {
+            response = response.header("content-type", "application/json");
+        }
+        let response = response
+            .body(recorded.response.body)
+            .map_err(anyhow::Error::from)?;
+        Ok(Response::from(response))
+    }
+}
+
+fn request_body(req: &Request) -> String {
+    req.body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        .unwrap_or_default()
+}
+
+fn redacted_headers(headers: &HeaderMap, redactor: &Redactor) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                redactor.redact(value.to_str().unwrap_or("[binary]"))
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn recording_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,260 @@
+use crate::paths;
+use crate::text::decode_entities;
+use anyhow::{bail, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageFormat, ImageReader};
+use log::debug;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+
+/// An `<img>` or SVG `<image>` element
+static IMAGE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<(?:img|image)\b[^>]*>").unwrap());
+/// The image an element points to
+static IMAGE_SOURCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\s(?:src|xlink:href|href)\s*=\s*["']([^"']+)["']"#).unwrap()
+});
+/// An element's alt text, in double or single quotes
+static IMAGE_ALT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\salt\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Which of the book's images are written to the images directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageMode {
+    /// No images are extracted
+    Skip,
+    /// Every image resource in the manifest is extracted
+    All,
+    /// Only images referenced from the reading order (drops unused manifest assets)
+    Referenced,
+}
+
+impl ImageMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "skip" => Ok(ImageMode::Skip),
+            "all" => Ok(ImageMode::All),
+            "referenced" => Ok(ImageMode::Referenced),
+            other => bail!(
+                "Unknown image mode '{}' (expected skip, all or referenced)",
+                other
+            ),
+        }
+    }
+}
+
+/// Options controlling which images are extracted and how they are stored
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    pub mode: ImageMode,
+    /// Images narrower or shorter than this many pixels are treated as decorative and dropped
+    pub min_size: u32,
+    /// Longest side in pixels that larger images are downscaled to (0 keeps the original size)
+    pub max_size: u32,
+    /// JPEG quality used when a downscaled photo is re-encoded
+    pub jpeg_quality: u8,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            mode: ImageMode::All,
+            min_size: 32,
+            max_size: 0,
+            jpeg_quality: 80,
+        }
+    }
+}
+
+/// An image shown by a document, with its path resolved inside the book
+#[derive(Debug, Clone, Default)]
+pub struct ImageReference {
+    pub path: PathBuf,
+    pub alt: String,
+}
+
+/// Collects the paths of the images an (X)HTML document refers to, resolved against the document
+pub fn referenced_images(html: &str, document_path: &Path) -> HashSet<PathBuf> {
+    image_references(html, document_path)
+        .into_iter()
+        .map(|reference| reference.path)
+        .collect()
+}
+
+/// The images an (X)HTML document shows, in order and without repeats, with their alt text
+pub fn image_references(html: &str, document_path: &Path) -> Vec<ImageReference> {
+    let document_path = normalize(document_path);
+    let base = document_path.parent().unwrap_or(Path::new(""));
+
+    let mut references: Vec<ImageReference> = Vec::new();
+    for element in IMAGE_TAG.find_iter(html) {
+        let Some(target) = IMAGE_SOURCE
+            .captures(element.as_str())
+            .map(|caps| caps[1].to_string())
+        else {
+            continue;
+        };
+        if target.contains("://") || target.starts_with("data:") {
+            continue;
+        }
+        let target = target.split(['#', '?']).next().unwrap_or_default();
+        let path = normalize(&base.join(percent_decode(target)));
+        if references.iter().any(|reference| reference.path == path) {
+            continue;
+        }
+        let alt = IMAGE_ALT
+            .captures(element.as_str())
+            .and_then(|caps| caps.get(1).or(caps.get(2)))
+            .map(|text| decode_entities(text.as_str().trim()))
+            .unwrap_or_default();
+        references.push(ImageReference { path, alt });
+    }
+    references
+}
+
+/// Name an extracted image is saved under in the images directory
+pub fn saved_file_name(resource_path: &Path, mime: &str) -> String {
+    format!(
+        "{}.{}",
+        paths::safe_file_name(&resource_path.to_string_lossy()),
+        extension(mime)
+    )
+}
+
+/// Name of the file a book image was extracted to, if it was kept
+pub fn find_saved(images_dir: &Path, resource_path: &Path) -> Option<String> {
+    ["image/jpeg", "image/png", "image/gif", "image/svg+xml"]
+        .iter()
+        .map(|mime| saved_file_name(resource_path, mime))
+        .find(|name| images_dir.join(name).is_file())
+}
+
+/// Resolves `.` and `..` components without touching the filesystem
+///
+/// Paths inside e-books always use `/`, but some EPUBs written on Windows have `\` in their
+/// hrefs, so both are read as separators.
+pub fn normalize(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut normalized = PathBuf::new();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Decodes `%XX` escapes in an href
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Applies the size filter and optional downscaling to one image
+///
+/// Returns `None` when the image is too small to be worth keeping. Formats the image crate
+/// cannot measure (such as SVG) are passed through unchanged.
+pub fn optimize(data: Vec<u8>, mime: &str, options: &ImageOptions) -> Option<Vec<u8>> {
+    let Some(format) = raster_format(mime) else {
+        return Some(data);
+    };
+    let Ok((width, height)) =
+        ImageReader::with_format(Cursor::new(&data), format).into_dimensions()
+    else {
+        return Some(data);
+    };
+
+    if width < options.min_size || height < options.min_size {
+        debug!("Dropping {}x{} decorative image", width, height);
+        return None;
+    }
+
+    // Animated GIFs would lose their frames, so only PNG and JPEG are recompressed
+    if options.max_size == 0 || width.max(height) <= options.max_size || format == ImageFormat::Gif
+    {
+        return Some(data);
+    }
+
+    match downscale(&data, format, options) {
+        Ok(smaller) if smaller.len() < data.len() => Some(smaller),
+        Ok(_) => Some(data),
+        Err(e) => {
+            debug!("Keeping original image, downscaling failed: {}", e);
+            Some(data)
+        }
+    }
+}
+
+fn downscale(data: &[u8], format: ImageFormat, options: &ImageOptions) -> Result<Vec<u8>> {
+    let image = ImageReader::with_format(Cursor::new(data), format).decode()?;
+    let resized = image.resize(
+        options.max_size,
+        options.max_size,
+        image::imageops::FilterType::Lanczos3,
+    );
+
+    let mut buffer = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgb8(resized.to_rgb8());
+            rgb.write_with_encoder(JpegEncoder::new_with_quality(
+                &mut buffer,
+                options.jpeg_quality,
+            ))?;
+        }
+        _ => {
+            resized.write_with_encoder(PngEncoder::new_with_quality(
+                &mut buffer,
+                CompressionType::Best,
+                FilterType::Adaptive,
+            ))?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// File extension for an image MIME type
+pub fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        _ => "bin", // default binary extension
+    }
+}
+
+fn raster_format(mime: &str) -> Option<ImageFormat> {
+    match mime {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/gif" => Some(ImageFormat::Gif),
+        _ => None,
+    }
+}
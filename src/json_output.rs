@@ -0,0 +1,71 @@
+use crate::llm::Usage;
+use crate::metadata::BookMetadata;
+use crate::output::ChapterSummary;
+use crate::schema;
+use crate::stats::{ChapterStats, ReadingStats};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The complete result of summarizing a book, for tools that render it themselves
+#[derive(Debug, Serialize)]
+struct SummaryDocument<'a> {
+    generator: String,
+    language: &'a str,
+    metadata: &'a BookMetadata,
+    /// File name of the cover in the output directory
+    cover: Option<&'a str>,
+    stats: StatsDocument<'a>,
+    usage: Usage,
+    chapters: &'a [ChapterSummary],
+    /// Glossary entries of all chapters, without repeats
+    glossary: Vec<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsDocument<'a> {
+    original_words: usize,
+    summary_words: usize,
+    original_minutes: f64,
+    summary_minutes: f64,
+    compression_ratio: f64,
+    chapters: &'a [ChapterStats],
+}
+
+/// Writes `summary.json` and returns its path
+pub fn write_summary(
+    output_dir: &Path,
+    metadata: &BookMetadata,
+    stats: &ReadingStats,
+    chapters: &[ChapterSummary],
+    cover: Option<&str>,
+    language: &str,
+    usage: Usage,
+) -> Result<PathBuf> {
+    let mut glossary: Vec<&str> = Vec::new();
+    for entry in chapters.iter().flat_map(|chapter| &chapter.glossary) {
+        if !glossary.contains(&entry.as_str()) {
+            glossary.push(entry);
+        }
+    }
+    let document = SummaryDocument {
+        generator: format!("aibook-cli {}", env!("CARGO_PKG_VERSION")),
+        language,
+        metadata,
+        cover,
+        stats: StatsDocument {
+            original_words: stats.original_words(),
+            summary_words: stats.summary_words(),
+            original_minutes: stats.original_minutes(),
+            summary_minutes: stats.summary_minutes(),
+            compression_ratio: stats.compression_ratio(),
+            chapters: &stats.chapters,
+        },
+        usage,
+        chapters,
+        glossary,
+    };
+    let path = output_dir.join("summary.json");
+    schema::write_json(&path, &document)?;
+    Ok(path)
+}
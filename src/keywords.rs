@@ -0,0 +1,174 @@
+use crate::output::ChapterSummary;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+
+/// Keywords derived per chapter when the summary response has none
+const FALLBACK_KEYWORDS: usize = 5;
+/// Shorter words are mostly function words in any language, so TF-IDF skips them
+const MIN_WORD_CHARS: usize = 5;
+
+/// Gives chapters whose summary response listed no keywords the terms that best distinguish
+/// their summary from the others (TF-IDF)
+pub fn fill_missing(chapters: &mut [ChapterSummary]) {
+    if chapters.iter().all(|c| !c.keywords.is_empty()) {
+        return;
+    }
+    let documents: Vec<Vec<String>> = chapters.iter().map(|c| words(&c.summary)).collect();
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for document in &documents {
+        let unique: HashSet<&str> = document.iter().map(String::as_str).collect();
+        for word in unique {
+            *document_frequency.entry(word).or_default() += 1;
+        }
+    }
+
+    let total = documents.len() as f64;
+    for (chapter, document) in chapters.iter_mut().zip(&documents) {
+        if !chapter.keywords.is_empty() {
+            continue;
+        }
+        let mut term_frequency: Vec<(&str, usize)> = Vec::new();
+        for word in document {
+            match term_frequency.iter_mut().find(|(w, _)| w == word) {
+                Some((_, count)) => *count += 1,
+                None => term_frequency.push((word, 1)),
+            }
+        }
+        // Smoothed IDF still ranks words of a single-chapter book by frequency
+        let mut scored: Vec<(&str, f64)> = term_frequency
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(word, count)| {
+                let idf = ((1.0 + total) / (1.0 + document_frequency[word] as f64)).ln() + 1.0;
+                (word, count as f64 * idf)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        chapter.keywords = scored
+            .into_iter()
+            .take(FALLBACK_KEYWORDS)
+            .map(|(word, _)| word.to_string())
+            .collect();
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|word| word.chars().count() >= MIN_WORD_CHARS)
+        .filter(|word| !word.chars().all(|c| c.is_numeric()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Keywords in alphabetical order with the indices of the chapters that list them
+pub fn index(chapters: &[ChapterSummary]) -> Vec<(String, Vec<usize>)> {
+    let mut entries: Vec<(String, Vec<usize>)> = Vec::new();
+    for (number, chapter) in chapters.iter().enumerate() {
+        for keyword in &chapter.keywords {
+            match entries
+                .iter_mut()
+                .find(|(existing, _)| existing.to_lowercase() == keyword.to_lowercase())
+            {
+                Some((_, found_in)) if !found_in.contains(&number) => found_in.push(number),
+                Some(_) => (),
+                None => entries.push((keyword.clone(), vec![number])),
+            }
+        }
+    }
+    entries.sort_by_key(|(keyword, _)| keyword.to_lowercase());
+    entries
+}
+
+/// The keywords listed by the most chapters, used as document tags
+pub fn book_tags(chapters: &[ChapterSummary], limit: usize) -> Vec<String> {
+    let mut entries = index(chapters);
+    entries.sort_by_key(|(_, found_in)| std::cmp::Reverse(found_in.len()));
+    entries
+        .into_iter()
+        .take(limit)
+        .map(|(keyword, _)| keyword)
+        .collect()
+}
+
+/// Wraps the first mention of each keyword in `open` and `close`
+///
+/// Code blocks, inline code, `$...$` math, Markdown links and images, URLs, tags and text that is
+/// already bold are left alone so the markup stays valid.
+pub fn highlight(text: &str, keywords: &[String], open: &str, close: &str) -> String {
+    let Some(pattern) = pattern(keywords) else {
+        return text.to_string();
+    };
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut highlighted = String::new();
+    let mut last = 0;
+    for captures in pattern.captures_iter(text) {
+        let Some(keyword) = captures.get(2) else {
+            continue;
+        };
+        if !seen.insert(keyword.as_str().to_lowercase()) {
+            continue;
+        }
+        highlighted.push_str(&text[last..keyword.start()]);
+        highlighted.push_str(open);
+        highlighted.push_str(keyword.as_str());
+        highlighted.push_str(close);
+        last = keyword.end();
+    }
+    highlighted.push_str(&text[last..]);
+    highlighted
+}
+
+/// Matches code, math, links, URLs, tags and bold text (group 1, skipped) or any keyword as a
+/// whole word (group 2), longest keywords first so "machine learning" wins over "learning"
+fn pattern(keywords: &[String]) -> Option<Regex> {
+    let mut keywords: Vec<&str> = keywords
+        .iter()
+        .map(|keyword| keyword.trim())
+        .filter(|keyword| !keyword.is_empty())
+        .collect();
+    if keywords.is_empty() {
+        return None;
+    }
+    keywords.sort_by_key(|keyword| std::cmp::Reverse(keyword.len()));
+    // Word boundaries only where the keyword starts or ends with a word character ("C++")
+    let boundary = |c: Option<char>| {
+        if c.is_some_and(char::is_alphanumeric) {
+            r"\b"
+        } else {
+            ""
+        }
+    };
+    let alternatives: Vec<String> = keywords
+        .iter()
+        .map(|k| {
+            format!(
+                "{}{}{}",
+                boundary(k.chars().next()),
+                regex::escape(k),
+                boundary(k.chars().last())
+            )
+        })
+        .collect();
+    RegexBuilder::new(&format!(
+        r"((?s:```.*?```)|`[^`]*`|\$[^$]*\$|!?\[[^\]]*\]\([^)]*\)|<[^>\n]*>|https?://\S+|\*\*.+?\*\*|__.+?__)|({})",
+        alternatives.join("|")
+    ))
+    .case_insensitive(true)
+    .build()
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_skips_links_urls_and_bold_text() {
+        let keywords = vec!["rust".to_string()];
+        let text = "See [Rust](https://rust-lang.org/rust), https://example.com/rust and **Rust**; Rust wins.";
+        assert_eq!(
+            highlight(text, &keywords, "**", "**"),
+            "See [Rust](https://rust-lang.org/rust), https://example.com/rust and **Rust**; **Rust** wins."
+        );
+    }
+}
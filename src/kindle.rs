@@ -0,0 +1,113 @@
+use crate::paths;
+use anyhow::{anyhow, bail, Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Largest e-mail attachment Amazon's Send to Kindle service accepts
+const MAX_ATTACHMENT_BYTES: u64 = 50 * 1024 * 1024;
+
+/// SMTP account and Kindle address used to e-mail summaries to an e-reader
+#[derive(Debug, Clone)]
+pub struct KindleConfig {
+    /// The device's `@kindle.com` address
+    pub to: String,
+    /// Sender, which must be on the account's approved personal document e-mail list
+    pub from: String,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+}
+
+impl KindleConfig {
+    /// Reads `KINDLE_EMAIL` (unless given) and the `SMTP_*` variables, failing before any
+    /// summarization work when one is missing
+    pub fn from_env(kindle_email: Option<String>) -> Result<Self> {
+        let var = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .ok_or_else(|| anyhow!("{} must be set for --send-to-kindle", name))
+        };
+        let to = match kindle_email {
+            Some(email) => email,
+            None => var("KINDLE_EMAIL")?,
+        };
+        let username = var("SMTP_USERNAME")?;
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => port
+                .parse()
+                .with_context(|| format!("invalid SMTP_PORT '{}'", port))?,
+            Err(_) => 587,
+        };
+
+        Ok(KindleConfig {
+            to,
+            from: env::var("SMTP_FROM").unwrap_or_else(|_| username.clone()),
+            host: var("SMTP_HOST")?,
+            port,
+            password: var("SMTP_PASSWORD")?,
+            username,
+        })
+    }
+}
+
+/// E-mails an EPUB to the Kindle address as an attachment
+///
+/// Port 465 uses implicit TLS, any other port STARTTLS.
+pub async fn send(config: &KindleConfig, epub_path: &Path, title: &str) -> Result<()> {
+    let size = fs::metadata(epub_path)?.len();
+    if size > MAX_ATTACHMENT_BYTES {
+        bail!(
+            "'{}' is {:.1} MB, over the {} MB Send to Kindle limit (try --images skip)",
+            epub_path.display(),
+            size as f64 / (1024.0 * 1024.0),
+            MAX_ATTACHMENT_BYTES / (1024 * 1024)
+        );
+    }
+
+    let file_name = format!("{}.epub", paths::safe_file_name(title));
+    let message = Message::builder()
+        .from(
+            config
+                .from
+                .parse()
+                .with_context(|| format!("invalid sender address '{}'", config.from))?,
+        )
+        .to(config
+            .to
+            .parse()
+            .with_context(|| format!("invalid Kindle address '{}'", config.to))?)
+        .subject(title)
+        .multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(format!("Summary of {}", title)))
+                .singlepart(Attachment::new(file_name).body(
+                    fs::read(epub_path)?,
+                    ContentType::parse("application/epub+zip")?,
+                )),
+        )?;
+
+    let builder = if config.port == 465 {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+    };
+    let transport = builder
+        .port(config.port)
+        .credentials(Credentials::new(
+            config.username.clone(),
+            config.password.clone(),
+        ))
+        .build();
+    transport
+        .send(message)
+        .await
+        .with_context(|| format!("could not send the summary through {}", config.host))?;
+    Ok(())
+}
@@ -0,0 +1,551 @@
+use anyhow::{anyhow, bail, Result};
+use whatlang::Lang;
+
+/// Characters of book text sampled for language detection
+pub const SAMPLE_CHARS: usize = 20_000;
+
+/// ISO 639-3 codes reported by whatlang mapped to the ISO 639-1 codes used in EPUB metadata
+const ISO_639_1: &[(&str, &str)] = &[
+    ("afr", "af"),
+    ("aka", "ak"),
+    ("amh", "am"),
+    ("ara", "ar"),
+    ("aze", "az"),
+    ("bel", "be"),
+    ("ben", "bn"),
+    ("bul", "bg"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("cmn", "zh"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("epo", "eo"),
+    ("est", "et"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("guj", "gu"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hrv", "hr"),
+    ("hun", "hu"),
+    ("hye", "hy"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jav", "jv"),
+    ("jpn", "ja"),
+    ("kan", "kn"),
+    ("kat", "ka"),
+    ("khm", "km"),
+    ("kor", "ko"),
+    ("lat", "la"),
+    ("lav", "lv"),
+    ("lit", "lt"),
+    ("mal", "ml"),
+    ("mar", "mr"),
+    ("mkd", "mk"),
+    ("mya", "my"),
+    ("nep", "ne"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("ori", "or"),
+    ("pan", "pa"),
+    ("pes", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("sin", "si"),
+    ("slk", "sk"),
+    ("slv", "sl"),
+    ("sna", "sn"),
+    ("spa", "es"),
+    ("srp", "sr"),
+    ("swe", "sv"),
+    ("tam", "ta"),
+    ("tel", "te"),
+    ("tgl", "tl"),
+    ("tha", "th"),
+    ("tuk", "tk"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("urd", "ur"),
+    ("uzb", "uz"),
+    ("vie", "vi"),
+    ("yid", "yi"),
+    ("zul", "zu"),
+];
+
+/// English names of the languages a tag's primary subtag may name: every ISO 639-1 code, and
+/// ISO 639-3 codes of languages without one
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("aa", "Afar"),
+    ("ab", "Abkhazian"),
+    ("ae", "Avestan"),
+    ("af", "Afrikaans"),
+    ("ak", "Akan"),
+    ("am", "Amharic"),
+    ("an", "Aragonese"),
+    ("ar", "Arabic"),
+    ("as", "Assamese"),
+    ("av", "Avaric"),
+    ("ay", "Aymara"),
+    ("az", "Azerbaijani"),
+    ("ba", "Bashkir"),
+    ("be", "Belarusian"),
+    ("bg", "Bulgarian"),
+    ("bi", "Bislama"),
+    ("bm", "Bambara"),
+    ("bn", "Bengali"),
+    ("bo", "Tibetan"),
+    ("br", "Breton"),
+    ("bs", "Bosnian"),
+    ("ca", "Catalan"),
+    ("ce", "Chechen"),
+    ("ch", "Chamorro"),
+    ("co", "Corsican"),
+    ("cr", "Cree"),
+    ("cs", "Czech"),
+    ("cu", "Church Slavic"),
+    ("cv", "Chuvash"),
+    ("cy", "Welsh"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("dv", "Divehi"),
+    ("dz", "Dzongkha"),
+    ("ee", "Ewe"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("eo", "Esperanto"),
+    ("es", "Spanish"),
+    ("et", "Estonian"),
+    ("eu", "Basque"),
+    ("fa", "Persian"),
+    ("ff", "Fulah"),
+    ("fi", "Finnish"),
+    ("fj", "Fijian"),
+    ("fo", "Faroese"),
+    ("fr", "French"),
+    ("fy", "Western Frisian"),
+    ("ga", "Irish"),
+    ("gd", "Scottish Gaelic"),
+    ("gl", "Galician"),
+    ("gn", "Guarani"),
+    ("gu", "Gujarati"),
+    ("gv", "Manx"),
+    ("ha", "Hausa"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("ho", "Hiri Motu"),
+    ("hr", "Croatian"),
+    ("ht", "Haitian Creole"),
+    ("hu", "Hungarian"),
+    ("hy", "Armenian"),
+    ("hz", "Herero"),
+    ("ia", "Interlingua"),
+    ("id", "Indonesian"),
+    ("ie", "Interlingue"),
+    ("ig", "Igbo"),
+    ("ii", "Sichuan Yi"),
+    ("ik", "Inupiaq"),
+    ("io", "Ido"),
+    ("is", "Icelandic"),
+    ("it", "Italian"),
+    ("iu", "Inuktitut"),
+    ("ja", "Japanese"),
+    ("jv", "Javanese"),
+    ("ka", "Georgian"),
+    ("kg", "Kongo"),
+    ("ki", "Kikuyu"),
+    ("kj", "Kuanyama"),
+    ("kk", "Kazakh"),
+    ("kl", "Kalaallisut"),
+    ("km", "Khmer"),
+    ("kn", "Kannada"),
+    ("ko", "Korean"),
+    ("kr", "Kanuri"),
+    ("ks", "Kashmiri"),
+    ("ku", "Kurdish"),
+    ("kv", "Komi"),
+    ("kw", "Cornish"),
+    ("ky", "Kyrgyz"),
+    ("la", "Latin"),
+    ("lb", "Luxembourgish"),
+    ("lg", "Ganda"),
+    ("li", "Limburgish"),
+    ("ln", "Lingala"),
+    ("lo", "Lao"),
+    ("lt", "Lithuanian"),
+    ("lu", "Luba-Katanga"),
+    ("lv", "Latvian"),
+    ("mg", "Malagasy"),
+    ("mh", "Marshallese"),
+    ("mi", "Maori"),
+    ("mk", "Macedonian"),
+    ("ml", "Malayalam"),
+    ("mn", "Mongolian"),
+    ("mr", "Marathi"),
+    ("ms", "Malay"),
+    ("mt", "Maltese"),
+    ("my", "Burmese"),
+    ("na", "Nauru"),
+    ("nb", "Norwegian Bokmål"),
+    ("nd", "North Ndebele"),
+    ("ne", "Nepali"),
+    ("ng", "Ndonga"),
+    ("nl", "Dutch"),
+    ("nn", "Norwegian Nynorsk"),
+    ("no", "Norwegian"),
+    ("nr", "South Ndebele"),
+    ("nv", "Navajo"),
+    ("ny", "Chichewa"),
+    ("oc", "Occitan"),
+    ("oj", "Ojibwa"),
+    ("om", "Oromo"),
+    ("or", "Odia"),
+    ("os", "Ossetian"),
+    ("pa", "Punjabi"),
+    ("pi", "Pali"),
+    ("pl", "Polish"),
+    ("ps", "Pashto"),
+    ("pt", "Portuguese"),
+    ("qu", "Quechua"),
+    ("rm", "Romansh"),
+    ("rn", "Rundi"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("rw", "Kinyarwanda"),
+    ("sa", "Sanskrit"),
+    ("sc", "Sardinian"),
+    ("sd", "Sindhi"),
+    ("se", "Northern Sami"),
+    ("sg", "Sango"),
+    ("si", "Sinhala"),
+    ("sk", "Slovak"),
+    ("sl", "Slovenian"),
+    ("sm", "Samoan"),
+    ("sn", "Shona"),
+    ("so", "Somali"),
+    ("sq", "Albanian"),
+    ("sr", "Serbian"),
+    ("ss", "Swati"),
+    ("st", "Southern Sotho"),
+    ("su", "Sundanese"),
+    ("sv", "Swedish"),
+    ("sw", "Swahili"),
+    ("ta", "Tamil"),
+    ("te", "Telugu"),
+    ("tg", "Tajik"),
+    ("th", "Thai"),
+    ("ti", "Tigrinya"),
+    ("tk", "Turkmen"),
+    ("tl", "Tagalog"),
+    ("tn", "Tswana"),
+    ("to", "Tongan"),
+    ("tr", "Turkish"),
+    ("ts", "Tsonga"),
+    ("tt", "Tatar"),
+    ("tw", "Twi"),
+    ("ty", "Tahitian"),
+    ("ug", "Uyghur"),
+    ("uk", "Ukrainian"),
+    ("ur", "Urdu"),
+    ("uz", "Uzbek"),
+    ("ve", "Venda"),
+    ("vi", "Vietnamese"),
+    ("vo", "Volapük"),
+    ("wa", "Walloon"),
+    ("wo", "Wolof"),
+    ("xh", "Xhosa"),
+    ("yi", "Yiddish"),
+    ("yo", "Yoruba"),
+    ("za", "Zhuang"),
+    ("zh", "Chinese"),
+    ("zu", "Zulu"),
+    ("arc", "Aramaic"),
+    ("ast", "Asturian"),
+    ("ckb", "Central Kurdish"),
+    ("chr", "Cherokee"),
+    ("fil", "Filipino"),
+    ("grc", "Ancient Greek"),
+    ("gsw", "Swiss German"),
+    ("haw", "Hawaiian"),
+    ("nds", "Low German"),
+    ("syr", "Syriac"),
+    ("yue", "Cantonese"),
+];
+/// Names of regional and script variants, and common alternative names, with their tags
+const VARIANT_NAMES: &[(&str, &str)] = &[
+    ("Brazilian Portuguese", "pt-BR"),
+    ("European Portuguese", "pt-PT"),
+    ("American English", "en-US"),
+    ("British English", "en-GB"),
+    ("Australian English", "en-AU"),
+    ("Canadian French", "fr-CA"),
+    ("Swiss French", "fr-CH"),
+    ("Austrian German", "de-AT"),
+    ("Swiss High German", "de-CH"),
+    ("European Spanish", "es-ES"),
+    ("Latin American Spanish", "es-419"),
+    ("Mexican Spanish", "es-MX"),
+    ("Argentinian Spanish", "es-AR"),
+    ("Flemish", "nl-BE"),
+    ("Simplified Chinese", "zh-Hans"),
+    ("Traditional Chinese", "zh-Hant"),
+    ("Mandarin", "zh"),
+    ("Farsi", "fa"),
+    ("Serbian Cyrillic", "sr-Cyrl"),
+    ("Serbian Latin", "sr-Latn"),
+];
+
+/// Result of detecting the language of the book text
+#[derive(Debug, Clone)]
+pub struct DetectedLanguage {
+    /// ISO 639-1 code when one exists, ISO 639-3 otherwise
+    pub code: String,
+    pub name: &'static str,
+    pub reliable: bool,
+}
+
+/// Detects the language of the book from a sample of its text
+pub fn detect(sample: &str) -> Option<DetectedLanguage> {
+    let info = whatlang::detect(sample)?;
+    Some(DetectedLanguage {
+        code: iso_639_1(info.lang()),
+        name: info.lang().eng_name(),
+        reliable: info.is_reliable(),
+    })
+}
+
+fn iso_639_1(lang: Lang) -> String {
+    let code = lang.code();
+    ISO_639_1
+        .iter()
+        .find(|(three, _)| *three == code)
+        .map_or(code, |(_, two)| *two)
+        .to_string()
+}
+
+/// Lowercase primary subtag of a language code, with ISO 639-3 codes folded to ISO 639-1
+pub fn primary_subtag(code: &str) -> String {
+    let primary = code
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    ISO_639_1
+        .iter()
+        .find(|(three, _)| *three == primary)
+        .map_or(primary.clone(), |(_, two)| two.to_string())
+}
+
+/// Compares two language codes by primary subtag ("en-US", "en" and "eng" are the same language)
+pub fn same_language(a: &str, b: &str) -> bool {
+    primary_subtag(a) == primary_subtag(b)
+}
+
+/// Whether text in the language is written right to left, from its primary subtag or a script
+/// subtag ("ar", "he-IL", "pa-Arab")
+pub fn is_right_to_left(code: &str) -> bool {
+    const RTL_LANGUAGES: &[&str] = &[
+        "ar", "arc", "ckb", "dv", "fa", "he", "iw", "ks", "ps", "sd", "syr", "ug", "ur", "yi",
+    ];
+    const RTL_SCRIPTS: &[&str] = &["arab", "hebr", "nkoo", "syrc", "thaa"];
+    RTL_LANGUAGES.contains(&primary_subtag(code).as_str())
+        || code
+            .split(['-', '_'])
+            .skip(1)
+            .any(|subtag| RTL_SCRIPTS.contains(&subtag.to_lowercase().as_str()))
+}
+
+/// Whether the first letter of `text` is written right to left, which is how readers and
+/// viewers guess the direction of a title or paragraph
+pub fn starts_right_to_left(text: &str) -> bool {
+    text.chars().find(|c| c.is_alphabetic()).is_some_and(|c| {
+        matches!(c,
+                '\u{0590}'..='\u{08ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{feff}')
+    })
+}
+
+/// Resolves an output language given as a BCP-47 tag ("pt-BR", "pt_br") or by name
+/// ("Brazilian Portuguese", "german") to its canonical tag
+pub fn resolve(value: &str) -> Result<String> {
+    let name = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    let by_name = VARIANT_NAMES
+        .iter()
+        .map(|(name, tag)| (*name, *tag))
+        .chain(LANGUAGE_NAMES.iter().map(|(code, name)| (*name, *code)))
+        .find(|(known, _)| known.eq_ignore_ascii_case(&name));
+    if let Some((_, tag)) = by_name {
+        return Ok(tag.to_string());
+    }
+    canonical_tag(&name).map_err(|e| {
+        anyhow!(
+            "Unknown language '{}': {} (expected a BCP-47 tag such as pt-BR, or a name such as \
+             \"Brazilian Portuguese\")",
+            value,
+            e
+        )
+    })
+}
+
+/// Checks a BCP-47 tag's syntax and primary language, and normalizes its case and separators
+/// ("PT_br" becomes "pt-BR", "zh-hant-tw" becomes "zh-Hant-TW")
+fn canonical_tag(tag: &str) -> Result<String> {
+    let mut subtags = tag.split(['-', '_']).filter(|subtag| !subtag.is_empty());
+    let alphabetic = |subtag: &str| subtag.chars().all(|c| c.is_ascii_alphabetic());
+    let alphanumeric = |subtag: &str| subtag.chars().all(|c| c.is_ascii_alphanumeric());
+
+    let primary = subtags.next().unwrap_or_default().to_ascii_lowercase();
+    if !(2..=3).contains(&primary.len()) || !alphabetic(&primary) {
+        bail!("'{}' is not a language code", primary);
+    }
+    let primary = ISO_639_1
+        .iter()
+        .find(|(three, _)| *three == primary)
+        .map_or(primary.clone(), |(_, two)| two.to_string());
+    if !LANGUAGE_NAMES.iter().any(|(code, _)| *code == primary) {
+        bail!("no language has the code '{}'", primary);
+    }
+
+    let mut canonical = vec![primary];
+    // 0 until a script, 1 until a region, 2 once only variants and extensions may follow
+    let mut stage = 0;
+    let mut in_extension = false;
+    for subtag in subtags {
+        let lower = subtag.to_ascii_lowercase();
+        if !alphanumeric(subtag) || subtag.len() > 8 {
+            bail!("'{}' is not a valid subtag", subtag);
+        }
+        if in_extension || lower == "x" {
+            in_extension = true;
+            canonical.push(lower);
+            continue;
+        }
+        match subtag.len() {
+            4 if stage == 0 && alphabetic(subtag) => {
+                stage = 1;
+                canonical.push(format!(
+                    "{}{}",
+                    subtag[..1].to_ascii_uppercase(),
+                    &lower[1..]
+                ));
+            }
+            2 if stage <= 1 && alphabetic(subtag) => {
+                stage = 2;
+                canonical.push(subtag.to_ascii_uppercase());
+            }
+            3 if stage <= 1 && subtag.chars().all(|c| c.is_ascii_digit()) => {
+                stage = 2;
+                canonical.push(lower);
+            }
+            5..=8 => {
+                stage = 2;
+                canonical.push(lower);
+            }
+            4 if subtag.starts_with(|c: char| c.is_ascii_digit()) => {
+                stage = 2;
+                canonical.push(lower);
+            }
+            1 => {
+                in_extension = true;
+                canonical.push(lower);
+            }
+            _ => bail!("'{}' is out of place", subtag),
+        }
+    }
+    Ok(canonical.join("-"))
+}
+
+/// English name of a language tag for prompts, e.g. "Brazilian Portuguese" for `pt-BR`,
+/// "Traditional Chinese (TW)" for `zh-Hant-TW` or "English (NZ)" for `en-NZ`; unknown codes are
+/// returned as given
+pub fn display_name(tag: &str) -> String {
+    let subtags: Vec<&str> = tag.split(['-', '_']).collect();
+    // The longest known prefix names the language, the subtags after it are appended; plain
+    // codes such as `fa` keep their standard name rather than an alias like "Farsi"
+    for length in (1..=subtags.len()).rev() {
+        let prefix = subtags[..length].join("-");
+        let name = if length > 1 {
+            VARIANT_NAMES
+                .iter()
+                .find(|(_, known)| known.eq_ignore_ascii_case(&prefix))
+                .map(|(name, _)| *name)
+        } else {
+            let primary = primary_subtag(&prefix);
+            LANGUAGE_NAMES
+                .iter()
+                .find(|(code, _)| *code == primary)
+                .map(|(_, name)| *name)
+        };
+        if let Some(name) = name {
+            return if length == subtags.len() {
+                name.to_string()
+            } else {
+                format!("{} ({})", name, subtags[length..].join("-"))
+            };
+        }
+    }
+    tag.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_and_names_resolve_to_canonical_tags() {
+        for (value, expected) in [
+            ("pt_br", "pt-BR"),
+            ("PT-BR", "pt-BR"),
+            ("zh-hant-tw", "zh-Hant-TW"),
+            ("es-419", "es-419"),
+            ("deu", "de"),
+            ("Brazilian  Portuguese", "pt-BR"),
+            ("german", "de"),
+            ("sl-rozaj-x-private", "sl-rozaj-x-private"),
+        ] {
+            assert_eq!(resolve(value).unwrap(), expected, "{}", value);
+        }
+        for value in ["klingon", "xx", "en-US-Latn", "en-toolongsubtag", "e"] {
+            assert!(resolve(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn display_names_append_unknown_subtags() {
+        assert_eq!(display_name("pt-BR"), "Brazilian Portuguese");
+        assert_eq!(display_name("zh-Hant-TW"), "Traditional Chinese (TW)");
+        assert_eq!(display_name("en-NZ"), "English (NZ)");
+        assert_eq!(display_name("fa"), "Persian");
+        assert_eq!(display_name("qq"), "qq");
+    }
+
+    #[test]
+    fn languages_compare_by_primary_subtag() {
+        assert!(same_language("en-US", "eng"));
+        assert!(!same_language("pt-BR", "es"));
+        assert_eq!(primary_subtag(" fra_CA"), "fr");
+    }
+
+    #[test]
+    fn right_to_left_scripts_are_recognized() {
+        assert!(is_right_to_left("he-IL"));
+        assert!(is_right_to_left("pa-Arab"));
+        assert!(!is_right_to_left("pa"));
+        assert!(starts_right_to_left("«مرحبا» world"));
+        assert!(!starts_right_to_left("1. Hello שלום"));
+    }
+
+    #[test]
+    fn detection_reports_iso_639_1_codes() {
+        let detected = detect(
+            "Der schnelle braune Fuchs springt über den faulen Hund, und alle Kinder lachen darüber.",
+        )
+        .unwrap();
+        assert_eq!(detected.code, "de");
+        assert_eq!(detected.name, "German");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::output::ChapterSummary;
+use crate::summarizer::Summarizer;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A depth at which a chapter can be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Eli5,
+    OneLiner,
+    Paragraph,
+    /// The full chapter summary
+    Detailed,
+}
+
+impl Layer {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "eli5" => Ok(Layer::Eli5),
+            "one-liner" => Ok(Layer::OneLiner),
+            "paragraph" => Ok(Layer::Paragraph),
+            "detailed" => Ok(Layer::Detailed),
+            other => Err(anyhow!(
+                "Unknown layer '{}' (expected eli5, one-liner, paragraph or detailed)",
+                other
+            )),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Layer::Eli5 => "Explain Like I'm Five",
+            Layer::OneLiner => "In One Line",
+            Layer::Paragraph => "In One Paragraph",
+            Layer::Detailed => "Detailed",
+        }
+    }
+
+    /// Field of the layers prompt's JSON response
+    fn field(self) -> Option<&'static str> {
+        match self {
+            Layer::Eli5 => Some("eli5"),
+            Layer::OneLiner => Some("one_liner"),
+            Layer::Paragraph => Some("paragraph"),
+            Layer::Detailed => None,
+        }
+    }
+}
+
+/// One depth of a chapter summary, rendered under its label
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SummaryLayer {
+    pub label: String,
+    pub text: String,
+}
+
+/// Builds the requested layers of a chapter in the given order, condensing the chapter summary
+/// with one request when any short layer is needed
+pub async fn build(
+    summarizer: &Summarizer,
+    layers: &[Layer],
+    chapter: &ChapterSummary,
+) -> Result<Vec<SummaryLayer>> {
+    let condensed = if layers.iter().any(|layer| layer.field().is_some()) {
+        summarizer
+            .generate_layers(&chapter.title, &chapter.summary)
+            .await?
+    } else {
+        Value::Null
+    };
+
+    Ok(layers
+        .iter()
+        .filter_map(|layer| {
+            let text = match layer.field() {
+                Some(field) => condensed.get(field)?.as_str()?.trim().to_string(),
+                None => chapter.summary.clone(),
+            };
+            Some(SummaryLayer {
+                label: layer.label().to_string(),
+                text,
+            })
+        })
+        .collect())
+}
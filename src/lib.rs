@@ -0,0 +1,3 @@
+//! C interface for embedding aibook in other programs; see `include/aibook.h`
+
+pub mod ffi;
@@ -1,53 +1,452 @@
-use anyhow::Result;
-use log::error;
+use crate::circuit::CircuitBreaker;
+use crate::encryption::CacheKey;
+use crate::extractive::ExtractiveProvider;
+use crate::http_log::{HttpLogger, Replayer};
+use crate::logs::LogPolicy;
+use crate::manifest::{self, RequestRecord};
+use crate::mock::MockProvider;
+use crate::privacy::PrivacyFilter;
+use crate::rate_limit::RateLimiter;
+use crate::redact::Redactor;
+use crate::refusal::Refused;
+use crate::telemetry;
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use opentelemetry::global::BoxedSpan;
+use opentelemetry::trace::Span;
+use opentelemetry::KeyValue;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::StatusCode;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Chat-completion APIs the client can talk to; all are OpenAI-compatible apart from a few quirks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    OpenRouter,
+    Groq,
+    Mistral,
+    /// No model: requests are answered in-process by the extractive summarizer
+    None,
+}
+
+impl Provider {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "openrouter" => Ok(Provider::OpenRouter),
+            "groq" => Ok(Provider::Groq),
+            "mistral" => Ok(Provider::Mistral),
+            "none" => Ok(Provider::None),
+            other => Err(anyhow!(
+                "Unknown provider '{}' (expected openrouter, groq, mistral or none)",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "openrouter",
+            Provider::Groq => "groq",
+            Provider::Mistral => "mistral",
+            Provider::None => "none",
+        }
+    }
+
+    fn endpoint(&self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "https://openrouter.ai/api/v1/chat/completions",
+            Provider::Groq => "https://api.groq.com/openai/v1/chat/completions",
+            Provider::Mistral => "https://api.mistral.ai/v1/chat/completions",
+            // Never reached: the extractive provider answers before the request is sent
+            Provider::None => "http://localhost/v1/chat/completions",
+        }
+    }
+
+    /// Environment variable holding the provider's API key(s), comma-separated
+    pub fn api_key_env(&self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "OPENROUTER_API_KEY",
+            Provider::Groq => "GROQ_API_KEY",
+            Provider::Mistral => "MISTRAL_API_KEY",
+            Provider::None => "",
+        }
+    }
+
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            Provider::OpenRouter => "openai/gpt-4o-mini",
+            Provider::Groq => "llama-3.1-8b-instant",
+            Provider::Mistral => "mistral-small-latest",
+            Provider::None => "extractive",
+        }
+    }
+
+    /// Adapts a request to the provider's accepted parameter ranges
+    fn adjust(&self, request: &mut OpenRouterRequest) {
+        match self {
+            // Groq turns 0 into 1e-8 itself but rejects exactly 0 on some models
+            Provider::Groq if request.temperature <= 0.0 => request.temperature = 1e-8,
+            // Mistral only accepts temperatures between 0 and 1 and names the seed differently
+            Provider::Mistral => {
+                request.temperature = request.temperature.clamp(0.0, 1.0);
+                request.random_seed = request.seed.take();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How requests are spread across several API keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRotation {
+    /// Each request uses the next key in turn
+    RoundRobin,
+    /// Keep using one key and move to the next when it is rate limited (HTTP 429)
+    OnRateLimit,
+}
+
+impl KeyRotation {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "round-robin" => Ok(KeyRotation::RoundRobin),
+            "on-429" => Ok(KeyRotation::OnRateLimit),
+            other => Err(anyhow!(
+                "Unknown key rotation '{}' (expected round-robin or on-429)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the HTTP client talking to the LLM API is set up
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Where `http.log` is written
+    pub log_dir: PathBuf,
+    pub log_policy: LogPolicy,
+    /// Scrubs the HTTP log and recordings
+    pub redactor: Redactor,
+    /// Also save every exchange as a numbered JSON file in this directory
+    pub record_dir: Option<PathBuf>,
+    /// Answer requests from a directory of recordings instead of the network
+    pub replay_dir: Option<PathBuf>,
+    /// Encrypts recordings and reads encrypted ones back (`--encrypt-cache`)
+    pub cache_key: Option<CacheKey>,
+    /// Answer requests locally with canned responses, for offline tests and demos
+    pub mock: bool,
+    /// Mask email addresses, phone numbers and these entities in prompts, restoring them in
+    /// the replies; `None` sends prompts as they are
+    pub private_entities: Option<Vec<String>>,
+    /// Proxy URL for all requests; `HTTPS_PROXY`/`HTTP_PROXY` are used when unset
+    pub proxy: Option<String>,
+    /// PEM bundle of extra root certificates, e.g. a TLS-intercepting corporate proxy's CA
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept invalid TLS certificates
+    pub insecure: bool,
+    /// Limit for a whole request, including the time the model takes to answer
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// Consecutive 5xx/429 responses after which the circuit opens
+    pub circuit_threshold: u32,
+    /// Initial cool-down of an open circuit, doubled on every failed probe
+    pub circuit_cooldown: Duration,
+    /// Client-side request limit per minute (0 for none)
+    pub requests_per_minute: u32,
+    /// Client-side prompt token limit per minute (0 for none)
+    pub tokens_per_minute: u32,
+    pub key_rotation: KeyRotation,
+    /// Reproducible runs: temperature 0, this seed, and every request recorded for the manifest
+    pub deterministic_seed: Option<u64>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            log_dir: PathBuf::from("logs"),
+            log_policy: LogPolicy::default(),
+            redactor: Redactor::default(),
+            record_dir: None,
+            replay_dir: None,
+            cache_key: None,
+            mock: false,
+            private_entities: None,
+            proxy: None,
+            ca_bundle: None,
+            insecure: false,
+            timeout: Duration::from_secs(300),
+            connect_timeout: Duration::from_secs(10),
+            circuit_threshold: 5,
+            circuit_cooldown: Duration::from_secs(30),
+            requests_per_minute: 0,
+            tokens_per_minute: 0,
+            key_rotation: KeyRotation::OnRateLimit,
+            deterministic_seed: None,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct LLMClient {
-    client: Arc<reqwest::Client>,
-    pub api_key: String,
+    client: Arc<ClientWithMiddleware>,
+    circuit: Arc<CircuitBreaker>,
+    api_keys: Arc<Vec<String>>,
+    key_rotation: KeyRotation,
+    /// Index of the key to use next, shared by all clones
+    next_key: Arc<AtomicUsize>,
+    pub provider: Provider,
     pub model_name: String,
+    deterministic_seed: Option<u64>,
+    /// Requests made so far, kept for the run manifest in deterministic mode
+    records: Arc<Mutex<Vec<RequestRecord>>>,
+    usage: Arc<Mutex<Usage>>,
+}
+
+/// Tokens and cost reported by the provider over a number of requests
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In USD; only OpenRouter reports it
+    pub cost: f64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: Usage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+    }
 }
 
 impl LLMClient {
-    pub fn new(api_key: String, model_name: String) -> Self {
-        LLMClient {
-            client: Arc::new(reqwest::Client::new()),
-            api_key,
+    /// Builds a client whose traffic is logged (secrets redacted) to `http.log` in the log directory
+    ///
+    /// The client is meant to be built once and cloned: clones share one connection pool, so
+    /// connections (and HTTP/2 sessions) are reused across chapters and books.
+    pub fn new(
+        provider: Provider,
+        mut api_keys: Vec<String>,
+        model_name: String,
+        options: &ClientOptions,
+    ) -> Result<Self> {
+        if options.mock && api_keys.is_empty() {
+            api_keys.push("mock".to_string());
+        }
+        if provider == Provider::None && api_keys.is_empty() {
+            api_keys.push("none".to_string());
+        }
+        if api_keys.is_empty() {
+            return Err(anyhow!(
+                "API key not provided for {} (use --api-key or {})",
+                provider.name(),
+                provider.api_key_env()
+            ));
+        }
+
+        let circuit = Arc::new(CircuitBreaker::new(
+            options.circuit_threshold,
+            options.circuit_cooldown,
+        ));
+        let mut builder = ClientBuilder::new(build_http_client(options)?);
+        // Masked before anything is logged, recorded or answered locally, restored last
+        if let Some(entities) = &options.private_entities {
+            builder = builder.with(PrivacyFilter::new(entities)?);
+        }
+        // Replayed responses are answered before the logger, so a replay is neither logged nor
+        // recorded again
+        if let Some(replay_dir) = &options.replay_dir {
+            builder = builder.with(Replayer::load(
+                replay_dir,
+                options.redactor.clone(),
+                options.cache_key.as_ref(),
+            )?);
+        }
+        builder = builder.with(HttpLogger::new(
+            &options.log_dir,
+            options.log_policy,
+            options.redactor.clone(),
+            options.record_dir.clone(),
+            options.cache_key.clone(),
+        )?);
+        // Replayed and mocked responses never reach the network, so they skip the circuit and rate limits
+        if options.mock {
+            builder = builder.with(MockProvider);
+        } else if provider == Provider::None {
+            builder = builder.with(ExtractiveProvider);
+        }
+        builder = builder.with_arc(circuit.clone()).with(RateLimiter::new(
+            options.requests_per_minute,
+            options.tokens_per_minute,
+        ));
+
+        Ok(LLMClient {
+            client: Arc::new(builder.build()),
+            circuit,
+            api_keys: Arc::new(api_keys),
+            key_rotation: options.key_rotation,
+            next_key: Arc::new(AtomicUsize::new(0)),
+            provider,
             model_name,
+            deterministic_seed: options.deterministic_seed,
+            records: Arc::new(Mutex::new(Vec::new())),
+            usage: Arc::new(Mutex::new(Usage::default())),
+        })
+    }
+
+    /// A client for another model of the same provider, sharing connections, keys and limits
+    pub fn with_model(&self, model_name: &str) -> Self {
+        LLMClient {
+            model_name: model_name.to_string(),
+            ..self.clone()
         }
     }
 
+    /// Describes the circuit breaker while the provider is considered down
+    pub fn circuit_status(&self) -> Option<String> {
+        self.circuit.status()
+    }
+
+    /// Returns and clears the requests recorded for the run manifest
+    pub fn take_records(&self) -> Vec<RequestRecord> {
+        self.records
+            .lock()
+            .map(|mut records| std::mem::take(&mut *records))
+            .unwrap_or_default()
+    }
+
+    /// Usage accumulated since the last `take_usage`, leaving it in place
+    pub fn usage(&self) -> Usage {
+        self.usage.lock().map(|usage| *usage).unwrap_or_default()
+    }
+
+    /// Returns and resets the usage accumulated since the last call
+    pub fn take_usage(&self) -> Usage {
+        self.usage
+            .lock()
+            .map(|mut usage| std::mem::take(&mut *usage))
+            .unwrap_or_default()
+    }
+
     pub async fn send_request(
         &self,
         messages: Vec<ChatMessage>,
         temperature: f32,
     ) -> Result<String> {
-        let request_body = OpenRouterRequest {
+        let mut span = telemetry::span(
+            "llm.request",
+            vec![
+                KeyValue::new("gen_ai.system", self.provider.name()),
+                KeyValue::new("gen_ai.request.model", self.model_name.clone()),
+            ],
+        );
+        let result = self.complete(messages, temperature, &mut span).await;
+        if let Err(e) = &result {
+            telemetry::fail(&mut span, e);
+        }
+        result
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        temperature: f32,
+        span: &mut BoxedSpan,
+    ) -> Result<String> {
+        let mut request_body = OpenRouterRequest {
             model: self.model_name.clone(),
             messages,
-            temperature,
+            temperature: if self.deterministic_seed.is_some() {
+                0.0
+            } else {
+                temperature
+            },
+            seed: self.deterministic_seed,
+            random_seed: None,
+            // Asks OpenRouter to report the cost of the request
+            usage: (self.provider == Provider::OpenRouter)
+                .then_some(UsageRequest { include: true }),
         };
+        self.provider.adjust(&mut request_body);
+
+        // With several keys, a rate-limited key is retried once with each of the others
+        let mut key_index = self.pick_key();
+        let mut attempts = 1;
+        let response = loop {
+            let response = self
+                .client
+                .post(self.provider.endpoint())
+                .headers(self.build_headers(&self.api_keys[key_index])?)
+                .json(&request_body)
+                .send()
+                .await?;
 
-        let response = self
-            .client
-            .post("https://openrouter.ai/api/v1/chat/completions")
-            .headers(self.build_headers()?)
-            .json(&request_body)
-            .send()
-            .await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempts >= self.api_keys.len()
+            {
+                break response;
+            }
+            let next = (key_index + 1) % self.api_keys.len();
+            warn!(
+                "API key #{} is rate limited, switching to key #{}",
+                key_index + 1,
+                next + 1
+            );
+            self.next_key.store(next, Ordering::Relaxed);
+            key_index = next;
+            attempts += 1;
+        };
 
         let status = response.status();
+        span.set_attribute(KeyValue::new(
+            "http.response.status_code",
+            i64::from(status.as_u16()),
+        ));
         let response_text = response.text().await?;
 
         if status.is_success() {
             match serde_json::from_str::<OpenRouterResponse>(&response_text) {
                 Ok(response_body) => {
+                    if let Some(reported) = &response_body.usage {
+                        span.set_attributes([
+                            KeyValue::new(
+                                "gen_ai.usage.input_tokens",
+                                reported.prompt_tokens as i64,
+                            ),
+                            KeyValue::new(
+                                "gen_ai.usage.output_tokens",
+                                reported.completion_tokens as i64,
+                            ),
+                        ]);
+                    }
+                    if let (Some(reported), Ok(mut usage)) =
+                        (&response_body.usage, self.usage.lock())
+                    {
+                        usage.add(Usage {
+                            requests: 1,
+                            prompt_tokens: reported.prompt_tokens,
+                            completion_tokens: reported.completion_tokens,
+                            cost: reported.cost.unwrap_or_default(),
+                        });
+                    }
                     if let Some(choice) = response_body.choices.first() {
-                        Ok(choice.message.content.clone())
+                        let content = choice.message.content.clone().unwrap_or_default();
+                        if choice.finish_reason.as_deref() == Some("content_filter") {
+                            return Err(Refused(format!(
+                                "{} stopped by the provider's content filter",
+                                self.model_name
+                            ))
+                            .into());
+                        }
+                        if self.deterministic_seed.is_some() {
+                            self.record(&request_body, response_body.model.clone(), &content);
+                        }
+                        Ok(content)
                     } else {
                         Err(anyhow::anyhow!("No response received from LLM"))
                     }
@@ -64,6 +463,13 @@ impl LLMClient {
             // Log the response body for debugging
             error!("API returned error status {}: {}", status, response_text);
 
+            // OpenRouter answers prompts its moderation flags with 403
+            if status == StatusCode::FORBIDDEN
+                && (response_text.contains("flagged") || response_text.contains("moderation"))
+            {
+                return Err(Refused(format!("{} flagged by moderation", self.model_name)).into());
+            }
+
             Err(anyhow::anyhow!(
                 "Request error: {} - {}",
                 status,
@@ -72,13 +478,45 @@ impl LLMClient {
         }
     }
 
-    fn build_headers(&self) -> Result<HeaderMap> {
+    fn record(&self, request: &OpenRouterRequest, served_model: Option<String>, response: &str) {
+        let prompt: String = request
+            .messages
+            .iter()
+            .map(|m| format!("{}\n{}\n", m.role, m.content))
+            .collect();
+        let record = RequestRecord {
+            provider: self.provider.name().to_string(),
+            model: request.model.clone(),
+            served_model,
+            temperature: request.temperature,
+            seed: request.seed.or(request.random_seed),
+            prompt_sha256: manifest::sha256_hex(prompt.as_bytes()),
+            response_sha256: manifest::sha256_hex(response.as_bytes()),
+        };
+        if let Ok(mut records) = self.records.lock() {
+            records.push(record);
+        }
+    }
+
+    /// Index of the key for the next request according to the rotation strategy
+    fn pick_key(&self) -> usize {
+        let count = self.api_keys.len();
+        match self.key_rotation {
+            KeyRotation::RoundRobin => self.next_key.fetch_add(1, Ordering::Relaxed) % count,
+            KeyRotation::OnRateLimit => self.next_key.load(Ordering::Relaxed) % count,
+        }
+    }
+
+    fn build_headers(&self, api_key: &str) -> Result<HeaderMap> {
         let mut headers = HeaderMap::new();
         headers.insert(
             "Authorization",
-            HeaderValue::from_str(&format!("Bearer {}", self.api_key))?,
+            HeaderValue::from_str(&format!("Bearer {}", api_key))?,
         );
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if self.provider != Provider::OpenRouter {
+            return Ok(headers);
+        }
         // Optional headers as per OpenRouter documentation
         headers.insert(
             "X-Title",
@@ -92,11 +530,61 @@ impl LLMClient {
     }
 }
 
+/// Builds the reqwest client with pooling, keep-alive, timeouts, and the proxy and TLS settings
+///
+/// Downloads of web pages, books and transcriptions use it as well, so they go through the same
+/// proxy and trust the same certificates as the LLM requests.
+pub fn build_http_client(options: &ClientOptions) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("aibook/", env!("CARGO_PKG_VERSION")))
+        .timeout(options.timeout)
+        .connect_timeout(options.connect_timeout)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(8)
+        .tcp_keepalive(Duration::from_secs(60))
+        .http2_keep_alive_interval(Duration::from_secs(30))
+        .http2_keep_alive_while_idle(true)
+        .http2_adaptive_window(true);
+
+    // Without an explicit proxy reqwest picks up HTTPS_PROXY/HTTP_PROXY/NO_PROXY itself
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(
+            reqwest::Proxy::all(proxy).map_err(|e| anyhow!("Invalid proxy '{}': {}", proxy, e))?,
+        );
+    }
+
+    if let Some(ca_bundle) = &options.ca_bundle {
+        let pem = std::fs::read(ca_bundle)
+            .map_err(|e| anyhow!("Could not read CA bundle '{}': {}", ca_bundle.display(), e))?;
+        for certificate in reqwest::Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if options.insecure {
+        warn!("TLS certificate verification is disabled (--insecure)");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder.build()?)
+}
+
 #[derive(Serialize)]
 struct OpenRouterRequest {
     model: String,
     messages: Vec<ChatMessage>,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    random_seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<UsageRequest>,
+}
+
+#[derive(Serialize)]
+struct UsageRequest {
+    include: bool,
 }
 
 #[derive(Serialize, Clone)]
@@ -108,16 +596,35 @@ pub struct ChatMessage {
 #[derive(Deserialize, Debug)]
 struct OpenRouterResponse {
     choices: Vec<Choice>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResponseUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+    #[serde(default)]
+    cost: Option<f64>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Choice {
     message: Message,
+    /// `content_filter` when the provider's safety system cut the answer
+    #[serde(default)]
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Message {
     #[allow(dead_code)]
     role: String,
-    content: String,
+    /// Null when a safety filter blocked the whole answer
+    #[serde(default)]
+    content: Option<String>,
 }
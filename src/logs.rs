@@ -0,0 +1,214 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+
+/// Name of a run directory, as `manifest::run_id` makes them
+static RUN_ID: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d{8}T\d{6}Z-[0-9a-f]{8}$").unwrap());
+
+/// Name of a log file written before runs had their own directories
+static LOOSE_LOG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(http\.log(\.\d+)?|llm_.+\.log(\.\d+)?)$").unwrap());
+
+/// When the log files of a run are rotated
+#[derive(Debug, Clone, Copy)]
+pub struct LogPolicy {
+    /// Size at which a log file is rotated
+    pub max_bytes: u64,
+    /// Age at which a log file is rotated whatever its size; `None` rotates on size only
+    pub max_age: Option<Duration>,
+    /// Rotated files kept per log (`http.log.1` … `http.log.N`)
+    pub rotated_files: usize,
+}
+
+impl Default for LogPolicy {
+    fn default() -> Self {
+        LogPolicy {
+            max_bytes: 10 * 1024 * 1024,
+            max_age: None,
+            rotated_files: 5,
+        }
+    }
+}
+
+impl LogPolicy {
+    /// Appends `text` to the log at `path`, rotating it first when it is due
+    pub fn append(&self, path: &Path, text: &str) -> Result<()> {
+        self.rotate(path)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Shifts `path` to `path.1` (and so on) once it is too large or too old
+    fn rotate(&self, path: &Path) -> Result<()> {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Ok(());
+        };
+        // Creation time is not recorded everywhere, in which case only the size counts
+        let too_old = self.max_age.is_some_and(|max_age| {
+            metadata
+                .created()
+                .ok()
+                .and_then(|created| created.elapsed().ok())
+                .is_some_and(|age| age >= max_age)
+        });
+        if metadata.len() < self.max_bytes && !too_old {
+            return Ok(());
+        }
+
+        if self.rotated_files == 0 {
+            fs::remove_file(path)?;
+            return Ok(());
+        }
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+        for index in (1..self.rotated_files).rev() {
+            if rotated(index).exists() {
+                fs::rename(rotated(index), rotated(index + 1))?;
+            }
+        }
+        fs::rename(path, rotated(1))?;
+        Ok(())
+    }
+}
+
+/// Which run log directories are kept
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// Runs last written to longer ago are removed; 0 keeps them whatever their age
+    pub max_age_days: u64,
+    /// Only the newest runs are kept; 0 keeps them all
+    pub keep_runs: usize,
+}
+
+/// Log directory of one run, `<log_dir>/<run id>`, so runs never write to each other's files
+pub fn run_dir(log_dir: &Path, run_id: &str) -> PathBuf {
+    log_dir.join(run_id)
+}
+
+/// Removes the run directories (and files from before per-run directories) that `retention`
+/// no longer keeps, returning them; with `dry_run` they are only listed
+///
+/// Only entries named like a run ID or one of aibook's own log files are considered, so a
+/// `--log-dir` shared with anything else loses nothing but aibook's logs.
+pub fn prune(log_dir: &Path, retention: &Retention, dry_run: bool) -> Result<Vec<PathBuf>> {
+    if !log_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    let mut loose_files = Vec::new();
+    for entry in
+        fs::read_dir(log_dir).with_context(|| format!("could not read '{}'", log_dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir && RUN_ID.is_match(&name) {
+            runs.push((entry.path(), last_written(&entry.path())));
+        } else if !is_dir && LOOSE_LOG.is_match(&name) {
+            loose_files.push((entry.path(), last_written(&entry.path())));
+        }
+    }
+    // Run IDs start with their UTC start time, so names sort oldest first
+    runs.sort();
+
+    let max_age = Duration::from_secs(retention.max_age_days * 24 * 60 * 60);
+    let expired = |modified: SystemTime| {
+        retention.max_age_days > 0 && modified.elapsed().is_ok_and(|age| age > max_age)
+    };
+    let excess = if retention.keep_runs > 0 {
+        runs.len().saturating_sub(retention.keep_runs)
+    } else {
+        0
+    };
+    let mut removed: Vec<PathBuf> = runs
+        .iter()
+        .enumerate()
+        .filter(|(index, (_, modified))| *index < excess || expired(*modified))
+        .map(|(_, (path, _))| path.clone())
+        .collect();
+    removed.extend(
+        loose_files
+            .iter()
+            .filter(|(_, modified)| expired(*modified))
+            .map(|(path, _)| path.clone()),
+    );
+
+    if !dry_run {
+        for path in &removed {
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// When anything in `path` was last written; a run directory counts from its newest file
+fn last_written(path: &Path) -> SystemTime {
+    let modified = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    let own = modified(path);
+    if !path.is_dir() {
+        return own;
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| modified(&entry.path()))
+                .fold(own, SystemTime::max)
+        })
+        .unwrap_or(own)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_leaves_foreign_entries_alone() {
+        let log_dir = tempfile::tempdir().unwrap();
+        let path = log_dir.path();
+        for run in ["20240101T000000Z-0123abcd", "20240102T000000Z-4567ef01"] {
+            fs::create_dir(path.join(run)).unwrap();
+        }
+        fs::create_dir(path.join("photos")).unwrap();
+        fs::write(path.join("notes.txt"), "keep").unwrap();
+        fs::write(path.join("http.log.2"), "").unwrap();
+
+        let retention = Retention {
+            max_age_days: 0,
+            keep_runs: 1,
+        };
+        let removed = prune(path, &retention, false).unwrap();
+        assert_eq!(removed, vec![path.join("20240101T000000Z-0123abcd")]);
+        assert!(path.join("photos").is_dir());
+        assert!(path.join("notes.txt").is_file());
+
+        let retention = Retention {
+            max_age_days: 1,
+            keep_runs: 0,
+        };
+        let old = SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60);
+        for name in ["notes.txt", "http.log.2"] {
+            fs::File::options()
+                .write(true)
+                .open(path.join(name))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        let removed = prune(path, &retention, true).unwrap();
+        assert_eq!(removed, vec![path.join("http.log.2")]);
+    }
+}
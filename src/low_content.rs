@@ -0,0 +1,118 @@
+use crate::reader::{Chapter, EbookReader};
+use crate::summarizer;
+use crate::unicode::{self, Normalization};
+use anyhow::{anyhow, Result};
+use log::info;
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// A Markdown image reference, which takes tokens without being prose
+static IMAGE_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"!\[[^\]]*\]\([^)]*\)").unwrap());
+
+/// What happens to chapters with too little text to summarize (image-only pages, part dividers,
+/// dedications)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LowContentMode {
+    /// The chapter's text and images are prepended to the next chapter
+    Merge,
+    /// The chapter is left out of the summary
+    Skip,
+    /// Every chapter is summarized however short
+    Off,
+}
+
+impl LowContentMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "merge" => Ok(LowContentMode::Merge),
+            "skip" => Ok(LowContentMode::Skip),
+            "off" => Ok(LowContentMode::Off),
+            other => Err(anyhow!(
+                "Unknown low-content mode '{}' (expected merge, skip or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// Tokens of a chapter's prose, not counting image references and blank lines
+pub fn content_tokens(text: &str) -> usize {
+    let prose = IMAGE_REFERENCE.replace_all(text, "");
+    if prose.trim().is_empty() {
+        return 0;
+    }
+    summarizer::count_tokens(prose.trim())
+}
+
+/// Positions of the chapters with fewer than `min_tokens` tokens of prose, in reading order
+///
+/// Chapters are read once more for this, so it is skipped when `mode` is
+/// [`LowContentMode::Off`]. A chapter that fails to extract is left to the summarization pass,
+/// and a book whose chapters are all short is summarized as it is. When merging, short
+/// chapters at the end of the book have no next chapter and are kept.
+pub fn find(
+    book: &mut dyn EbookReader,
+    mode: LowContentMode,
+    min_tokens: usize,
+    normalization: Normalization,
+) -> Vec<usize> {
+    if mode == LowContentMode::Off || min_tokens == 0 {
+        return Vec::new();
+    }
+    let mut low = Vec::new();
+    let mut chapters = 0;
+    for (index, chapter) in book.chapters().enumerate() {
+        chapters += 1;
+        let Ok(chapter) = chapter else {
+            continue;
+        };
+        let text = unicode::clean(&chapter.text, normalization);
+        if content_tokens(&text) < min_tokens {
+            low.push(index);
+        }
+    }
+    if low.len() == chapters {
+        return Vec::new();
+    }
+    if mode == LowContentMode::Merge {
+        // Nothing follows the last chapters to merge them into, so they are summarized as usual
+        let mut end = chapters;
+        while low.last() == Some(&(end - 1)) {
+            low.pop();
+            end -= 1;
+        }
+    }
+    if !low.is_empty() {
+        info!(
+            "{} chapter(s) have fewer than {} tokens of text and will be {}",
+            low.len(),
+            min_tokens,
+            match mode {
+                LowContentMode::Merge => "merged into the next chapter",
+                _ => "skipped",
+            }
+        );
+    }
+    low
+}
+
+/// The table of contents without the entries of low-content chapters, for the summary plan
+pub fn plan_toc(toc: &[String], low: &[usize]) -> Vec<String> {
+    toc.iter()
+        .enumerate()
+        .filter(|(index, _)| !low.contains(index))
+        .map(|(_, title)| title.clone())
+        .collect()
+}
+
+/// Prepends a low-content chapter to the chapter after it, keeping the later chapter's title
+pub fn merge(previous: Chapter, next: Chapter) -> Chapter {
+    let mut images = previous.images;
+    images.extend(next.images);
+    Chapter {
+        title: next.title,
+        text: format!("{}\n\n{}", previous.text.trim_end(), next.text),
+        images,
+    }
+}
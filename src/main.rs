@@ -1,36 +1,190 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use env_logger::Env;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{error, info};
+use log::{error, info, warn};
+use opentelemetry::KeyValue;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+mod argument_map;
+mod audio;
+mod batch;
+mod bench;
+mod boilerplate;
+mod chapter_error;
+mod circuit;
+mod content_warnings;
+mod cover;
+mod debug_bundle;
+mod diff;
+mod discussion;
+mod djvu;
 mod ebook;
+mod encryption;
+mod epub_check;
+mod epub_writer;
+mod evaluation;
+mod extract;
+mod extractive;
+mod fb2;
+mod grounding;
+mod gutenberg;
+mod highlights;
+mod history;
+mod hooks;
+mod http_log;
+mod images;
+mod json_output;
+mod keywords;
+mod kindle;
+mod language;
+mod layers;
 mod llm;
+mod logs;
+mod low_content;
+mod manifest;
+mod markdown;
+mod marketing;
+mod math;
+mod metadata;
+mod mock;
+mod ocr;
+mod office;
+mod one_pager;
+mod output;
+mod pandoc;
+mod paths;
+mod pause;
+mod plan;
+mod plugins;
+mod podcast;
+mod privacy;
+mod procedures;
+mod progress;
+mod rate_limit;
+mod reader;
+mod redact;
+mod refusal;
+mod run_report;
+mod schema;
+mod sentiment;
+mod ssml;
+mod state;
+mod stats;
+mod subtitles;
 mod summarizer;
+mod telemetry;
+mod text;
+mod theme;
+mod titles;
+mod unicode;
+mod vocabulary;
+mod web;
+
+/// Token limit chapters are split into sections at
+const SECTION_TOKENS: usize = 2000;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path(s) to the EPUB file(s)
+    /// Path(s) to the EPUB, FB2, DjVu, DOCX, ODT, scanned PDF/CBZ, audio or subtitle file(s), or http(s) URLs of web articles,
+    /// each optionally followed by per-book overrides, e.g. book.epub:lang=de,model=openai/gpt-4o,detail=long
     #[arg(short, long)]
     input: Vec<PathBuf>,
 
+    /// JSON file listing inputs with per-book overrides:
+    /// [{"input": "book.epub", "language": "de", "model": "...", "detail_level": "long"}]
+    #[arg(long)]
+    batch: Option<PathBuf>,
+
+    /// Speech-to-text for audio and video inputs: openai, groq or whisper-cpp
+    #[arg(long, default_value = "openai")]
+    transcriber: String,
+
+    /// Transcription model, or the ggml model file for whisper-cpp
+    #[arg(long)]
+    whisper_model: Option<String>,
+
+    /// Pause length in seconds that starts a new chapter in recordings without chapter markers
+    #[arg(long, default_value_t = 3.0)]
+    silence_gap: f64,
+
+    /// Read scanned PDFs and CBZ archives with tesseract OCR
+    #[arg(long)]
+    ocr: bool,
+
+    /// Tesseract language code(s) of the scanned pages, e.g. eng or deu+fra
+    #[arg(long, default_value = "eng")]
+    ocr_language: String,
+
+    /// Resolution PDF pages are rendered at for OCR
+    #[arg(long, default_value_t = 300)]
+    ocr_dpi: u32,
+
+    /// Mean word confidence (0-100) below which a page is flagged in the OCR report
+    #[arg(long, default_value_t = 70.0)]
+    ocr_min_confidence: f32,
+
+    /// Summarize OCR text as recognized, without the LLM cleanup pass
+    #[arg(long)]
+    no_ocr_cleanup: bool,
+
+    /// Kindle `My Clippings.txt` or exported highlights; highlighted passages get extra weight
+    #[arg(long)]
+    highlights: Option<PathBuf>,
+
+    /// Project Gutenberg book ID(s) to download (cached locally) and summarize
+    #[arg(long, value_delimiter = ',')]
+    gutenberg: Vec<u64>,
+
     /// Output directory
     #[arg(short, long)]
     output_dir: Option<PathBuf>,
 
-    /// API key for OpenRouter (optional, can use environment variable)
-    #[arg(short, long)]
-    api_key: Option<String>,
+    /// API key(s) for the provider, repeated or comma-separated (optional, can use environment variable)
+    #[arg(short, long, value_delimiter = ',')]
+    api_key: Vec<String>,
+
+    /// How requests are spread across several API keys (round-robin, on-429)
+    #[arg(long, default_value = "on-429")]
+    key_rotation: String,
+
+    /// LLM provider (openrouter, groq, mistral, or none with --strategy extractive)
+    #[arg(long, default_value = "openrouter")]
+    provider: String,
+
+    /// How sections are summarized: llm, or extractive to pick each section's most salient
+    /// sentences offline, without a model or API key (needs --provider none)
+    #[arg(long, default_value = "llm")]
+    strategy: String,
 
     /// Model to be used (optional, can use environment variable)
     #[arg(long)]
     model: Option<String>,
 
-    /// Output language (optional, can use environment variable)
+    /// Provider for the fast per-section (map) summaries; the main provider handles the rest
+    #[arg(long)]
+    map_provider: Option<String>,
+
+    /// Model for the per-section (map) summaries (defaults to the map provider's default model)
+    #[arg(long)]
+    map_model: Option<String>,
+
+    /// Models that each summarize every section, merged by the main model (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    ensemble: Vec<String>,
+
+    /// Model asked for sections the section model refuses to summarize, after a retry with a
+    /// softened prompt
+    #[arg(long)]
+    refusal_model: Option<String>,
+
+    /// Output language as a BCP-47 tag (pt-BR) or a name ("Brazilian Portuguese"), or "auto" to
+    /// match each book's language (optional, can use environment variable)
     #[arg(long)]
     language: Option<String>,
 
@@ -38,19 +192,451 @@ struct Args {
     #[arg(long, default_value = "medium")]
     detail_level: String,
 
-    /// Output format (markdown, html)
+    /// Only summarize sections whose text, prompt or model changed since the last run
+    #[arg(long)]
+    incremental: bool,
+
+    /// How often the summary plan is generated before giving up when it does not match the TOC
+    #[arg(long, default_value_t = 3)]
+    plan_attempts: usize,
+
+    /// Keep a JSON file up to date with the current book, stage and chapters done, for programs
+    /// driving aibook
+    #[arg(long)]
+    progress_file: Option<PathBuf>,
+
+    /// WASM plugin adding summarization stages (transform_chunk, post_process_summary); repeat to
+    /// chain several, applied in order. Needs a build with the wasm-plugins feature
+    #[arg(long = "plugin")]
+    plugins: Vec<PathBuf>,
+
+    /// Command run before each chapter is summarized, getting the chapter as JSON on stdin and
+    /// optionally printing a modified copy (or set PRE_CHAPTER_HOOK)
+    #[arg(long)]
+    pre_chapter_hook: Option<String>,
+
+    /// Command run after each chapter is summarized, getting the chapter summary as JSON on
+    /// stdin and optionally printing a modified copy (or set POST_CHAPTER_HOOK)
+    #[arg(long)]
+    post_chapter_hook: Option<String>,
+
+    /// Command run once a book's outputs are written, getting the summaries as JSON on stdin
+    /// (or set POST_BOOK_HOOK)
+    #[arg(long)]
+    post_book_hook: Option<String>,
+
+    /// Output format (markdown, html, epub, study-epub, json, podcast-script)
     #[arg(long, default_value = "markdown")]
     output_format: String,
 
+    /// Map each chapter's claims, evidence and counterarguments (argument_map.md with Mermaid graphs)
+    #[arg(long)]
+    argument_map: bool,
+
+    /// Extract recipes and how-to procedures into procedures.json and a printable procedures.md
+    #[arg(long)]
+    procedures: bool,
+
+    /// Emphasize the first mention of each chapter keyword in the summary text
+    #[arg(long)]
+    highlight_keywords: bool,
+
+    /// Append an index of keywords and the chapters that cover them
+    #[arg(long)]
+    index: bool,
+
+    /// In the study-epub edition, follow each summary with the passages its key points quote
+    /// instead of the whole chapter
+    #[arg(long)]
+    study_excerpts: bool,
+
+    /// Stylesheet of HTML and EPUB output (light, dark, print)
+    #[arg(long, default_value = "light")]
+    theme: String,
+
+    /// CSS file applied after the theme, for your own fonts, colours or layout
+    #[arg(long, value_name = "PATH")]
+    theme_css: Option<PathBuf>,
+
+    /// Font (.ttf, .otf, .woff, .woff2) embedded in HTML and EPUB output, for scripts e-readers
+    /// lack glyphs for
+    #[arg(long, value_name = "PATH")]
+    embed_font: Option<PathBuf>,
+
+    /// Chart each chapter's sentiment and list its tone and themes (sentiment.json, sentiment.svg)
+    #[arg(long)]
+    sentiment: bool,
+
+    /// Summary depths stacked under each chapter, shallowest first: eli5, one-liner, paragraph, detailed
+    #[arg(long, value_delimiter = ',')]
+    layers: Vec<String>,
+
+    /// E-mail the summary EPUB to your Kindle through the SMTP account in the environment
+    #[arg(long)]
+    send_to_kindle: bool,
+
+    /// Kindle address to send to, overriding KINDLE_EMAIL
+    #[arg(long)]
+    kindle_email: Option<String>,
+
+    /// Check generated EPUBs (container, package, spine, navigation, missing resources) and
+    /// report problems; an EPUB with errors is not sent to Kindle
+    #[arg(long)]
+    validate_output: bool,
+
+    /// Also convert the summary with pandoc to another format (odt, rtf, docx, pdf, ...)
+    #[arg(long, value_name = "FORMAT")]
+    via_pandoc: Option<String>,
+
+    /// Extract difficult words and idioms when the summary language differs from the book language
+    #[arg(long)]
+    vocabulary: bool,
+
+    /// Maximum number of vocabulary entries per chapter
+    #[arg(long, default_value_t = 15)]
+    vocabulary_size: usize,
+
+    /// Also write the vocabulary as an Anki-importable deck (implies --vocabulary)
+    #[arg(long)]
+    anki: bool,
+
+    /// Flag potentially sensitive content per chapter and write a content warning report
+    #[arg(long)]
+    content_warnings: bool,
+
+    /// Require a source quote for every key point and handle unsupported ones (off, flag, drop)
+    #[arg(long, default_value = "off")]
+    grounding: String,
+
+    /// Score each chapter summary against its source (coverage, faithfulness, clarity)
+    #[arg(long)]
+    evaluate: bool,
+
+    /// Summarize chapters scoring below this (1-5) once more; implies --evaluate (0 = off)
+    #[arg(long, default_value_t = 0.0)]
+    min_score: f32,
+
+    /// Also emit SSML (sentence breaks, emphasis on key terms, language tags) for audio outputs
+    #[arg(long)]
+    ssml: bool,
+
+    /// Cover for the summary document (none, generated, original)
+    #[arg(long, default_value = "none")]
+    cover: String,
+
+    /// With --cover original, overlay a "Summary of" banner on the original cover
+    #[arg(long)]
+    cover_overlay: bool,
+
+    /// How footnotes and endnotes are handled (strip, inline, collect)
+    #[arg(long, default_value = "collect")]
+    footnotes: String,
+
+    /// Line width of the extracted text (0 for no wrapping)
+    #[arg(long, default_value_t = 0)]
+    text_width: usize,
+
+    /// Extract plain text without Markdown-style headings, emphasis and inline code
+    #[arg(long)]
+    no_markdown: bool,
+
+    /// Keep text blocks repeated across chapters (headers, author bios, promo text)
+    #[arg(long)]
+    keep_boilerplate: bool,
+
+    /// How links are rendered in the extracted text (keep, footnote, strip)
+    #[arg(long, default_value = "footnote")]
+    links: String,
+
+    /// How list items are rendered in the extracted text (markdown, plain)
+    #[arg(long, default_value = "markdown")]
+    lists: String,
+
+    /// Unicode normalization of the extracted text (nfc, nfkc, off); unless off, control
+    /// characters, soft hyphens, zero-width characters and typographic quotes are cleaned up too
+    #[arg(long, default_value = "nfc")]
+    unicode_normalization: String,
+
+    /// What to do when a chapter cannot be summarized: abort the run, skip the chapter (marked
+    /// as not summarized), or retry:N to summarize it up to N more times before skipping it
+    #[arg(long, default_value = "abort")]
+    on_chapter_error: String,
+
+    /// How table of contents labels that are file names or ids ("Section0012.xhtml") are
+    /// replaced: auto (the chapter's first heading, else a generated title), heading, off
+    #[arg(long, default_value = "auto")]
+    chapter_titles: String,
+
+    /// What to do with chapters too short to summarize (image-only pages, part dividers):
+    /// merge them into the next chapter, skip them, or off to summarize them anyway
+    #[arg(long, default_value = "merge")]
+    low_content: String,
+
+    /// Chapters with fewer tokens of text than this count as low-content
+    #[arg(long, default_value_t = 40)]
+    min_chapter_tokens: usize,
+
+    /// Sections shorter than this many tokens are merged into a neighbouring section of the
+    /// same chapter, which may then run this much over the section size (0 only merges
+    /// sections that fit within it)
+    #[arg(long, default_value_t = 400)]
+    min_section_tokens: usize,
+
+    /// Before each section request, keep only this share (0-1) of the section's most salient
+    /// sentences, ranked locally with TextRank, to cut input tokens (0 = off)
+    #[arg(long, default_value_t = 0.0)]
+    pre_extract: f32,
+
+    /// Which images to extract (skip, all, referenced)
+    #[arg(long, default_value = "all")]
+    images: String,
+
+    /// Drop images narrower or shorter than this many pixels (decorative icons, spacers)
+    #[arg(long, default_value_t = 32)]
+    min_image_size: u32,
+
+    /// Downscale images whose longest side exceeds this many pixels (0 keeps the original size)
+    #[arg(long, default_value_t = 0)]
+    max_image_size: u32,
+
+    /// JPEG quality used when recompressing downscaled images
+    #[arg(long, default_value_t = 80)]
+    image_quality: u8,
+
+    /// Deflate level of EPUB output, 0 (stored) to 9 (smallest)
+    #[arg(long, default_value_t = 6)]
+    epub_compression: u32,
+
+    /// Downscale images packaged in EPUB output whose longest side exceeds this many pixels
+    /// (0 keeps them as extracted)
+    #[arg(long, default_value_t = 0)]
+    epub_image_size: u32,
+
+    /// Package identical images once and leave out files nothing in the EPUB refers to
+    #[arg(long)]
+    epub_strip_unused: bool,
+
+    /// Save every LLM request/response pair (API key redacted) as JSON files in this directory
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Answer LLM requests from a directory written by --record instead of calling the API
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Answer LLM requests with canned responses derived from the prompts, running the whole
+    /// pipeline offline without an API key (for tests and demos)
+    #[arg(long)]
+    mock_provider: bool,
+
+    /// Export traces of books, pipeline stages and provider calls to this OTLP/HTTP collector,
+    /// e.g. http://localhost:4318 (or set the standard OTEL_EXPORTER_OTLP_ENDPOINT)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Proxy URL for API requests (defaults to the HTTPS_PROXY/HTTP_PROXY environment variables)
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// PEM file with additional root certificates to trust (e.g. a corporate proxy CA)
+    #[arg(long)]
+    ca_bundle: Option<PathBuf>,
+
+    /// Disable TLS certificate verification (only for trusted, intercepting proxies)
+    #[arg(long)]
+    insecure: bool,
+
+    /// Seconds before an API request is abandoned, including generation time
+    #[arg(long, default_value_t = 300)]
+    request_timeout: u64,
+
+    /// Seconds allowed for establishing a connection to the API
+    #[arg(long, default_value_t = 10)]
+    connect_timeout: u64,
+
+    /// Consecutive 5xx/429 API responses after which requests pause for a cool-down
+    #[arg(long, default_value_t = 5)]
+    circuit_threshold: u32,
+
+    /// Initial cool-down in seconds once the circuit opens (doubled on every failed probe)
+    #[arg(long, default_value_t = 30)]
+    circuit_cooldown: u64,
+
+    /// Maximum API requests per minute (0 for no client-side limit)
+    #[arg(long, default_value_t = 0)]
+    rpm: u32,
+
+    /// Maximum prompt tokens sent per minute (0 for no client-side limit)
+    #[arg(long, default_value_t = 0)]
+    tpm: u32,
+
+    /// Reproducible run: temperature 0, a fixed seed and a run_manifest.json of prompt hashes and models
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Seed sent to providers that support one, with --deterministic
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Directory for the .env file and prompt overrides (default: platform config directory)
+    #[arg(long)]
+    config_dir: Option<PathBuf>,
+
+    /// Directory holding the default output directory (default: platform data directory)
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// Directory for HTTP and LLM response logs, one subdirectory per run (default: platform
+    /// state directory)
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+
+    /// Regular expression of text to scrub from logs, recordings, run manifests and run reports,
+    /// on top of API keys and email addresses; repeat for several
+    #[arg(long = "redact")]
+    redact_patterns: Vec<String>,
+
+    /// Encrypt the files kept with book text in them (the section summaries in state.json and
+    /// cached transcripts) with a key from the OS keyring or AIBOOK_CACHE_KEY
+    #[arg(long)]
+    encrypt_cache: bool,
+
+    /// Mask email addresses and phone numbers in everything sent to the provider, restoring
+    /// them in the summaries
+    #[arg(long)]
+    privacy: bool,
+
+    /// A name or term to mask like personal data, e.g. a client or project name; repeat for
+    /// several (implies --privacy)
+    #[arg(long = "private-entity")]
+    private_entities: Vec<String>,
+
+    /// Size in MB at which a log file is rotated
+    #[arg(long, default_value_t = 10)]
+    log_max_size: u64,
+
+    /// Hours after which a log file is rotated whatever its size (0 rotates on size only)
+    #[arg(long, default_value_t = 0)]
+    log_max_age: u64,
+
+    /// Days a run's logs are kept after it last wrote to them (0 keeps them forever)
+    #[arg(long, default_value_t = 30)]
+    log_retention_days: u64,
+
+    /// Number of most recent runs whose logs are kept (0 keeps them all)
+    #[arg(long, default_value_t = 0)]
+    log_keep_runs: usize,
+
+    /// Directory for downloaded books (default: platform cache directory)
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
     /// Verbosity level
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Extra outputs generated from the chapter summaries, and tools working on them
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate open-ended book-club questions with talking points
+    DiscussionQuestions {
+        /// Also generate a few questions for each chapter
+        #[arg(long)]
+        per_chapter: bool,
+    },
+    /// Generate a back-cover blurb, one-tweet pitch, subject categories and SEO keywords
+    Marketing,
+    /// Generate a one-page brief: thesis, 5 key insights, 3 action items and who should read it
+    OnePager,
+    /// Compare the chapter summaries of two output directories and write a diff report
+    Diff {
+        /// Output directory of the earlier run (a book directory or a whole run)
+        old: PathBuf,
+        /// Output directory of the later run
+        new: PathBuf,
+        /// Where to write the report (defaults to diff_report.md in the later run's directory)
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// List past runs, newest first, optionally filtered by title, author or input path
+    History {
+        query: Option<String>,
+        /// Maximum number of runs listed
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// List every summarized book once (its latest run) and reopen a summary
+    Bookshelf {
+        query: Option<String>,
+        /// Open the summary of the run with this id
+        #[arg(long)]
+        open: Option<i64>,
+    },
+    /// Upgrade the JSON files of an output directory (a book directory or a whole run) written
+    /// by an older aibook to the current schema
+    MigrateOutput { dir: PathBuf },
+    /// Summarize bundled public-domain texts with each model and strategy, and compare latency,
+    /// tokens, cost and judge-scored quality
+    Bench {
+        /// Models to compare, comma-separated (defaults to --model)
+        #[arg(long, value_delimiter = ',')]
+        models: Vec<String>,
+        /// Strategies to compare, comma-separated: plain, grounded
+        #[arg(long, value_delimiter = ',', default_value = "plain")]
+        strategies: Vec<String>,
+        /// Model scoring the summaries (defaults to --model)
+        #[arg(long)]
+        judge: Option<String>,
+    },
+    /// Zip a run's redacted logs, settings, prompt versions, reports and failed responses for a
+    /// bug report
+    DebugBundle {
+        /// The run's log directory, or its run ID
+        run: String,
+        /// Where to write the zip (defaults to debug-bundle-<run id>.zip here)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Manage the log directory
+    Logs {
+        #[command(subcommand)]
+        action: LogsCommand,
+    },
+    /// Search Project Gutenberg by title or author, to find IDs for --gutenberg
+    GutenbergSearch {
+        query: String,
+        /// Maximum number of books listed
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LogsCommand {
+    /// Remove the logs of runs that --log-retention-days and --log-keep-runs no longer keep
+    Prune {
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenv().ok();
     let args = Args::parse();
+    let dirs = paths::AppDirs::resolve(
+        args.config_dir.clone(),
+        args.data_dir.clone(),
+        args.log_dir.clone(),
+        args.cache_dir.clone(),
+    );
+    // A local .env takes precedence over the one in the config directory
+    dotenv().ok();
+    dotenv::from_path(dirs.config_dir.join(".env")).ok();
 
     // Configure logging
     let log_level = match args.verbose {
@@ -60,107 +646,1656 @@ async fn main() -> anyhow::Result<()> {
     };
     env_logger::Builder::from_env(Env::default().default_filter_or(log_level)).init();
 
-    // Get the API key from argument or environment variable
-    let api_key = args
-        .api_key
-        .or_else(|| env::var("OPENROUTER_API_KEY").ok())
-        .expect("API key not provided");
+    let log_retention = logs::Retention {
+        max_age_days: args.log_retention_days,
+        keep_runs: args.log_keep_runs,
+    };
+    let redactor = redact::Redactor::new(known_secrets(&args.api_key), &args.redact_patterns)?;
+
+    // Diffing only reads earlier outputs, so it needs no provider or API key
+    if let Some(Command::Diff { old, new, report }) = &args.command {
+        let report = report.clone().unwrap_or_else(|| new.join("diff_report.md"));
+        let path = diff::write_report(old, new, &report)?;
+        println!("Diff report written to '{}'", path.display());
+        return Ok(());
+    }
+    match &args.command {
+        Some(Command::History { query, limit }) => {
+            let history = history::History::open(&dirs.data_dir)?;
+            print!(
+                "{}",
+                history::format_runs(&history.runs(query.as_deref(), *limit)?)
+            );
+            return Ok(());
+        }
+        Some(Command::Bookshelf { query, open }) => {
+            let history = history::History::open(&dirs.data_dir)?;
+            match open {
+                Some(id) => {
+                    let path = history::open_summary(&history.get(*id)?)?;
+                    println!("Opening '{}'", path.display());
+                }
+                None => print!(
+                    "{}",
+                    history::format_runs(&history.bookshelf(query.as_deref())?)
+                ),
+            }
+            return Ok(());
+        }
+        Some(Command::Logs {
+            action: LogsCommand::Prune { dry_run },
+        }) => {
+            let removed = logs::prune(&dirs.log_dir, &log_retention, *dry_run)?;
+            for path in &removed {
+                println!(
+                    "{} '{}'",
+                    if *dry_run { "Would remove" } else { "Removed" },
+                    path.display()
+                );
+            }
+            if removed.is_empty() {
+                println!("Nothing to prune in '{}'", dirs.log_dir.display());
+            }
+            return Ok(());
+        }
+        Some(Command::DebugBundle { run, output }) => {
+            let path = debug_bundle::create(run, &dirs.log_dir, output.clone(), &redactor)?;
+            println!("Debug bundle written to '{}'", path.display());
+            return Ok(());
+        }
+        Some(Command::MigrateOutput { dir }) => {
+            migrate_output(dir)?;
+            return Ok(());
+        }
+        Some(Command::GutenbergSearch { query, limit }) => {
+            print!(
+                "{}",
+                gutenberg::format_books(
+                    &gutenberg::search(query, *limit, &download_client(&args)?).await?
+                )
+            );
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let provider = llm::Provider::parse(&args.provider)?;
+    let strategy = extractive::Strategy::parse(&args.strategy)?;
+    if (strategy == extractive::Strategy::Extractive) != (provider == llm::Provider::None) {
+        anyhow::bail!(
+            "--strategy extractive and --provider none go together: extractive summaries are written offline without a provider"
+        );
+    }
+    if !(0.0..=1.0).contains(&args.pre_extract) {
+        anyhow::bail!(
+            "--pre-extract must be a share between 0 and 1, got {}",
+            args.pre_extract
+        );
+    }
+    if args.privacy || !args.private_entities.is_empty() {
+        info!(
+            "Privacy mode: masking email addresses, phone numbers and {} entities before sending",
+            args.private_entities.len()
+        );
+    }
+    let map_provider = args
+        .map_provider
+        .as_deref()
+        .map(llm::Provider::parse)
+        .transpose()?;
+    if strategy == extractive::Strategy::Extractive {
+        if map_provider.is_some_and(|map_provider| map_provider != llm::Provider::None) {
+            anyhow::bail!(
+                "--map-provider cannot be used with --strategy extractive, which sends nothing to a provider"
+            );
+        }
+        info!(
+            "Summarizing offline from the books' own sentences; no book text is sent to a model provider"
+        );
+    }
 
     // Get the model name from argument or environment variable
     let model_name = args
         .model
+        .clone()
         .or_else(|| env::var("MODEL_NAME").ok())
-        .unwrap_or_else(|| "openai/gpt-4o-mini".to_string());
+        .unwrap_or_else(|| provider.default_model().to_string());
+
+    let download_client = download_client(&args)?;
 
     // Get the output language from argument or environment variable
     let output_language = args
         .language
         .or_else(|| env::var("OUTPUT_LANGUAGE").ok())
         .unwrap_or_else(|| "en".to_string());
+    let output_language = if output_language.trim().eq_ignore_ascii_case("auto") {
+        "auto".to_string()
+    } else {
+        language::resolve(&output_language)?
+    };
+
+    let extraction_options = extract::ExtractionOptions {
+        width: args.text_width,
+        markdown: !args.no_markdown,
+        links: extract::LinkMode::parse(&args.links)?,
+        lists: extract::ListStyle::parse(&args.lists)?,
+        footnotes: extract::FootnoteMode::parse(&args.footnotes)?,
+        strip_boilerplate: !args.keep_boilerplate,
+    };
+    let normalization = unicode::Normalization::parse(&args.unicode_normalization)?;
+    let title_mode = titles::TitleMode::parse(&args.chapter_titles)?;
+    let low_content_mode = low_content::LowContentMode::parse(&args.low_content)?;
+    let cover_mode = cover::CoverMode::parse(&args.cover)?;
+    let chapter_error_policy = chapter_error::ChapterErrorPolicy::parse(&args.on_chapter_error)?;
+
+    let grounding_mode = grounding::GroundingMode::parse(&args.grounding)?;
+    let summary_layers = args
+        .layers
+        .iter()
+        .map(|layer| layers::Layer::parse(layer))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let cache_key = if args.encrypt_cache {
+        Some(encryption::CacheKey::load_or_create()?)
+    } else {
+        None
+    };
+    let transcription_options = audio::TranscriptionOptions {
+        transcriber: audio::Transcriber::parse(&args.transcriber)?,
+        model: args.whisper_model.clone(),
+        silence_gap: args.silence_gap,
+        cache_dir: dirs.cache_dir.clone(),
+        cache_key: cache_key.clone(),
+        client: download_client.clone(),
+    };
+
+    let ocr_options = ocr::OcrOptions {
+        enabled: args.ocr,
+        language: args.ocr_language.clone(),
+        dpi: args.ocr_dpi,
+        min_confidence: args.ocr_min_confidence,
+    };
+
+    if let Some(format) = &args.via_pandoc {
+        pandoc::check(format)?;
+    }
+    let epub_output = matches!(args.output_format.as_str(), "epub" | "study-epub");
+    if args.validate_output
+        && !epub_output
+        && !args.send_to_kindle
+        && !args
+            .via_pandoc
+            .as_deref()
+            .is_some_and(|f| f.starts_with("epub"))
+    {
+        warn!("--validate-output only checks EPUBs (--output-format epub, --send-to-kindle or --via-pandoc epub)");
+    }
+    let stylesheet = theme::stylesheet(
+        theme::Theme::parse(&args.theme)?,
+        args.theme_css.as_deref(),
+        args.embed_font.as_deref(),
+    )?;
+
+    let kindle_config = if args.send_to_kindle {
+        Some(kindle::KindleConfig::from_env(args.kindle_email.clone())?)
+    } else {
+        None
+    };
+
+    let package_options = epub_writer::PackageOptions::new(
+        args.epub_compression,
+        args.epub_image_size,
+        args.image_quality,
+        args.epub_strip_unused,
+    )?;
+    let image_options = images::ImageOptions {
+        mode: images::ImageMode::parse(&args.images)?,
+        min_size: args.min_image_size,
+        max_size: args.max_image_size,
+        jpeg_quality: args.image_quality.clamp(1, 100),
+    };
+
+    // Kept to the end of main, so the spans still buffered are exported on the way out
+    let _telemetry = telemetry::Telemetry::init(args.otlp_endpoint.as_deref())?;
+
+    let run_id = manifest::run_id();
+    info!("Run {}", run_id);
+    for path in logs::prune(&dirs.log_dir, &log_retention, false)? {
+        info!("Removed old logs '{}'", path.display());
+    }
+    // Each run logs to its own directory, so runs and their books are easy to tell apart
+    let dirs = paths::AppDirs {
+        log_dir: logs::run_dir(&dirs.log_dir, &run_id),
+        ..dirs
+    };
+    let log_policy = logs::LogPolicy {
+        max_bytes: args.log_max_size.max(1) * 1024 * 1024,
+        max_age: (args.log_max_age > 0).then(|| Duration::from_secs(args.log_max_age * 60 * 60)),
+        ..Default::default()
+    };
+
+    dirs.create_log_dir()?;
+    let mut run_info =
+        debug_bundle::RunInfo::new(&run_id, &dirs.log_dir, &dirs.prompt_dirs(), &redactor);
+    run_info.save();
+    let client_options = llm::ClientOptions {
+        log_dir: dirs.log_dir.clone(),
+        log_policy,
+        redactor: redactor.clone(),
+        record_dir: args.record.clone(),
+        replay_dir: args.replay.clone(),
+        cache_key: cache_key.clone(),
+        mock: args.mock_provider,
+        private_entities: (args.privacy || !args.private_entities.is_empty())
+            .then(|| args.private_entities.clone()),
+        proxy: args.proxy.clone(),
+        ca_bundle: args.ca_bundle.clone(),
+        insecure: args.insecure,
+        timeout: Duration::from_secs(args.request_timeout),
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        circuit_threshold: args.circuit_threshold,
+        circuit_cooldown: Duration::from_secs(args.circuit_cooldown),
+        requests_per_minute: args.rpm,
+        tokens_per_minute: args.tpm,
+        key_rotation: llm::KeyRotation::parse(&args.key_rotation)?,
+        deterministic_seed: args.deterministic.then_some(args.seed),
+    };
+    // One client for the whole run so connections are pooled across chapters and books
+    let llm_client = llm::LLMClient::new(
+        provider,
+        api_keys(provider, provider, &args.api_key),
+        model_name,
+        &client_options,
+    )?;
+    let map_client = match (map_provider, &args.map_model) {
+        (None, None) => None,
+        (map_provider, map_model) => {
+            let map_provider = map_provider.unwrap_or(provider);
+            let map_model = map_model
+                .clone()
+                .unwrap_or_else(|| map_provider.default_model().to_string());
+            Some(llm::LLMClient::new(
+                map_provider,
+                api_keys(map_provider, provider, &args.api_key),
+                map_model,
+                &client_options,
+            )?)
+        }
+    };
+    let ensemble_clients: Vec<_> = args
+        .ensemble
+        .iter()
+        .map(|model| llm_client.with_model(model.trim()))
+        .collect();
+
+    if let Some(Command::Bench {
+        models,
+        strategies,
+        judge,
+    }) = &args.command
+    {
+        let judge = match judge {
+            Some(judge) => llm_client.with_model(judge),
+            None => llm_client.clone(),
+        };
+        let models = if models.is_empty() {
+            vec![llm_client.model_name.clone()]
+        } else {
+            models
+                .iter()
+                .map(|model| model.trim().to_string())
+                .collect()
+        };
+        let strategies = strategies
+            .iter()
+            .map(|strategy| bench::Strategy::parse(strategy.trim()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let results = bench::run(
+            &judge,
+            &models,
+            &strategies,
+            &dirs,
+            // The bundled texts are in English
+            if output_language == "auto" {
+                "en"
+            } else {
+                &output_language
+            },
+            &args.detail_level,
+        )
+        .await?;
+        print!("{}", bench::format_results(&results));
+        return Ok(());
+    }
 
     // Get the output directory from argument or environment variable
-    let default_output_dir = env::var("OUTPUT_DIR").unwrap_or_else(|_| "output".to_string());
+    let default_output_dir = env::var("OUTPUT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| dirs.default_output_dir());
+
+    let mut inputs = args
+        .input
+        .iter()
+        .map(|input| batch::BookInput::parse(input))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(batch_file) = &args.batch {
+        inputs.extend(batch::load(batch_file)?);
+    }
+    if strategy == extractive::Strategy::Extractive
+        && transcription_options.transcriber != audio::Transcriber::WhisperCpp
+        && inputs.iter().any(|input| audio::is_audio(&input.path))
+    {
+        anyhow::bail!(
+            "--strategy extractive keeps the book on this machine, so audio inputs need --transcriber whisper-cpp"
+        );
+    }
+    let requested = inputs.len() + args.gutenberg.len();
+    let mut failed = Vec::new();
+    let mut invalid_outputs = Vec::new();
+    for id in &args.gutenberg {
+        match gutenberg::download(*id, &dirs.cache_dir, &download_client).await {
+            Ok(path) => inputs.push(batch::BookInput::plain(&path)),
+            Err(e) => {
+                error!("Skipping Project Gutenberg #{}: {:#}", id, e);
+                failed.push(PathBuf::from(format!("gutenberg:{}", id)));
+            }
+        }
+    }
+
+    let hooks = hooks::Hooks {
+        pre_chapter: args
+            .pre_chapter_hook
+            .clone()
+            .or_else(|| env::var("PRE_CHAPTER_HOOK").ok())
+            .filter(|command| !command.trim().is_empty()),
+        post_chapter: args
+            .post_chapter_hook
+            .clone()
+            .or_else(|| env::var("POST_CHAPTER_HOOK").ok())
+            .filter(|command| !command.trim().is_empty()),
+        post_book: args
+            .post_book_hook
+            .clone()
+            .or_else(|| env::var("POST_BOOK_HOOK").ok())
+            .filter(|command| !command.trim().is_empty()),
+    };
+    let plugins = plugins::Plugins::load(&args.plugins)?;
+
+    let mut progress_file = progress::ProgressFile::new(args.progress_file.clone(), inputs.len());
+    let mut eta = progress::Eta::new(inputs.len());
+    let pause = pause::Pause::listen();
 
     // Process multiple e-books
-    for input_path in &args.input {
+    'books: for book_input in &inputs {
+        let input_path = &book_input.path;
+        let overrides = &book_input.overrides;
+        let _book_span = telemetry::enter(
+            "book",
+            vec![KeyValue::new(
+                "aibook.input",
+                input_path.display().to_string(),
+            )],
+        );
         // Determine the output directory for each e-book
         let output_dir = match &args.output_dir {
             Some(path) => path.clone(),
-            None => PathBuf::from(&default_output_dir),
+            None => default_output_dir.clone(),
         };
-        let ebook_stem = input_path
-            .file_stem()
-            .unwrap_or_else(|| input_path.as_os_str())
-            .to_string_lossy();
-        let ebook_output_dir = output_dir.join(ebook_stem.to_string());
+        let ebook_stem = match web::url(input_path) {
+            Some(url) => web::slug(url),
+            None => input_path
+                .file_stem()
+                .unwrap_or_else(|| input_path.as_os_str())
+                .to_string_lossy()
+                .to_string(),
+        };
+        let ebook_output_dir =
+            paths::long_path(&output_dir.join(paths::safe_file_name(&ebook_stem)));
 
         fs::create_dir_all(&ebook_output_dir)?;
+        run_info.add_book(&ebook_output_dir);
         let images_dir = ebook_output_dir.join("images");
         fs::create_dir_all(&images_dir)?;
 
-        // Update the read_ebook function call to match the new return type
-        let (doc, chapters, _chapters_images, _metadata) =
-            ebook::read_ebook(&input_path, &images_dir)?;
+        let read_span = telemetry::enter("read", Vec::new());
+        let opened = reader::open_input(
+            input_path,
+            &download_client,
+            &extraction_options,
+            &transcription_options,
+            &ocr_options,
+        )
+        .await
+        .and_then(|mut book| {
+            book.images(&images_dir, &image_options)?;
+            Ok(book)
+        });
+        drop(read_span);
+        let mut book = match opened {
+            Ok(book) => book,
+            Err(e) => {
+                // A single unreadable book should not abort the rest of the batch
+                error!("Skipping '{}': {:#}", input_path.display(), e);
+                failed.push(input_path.clone());
+                continue;
+            }
+        };
 
         info!("E-book '{}' successfully read.", input_path.display());
 
-        let toc = ebook::extract_table_of_contents(&doc);
+        let metadata = book.metadata().clone();
+        if let Some(title) = &metadata.title {
+            telemetry::record(vec![KeyValue::new("aibook.title", title.clone())]);
+        }
+        let metadata_path = metadata.write_json(&ebook_output_dir)?;
+        info!("Metadata written to '{}'", metadata_path.display());
+
+        if let Some(report) = book.extraction_report() {
+            let report_path = ebook_output_dir.join("extraction_report.md");
+            fs::write(&report_path, report)?;
+            info!("Extraction report written to '{}'", report_path.display());
+        }
+        let clean_ocr = book.is_ocr() && !args.no_ocr_cleanup;
+
+        let book_highlights = match &args.highlights {
+            Some(path) => highlights::load(path, metadata.title.as_deref())?,
+            None => Vec::new(),
+        };
+        if args.highlights.is_some() {
+            info!("Found {} highlight(s) for this book", book_highlights.len());
+        }
+        let mut matched_highlights = HashSet::new();
+
+        let mut toc: Vec<String> = book
+            .toc()
+            .iter()
+            .map(|title| unicode::clean(title, normalization))
+            .collect();
+
+        let book_language = source_language(
+            input_path,
+            &metadata,
+            &book.text_sample(language::SAMPLE_CHARS),
+        );
+        let requested_language = overrides.language.as_deref().unwrap_or(&output_language);
+        let output_language = if requested_language == "auto" {
+            if book_language.is_empty() {
+                warn!(
+                    "Could not determine the language of '{}', summarizing in English",
+                    input_path.display()
+                );
+                "en".to_string()
+            } else {
+                // Metadata may hold anything from "en_US" to "English"
+                let resolved =
+                    language::resolve(&book_language).unwrap_or_else(|_| book_language.clone());
+                // Detection only knows the base language; keep a declared regional variant
+                // such as pt-BR when it agrees
+                let output_language = metadata
+                    .language
+                    .as_deref()
+                    .and_then(|declared| language::resolve(declared).ok())
+                    .filter(|declared| {
+                        declared.contains('-') && language::same_language(declared, &resolved)
+                    })
+                    .unwrap_or(resolved);
+                info!(
+                    "Summarizing '{}' in {}",
+                    input_path.display(),
+                    language::display_name(&output_language)
+                );
+                output_language
+            }
+        } else {
+            requested_language.to_string()
+        };
+        let book_client = match &overrides.model {
+            Some(model) => llm_client.with_model(model),
+            None => llm_client.clone(),
+        };
+        let detail_level = overrides
+            .detail_level
+            .clone()
+            .unwrap_or_else(|| args.detail_level.clone());
 
         let summarizer = summarizer::Summarizer::new(
-            api_key.clone(),
-            model_name.clone(),
+            book_client.clone(),
             output_language.clone(),
-            args.detail_level.clone(),
-        );
+            detail_level.clone(),
+            &dirs,
+        )
+        .with_metadata(metadata.clone())
+        .with_source_language(book_language.clone())
+        .with_map_client(map_client.clone())
+        .with_ensemble(ensemble_clients.clone())
+        .with_refusal_client(
+            args.refusal_model
+                .as_deref()
+                .map(|model| book_client.with_model(model)),
+        )
+        .with_grounding(grounding_mode != grounding::GroundingMode::Off)
+        .with_log_policy(log_policy)
+        .with_redactor(redactor.clone());
 
-        println!("Generating summary plan...");
-        let plan = summarizer.generate_summary_plan(&toc).await?;
+        let vocabulary_requested = args.vocabulary || args.anki;
+        let build_vocabulary =
+            vocabulary_requested && vocabulary::languages_differ(&book_language, &output_language);
+        if vocabulary_requested && !build_vocabulary {
+            if vocabulary::is_undetermined(&book_language) {
+                info!("Skipping vocabulary: the language of the book could not be determined");
+            } else {
+                warn!(
+                    "Skipping vocabulary: book language '{}' matches output language '{}'",
+                    book_language, output_language
+                );
+            }
+        }
 
-        let plan_sections: Vec<String> = plan
-            .split("##")
-            .skip(1)
-            .map(|s| format!("##{}", s.trim()))
-            .collect();
+        eta.start_book();
+        progress_file.start_book(
+            input_path,
+            &ebook_output_dir,
+            book.chapter_count_hint() as u64,
+        );
+        titles::replace_placeholders(
+            book.as_mut(),
+            &mut toc,
+            &summarizer,
+            title_mode,
+            normalization,
+        )
+        .await;
+        let low_content_chapters = low_content::find(
+            book.as_mut(),
+            low_content_mode,
+            args.min_chapter_tokens,
+            normalization,
+        );
+        println!("Generating summary plan...");
+        let plan_span = telemetry::enter("plan", Vec::new());
+        let plan = plan::generate(
+            &summarizer,
+            &low_content::plan_toc(&toc, &low_content_chapters),
+            args.plan_attempts,
+        )
+        .await;
+        drop(plan_span);
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                error!("Skipping '{}': {:#}", input_path.display(), e);
+                progress_file.set_stage("failed");
+                failed.push(input_path.clone());
+                continue;
+            }
+        };
+        progress_file.set_stage("summarizing");
 
-        let pb = ProgressBar::new(chapters.len() as u64); // Use total number of chapters
+        // Split giant documents can add chapters, so the length grows as they are discovered
+        let pb = ProgressBar::new(book.chapter_count_hint() as u64);
         let style = ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
             .unwrap()
             .progress_chars("#>-");
         pb.set_style(style);
 
+        let mut chapter_summaries = Vec::new();
+        let mut reading_stats = stats::ReadingStats::default();
+        let mut content_report = content_warnings::ContentReport::default();
+        let mut argument_maps = Vec::new();
+        let mut chapter_procedures = Vec::new();
+        let evaluate = args.evaluate || args.min_score > 0.0;
+        let extractive_fingerprint = if args.pre_extract > 0.0 {
+            format!("pre-extract {}", args.pre_extract)
+        } else {
+            String::new()
+        };
+        let previous_state = if args.incremental {
+            resumable_state(&ebook_output_dir, cache_key.as_ref())
+        } else {
+            state::SectionState::default()
+        };
+        manifest::OutputManifest::remove(&ebook_output_dir)?;
+        let mut section_state = state::SectionState::default();
+        let mut evaluation_report = evaluation::EvaluationReport::default();
+
+        let mut run_report = run_report::RunReport::new(&run_id, SECTION_TOKENS);
+        run_report.plan_tokens = summarizer::count_tokens(&plan.full_text());
+
+        let title = metadata.title.clone().unwrap_or_else(|| ebook_stem.clone());
+        let hook_context = hooks::BookContext {
+            input: input_path,
+            title: &title,
+            output_dir: &ebook_output_dir,
+        };
+
+        // Low-content chapters waiting to be prepended to the next chapter
+        let mut merged: Option<reader::Chapter> = None;
+
         // Iterate through chapters
-        for (index, chapter) in chapters.iter().enumerate() {
-            let chapter_plan = plan_sections.get(index).cloned().unwrap_or_default();
+        for (index, chapter) in book.chapters().enumerate() {
+            if index as u64 >= pb.length().unwrap_or_default() {
+                pb.inc_length(1);
+            }
+            let mut chapter = match chapter {
+                Ok(chapter) => chapter,
+                Err(e) => {
+                    error!("Skipping chapter {}: {:#}", index + 1, e);
+                    // The chapter keeps its place in the outputs, so the gap is visible
+                    let chapter_title = toc
+                        .get(index)
+                        .cloned()
+                        .unwrap_or_else(|| format!("Chapter {}", index + 1));
+                    let reason = format!("its text could not be extracted: {:#}", e);
+                    run_report.skip_chapter(&chapter_title, chrono::Utc::now(), 0, 0, &reason);
+                    progress_file.finish_chapter(&chapter_title, pb.length().unwrap_or_default());
+                    chapter_summaries.push(output::ChapterSummary::skipped(chapter_title, &reason));
+                    pb.inc(1);
+                    continue;
+                }
+            };
+            let chapter_started = chrono::Utc::now();
+            chapter.text = unicode::clean(&chapter.text, normalization);
+            chapter.title = chapter
+                .title
+                .map(|title| unicode::clean(&title, normalization));
+            let mut chapter_title = chapter
+                .title
+                .clone()
+                .or_else(|| toc.get(index).cloned())
+                .unwrap_or_else(|| format!("Chapter {}", index + 1));
+            if low_content_chapters.contains(&index) {
+                if low_content_mode == low_content::LowContentMode::Merge {
+                    info!(
+                        "Merging low-content chapter '{}' into the next one",
+                        chapter_title
+                    );
+                    if let Some(previous) = merged.take() {
+                        chapter = low_content::merge(previous, chapter);
+                    }
+                    merged = Some(chapter);
+                } else {
+                    info!("Skipping low-content chapter '{}'", chapter_title);
+                }
+                pb.inc(1);
+                progress_file.finish_chapter(&chapter_title, pb.length().unwrap_or_default());
+                continue;
+            }
+            if let Some(previous) = merged.take() {
+                chapter = low_content::merge(previous, chapter);
+            }
+            let _chapter_span = telemetry::enter(
+                "chapter",
+                vec![
+                    KeyValue::new("aibook.chapter.number", index as i64 + 1),
+                    KeyValue::new("aibook.chapter.title", chapter_title.clone()),
+                ],
+            );
+            if let Err(e) = hooks
+                .pre_chapter(
+                    &hook_context,
+                    index + 1,
+                    &mut chapter_title,
+                    &mut chapter.text,
+                )
+                .await
+            {
+                pb.abandon_with_message("Hook failed. Check logs for details.");
+                error!("Skipping '{}': {:#}", input_path.display(), e);
+                progress_file.set_stage("failed");
+                failed.push(input_path.clone());
+                continue 'books;
+            }
+            let mut chapter_plan = plan.for_chapter(index, &chapter_title);
+            let outline_tokens =
+                summarizer::count_tokens(&plan.outline_for_chapter(index, &chapter_title));
+            let instructions = summarizer.chapter_instructions(index + 1);
+            if let Some(instructions) = &instructions {
+                info!("Using custom instructions for chapter {}", index + 1);
+                chapter_plan.push_str(&format!(
+                    "\n\nInstructions for this chapter (follow them over the general ones):\n{}",
+                    instructions
+                ));
+            }
+            let chapter_highlights = highlights::matching(&book_highlights, &chapter.text);
+            matched_highlights.extend(chapter_highlights.iter().copied());
+            let highlight_instructions =
+                highlights::instructions(&book_highlights, &chapter_highlights);
+            if let Some(highlight_instructions) = &highlight_instructions {
+                chapter_plan.push_str("\n\n");
+                chapter_plan.push_str(highlight_instructions);
+            }
 
             // Split chapter into sections based on token limit
-            let sections = summarizer.split_text_by_tokens(chapter, 2000);
+            let chunking_span = telemetry::enter("chunking", Vec::new());
+            let mut sections = summarizer::merge_small_sections(
+                summarizer.split_text_by_tokens(&chapter.text, SECTION_TOKENS),
+                SECTION_TOKENS,
+                args.min_section_tokens,
+            );
+            telemetry::record(vec![KeyValue::new(
+                "aibook.sections",
+                sections.len() as i64,
+            )]);
+            drop(chunking_span);
+            let source_hashes: Vec<String> = sections
+                .iter()
+                .map(|section| manifest::sha256_hex(section.as_bytes()))
+                .collect();
+            // Changed highlights or extraction must invalidate cached summaries like changed
+            // instructions do
+            let (prompt_hash, section_model) = summarizer.section_fingerprint(&format!(
+                "{}{}{}{}",
+                instructions.as_deref().unwrap_or_default(),
+                highlight_instructions.as_deref().unwrap_or_default(),
+                plugins.fingerprint(),
+                extractive_fingerprint
+            ))?;
+            let cached: Vec<Option<serde_json::Value>> = source_hashes
+                .iter()
+                .map(|source_hash| {
+                    previous_state
+                        .get(source_hash, &prompt_hash, &section_model)
+                        .cloned()
+                })
+                .collect();
+            let reused = cached.iter().flatten().count();
+            if reused > 0 {
+                info!(
+                    "Reusing {} of {} section summaries for '{}'",
+                    reused,
+                    sections.len(),
+                    chapter_title
+                );
+            }
 
-            // Process each section of the chapter
-            for section in sections {
-                let result = summarizer
-                    .summarize_with_plan(&section, &chapter_plan)
-                    .await;
+            // Cached sections keep the hash of their raw OCR text, so only new ones are cleaned
+            if clean_ocr {
+                for (section, cached) in sections.iter_mut().zip(&cached) {
+                    if cached.is_some() {
+                        continue;
+                    }
+                    match summarizer.clean_ocr_text(section).await {
+                        Ok(cleaned) => *section = cleaned,
+                        Err(e) => warn!("OCR cleanup failed, using the raw text: {:#}", e),
+                    }
+                }
+            }
+            for (section, cached) in sections.iter_mut().zip(&cached) {
+                if cached.is_none() {
+                    *section = plugins.transform_chunk(std::mem::take(section))?;
+                }
+            }
+            if args.pre_extract > 0.0 {
+                let before: usize = sections.iter().map(|s| summarizer::count_tokens(s)).sum();
+                for (section, cached) in sections.iter_mut().zip(&cached) {
+                    if cached.is_none() {
+                        *section = extractive::condense(section, args.pre_extract);
+                    }
+                }
+                let after: usize = sections.iter().map(|s| summarizer::count_tokens(s)).sum();
+                info!(
+                    "Extractive pass kept {} of {} tokens of '{}'",
+                    after, before, chapter_title
+                );
+            }
 
+            // The sections finished so far, stored where --incremental finds them if a paused
+            // run is stopped
+            let checkpoint = |finished: &[serde_json::Value]| {
+                let mut checkpoint = section_state.clone();
+                for (source_hash, summary) in source_hashes.iter().zip(finished) {
+                    checkpoint.push(section_entry(
+                        &chapter_title,
+                        source_hash,
+                        &prompt_hash,
+                        &section_model,
+                        summary,
+                    ));
+                }
+                checkpoint
+                    .save(&ebook_output_dir, cache_key.as_ref())
+                    .map(drop)
+            };
+            let mut chapter_progress =
+                progress::ChapterProgress::new(&pb, &mut eta, &chapter_title)
+                    .with_pause(pause.as_ref(), &checkpoint);
+            let mut attempts = 0;
+            let summarized = loop {
+                attempts += 1;
+                let result = summarize_sections(
+                    &summarizer,
+                    &sections,
+                    &chapter_plan,
+                    grounding_mode,
+                    &cached,
+                    &plugins,
+                    &mut chapter_progress,
+                )
+                .await;
                 match result {
-                    Ok(_) => (),
-                    Err(e) => {
-                        error!("Error summarizing section: {}", e);
+                    Ok(section_summaries) => break Ok(section_summaries),
+                    Err(e) if chapter_error_policy == chapter_error::ChapterErrorPolicy::Abort => {
                         pb.finish_with_message("Summarization failed. Check logs for details.");
-                        return Err(e.into());
+                        return Err(e);
+                    }
+                    Err(e) if chapter_error_policy.retries(attempts) => {
+                        warn!(
+                            "Summarizing '{}' failed (attempt {}), trying again: {:#}",
+                            chapter_title, attempts, e
+                        );
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            let mut section_summaries = match summarized {
+                Ok(section_summaries) => section_summaries,
+                Err(e) => {
+                    warn!(
+                        "Skipping '{}' after {} failed attempt(s): {:#}",
+                        chapter_title, attempts, e
+                    );
+                    let reason = format!("{:#}", e);
+                    eta.finish_chapter();
+                    run_report.skip_chapter(
+                        &chapter_title,
+                        chapter_started,
+                        sections.len(),
+                        attempts,
+                        &reason,
+                    );
+                    progress_file.finish_chapter(&chapter_title, pb.length().unwrap_or_default());
+                    chapter_summaries.push(output::ChapterSummary::skipped(chapter_title, &reason));
+                    pb.inc(1);
+                    continue;
+                }
+            };
+
+            if evaluate {
+                let mut chapter_evaluation = evaluation::evaluate_chapter(
+                    &summarizer,
+                    &chapter_title,
+                    &sections,
+                    &section_summaries,
+                )
+                .await;
+                if chapter_evaluation
+                    .overall()
+                    .is_some_and(|score| score < args.min_score)
+                {
+                    info!(
+                        "'{}' scored below {}, summarizing it again",
+                        chapter_title, args.min_score
+                    );
+                    let retry = summarize_sections(
+                        &summarizer,
+                        &sections,
+                        &chapter_plan,
+                        grounding_mode,
+                        &[],
+                        &plugins,
+                        &mut chapter_progress,
+                    )
+                    .await;
+                    match retry {
+                        Ok(retry) => {
+                            let retry_evaluation = evaluation::evaluate_chapter(
+                                &summarizer,
+                                &chapter_title,
+                                &sections,
+                                &retry,
+                            )
+                            .await;
+                            // Keep whichever attempt the judge preferred
+                            if retry_evaluation.overall() > chapter_evaluation.overall() {
+                                section_summaries = retry;
+                                chapter_evaluation = retry_evaluation;
+                            }
+                        }
+                        Err(e)
+                            if chapter_error_policy == chapter_error::ChapterErrorPolicy::Abort =>
+                        {
+                            pb.finish_with_message("Summarization failed. Check logs for details.");
+                            return Err(e);
+                        }
+                        // The first attempt is still there to fall back on
+                        Err(e) => warn!("Summarizing '{}' again failed: {:#}", chapter_title, e),
+                    }
+                    chapter_evaluation.resummarized = true;
+                }
+                evaluation_report.push(chapter_evaluation);
+            }
+
+            // Refused sections are not kept, so an --incremental run asks for them again
+            for (source_hash, summary) in source_hashes
+                .iter()
+                .zip(&section_summaries)
+                .filter(|(_, summary)| summary.get("refused").is_none())
+            {
+                section_state.push(section_entry(
+                    &chapter_title,
+                    source_hash,
+                    &prompt_hash,
+                    &section_model,
+                    summary,
+                ));
+            }
+            // Saved after every chapter so an interrupted run can still be resumed incrementally
+            section_state.save(&ebook_output_dir, cache_key.as_ref())?;
+
+            let mut chapter_summary =
+                output::ChapterSummary::from_sections(chapter_title, &section_summaries);
+            if args.output_format == "study-epub" {
+                chapter_summary.original = Some(chapter.text.clone());
+            }
+            chapter_summary.figures = chapter
+                .images
+                .iter()
+                .filter_map(|image| {
+                    Some(output::Figure {
+                        file: images::find_saved(&images_dir, &image.path)?,
+                        alt: image.alt.clone(),
+                    })
+                })
+                .collect();
+            chapter_summary.highlights = chapter_highlights
+                .iter()
+                .map(|&index| &book_highlights[index])
+                .map(|highlight| match &highlight.note {
+                    Some(note) => format!("“{}” — {}", highlight.text, note),
+                    None => format!("“{}”", highlight.text),
+                })
+                .collect();
+
+            if !summary_layers.is_empty() {
+                match layers::build(&summarizer, &summary_layers, &chapter_summary).await {
+                    Ok(built) => chapter_summary.layers = built,
+                    Err(e) => warn!(
+                        "Layered summaries failed for '{}': {}",
+                        chapter_summary.title, e
+                    ),
+                }
+            }
+
+            if args.sentiment {
+                // The summary carries the plot, the opening section the voice of the prose
+                let opening = sections.first().map(String::as_str).unwrap_or_default();
+                match summarizer
+                    .analyze_sentiment(&chapter_summary.title, &chapter_summary.summary, opening)
+                    .await
+                {
+                    Ok(value) => chapter_summary.mood = sentiment::parse(&value),
+                    Err(e) => warn!("Sentiment analysis failed: {}", e),
+                }
+            }
+
+            if build_vocabulary {
+                // Every section gets an equal share, so the terms cover the whole chapter
+                let per_section = args.vocabulary_size.div_ceil(sections.len().max(1));
+                for section in &sections {
+                    match summarizer.extract_vocabulary(section, per_section).await {
+                        Ok(value) => vocabulary::merge_entries(
+                            &mut chapter_summary.vocabulary,
+                            vocabulary::parse_entries(&value),
+                            args.vocabulary_size,
+                        ),
+                        Err(e) => warn!("Vocabulary extraction failed: {}", e),
+                    }
+                }
+            }
+
+            if args.content_warnings {
+                let mut warnings =
+                    content_warnings::ChapterWarnings::new(chapter_summary.title.clone());
+                for section in &sections {
+                    match summarizer.analyze_content_warnings(section).await {
+                        Ok(value) => warnings.merge_section(&value),
+                        Err(e) => {
+                            warn!("Content warning analysis failed: {}", e);
+                            warnings.mark_unchecked();
+                        }
+                    }
+                }
+                content_report.push(warnings);
+            }
+
+            if args.argument_map {
+                let mut arguments =
+                    argument_map::ChapterArguments::new(chapter_summary.title.clone());
+                for section in &sections {
+                    match summarizer.map_arguments(section).await {
+                        Ok(value) => arguments.merge_section(&value),
+                        Err(e) => warn!("Argument mapping failed: {}", e),
+                    }
+                }
+                argument_maps.push(arguments);
+            }
+
+            if args.procedures {
+                let mut procedures =
+                    procedures::ChapterProcedures::new(chapter_summary.title.clone());
+                for section in &sections {
+                    match summarizer.extract_procedures(section).await {
+                        Ok(value) => procedures.merge_section(&value),
+                        Err(e) => warn!("Procedure extraction failed: {}", e),
                     }
                 }
+                chapter_procedures.push(procedures);
             }
 
+            let chapter_summary = match hooks
+                .post_chapter(&hook_context, index + 1, chapter_summary)
+                .await
+            {
+                Ok(chapter_summary) => chapter_summary,
+                Err(e) => {
+                    pb.abandon_with_message("Hook failed. Check logs for details.");
+                    error!("Skipping '{}': {:#}", input_path.display(), e);
+                    progress_file.set_stage("failed");
+                    failed.push(input_path.clone());
+                    continue 'books;
+                }
+            };
+            reading_stats.push(stats::ChapterStats::new(
+                chapter_summary.title.clone(),
+                &chapter.text,
+                &chapter_summary.summary,
+            ));
+            eta.finish_chapter();
+            run_report.outline_sent(outline_tokens, sections.len() - reused);
+            run_report.finish_chapter(
+                &chapter_summary.title,
+                chapter_started,
+                sections.len(),
+                reused,
+                attempts,
+            );
+            progress_file.finish_chapter(&chapter_summary.title, pb.length().unwrap_or_default());
+            chapter_summaries.push(chapter_summary);
+
             // Increment progress bar only after finishing all sections of the chapter
             pb.inc(1);
         }
 
         pb.finish_with_message("Summarization completed successfully!");
+        progress_file.set_stage("writing");
+        let _writing_span = telemetry::enter("write_outputs", Vec::new());
+
+        keywords::fill_missing(&mut chapter_summaries);
+        let document_options = output::DocumentOptions {
+            language: output_language.clone(),
+            highlight_keywords: args.highlight_keywords,
+            index: args.index,
+            stylesheet: stylesheet.clone(),
+            study_excerpts: args.study_excerpts,
+            package: package_options.clone(),
+            font: match &args.embed_font {
+                Some(font) => Some(theme::install_font(font, &ebook_output_dir)?),
+                None => None,
+            },
+        };
+
+        if matched_highlights.len() < book_highlights.len() {
+            warn!(
+                "{} highlight(s) could not be matched to a chapter",
+                book_highlights.len() - matched_highlights.len()
+            );
+        }
+
+        if args.ssml && args.output_format != "podcast-script" {
+            warn!("--ssml only applies to audio outputs such as --output-format podcast-script");
+        }
+
+        let cover_file = match cover_mode {
+            cover::CoverMode::Generated => {
+                let cover_path =
+                    cover::write_generated_cover(&ebook_output_dir, &title, metadata.author())?;
+                cover_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+            }
+            cover::CoverMode::Original => {
+                match reader::save_cover(book.as_mut(), &ebook_output_dir)? {
+                    Some(original_path) => {
+                        let original_file = original_path
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        if args.cover_overlay {
+                            cover::write_overlay_cover(&ebook_output_dir, &original_file, &title)?;
+                            Some("cover.svg".to_string())
+                        } else {
+                            Some(original_file)
+                        }
+                    }
+                    None => {
+                        warn!("'{}' has no cover image", input_path.display());
+                        None
+                    }
+                }
+            }
+            cover::CoverMode::None => None,
+        };
+
+        let output_path = if args.output_format == "podcast-script" {
+            println!("Generating podcast script...");
+            let episodes = podcast::generate(&summarizer, &chapter_summaries).await;
+            if args.ssml {
+                let ssml_path =
+                    ssml::write_podcast_ssml(&ebook_output_dir, &output_language, &episodes)?;
+                info!("SSML written to '{}'", ssml_path.display());
+            }
+            podcast::write_script(&ebook_output_dir, &title, &episodes)?
+        } else if args.output_format == "json" {
+            let mut usage = llm_client.usage();
+            if let Some(map_client) = &map_client {
+                usage.add(map_client.usage());
+            }
+            json_output::write_summary(
+                &ebook_output_dir,
+                &metadata,
+                &reading_stats,
+                &chapter_summaries,
+                cover_file.as_deref(),
+                &output_language,
+                usage,
+            )?
+        } else {
+            output::write_document(
+                &ebook_output_dir,
+                &args.output_format,
+                &metadata,
+                &reading_stats,
+                &chapter_summaries,
+                cover_file.as_deref(),
+                &document_options,
+            )?
+        };
+        info!("Summary written to '{}'", output_path.display());
+        let mut epub_valid = true;
+        if args.validate_output && epub_output {
+            epub_valid = epub_check::report(&output_path);
+            if !epub_valid {
+                invalid_outputs.push(output_path.clone());
+            }
+        }
+
+        if let Some(format) = &args.via_pandoc {
+            // A PDF gets the cover as a page of its own rather than as a figure
+            let pdf = format == "pdf";
+            let markdown = output::render_markdown(
+                &metadata,
+                &reading_stats,
+                &chapter_summaries,
+                cover_file.as_deref().filter(|_| !pdf),
+                &document_options,
+            );
+            let mut metadata_yaml = pandoc::metadata_block(
+                &metadata,
+                &output_language,
+                &keywords::book_tags(&chapter_summaries, output::FRONTMATTER_TAGS),
+                cover_file.as_deref(),
+            );
+            if let Some(cover) = cover_file.as_deref().filter(|_| pdf) {
+                match pandoc::pdf_cover_block(&ebook_output_dir, cover) {
+                    Ok(block) => metadata_yaml.push_str(&block),
+                    Err(e) => warn!("The PDF will have no cover: {:#}", e),
+                }
+            }
+            match pandoc::convert(
+                &ebook_output_dir,
+                format,
+                &markdown,
+                &metadata_yaml,
+                &output_path,
+            ) {
+                Ok(path) => {
+                    println!("Pandoc output written to '{}'", path.display());
+                    if args.validate_output
+                        && path.extension().is_some_and(|e| e == "epub")
+                        && !epub_check::report(&path)
+                    {
+                        invalid_outputs.push(path);
+                    }
+                }
+                Err(e) => error!("Pandoc conversion failed: {:#}", e),
+            }
+        }
+
+        if let Some(kindle_config) = &kindle_config {
+            let epub_path = if epub_output {
+                output_path.clone()
+            } else {
+                output::write_document(
+                    &ebook_output_dir,
+                    "epub",
+                    &metadata,
+                    &reading_stats,
+                    &chapter_summaries,
+                    cover_file.as_deref(),
+                    &document_options,
+                )?
+            };
+            let valid = if args.validate_output && !epub_output {
+                let valid = epub_check::report(&epub_path);
+                if !valid {
+                    invalid_outputs.push(epub_path.clone());
+                }
+                valid
+            } else {
+                epub_valid
+            };
+            if !valid {
+                error!(
+                    "Not sending '{}' to Kindle: it failed validation",
+                    epub_path.display()
+                );
+            } else {
+                match kindle::send(kindle_config, &epub_path, &title).await {
+                    Ok(()) => println!("Summary sent to {}", kindle_config.to),
+                    Err(e) => error!("Send to Kindle failed: {:#}", e),
+                }
+            }
+        }
+
+        if build_vocabulary && args.anki {
+            let deck_path = vocabulary::write_anki_deck(&ebook_output_dir, &chapter_summaries)?;
+            info!("Anki deck written to '{}'", deck_path.display());
+        }
+
+        if args.argument_map {
+            let map_path = argument_map::write_map(&ebook_output_dir, &title, &argument_maps)?;
+            println!("Argument map written to '{}'", map_path.display());
+        }
+
+        if args.procedures {
+            let procedures_path =
+                procedures::write_procedures(&ebook_output_dir, &title, &chapter_procedures)?;
+            println!("Procedures written to '{}'", procedures_path.display());
+        }
+
+        if args.sentiment {
+            let report_path = sentiment::write_report(&ebook_output_dir, &chapter_summaries)?;
+            println!("Sentiment report written to '{}'", report_path.display());
+        }
+
+        if args.content_warnings {
+            let report_path =
+                content_warnings::write_report(&ebook_output_dir, &title, &content_report)?;
+            println!("Content warnings written to '{}'", report_path.display());
+        }
+
+        if evaluate {
+            let report_path = evaluation::write_report(
+                &ebook_output_dir,
+                &title,
+                &evaluation_report,
+                args.min_score,
+            )?;
+            println!("Evaluation written to '{}'", report_path.display());
+        }
+
+        if let Some(Command::DiscussionQuestions { per_chapter }) = &args.command {
+            println!("Generating discussion questions...");
+            let (book_questions, chapter_questions) =
+                discussion::generate(&summarizer, &chapter_summaries, *per_chapter).await?;
+
+            let questions_path = discussion::write_questions(
+                &ebook_output_dir,
+                &title,
+                &book_questions,
+                &chapter_questions,
+            )?;
+            println!(
+                "Discussion questions written to '{}'",
+                questions_path.display()
+            );
+        }
+
+        if let Some(Command::Marketing) = &args.command {
+            println!("Generating marketing copy...");
+            let copy = marketing::generate(&summarizer, &chapter_summaries).await?;
+            let copy_path = marketing::write_copy(&ebook_output_dir, &title, &copy)?;
+            println!("Marketing copy written to '{}'", copy_path.display());
+        }
+
+        if let Some(Command::OnePager) = &args.command {
+            println!("Generating one-page brief...");
+            let brief = one_pager::generate(&summarizer, &chapter_summaries).await?;
+            let brief_path =
+                one_pager::write_one_pager(&ebook_output_dir, &title, metadata.author(), &brief)?;
+            println!("One-page brief written to '{}'", brief_path.display());
+        }
+
+        let mut usage = llm_client.take_usage();
+        if let Some(map_client) = &map_client {
+            usage.add(map_client.take_usage());
+        }
+        let run = history::RunRecord {
+            input: input_path.display().to_string(),
+            title: title.clone(),
+            author: metadata.author().unwrap_or_default().to_string(),
+            language: book_language.clone(),
+            provider: provider.name().to_string(),
+            model: book_client.model_name.clone(),
+            output_language: output_language.clone(),
+            detail_level: detail_level.clone(),
+            chapters: chapter_summaries.len() as i64,
+            requests: usage.requests as i64,
+            prompt_tokens: usage.prompt_tokens as i64,
+            completion_tokens: usage.completion_tokens as i64,
+            cost: usage.cost,
+            summary_path: fs::canonicalize(&output_path)
+                .unwrap_or(output_path.clone())
+                .display()
+                .to_string(),
+            ..Default::default()
+        };
+        // The history is a convenience; failing to update it should not fail the book
+        if let Err(e) =
+            history::History::open(&dirs.data_dir).and_then(|history| history.record(&run))
+        {
+            warn!("Could not record the run in the history: {}", e);
+        }
+
+        run_report.write(&ebook_output_dir, &redactor)?;
+        if run_report.outline_tokens > 0 {
+            info!(
+                "The book outline added {} prompt tokens to the section requests",
+                run_report.outline_tokens
+            );
+        }
+        if run_report.skipped() > 0 {
+            warn!(
+                "{} chapter(s) of '{}' could not be summarized and were skipped, see run_report.json",
+                run_report.skipped(),
+                input_path.display()
+            );
+        }
+
+        if args.deterministic {
+            let mut run_manifest = manifest::RunManifest::new(
+                &run_id,
+                input_path,
+                Some(args.seed),
+                &detail_level,
+                &output_language,
+            );
+            // Input paths can carry user names or other patterns the user asked to scrub
+            run_manifest.input = PathBuf::from(redactor.redact(&input_path.to_string_lossy()));
+            run_manifest.requests = llm_client.take_records();
+            if let Some(map_client) = &map_client {
+                run_manifest.requests.extend(map_client.take_records());
+            }
+            let manifest_path = run_manifest.write(&ebook_output_dir)?;
+            info!("Run manifest written to '{}'", manifest_path.display());
+        }
+
+        if let Err(e) = hooks
+            .post_book(&hook_context, &output_path, &chapter_summaries)
+            .await
+        {
+            error!("Skipping '{}': {:#}", input_path.display(), e);
+            progress_file.set_stage("failed");
+            failed.push(input_path.clone());
+            continue;
+        }
+
+        let output_manifest = manifest::OutputManifest::build(&ebook_output_dir, &run_id)?;
+        let output_manifest_path = output_manifest.write(&ebook_output_dir)?;
+        info!(
+            "Output manifest written to '{}'",
+            output_manifest_path.display()
+        );
+        progress_file.set_stage("done");
+        eta.finish_book();
+
+        println!("\nReading statistics for '{}':", input_path.display());
+        println!("{}", reading_stats.terminal_report());
+        if let Some(average) = evaluation_report.average {
+            println!("Average summary score: {:.1} / 5", average);
+        }
     }
 
-    info!("Summarization completed for {} e-books", args.input.len());
-    println!("Summarization completed for {} e-books", args.input.len());
+    let completed = requested - failed.len();
+    info!("Summarization completed for {} e-books", completed);
+    println!("Summarization completed for {} e-books", completed);
+
+    if !failed.is_empty() {
+        println!("{} e-book(s) could not be summarized:", failed.len());
+        for path in &failed {
+            println!("  - {}", path.display());
+        }
+    }
+    if !invalid_outputs.is_empty() {
+        println!("{} EPUB(s) failed validation:", invalid_outputs.len());
+        for path in &invalid_outputs {
+            println!("  - {}", path.display());
+        }
+    }
+
+    Ok(())
+}
 
+/// Upgrades every book directory under `dir` to the current schema, keeping their manifests'
+/// hashes in step with the rewritten files so `--incremental` still trusts them
+fn migrate_output(dir: &Path) -> anyhow::Result<()> {
+    let books = schema::book_dirs(dir)?;
+    if books.is_empty() {
+        anyhow::bail!("No aibook output found in '{}'", dir.display());
+    }
+    for book_dir in books {
+        let migrated = schema::migrate_book(&book_dir)?;
+        if migrated.is_empty() {
+            println!("'{}' is up to date", book_dir.display());
+            continue;
+        }
+        if let Some(mut output_manifest) = manifest::OutputManifest::load(&book_dir) {
+            output_manifest.refresh(&book_dir, &migrated)?;
+            output_manifest.write(&book_dir)?;
+        }
+        println!(
+            "Migrated {} in '{}' to schema version {}",
+            migrated.join(", "),
+            book_dir.display(),
+            schema::SCHEMA_VERSION
+        );
+    }
     Ok(())
 }
+
+/// Section summaries of the previous run that `--incremental` may reuse
+///
+/// Files changed since the last finished run are reported, and its state is dropped when it was
+/// one of them; without a manifest the previous run was interrupted, and the sections it saved
+/// before stopping are reused.
+fn resumable_state(output_dir: &Path, key: Option<&encryption::CacheKey>) -> state::SectionState {
+    let Some(previous) = manifest::OutputManifest::load(output_dir) else {
+        return state::SectionState::load(output_dir, key);
+    };
+    let changed = previous.changed_files(output_dir);
+    for path in &changed {
+        warn!(
+            "'{}' was changed or removed after run {}",
+            output_dir.join(path).display(),
+            previous.run_id
+        );
+    }
+    if changed.iter().any(|path| path == state::STATE_FILE) {
+        warn!("Not reusing section summaries of a modified state file");
+        return state::SectionState::default();
+    }
+    state::SectionState::load(output_dir, key)
+}
+
+/// A section summary as stored in `state.json`
+///
+/// The summary is kept as written: `--incremental` puts it back into the book, so scrubbing
+/// it would carry `[REDACTED]` into the output. `--encrypt-cache` protects it at rest.
+fn section_entry(
+    chapter_title: &str,
+    source_hash: &str,
+    prompt_hash: &str,
+    section_model: &str,
+    summary: &serde_json::Value,
+) -> state::SectionEntry {
+    state::SectionEntry {
+        chapter: chapter_title.to_string(),
+        source_sha256: source_hash.to_string(),
+        prompt_sha256: prompt_hash.to_string(),
+        model: section_model.to_string(),
+        summary: summary.clone(),
+    }
+}
+
+/// Summarizes every section of a chapter, returning the first failure
+///
+/// Sections with a summary in `cached` are not sent again; new summaries go through the
+/// plugins' `post_process_summary` stage. Key points are checked against
+/// their section before the summaries are returned.
+async fn summarize_sections(
+    summarizer: &summarizer::Summarizer,
+    sections: &[String],
+    chapter_plan: &str,
+    grounding_mode: grounding::GroundingMode,
+    cached: &[Option<serde_json::Value>],
+    plugins: &plugins::Plugins,
+    progress: &mut progress::ChapterProgress<'_>,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let section_client = summarizer
+        .map_client
+        .as_ref()
+        .unwrap_or(&summarizer.llm_client);
+
+    let mut sections_left = (0..sections.len())
+        .filter(|&index| !matches!(cached.get(index), Some(Some(_))))
+        .count();
+    let mut section_summaries = Vec::new();
+    for (index, section) in sections.iter().enumerate() {
+        if let Some(Some(summary)) = cached.get(index) {
+            let mut summary = summary.clone();
+            grounding::verify(&mut summary, section, grounding_mode);
+            section_summaries.push(summary);
+            continue;
+        }
+        progress.pause_point(&section_summaries).await?;
+        progress.section(
+            index,
+            sections.len(),
+            sections_left,
+            section_client.circuit_status(),
+        );
+        let _section_span = telemetry::enter(
+            "summarize_section",
+            vec![KeyValue::new("aibook.section", index as i64 + 1)],
+        );
+        let started = std::time::Instant::now();
+        let completion_tokens = section_client.usage().completion_tokens;
+        match summarizer.summarize_with_plan(section, chapter_plan).await {
+            Ok(value) => {
+                progress.eta.finish_section(
+                    started.elapsed(),
+                    section_client
+                        .usage()
+                        .completion_tokens
+                        .saturating_sub(completion_tokens),
+                );
+                sections_left -= 1;
+                let mut value = plugins.post_process_summary(value)?;
+                grounding::verify(&mut value, section, grounding_mode);
+                section_summaries.push(value);
+            }
+            Err(e) => {
+                error!("Error summarizing section: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(section_summaries)
+}
+
+/// API keys for a provider: `--api-key` values belong to the main provider, otherwise the
+/// provider's comma-separated environment variable is used
+fn api_keys(provider: llm::Provider, main: llm::Provider, cli_keys: &[String]) -> Vec<String> {
+    let keys: Vec<String> = if provider == main && !cli_keys.is_empty() {
+        cli_keys.to_vec()
+    } else {
+        env::var(provider.api_key_env())
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim().to_string())
+            .collect()
+    };
+    keys.into_iter().filter(|key| !key.is_empty()).collect()
+}
+
+/// HTTP client for fetching inputs, with the proxy and TLS settings of the LLM client
+fn download_client(args: &Args) -> anyhow::Result<reqwest::Client> {
+    llm::build_http_client(&llm::ClientOptions {
+        proxy: args.proxy.clone(),
+        ca_bundle: args.ca_bundle.clone(),
+        insecure: args.insecure,
+        timeout: Duration::from_secs(args.request_timeout),
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        ..Default::default()
+    })
+}
+
+/// API keys and passwords aibook was given, which must never be written to disk
+fn known_secrets(cli_keys: &[String]) -> Vec<String> {
+    let providers = [
+        llm::Provider::OpenRouter,
+        llm::Provider::Groq,
+        llm::Provider::Mistral,
+    ];
+    let variables = providers
+        .iter()
+        .map(|provider| provider.api_key_env())
+        .chain(["OPENAI_API_KEY", "SMTP_PASSWORD"]);
+    let mut secrets = cli_keys.to_vec();
+    for variable in variables {
+        let value = env::var(variable).unwrap_or_default();
+        secrets.extend(value.split(',').map(|key| key.trim().to_string()));
+    }
+    secrets
+}
+
+/// Determines the language a book is written in, preferring the detected language over metadata
+fn source_language(
+    input_path: &std::path::Path,
+    metadata: &metadata::BookMetadata,
+    sample: &str,
+) -> String {
+    let declared = metadata.language.clone().unwrap_or_default();
+    let detected = language::detect(sample);
+
+    match detected {
+        Some(detected) if detected.reliable => {
+            if !declared.is_empty() && !language::same_language(&declared, &detected.code) {
+                warn!(
+                    "'{}' declares language '{}' but its text looks like {} ('{}'); using '{}'",
+                    input_path.display(),
+                    declared,
+                    detected.name,
+                    detected.code,
+                    detected.code
+                );
+            }
+            info!(
+                "Detected language of '{}': {}",
+                input_path.display(),
+                detected.name
+            );
+            detected.code
+        }
+        Some(detected) if declared.is_empty() => {
+            info!(
+                "Language of '{}' is uncertain, assuming {}",
+                input_path.display(),
+                detected.name
+            );
+            detected.code
+        }
+        _ => declared,
+    }
+}
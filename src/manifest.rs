@@ -0,0 +1,211 @@
+use crate::schema;
+use anyhow::Result;
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const OUTPUT_MANIFEST_FILE: &str = "manifest.json";
+
+/// One LLM call as recorded for a reproducible run
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRecord {
+    pub provider: String,
+    /// Model that was asked for
+    pub model: String,
+    /// Model (and version) the provider reports having used, when it says
+    pub served_model: Option<String>,
+    pub temperature: f32,
+    pub seed: Option<u64>,
+    /// SHA-256 of the full prompt, so changed prompts show up when two manifests are diffed
+    pub prompt_sha256: String,
+    pub response_sha256: String,
+}
+
+/// Everything needed to tell whether two runs of the same book should match
+#[derive(Debug, Clone, Serialize)]
+pub struct RunManifest {
+    pub run_id: String,
+    pub aibook_version: String,
+    pub created_at: String,
+    pub input: PathBuf,
+    pub input_sha256: String,
+    pub deterministic: bool,
+    pub seed: Option<u64>,
+    pub detail_level: String,
+    pub output_language: String,
+    pub requests: Vec<RequestRecord>,
+}
+
+impl RunManifest {
+    pub fn new(
+        run_id: &str,
+        input: &Path,
+        seed: Option<u64>,
+        detail_level: &str,
+        output_language: &str,
+    ) -> Self {
+        RunManifest {
+            run_id: run_id.to_string(),
+            aibook_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            input: input.to_path_buf(),
+            input_sha256: fs::read(input)
+                .map(|bytes| sha256_hex(&bytes))
+                .unwrap_or_default(),
+            deterministic: seed.is_some(),
+            seed,
+            detail_level: detail_level.to_string(),
+            output_language: output_language.to_string(),
+            requests: Vec::new(),
+        }
+    }
+
+    /// Writes `run_manifest.json`, returning its path
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf> {
+        let path = output_dir.join("run_manifest.json");
+        schema::write_json(&path, self)?;
+        Ok(path)
+    }
+}
+
+/// A file of a book's output directory as the run that wrote it left it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputFile {
+    /// Path relative to the output directory, with `/` separators
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Every file in a book's output directory with its hash, written as `manifest.json` once the
+/// run has finished
+///
+/// A missing manifest means the last run did not finish; a file whose hash no longer matches
+/// was changed or truncated after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputManifest {
+    pub run_id: String,
+    pub aibook_version: String,
+    pub created_at: String,
+    pub files: Vec<OutputFile>,
+}
+
+impl OutputManifest {
+    /// Hashes every file under `output_dir` (except the manifest itself), sorted by path
+    pub fn build(output_dir: &Path, run_id: &str) -> Result<Self> {
+        let mut files = Vec::new();
+        collect_files(output_dir, output_dir, &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(OutputManifest {
+            run_id: run_id.to_string(),
+            aibook_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            files,
+        })
+    }
+
+    /// Reads the manifest of the last finished run, if there is a readable one
+    pub fn load(output_dir: &Path) -> Option<Self> {
+        let path = output_dir.join(OUTPUT_MANIFEST_FILE);
+        let content = fs::read_to_string(&path).ok()?;
+        serde_json::from_str(&content)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| schema::upgrade(OUTPUT_MANIFEST_FILE, value))
+            .and_then(|value| Ok(serde_json::from_value(value)?))
+            .map_err(|e| warn!("Ignoring unreadable manifest '{}': {}", path.display(), e))
+            .ok()
+    }
+
+    /// Removes the manifest before a run writes its outputs, so an interrupted run is not
+    /// mistaken for a finished one
+    pub fn remove(output_dir: &Path) -> Result<()> {
+        match fs::remove_file(output_dir.join(OUTPUT_MANIFEST_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hashes the listed files at `paths` again, after they were rewritten on purpose
+    pub fn refresh(&mut self, output_dir: &Path, paths: &[String]) -> Result<()> {
+        for file in self
+            .files
+            .iter_mut()
+            .filter(|file| paths.contains(&file.path))
+        {
+            let bytes = fs::read(output_dir.join(&file.path))?;
+            file.size = bytes.len() as u64;
+            file.sha256 = sha256_hex(&bytes);
+        }
+        Ok(())
+    }
+
+    /// Paths of the listed files that are missing or no longer match their hash
+    pub fn changed_files(&self, output_dir: &Path) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|file| {
+                fs::read(output_dir.join(&file.path))
+                    .map(|bytes| sha256_hex(&bytes) != file.sha256)
+                    .unwrap_or(true)
+            })
+            .map(|file| file.path.clone())
+            .collect()
+    }
+
+    /// Writes `manifest.json`, returning its path
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf> {
+        let path = output_dir.join(OUTPUT_MANIFEST_FILE);
+        schema::write_json(&path, self)?;
+        Ok(path)
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<OutputFile>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+            continue;
+        }
+        let relative = path.strip_prefix(root)?;
+        if relative == Path::new(OUTPUT_MANIFEST_FILE) {
+            continue;
+        }
+        let bytes = fs::read(&path)?;
+        files.push(OutputFile {
+            path: relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            size: bytes.len() as u64,
+            sha256: sha256_hex(&bytes),
+        });
+    }
+    Ok(())
+}
+
+/// Identifier of this invocation: its start time and a random-enough suffix
+pub fn run_id() -> String {
+    let now = Utc::now();
+    let seed = format!(
+        "{}|{}",
+        now.timestamp_nanos_opt().unwrap_or_default(),
+        std::process::id()
+    );
+    format!(
+        "{}-{}",
+        now.format("%Y%m%dT%H%M%SZ"),
+        &sha256_hex(seed.as_bytes())[..8]
+    )
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
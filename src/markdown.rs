@@ -0,0 +1,239 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Invisible strong right-to-left character, U+200F
+const RIGHT_TO_LEFT_MARK: char = '\u{200f}';
+
+/// A `*` or `+` bullet
+static BULLET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\s*)[*+](\s+)").unwrap());
+/// `***`, `___` or `---`, possibly spaced out
+static THEMATIC_BREAK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s{0,3}([*_-])(\s*[*_-]){2,}\s*$").unwrap());
+/// A `1)` list item
+static ORDERED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\s*\d+)\)(\s+)").unwrap());
+/// `[text] (url)`, only with URL-like targets so "[sic] (see above)" in prose stays as written
+static SPACED_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[([^\]\n]+)\]\s+\(((?:https?://|mailto:|/|\./|#)[^)\n]*)\)").unwrap()
+});
+/// A link target containing whitespace
+static LINK_TARGET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]\(([^)\n]*\s[^)\n]*)\)").unwrap());
+/// The heading, list and quote markers a block starts with
+static BLOCK_MARKER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:(?:#{1,6}|[-*+]|\d+[.)]|>)\s+)*").unwrap());
+
+/// Shifts every ATX heading in model output down so the shallowest is at least `min_level`,
+/// keeping the summary's own headings below the chapter heading it is placed under
+pub fn demote_headings(text: &str, min_level: usize) -> String {
+    let shallowest = outside_code(text)
+        .filter_map(|line| heading(line).map(|(level, _)| level))
+        .min();
+    let Some(shallowest) = shallowest.filter(|&level| level < min_level) else {
+        return text.to_string();
+    };
+    let shift = min_level - shallowest;
+
+    map_outside_code(text, |line| match heading(line) {
+        Some((level, title)) => format!("{} {}", "#".repeat((level + shift).min(6)), title),
+        None => line.to_string(),
+    })
+}
+
+/// Normalizes an assembled Markdown document so it renders the same in every viewer
+///
+/// Fenced code blocks and the YAML frontmatter are left untouched. Elsewhere: trailing
+/// whitespace is trimmed, headings get a space after the `#`s, lose closing `#`s, never skip a
+/// level and are surrounded by blank lines, `*`/`+` bullets become `-` and `1)` items become
+/// `1.`, `[text] (url)` links are joined and spaces in link targets encoded, and runs of blank
+/// lines collapse into one.
+pub fn normalize(doc: &str) -> String {
+    let doc = doc.replace("\r\n", "\n");
+    let (frontmatter, body) = split_frontmatter(&doc);
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut previous_level = 0;
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+        if in_code {
+            lines.push(line.to_string());
+            continue;
+        }
+
+        let line = line.trim_end();
+        if let Some((level, title)) = heading(line) {
+            // A heading may go any number of levels up, but only one level down
+            let level = level.min(previous_level + 1);
+            previous_level = level;
+            push_blank(&mut lines);
+            lines.push(format!("{} {}", "#".repeat(level), title));
+            lines.push(String::new());
+            continue;
+        }
+
+        // Without a blank line above, "---" would turn the paragraph into a heading
+        if THEMATIC_BREAK.is_match(line) {
+            push_blank(&mut lines);
+            lines.push("---".to_string());
+            continue;
+        }
+        let line = BULLET.replace(line, "$1-$2");
+        let line = ORDERED.replace(&line, "$1.$2");
+        let line = SPACED_LINK.replace_all(&line, "[$1]($2)");
+        let line = LINK_TARGET.replace_all(&line, |captures: &regex::Captures| {
+            format!("]({})", captures[1].trim().replace(' ', "%20"))
+        });
+        if line.is_empty() && lines.last().is_some_and(String::is_empty) {
+            continue;
+        }
+        lines.push(line.into_owned());
+    }
+
+    while lines.first().is_some_and(String::is_empty) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    let separator = if frontmatter.is_empty() { "" } else { "\n" };
+    format!("{}{}{}\n", frontmatter, separator, lines.join("\n"))
+}
+
+/// Marks every block of a right-to-left document as right to left for viewers that guess a
+/// paragraph's direction from its first letter
+///
+/// A heading, list item or paragraph opening with a Latin keyword, a number or a link would
+/// otherwise be laid out left to right, with its punctuation on the wrong side. A right-to-left
+/// mark is put after the block's Markdown markers; the frontmatter, code blocks, tables and
+/// thematic breaks are left alone.
+pub fn mark_right_to_left(doc: &str) -> String {
+    let (frontmatter, body) = split_frontmatter(doc);
+    let body = map_outside_code(body, |line| {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('|') || trimmed == "---" {
+            return line.to_string();
+        }
+        let content = BLOCK_MARKER.find(line).map_or(0, |m| m.end());
+        if line[content..].starts_with(RIGHT_TO_LEFT_MARK) {
+            return line.to_string();
+        }
+        format!(
+            "{}{}{}",
+            &line[..content],
+            RIGHT_TO_LEFT_MARK,
+            &line[content..]
+        )
+    });
+    let newline = if doc.ends_with('\n') { "\n" } else { "" };
+    format!("{}{}{}", frontmatter, body, newline)
+}
+
+/// Level and title of an ATX heading, accepting the common `##Title` and `## Title ##` forms
+pub fn heading(line: &str) -> Option<(usize, String)> {
+    let trimmed = line.trim_start();
+    // Indented four spaces or more it is a code block
+    if line.len() - trimmed.len() >= 4 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let title = trimmed[level..].trim().trim_end_matches('#').trim();
+    // "#hashtag" or "#1 bestseller" without a space are not headings in any viewer, but an
+    // emitted "##Title" clearly is
+    if title.is_empty() || (!trimmed[level..].starts_with(' ') && level == 1) {
+        return None;
+    }
+    Some((level, title.to_string()))
+}
+
+fn push_blank(lines: &mut Vec<String>) {
+    if lines.last().is_some_and(|line| !line.is_empty()) {
+        lines.push(String::new());
+    }
+}
+
+/// The leading `---` YAML block, kept verbatim, and the rest of the document
+fn split_frontmatter(doc: &str) -> (&str, &str) {
+    if let Some(rest) = doc.strip_prefix("---\n") {
+        if rest.starts_with("---\n") {
+            return (&doc[..8], &doc[8..]);
+        }
+        if let Some(end) = rest.find("\n---\n") {
+            let split = 4 + end + 5;
+            return (&doc[..split], &doc[split..]);
+        }
+    }
+    ("", doc)
+}
+
+fn outside_code(text: &str) -> impl Iterator<Item = &str> {
+    let mut in_code = false;
+    text.lines().filter(move |line| {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            return false;
+        }
+        !in_code
+    })
+}
+
+fn map_outside_code(text: &str, mut map: impl FnMut(&str) -> String) -> String {
+    let mut in_code = false;
+    let mut mapped: Vec<String> = Vec::new();
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            mapped.push(line.to_string());
+        } else if in_code {
+            mapped.push(line.to_string());
+        } else {
+            mapped.push(map(line));
+        }
+    }
+    mapped.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_heading_levels_are_closed_up() {
+        let doc = "# Book\n#### Deep ##\nText\n##Next\n";
+        assert_eq!(normalize(doc), "# Book\n\n## Deep\n\nText\n\n## Next\n");
+    }
+
+    #[test]
+    fn code_blocks_and_frontmatter_are_kept_verbatim() {
+        let doc =
+            "---\ntitle: \"A  *B*\"  \n---\n\n```\n* item  \n#comment\n\n\n1) x\n```\n* item\n";
+        assert_eq!(
+            normalize(doc),
+            "---\ntitle: \"A  *B*\"  \n---\n\n```\n* item  \n#comment\n\n\n1) x\n```\n- item\n"
+        );
+    }
+
+    #[test]
+    fn only_url_like_links_are_joined() {
+        assert_eq!(
+            normalize("See [docs] (https://example.com/a b) and [sic] (see above)\n"),
+            "See [docs](https://example.com/a%20b) and [sic] (see above)\n"
+        );
+    }
+
+    #[test]
+    fn headings_are_demoted_below_the_chapter_heading() {
+        let text = "# Key ideas\n```\n# not a heading\n```\n## Detail\n#hashtag";
+        assert_eq!(
+            demote_headings(text, 3),
+            "### Key ideas\n```\n# not a heading\n```\n#### Detail\n#hashtag"
+        );
+        assert_eq!(demote_headings("### Already deep", 3), "### Already deep");
+    }
+}
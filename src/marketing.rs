@@ -0,0 +1,48 @@
+use crate::output::{self, ChapterSummary};
+use crate::schema;
+use crate::summarizer::Summarizer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Catalog copy for self-publishers, generated from the full summary
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarketingCopy {
+    pub blurb: String,
+    pub pitch: String,
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+/// Requests the marketing copy for the whole book
+pub async fn generate(
+    summarizer: &Summarizer,
+    chapters: &[ChapterSummary],
+) -> Result<MarketingCopy> {
+    let value = summarizer
+        .generate_marketing_copy(&output::full_summary(chapters))
+        .await?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Writes `marketing.md` for humans and `marketing.json` for catalog tooling, returning the Markdown path
+pub fn write_copy(output_dir: &Path, title: &str, copy: &MarketingCopy) -> Result<PathBuf> {
+    let mut doc = format!("# Marketing Copy: {}\n\n", title);
+    doc.push_str(&format!("## Back-Cover Blurb\n\n{}\n\n", copy.blurb));
+    doc.push_str(&format!("## One-Tweet Pitch\n\n{}\n\n", copy.pitch));
+    doc.push_str("## Subject Categories\n\n");
+    for category in &copy.categories {
+        doc.push_str(&format!("- {}\n", category));
+    }
+    doc.push_str("\n## SEO Keywords\n\n");
+    doc.push_str(&copy.keywords.join(", "));
+    doc.push('\n');
+
+    schema::write_json(&output_dir.join("marketing.json"), copy)?;
+
+    let path = output_dir.join("marketing.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
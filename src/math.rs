@@ -0,0 +1,711 @@
+use crate::text::escape_html;
+use log::warn;
+use regex::{Captures, Regex};
+use roxmltree::{Document, Node};
+use std::sync::LazyLock;
+
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// A `<math>` element, with or without a namespace prefix
+static MATH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:[a-zA-Z]+:)?math\b.*?</(?:[a-zA-Z]+:)?math>").unwrap());
+/// An HTML named entity such as `&nbsp;`
+static NAMED_ENTITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&([a-zA-Z]+);").unwrap());
+
+/// Replaces every MathML `<math>` element with its LaTeX form between `$...$` (or `$$...$$`
+/// for display equations), so equations survive text conversion and reach the LLM
+pub fn mathml_to_latex(html: &str) -> String {
+    if !html.contains("math") {
+        return html.to_string();
+    }
+    MATH.replace_all(html, |caps: &Captures| match convert_fragment(&caps[0]) {
+        Some(latex) => escape_html(&latex),
+        None => {
+            warn!("Could not convert MathML equation, keeping its text content");
+            caps[0].to_string()
+        }
+    })
+    .into_owned()
+}
+
+fn convert_fragment(fragment: &str) -> Option<String> {
+    // Fragments are cut out of their document, so namespaces and HTML entities are restored here
+    let wrapped = format!(
+        "<root xmlns=\"{ns}\" xmlns:m=\"{ns}\" xmlns:mml=\"{ns}\">{}</root>",
+        decode_html_entities(fragment),
+        ns = MATHML_NS
+    );
+    let doc = Document::parse(&wrapped).ok()?;
+    let math = doc.root_element().first_element_child()?;
+
+    let latex = convert(math).trim().to_string();
+    if latex.is_empty() {
+        return None;
+    }
+    let display =
+        math.attribute("display") == Some("block") || math.attribute("mode") == Some("display");
+    Some(if display {
+        format!("$${}$$", latex)
+    } else {
+        format!("${}$", latex)
+    })
+}
+
+fn convert(node: Node) -> String {
+    if node.is_text() {
+        return node.text().map(symbol_text).unwrap_or_default();
+    }
+    if !node.is_element() {
+        return String::new();
+    }
+
+    let children: Vec<Node> = node.children().filter(|c| c.is_element()).collect();
+    let arg = |index: usize| children.get(index).map(|c| convert(*c)).unwrap_or_default();
+
+    match node.tag_name().name() {
+        "mi" | "mn" | "mo" => node.text().map(symbol_text).unwrap_or_default(),
+        "mtext" => format!("\\text{{{}}}", node.text().unwrap_or_default().trim()),
+        "mspace" => " ".to_string(),
+        "mphantom" | "annotation-xml" => String::new(),
+        "semantics" => node
+            .children()
+            .find(|c| {
+                c.tag_name().name() == "annotation"
+                    && c.attribute("encoding")
+                        .is_some_and(|e| e.contains("tex") || e.contains("latex"))
+            })
+            .and_then(|c| c.text())
+            .map(|tex| tex.trim().to_string())
+            .unwrap_or_else(|| arg(0)),
+        "annotation" => String::new(),
+        "mfrac" => format!("\\frac{{{}}}{{{}}}", arg(0), arg(1)),
+        "msqrt" => format!("\\sqrt{{{}}}", join(&children)),
+        "mroot" => format!("\\sqrt[{}]{{{}}}", arg(1), arg(0)),
+        "msup" => format!("{{{}}}^{{{}}}", arg(0), arg(1)),
+        "msub" => format!("{{{}}}_{{{}}}", arg(0), arg(1)),
+        "msubsup" | "munderover" => format!("{{{}}}_{{{}}}^{{{}}}", arg(0), arg(1), arg(2)),
+        "munder" if is_large_operator(&arg(0)) => format!("{}_{{{}}}", arg(0), arg(1)),
+        "munder" => format!("\\underset{{{}}}{{{}}}", arg(1), arg(0)),
+        "mover" => match arg(1).trim() {
+            "^" | "\\hat" => format!("\\hat{{{}}}", arg(0)),
+            "¯" | "‾" | "_" => format!("\\overline{{{}}}", arg(0)),
+            "→" | "\\to" => format!("\\vec{{{}}}", arg(0)),
+            "~" => format!("\\tilde{{{}}}", arg(0)),
+            "." | "˙" => format!("\\dot{{{}}}", arg(0)),
+            accent => format!("\\overset{{{}}}{{{}}}", accent, arg(0)),
+        },
+        "mfenced" => {
+            let open = node.attribute("open").unwrap_or("(");
+            let close = node.attribute("close").unwrap_or(")");
+            let separator = node.attribute("separators").unwrap_or(",");
+            let items: Vec<String> = children.iter().map(|c| convert(*c)).collect();
+            format!(
+                "\\left{} {} \\right{}",
+                fence(open),
+                items.join(separator.trim()),
+                fence(close)
+            )
+        }
+        "mtable" => {
+            let rows: Vec<String> = children
+                .iter()
+                .map(|row| {
+                    row.children()
+                        .filter(|c| c.is_element())
+                        .map(convert)
+                        .collect::<Vec<_>>()
+                        .join(" & ")
+                })
+                .collect();
+            format!("\\begin{{matrix}} {} \\end{{matrix}}", rows.join(" \\\\ "))
+        }
+        _ => join(&children),
+    }
+}
+
+fn join(children: &[Node]) -> String {
+    let parts: Vec<String> = children.iter().map(|c| convert(*c)).collect();
+    parts
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_large_operator(base: &str) -> bool {
+    matches!(
+        base.trim(),
+        "\\sum" | "\\prod" | "\\lim" | "\\int" | "\\bigcup" | "\\bigcap" | "lim" | "max" | "min"
+    )
+}
+
+fn fence(delimiter: &str) -> &str {
+    match delimiter {
+        "" => ".",
+        "{" => "\\{",
+        "}" => "\\}",
+        other => other,
+    }
+}
+
+/// Unicode math symbols and the LaTeX commands naming them
+const SYMBOLS: [(char, &str); 34] = [
+    ('×', "times"),
+    ('÷', "div"),
+    ('·', "cdot"),
+    ('±', "pm"),
+    ('∓', "mp"),
+    ('≤', "leq"),
+    ('≥', "geq"),
+    ('≠', "neq"),
+    ('≈', "approx"),
+    ('≡', "equiv"),
+    ('∝', "propto"),
+    ('∞', "infty"),
+    ('∑', "sum"),
+    ('∏', "prod"),
+    ('∫', "int"),
+    ('∂', "partial"),
+    ('∇', "nabla"),
+    ('√', "sqrt"),
+    ('→', "to"),
+    ('←', "leftarrow"),
+    ('⇒', "Rightarrow"),
+    ('⇔', "Leftrightarrow"),
+    ('∈', "in"),
+    ('∉', "notin"),
+    ('⊂', "subset"),
+    ('⊆', "subseteq"),
+    ('∪', "cup"),
+    ('∩', "cap"),
+    ('∀', "forall"),
+    ('∃', "exists"),
+    ('¬', "neg"),
+    ('∧', "wedge"),
+    ('∨', "vee"),
+    ('⋅', "cdot"),
+];
+
+/// Other spellings of symbols that models write, only read when rendering
+const SYMBOL_ALIASES: [(char, &str); 12] = [
+    ('≤', "le"),
+    ('≥', "ge"),
+    ('≠', "ne"),
+    ('→', "rightarrow"),
+    ('…', "ldots"),
+    ('⋯', "cdots"),
+    ('∘', "circ"),
+    ('′', "prime"),
+    ('∣', "mid"),
+    ('<', "lt"),
+    ('>', "gt"),
+    ('∅', "emptyset"),
+];
+
+/// Maps Unicode math symbols to their LaTeX commands
+fn symbol_text(text: &str) -> String {
+    text.trim()
+        .chars()
+        .map(|c| match c {
+            '−' => "-".to_string(),
+            '\u{2061}' | '\u{2062}' | '\u{2063}' => String::new(),
+            c => match SYMBOLS.iter().find(|(symbol, _)| *symbol == c) {
+                Some((_, command)) => format!("\\{} ", command),
+                None => greek_letter(c).map_or_else(|| c.to_string(), |g| format!("\\{} ", g)),
+            },
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn greek_letter(c: char) -> Option<&'static str> {
+    const LOWER: [&str; 24] = [
+        "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+        "lambda", "mu", "nu", "xi", "", "pi", "rho", "varsigma", "sigma", "tau", "upsilon", "phi",
+        "chi", "psi",
+    ];
+    match c {
+        'α'..='ψ' => LOWER
+            .get(c as usize - 'α' as usize)
+            .copied()
+            .filter(|g| !g.is_empty()),
+        'ω' => Some("omega"),
+        'Γ' => Some("Gamma"),
+        'Δ' => Some("Delta"),
+        'Θ' => Some("Theta"),
+        'Λ' => Some("Lambda"),
+        'Π' => Some("Pi"),
+        'Σ' => Some("Sigma"),
+        'Φ' => Some("Phi"),
+        'Ψ' => Some("Psi"),
+        'Ω' => Some("Omega"),
+        _ => None,
+    }
+}
+
+/// Turns HTML named entities into characters, leaving the five XML ones for the parser
+fn decode_html_entities(text: &str) -> String {
+    NAMED_ENTITY
+        .replace_all(text, |caps: &Captures| match &caps[1] {
+            "amp" | "lt" | "gt" | "quot" | "apos" => caps[0].to_string(),
+            "nbsp" => "\u{a0}".to_string(),
+            "minus" => "−".to_string(),
+            "times" => "×".to_string(),
+            "InvisibleTimes" | "it" | "ApplyFunction" | "af" => String::new(),
+            _ => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Finds the equations in summary text: `$$...$$` for display math and `$...$` for inline math
+///
+/// Returns the text between equations and each equation's LaTeX with whether it is displayed.
+/// A `$` next to a digit never delimits inline math, so prices such as "$5 and $10" stay text;
+/// the opening `$` must also be followed, and the closing one preceded, by a non-space.
+pub fn split_math(text: &str) -> Vec<MathSpan<'_>> {
+    let mut spans = Vec::new();
+    let mut rest = text;
+    while let Some((before, latex, display, after)) = next_equation(rest) {
+        if !before.is_empty() {
+            spans.push(MathSpan::Text(before));
+        }
+        spans.push(MathSpan::Math { latex, display });
+        rest = after;
+    }
+    if !rest.is_empty() {
+        spans.push(MathSpan::Text(rest));
+    }
+    spans
+}
+
+/// A piece of summary text, see [`split_math`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathSpan<'a> {
+    Text(&'a str),
+    Math { latex: &'a str, display: bool },
+}
+
+/// The text before the first equation in `text`, the equation and the text after it
+fn next_equation(text: &str) -> Option<(&str, &str, bool, &str)> {
+    let mut search = 0;
+    while let Some(offset) = text[search..].find('$') {
+        let start = search + offset;
+        if text[start + 1..].starts_with('$') {
+            if let Some(length) = text[start + 2..].find("$$") {
+                let latex = &text[start + 2..start + 2 + length];
+                if !latex.trim().is_empty() {
+                    return Some((&text[..start], latex, true, &text[start + 4 + length..]));
+                }
+            }
+            search = start + 2;
+            continue;
+        }
+        let opens = text[start + 1..]
+            .chars()
+            .next()
+            .is_some_and(|c| !c.is_whitespace() && !c.is_ascii_digit())
+            && !text[..start].ends_with(|c: char| c.is_ascii_digit());
+        if opens {
+            let body = &text[start + 1..];
+            let close = body.char_indices().find(|&(index, c)| {
+                c == '$'
+                    && index > 0
+                    && !body[..index].ends_with(char::is_whitespace)
+                    && !body[index + 1..].starts_with(|c: char| c.is_ascii_digit() || c == '$')
+            });
+            if let Some((end, _)) = close {
+                let latex = &body[..end];
+                if !latex.contains('\n') {
+                    return Some((&text[..start], latex, false, &body[end + 1..]));
+                }
+            }
+        }
+        search = start + 1;
+    }
+    None
+}
+
+/// Renders LaTeX as MathML, so equations display in browsers and EPUB 3 readers without a
+/// script
+///
+/// This covers what [`mathml_to_latex`] writes and what models commonly answer with: fractions,
+/// roots, scripts, accents, matrices, Greek letters and operator symbols. `None` when the LaTeX
+/// uses anything else, so the caller can show it as it is.
+pub fn latex_to_mathml(latex: &str, display: bool) -> Option<String> {
+    let mut parser = LatexParser {
+        tokens: tokenize(latex),
+        position: 0,
+    };
+    let body = parser.row()?;
+    if parser.position < parser.tokens.len() {
+        return None;
+    }
+    Some(format!(
+        "<math xmlns=\"{}\"{}>{}</math>",
+        MATHML_NS,
+        if display { " display=\"block\"" } else { "" },
+        body
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Open,
+    Close,
+    Superscript,
+    Subscript,
+    Column,
+    Row,
+    Number(String),
+    Char(char),
+    /// The verbatim argument of a command in [`TEXT_COMMANDS`]
+    Text(String),
+}
+
+/// Commands whose argument is text rather than math
+const TEXT_COMMANDS: [&str; 7] = [
+    "text",
+    "textrm",
+    "mbox",
+    "mathrm",
+    "operatorname",
+    "begin",
+    "end",
+];
+
+fn tokenize(latex: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = latex.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\\') => tokens.push(Token::Row),
+                Some(first) if first.is_ascii_alphabetic() => {
+                    let mut name = first.to_string();
+                    while let Some(next) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                        name.push(*next);
+                        chars.next();
+                    }
+                    if TEXT_COMMANDS.contains(&name.as_str()) && chars.peek() == Some(&'{') {
+                        chars.next();
+                        let mut text = String::new();
+                        let mut depth = 0;
+                        for c in chars.by_ref() {
+                            match c {
+                                '{' => depth += 1,
+                                '}' if depth == 0 => break,
+                                '}' => depth -= 1,
+                                _ => {}
+                            }
+                            text.push(c);
+                        }
+                        tokens.push(Token::Command(name));
+                        tokens.push(Token::Text(text));
+                    } else {
+                        tokens.push(Token::Command(name));
+                    }
+                }
+                Some(other) => tokens.push(Token::Command(other.to_string())),
+                None => tokens.push(Token::Char('\\')),
+            },
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Superscript),
+            '_' => tokens.push(Token::Subscript),
+            '&' => tokens.push(Token::Column),
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(next) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    number.push(*next);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number));
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+struct LatexParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl LatexParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Items up to the end of the current group, table cell or input, as one `<mrow>`
+    fn row(&mut self) -> Option<String> {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            if matches!(token, Token::Close | Token::Column | Token::Row)
+                || *token == Token::Command("end".to_string())
+            {
+                break;
+            }
+            items.push(self.scripted()?);
+        }
+        Some(match items.len() {
+            1 => items.remove(0),
+            _ => format!("<mrow>{}</mrow>", items.concat()),
+        })
+    }
+
+    /// An atom with its subscript and superscript, if any
+    fn scripted(&mut self) -> Option<String> {
+        let base = self.atom()?;
+        let (mut sub, mut sup) = (None, None);
+        loop {
+            match self.peek() {
+                Some(Token::Subscript) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.atom()?);
+                }
+                Some(Token::Superscript) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.atom()?);
+                }
+                _ => break,
+            }
+        }
+        Some(match (sub, sup) {
+            (Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+            (Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+            (None, None) => base,
+        })
+    }
+
+    /// A `{...}` group as one `<mrow>`
+    fn group(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Open => {
+                let row = self.row()?;
+                (self.next()? == Token::Close).then_some(row)
+            }
+            _ => {
+                self.position -= 1;
+                self.atom()
+            }
+        }
+    }
+
+    /// The raw text of a `{...}` group, for `\text` and environment names
+    fn group_text(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn atom(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Open => {
+                let row = self.row()?;
+                (self.next()? == Token::Close).then_some(row)
+            }
+            Token::Number(number) => Some(format!("<mn>{}</mn>", number)),
+            Token::Char(c) if c.is_alphabetic() => Some(format!("<mi>{}</mi>", c)),
+            Token::Char(c) => Some(format!("<mo>{}</mo>", escape_html(&c.to_string()))),
+            Token::Command(name) => self.command(&name),
+            _ => None,
+        }
+    }
+
+    fn command(&mut self, name: &str) -> Option<String> {
+        Some(match name {
+            "frac" | "dfrac" | "tfrac" => {
+                format!("<mfrac>{}{}</mfrac>", self.group()?, self.group()?)
+            }
+            "sqrt" if self.peek() == Some(&Token::Char('[')) => {
+                self.next();
+                let mut index = Vec::new();
+                while self.peek() != Some(&Token::Char(']')) {
+                    index.push(self.scripted()?);
+                }
+                self.next();
+                format!(
+                    "<mroot>{}<mrow>{}</mrow></mroot>",
+                    self.group()?,
+                    index.concat()
+                )
+            }
+            "sqrt" => format!("<msqrt>{}</msqrt>", self.group()?),
+            "text" | "textrm" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape_html(&self.group_text()?))
+            }
+            "mathrm" | "operatorname" => format!(
+                "<mi mathvariant=\"normal\">{}</mi>",
+                escape_html(&self.group_text()?)
+            ),
+            "mathbf" | "mathit" | "mathbb" | "mathcal" => self.group()?,
+            "hat" | "widehat" => self.accent("^")?,
+            "vec" => self.accent("→")?,
+            "overline" | "bar" => self.accent("‾")?,
+            "tilde" | "widetilde" => self.accent("~")?,
+            "dot" => self.accent("˙")?,
+            "underset" => {
+                let under = self.group()?;
+                format!("<munder>{}{}</munder>", self.group()?, under)
+            }
+            "overset" => {
+                let over = self.group()?;
+                format!("<mover>{}{}</mover>", self.group()?, over)
+            }
+            "left" | "right" | "big" | "Big" | "bigl" | "bigr" => match self.next()? {
+                Token::Char('.') => String::new(),
+                Token::Char(c) => format!("<mo>{}</mo>", escape_html(&c.to_string())),
+                Token::Command(name) if name == "{" || name == "}" || name == "|" => {
+                    format!("<mo>{}</mo>", name)
+                }
+                Token::Command(name) => self.command(&name)?,
+                _ => return None,
+            },
+            "begin" => self.table()?,
+            "," | ":" | ";" | " " | "quad" | "qquad" => "<mspace width=\"0.3em\"/>".to_string(),
+            "!" => String::new(),
+            "{" | "}" | "%" | "$" | "#" | "|" => format!("<mo>{}</mo>", name),
+            "lim" | "max" | "min" | "log" | "ln" | "exp" | "sin" | "cos" | "tan" | "det" => {
+                format!("<mi>{}</mi>", name)
+            }
+            name => {
+                let symbol = SYMBOLS
+                    .iter()
+                    .chain(SYMBOL_ALIASES.iter())
+                    .find(|(_, command)| *command == name)
+                    .map(|(symbol, _)| *symbol);
+                match symbol {
+                    Some(symbol @ ('∞' | '∂' | '∇' | '∅')) => {
+                        format!("<mi>{}</mi>", symbol)
+                    }
+                    Some(symbol) => format!("<mo>{}</mo>", escape_html(&symbol.to_string())),
+                    None => format!("<mi>{}</mi>", greek_symbol(name)?),
+                }
+            }
+        })
+    }
+
+    fn accent(&mut self, mark: &str) -> Option<String> {
+        Some(format!(
+            "<mover accent=\"true\">{}<mo>{}</mo></mover>",
+            self.group()?,
+            mark
+        ))
+    }
+
+    /// A `\begin{matrix}...\end{matrix}` environment, with the brackets its name implies
+    fn table(&mut self) -> Option<String> {
+        let environment = self.group_text()?;
+        let (open, close) = match environment.as_str() {
+            "matrix" | "array" | "aligned" | "align" | "align*" | "cases" => ("", ""),
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "vmatrix" => ("|", "|"),
+            _ => return None,
+        };
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(format!("<mtd>{}</mtd>", self.row()?));
+            match self.next()? {
+                Token::Column => {}
+                Token::Row => {
+                    rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+                    cells.clear();
+                }
+                Token::Command(name) if name == "end" => {
+                    rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+                    self.group_text()?;
+                    break;
+                }
+                _ => return None,
+            }
+        }
+        let table = format!("<mtable>{}</mtable>", rows.concat());
+        Some(if open.is_empty() {
+            table
+        } else {
+            format!("<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>", open, table, close)
+        })
+    }
+}
+
+/// The Greek letter a LaTeX command such as `alpha` or `Omega` names
+fn greek_symbol(name: &str) -> Option<char> {
+    ('α'..='ω')
+        .chain("ΓΔΘΛΠΣΦΨΩ".chars())
+        .find(|c| greek_letter(*c) == Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_are_not_math() {
+        let text = "It costs $5 and $10, or $ 3 $ at most.";
+        assert_eq!(split_math(text), [MathSpan::Text(text)]);
+    }
+
+    #[test]
+    fn inline_and_display_equations_are_found() {
+        assert_eq!(
+            split_math("Energy is $E = mc^2$ and $$\\frac{a}{b}$$ too."),
+            [
+                MathSpan::Text("Energy is "),
+                MathSpan::Math {
+                    latex: "E = mc^2",
+                    display: false
+                },
+                MathSpan::Text(" and "),
+                MathSpan::Math {
+                    latex: "\\frac{a}{b}",
+                    display: true
+                },
+                MathSpan::Text(" too."),
+            ]
+        );
+    }
+
+    #[test]
+    fn latex_renders_as_mathml() {
+        assert_eq!(
+            latex_to_mathml("\\frac{x^2}{\\alpha}", false).unwrap(),
+            format!(
+                "<math xmlns=\"{}\"><mfrac><msup><mi>x</mi><mn>2</mn></msup><mi>α</mi></mfrac></math>",
+                MATHML_NS
+            )
+        );
+        let matrix = latex_to_mathml("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}", true);
+        assert!(matrix
+            .unwrap()
+            .contains("<mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr>"));
+        assert!(latex_to_mathml("x \\leq \\text{max value}", false)
+            .unwrap()
+            .contains("<mtext>max value</mtext>"));
+    }
+
+    #[test]
+    fn unknown_latex_is_not_rendered() {
+        assert_eq!(latex_to_mathml("\\unknowncommand{x}", false), None);
+        assert_eq!(latex_to_mathml("\\frac{a}", false), None);
+    }
+
+    #[test]
+    fn mathml_converts_to_latex() {
+        let html = r#"<p><math><mfrac><mi>a</mi><mi>β</mi></mfrac></math></p>"#;
+        assert_eq!(mathml_to_latex(html), "<p>$\\frac{a}{\\beta}$</p>");
+    }
+}
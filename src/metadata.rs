@@ -0,0 +1,177 @@
+use crate::schema;
+use anyhow::Result;
+use epub::doc::{EpubDoc, MetadataItem};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// An identifier declared by the e-book (ISBN, UUID, DOI, ...)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Identifier {
+    pub scheme: Option<String>,
+    pub value: String,
+}
+
+/// Dublin Core metadata of the e-book
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BookMetadata {
+    pub title: Option<String>,
+    pub creators: Vec<String>,
+    pub contributors: Vec<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub date: Option<String>,
+    pub subjects: Vec<String>,
+    pub rights: Option<String>,
+    pub source: Option<String>,
+    pub isbn: Option<String>,
+    pub identifiers: Vec<Identifier>,
+}
+
+impl BookMetadata {
+    /// Reads every Dublin Core field from the EPUB package document
+    pub fn from_epub<R: std::io::Read + std::io::Seek>(doc: &EpubDoc<R>) -> Self {
+        let values = |property: &str| -> Vec<String> {
+            doc.metadata
+                .iter()
+                .filter(|item| item.property == property)
+                .map(|item| item.value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        let first = |property: &str| values(property).into_iter().next();
+
+        let identifiers: Vec<Identifier> = doc
+            .metadata
+            .iter()
+            .filter(|item| item.property == "identifier" && !item.value.trim().is_empty())
+            .map(|item| Identifier {
+                scheme: identifier_scheme(item),
+                value: item.value.trim().to_string(),
+            })
+            .collect();
+        let isbn = identifiers.iter().find_map(isbn_of);
+
+        BookMetadata {
+            title: first("title"),
+            creators: values("creator"),
+            contributors: values("contributor"),
+            language: first("language"),
+            publisher: first("publisher"),
+            description: first("description").map(|d| strip_tags(&d)),
+            date: first("date"),
+            subjects: values("subject"),
+            rights: first("rights"),
+            source: first("source"),
+            isbn,
+            identifiers,
+        }
+    }
+
+    /// Main author, used where a single name is expected
+    pub fn author(&self) -> Option<&str> {
+        self.creators.first().map(String::as_str)
+    }
+
+    /// Replaces `{{title}}`, `{{author}}`, `{{publisher}}`, `{{date}}`, `{{isbn}}`,
+    /// `{{subjects}}` and `{{description}}` placeholders in a prompt template
+    pub fn fill_template(&self, template: &str) -> String {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        template
+            .replace("{{title}}", &text(&self.title))
+            .replace("{{author}}", &self.creators.join(", "))
+            .replace("{{publisher}}", &text(&self.publisher))
+            .replace("{{date}}", &text(&self.date))
+            .replace("{{isbn}}", &text(&self.isbn))
+            .replace("{{subjects}}", &self.subjects.join(", "))
+            .replace("{{description}}", &text(&self.description))
+    }
+
+    /// Renders YAML frontmatter for Markdown outputs, with `tags` taken from the summary
+    pub fn frontmatter(&self, tags: &[String]) -> String {
+        let mut yaml = String::from("---\n");
+        let mut field = |key: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                yaml.push_str(&format!("{}: {}\n", key, yaml_string(value)));
+            }
+        };
+        field("title", &self.title);
+        field("author", &self.author().map(str::to_string));
+        field("language", &self.language);
+        field("publisher", &self.publisher);
+        field("date", &self.date);
+        field("isbn", &self.isbn);
+        if !self.subjects.is_empty() {
+            yaml.push_str("subjects:\n");
+            for subject in &self.subjects {
+                yaml.push_str(&format!("  - {}\n", yaml_string(subject)));
+            }
+        }
+        if !tags.is_empty() {
+            yaml.push_str("tags:\n");
+            for tag in tags {
+                yaml.push_str(&format!("  - {}\n", yaml_string(tag)));
+            }
+        }
+        yaml.push_str("---\n\n");
+        yaml
+    }
+
+    /// Writes the metadata as `metadata.json`
+    pub fn write_json(&self, output_dir: &Path) -> Result<PathBuf> {
+        let path = output_dir.join("metadata.json");
+        schema::write_json(&path, self)?;
+        Ok(path)
+    }
+}
+
+fn identifier_scheme(item: &MetadataItem) -> Option<String> {
+    // EPUB2 uses opf:scheme, EPUB3 refines the identifier with identifier-type
+    item.refinement("scheme")
+        .or_else(|| item.refinement("identifier-type"))
+        .map(|r| r.value.clone())
+        .or_else(|| {
+            let value = item.value.to_lowercase();
+            value
+                .strip_prefix("urn:")
+                .and_then(|rest| rest.split(':').next())
+                .map(str::to_string)
+        })
+}
+
+fn isbn_of(identifier: &Identifier) -> Option<String> {
+    let value = identifier.value.trim();
+    let stripped = value
+        .strip_prefix("urn:isbn:")
+        .or_else(|| value.strip_prefix("URN:ISBN:"))
+        .unwrap_or(value);
+    let digits: String = stripped
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == 'X' || *c == 'x')
+        .collect();
+    let declared_isbn = identifier
+        .scheme
+        .as_deref()
+        .is_some_and(|s| s.eq_ignore_ascii_case("isbn") || s == "15");
+    let looks_like_isbn = (digits.len() == 10 || digits.len() == 13)
+        && stripped
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '-' || c == ' ' || c == 'X' || c == 'x');
+
+    if declared_isbn || looks_like_isbn {
+        Some(digits)
+    } else {
+        None
+    }
+}
+
+fn strip_tags(text: &str) -> String {
+    html2text::from_read(text.as_bytes(), usize::MAX)
+        .map(|t| t.trim().to_string())
+        .unwrap_or_else(|_| text.to_string())
+}
+
+/// Quotes a value for a YAML block
+pub fn yaml_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use http::Extensions;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use serde_json::{json, Value};
+
+/// Words of the source a mock summary keeps
+const SUMMARY_WORDS: usize = 40;
+/// Words of a chapter's opening a chapter title written offline keeps
+const TITLE_WORDS: usize = 4;
+
+/// Answers chat-completion requests locally with deterministic JSON derived from the prompt,
+/// so the whole pipeline can run without a network or API key (`--mock-provider`)
+///
+/// Plans copy the table of contents, summaries quote the opening of their section, chapter
+/// titles are its first words and the judge gives every summary 4/5; any other prompt gets an
+/// empty object.
+pub struct MockProvider;
+
+#[async_trait]
+impl Middleware for MockProvider {
+    async fn handle(
+        &self,
+        req: Request,
+        _extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> Result<Response> {
+        respond(&req, answer)
+    }
+}
+
+/// A chat-completion response to `req` built locally, with `answer` turning the request's
+/// prompt into the assistant's JSON reply
+pub fn respond(req: &Request, answer: fn(&str) -> Value) -> Result<Response> {
+    let request: Value = req
+        .body()
+        .and_then(|body| body.as_bytes())
+        .and_then(|bytes| serde_json::from_slice(bytes).ok())
+        .unwrap_or_default();
+    let prompt: String = request["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message["content"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let content = answer(&prompt).to_string();
+
+    let body = json!({
+        "model": request["model"],
+        "choices": [{ "message": { "role": "assistant", "content": content } }],
+        "usage": {
+            "prompt_tokens": prompt.split_whitespace().count(),
+            "completion_tokens": content.split_whitespace().count(),
+            "cost": 0.0
+        }
+    });
+    let response = http::Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .map_err(anyhow::Error::from)?;
+    Ok(Response::from(response))
+}
+
+fn answer(prompt: &str) -> Value {
+    if let Some(answer) = template_answer(prompt) {
+        return answer;
+    }
+    if prompt.starts_with("You are reviewing the quality of a summary") {
+        return json!({ "coverage": 4, "faithfulness": 4, "clarity": 4, "issues": [] });
+    }
+    if let Some((_, text)) = prompt.rsplit_once("\nText:\n") {
+        let prose = section_prose(text);
+        let words: Vec<&str> = prose.split_whitespace().collect();
+        let summary = words[..words.len().min(SUMMARY_WORDS)].join(" ");
+        let mut answer = json!({
+            "summary": summary,
+            "keywords": keywords(&prose),
+            "glossary": [],
+            "references": [],
+            "additional_resources": []
+        });
+        if prompt.contains("\"key_points\"") {
+            let quote = prose
+                .split_inclusive(['.', '!', '?'])
+                .map(str::trim)
+                .find(|sentence| !sentence.is_empty())
+                .unwrap_or_default();
+            answer["key_points"] = json!([{ "point": summary, "quote": quote }]);
+        }
+        return answer;
+    }
+    json!({})
+}
+
+/// The answers written from templates without a model, shared with the extractive strategy:
+/// a summary plan copying the table of contents, and a chapter title of the chapter's first
+/// words
+pub fn template_answer(prompt: &str) -> Option<Value> {
+    if let Some((_, toc)) = prompt.rsplit_once("Table of Contents:\n") {
+        let chapters: Vec<Value> = toc
+            .lines()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .map(|title| {
+                json!({
+                    "title": title,
+                    "plan": format!("Cover the main points of {}.", title),
+                    "keywords": keywords(title)
+                })
+            })
+            .collect();
+        return Some(json!({ "chapters": chapters }));
+    }
+    if prompt.starts_with("You are titling a chapter") {
+        let text = prompt.rsplit_once("\nText:\n").unwrap_or_default().1;
+        let words: Vec<&str> = text.split_whitespace().take(TITLE_WORDS).collect();
+        return Some(json!({ "title": words.join(" ") }));
+    }
+    None
+}
+
+/// A section prompt's text without its Markdown heading, which is not part of the prose
+pub fn section_prose(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The first few distinct capitalized words, standing in for the model's keywords
+fn keywords(text: &str) -> Vec<String> {
+    let mut keywords: Vec<String> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.chars().count() > 3
+            && word.chars().next().is_some_and(char::is_uppercase)
+            && !keywords.iter().any(|keyword| keyword == word)
+        {
+            keywords.push(word.to_string());
+        }
+        if keywords.len() == 5 {
+            break;
+        }
+    }
+    keywords
+}
@@ -0,0 +1,333 @@
+use crate::images::ImageOptions;
+use crate::metadata::BookMetadata;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Pages per chapter when the scan has no folder structure to follow
+const PAGES_PER_CHAPTER: usize = 20;
+
+/// Options for reading scanned PDFs and CBZ archives with tesseract
+#[derive(Debug, Clone)]
+pub struct OcrOptions {
+    /// Scanned inputs are only read when OCR is explicitly enabled
+    pub enabled: bool,
+    /// Tesseract language codes, e.g. `eng` or `deu+fra`
+    pub language: String,
+    /// Resolution PDF pages are rendered at before recognition
+    pub dpi: u32,
+    /// Pages whose mean word confidence (0-100) is below this are flagged in the report
+    pub min_confidence: f32,
+}
+
+/// Whether the input is a scanned format that needs OCR
+pub fn is_scanned(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| e == "pdf" || e == "cbz")
+}
+
+/// One recognized page
+#[derive(Debug, Clone)]
+struct Page {
+    /// Page image name, used as the page label in the report
+    label: String,
+    /// Folder of the page inside a CBZ archive, used as its chapter
+    group: Option<String>,
+    text: String,
+    /// Mean word confidence, or `None` when no words were recognized
+    confidence: Option<f32>,
+}
+
+/// A scanned PDF or CBZ archive read page by page with tesseract
+pub struct ScannedBook {
+    metadata: BookMetadata,
+    pages: Vec<Page>,
+    chapters: Vec<(String, String)>,
+    min_confidence: f32,
+}
+
+/// Renders every page to an image and recognizes it
+///
+/// PDFs are rendered with poppler's `pdftoppm`; CBZ pages are read from the archive in name
+/// order. Needs `tesseract` (and `pdftoppm` for PDFs) on the `PATH`.
+pub fn open_scanned(path: &Path, options: &OcrOptions) -> Result<ScannedBook> {
+    if !options.enabled {
+        bail!("scanned PDFs and CBZ archives are only read with --ocr");
+    }
+
+    // Each input gets its own directory, removed on every path when it goes out of scope
+    let temp_dir = tempfile::Builder::new().prefix("aibook-ocr-").tempdir()?;
+    let work_dir = temp_dir.path();
+    let images = if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pdf"))
+    {
+        render_pdf(path, work_dir, options.dpi)?
+    } else {
+        unpack_cbz(path, work_dir)?
+    };
+    info!(
+        "Recognizing {} page(s) of '{}'",
+        images.len(),
+        path.display()
+    );
+
+    let pages: Result<Vec<Page>> = images
+        .into_iter()
+        .map(|(image, group)| {
+            let (text, confidence) = recognize(&image, &options.language)?;
+            Ok(Page {
+                label: image
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                group,
+                text,
+                confidence,
+            })
+        })
+        .collect();
+    let pages = pages?;
+
+    let low = pages
+        .iter()
+        .filter(|page| page.confidence.unwrap_or(0.0) < options.min_confidence)
+        .count();
+    if low > 0 {
+        warn!(
+            "{} of {} page(s) of '{}' were recognized with low confidence",
+            low,
+            pages.len(),
+            path.display()
+        );
+    }
+
+    Ok(ScannedBook {
+        metadata: BookMetadata {
+            title: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            ..Default::default()
+        },
+        chapters: group_chapters(&pages),
+        pages,
+        min_confidence: options.min_confidence,
+    })
+}
+
+impl EbookReader for ScannedBook {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|(title, text)| {
+            Ok(Chapter {
+                title: Some(title.clone()),
+                text: text.clone(),
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Page scans are not figures, so none are extracted
+    fn images(&mut self, _images_dir: &Path, _options: &ImageOptions) -> Result<()> {
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn is_ocr(&self) -> bool {
+        true
+    }
+
+    /// Mean word confidence of every page, with low-confidence pages flagged for review
+    fn extraction_report(&self) -> Option<String> {
+        let mut report =
+            String::from("# OCR Confidence\n\n| Page | Confidence | |\n|---|---|---|\n");
+        for page in &self.pages {
+            let (confidence, flag) = match page.confidence {
+                Some(c) if c < self.min_confidence => (format!("{:.0}%", c), "⚠ check"),
+                Some(c) => (format!("{:.0}%", c), ""),
+                None => ("-".to_string(), "⚠ no text"),
+            };
+            report.push_str(&format!("| {} | {} | {} |\n", page.label, confidence, flag));
+        }
+        let scored: Vec<f32> = self.pages.iter().filter_map(|p| p.confidence).collect();
+        if !scored.is_empty() {
+            report.push_str(&format!(
+                "\nAverage confidence: {:.0}%\n",
+                scored.iter().sum::<f32>() / scored.len() as f32
+            ));
+        }
+        Some(report)
+    }
+}
+
+fn render_pdf(path: &Path, work_dir: &Path, dpi: u32) -> Result<Vec<(PathBuf, Option<String>)>> {
+    let status = Command::new("pdftoppm")
+        .args(["-r", &dpi.to_string(), "-png"])
+        .arg(path)
+        .arg(work_dir.join("page"))
+        .status()
+        .context("pdftoppm (poppler) is required to OCR PDF files")?;
+    if !status.success() {
+        bail!("pdftoppm could not render '{}'", path.display());
+    }
+
+    // pdftoppm zero-pads page numbers, so name order is page order
+    let mut images: Vec<PathBuf> = fs::read_dir(work_dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|image| image.extension().is_some_and(|e| e == "png"))
+        .collect();
+    images.sort();
+    Ok(images.into_iter().map(|image| (image, None)).collect())
+}
+
+/// Extracts the page images of a comic archive, remembering each page's folder
+fn unpack_cbz(path: &Path, work_dir: &Path) -> Result<Vec<(PathBuf, Option<String>)>> {
+    let file = File::open(path).context("could not open file")?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))
+        .map_err(|e| anyhow!("corrupted CBZ, the file is not a valid ZIP archive ({})", e))?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            let name = name.to_lowercase();
+            [".png", ".jpg", ".jpeg", ".gif", ".webp", ".tif", ".tiff"]
+                .iter()
+                .any(|extension| name.ends_with(extension))
+        })
+        .map(str::to_string)
+        .collect();
+    names.sort();
+
+    let mut images = Vec::new();
+    for (index, name) in names.iter().enumerate() {
+        let mut data = Vec::new();
+        archive.by_name(name)?.read_to_end(&mut data)?;
+        let extension = name.rsplit('.').next().unwrap_or("png");
+        let image = work_dir.join(format!("{:05}.{}", index + 1, extension));
+        fs::write(&image, data)?;
+        let group = name.rsplit_once('/').map(|(folder, _)| folder.to_string());
+        images.push((image, group));
+    }
+    Ok(images)
+}
+
+/// Runs tesseract on one page, returning its text and mean word confidence
+fn recognize(image: &Path, language: &str) -> Result<(String, Option<f32>)> {
+    let output = Command::new("tesseract")
+        .arg(image)
+        .args(["stdout", "-l", language, "tsv"])
+        .output()
+        .context("tesseract is required for --ocr")?;
+    if !output.status.success() {
+        bail!(
+            "tesseract failed on '{}': {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_tsv(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Rebuilds paragraphs from tesseract's TSV output, one row per recognized word
+///
+/// Columns: level, page, block, paragraph, line, word, left, top, width, height, conf, text.
+fn parse_tsv(tsv: &str) -> (String, Option<f32>) {
+    let mut paragraphs: BTreeMap<(u32, u32), BTreeMap<u32, Vec<String>>> = BTreeMap::new();
+    let mut confidences = Vec::new();
+
+    for row in tsv.lines().skip(1) {
+        let columns: Vec<&str> = row.splitn(12, '\t').collect();
+        let [level, _, block, paragraph, line, _, _, _, _, _, conf, text] = columns[..] else {
+            continue;
+        };
+        if level != "5" || text.trim().is_empty() {
+            continue;
+        }
+        let number = |value: &str| value.parse::<u32>().unwrap_or_default();
+        if let Ok(conf) = conf.parse::<f32>() {
+            if conf >= 0.0 {
+                confidences.push(conf);
+            }
+        }
+        paragraphs
+            .entry((number(block), number(paragraph)))
+            .or_default()
+            .entry(number(line))
+            .or_default()
+            .push(text.trim().to_string());
+    }
+
+    let text = paragraphs
+        .values()
+        .map(|lines| {
+            lines
+                .values()
+                .map(|words| words.join(" "))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let confidence = (!confidences.is_empty())
+        .then(|| confidences.iter().sum::<f32>() / confidences.len() as f32);
+    (text, confidence)
+}
+
+/// Chapters from the archive's folders, or fixed runs of pages
+fn group_chapters(pages: &[Page]) -> Vec<(String, String)> {
+    let folders: Vec<&Option<String>> = pages.iter().map(|page| &page.group).collect();
+    let by_folder = folders.iter().any(|folder| folder.is_some())
+        && folders.windows(2).any(|pair| pair[0] != pair[1]);
+
+    let mut chapters: Vec<(String, String)> = Vec::new();
+    if by_folder {
+        let mut current: Option<&Option<String>> = None;
+        for page in pages {
+            if current != Some(&page.group) {
+                let title = page
+                    .group
+                    .as_deref()
+                    .and_then(|folder| folder.rsplit('/').next())
+                    .unwrap_or("Pages")
+                    .to_string();
+                chapters.push((title, String::new()));
+                current = Some(&page.group);
+            }
+            let (_, text) = chapters.last_mut().unwrap();
+            text.push_str(&page.text);
+            text.push_str("\n\n");
+        }
+    } else {
+        for (index, chunk) in pages.chunks(PAGES_PER_CHAPTER).enumerate() {
+            let first = index * PAGES_PER_CHAPTER + 1;
+            let title = format!("Pages {}–{}", first, first + chunk.len() - 1);
+            let text = chunk
+                .iter()
+                .map(|page| page.text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            chapters.push((title, text));
+        }
+    }
+    chapters.retain(|(_, text)| !text.trim().is_empty());
+    chapters
+}
@@ -0,0 +1,669 @@
+use crate::ebook::html_to_text;
+use crate::extract::{self, ExtractionOptions};
+use crate::images::{self, ImageMode, ImageOptions};
+use crate::metadata::BookMetadata;
+use crate::paths::safe_file_name;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use regex::Regex;
+use roxmltree::{Document, Node};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// A DOCX or ODT manuscript
+///
+/// The body is translated to XHTML with headings taken from the paragraph styles, then split into
+/// chapters at the highest heading level used, so a manuscript with "Heading 1" chapters reads
+/// like a book. Embedded images are extracted like an EPUB's.
+pub struct OfficeDocument {
+    path: PathBuf,
+    metadata: BookMetadata,
+    chapters: Vec<(Option<String>, String)>,
+    /// Notes of the single document, so their paths are empty
+    notes: extract::Notes,
+    /// Archive paths of the embedded images
+    images: Vec<String>,
+    referenced_images: HashSet<String>,
+    options: ExtractionOptions,
+}
+
+/// What a converter collects besides the XHTML
+#[derive(Default)]
+struct Converted {
+    html: String,
+    notes: HashMap<String, String>,
+    images: HashSet<String>,
+}
+
+pub fn open_docx(path: &Path, options: &ExtractionOptions) -> Result<OfficeDocument> {
+    let mut archive = open_archive(path)?;
+    let document = read_entry(&mut archive, "word/document.xml")?
+        .ok_or_else(|| anyhow!("malformed DOCX, word/document.xml is missing"))?;
+    let styles = read_entry(&mut archive, "word/styles.xml")?.unwrap_or_default();
+    let relationships =
+        read_entry(&mut archive, "word/_rels/document.xml.rels")?.unwrap_or_default();
+    let footnotes = read_entry(&mut archive, "word/footnotes.xml")?.unwrap_or_default();
+    let endnotes = read_entry(&mut archive, "word/endnotes.xml")?.unwrap_or_default();
+    let core = read_entry(&mut archive, "docProps/core.xml")?.unwrap_or_default();
+
+    let converted = docx::convert(&document, &styles, &relationships, &footnotes, &endnotes)?;
+    finish(path, options, converted, core_metadata(&core), &archive)
+}
+
+pub fn open_odt(path: &Path, options: &ExtractionOptions) -> Result<OfficeDocument> {
+    let mut archive = open_archive(path)?;
+    let content = read_entry(&mut archive, "content.xml")?
+        .ok_or_else(|| anyhow!("malformed ODT, content.xml is missing"))?;
+    let meta = read_entry(&mut archive, "meta.xml")?.unwrap_or_default();
+
+    let converted = odt::convert(&content)?;
+    finish(path, options, converted, core_metadata(&meta), &archive)
+}
+
+fn finish(
+    path: &Path,
+    options: &ExtractionOptions,
+    converted: Converted,
+    mut metadata: BookMetadata,
+    archive: &zip::ZipArchive<BufReader<File>>,
+) -> Result<OfficeDocument> {
+    let chapters = split_chapters(&converted.html);
+    info!("Total chapters: {}", chapters.len());
+    if metadata.title.is_none() {
+        metadata.title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string());
+    }
+
+    Ok(OfficeDocument {
+        path: path.to_path_buf(),
+        metadata,
+        chapters,
+        notes: converted
+            .notes
+            .into_iter()
+            .map(|(id, text)| ((PathBuf::new(), id), text))
+            .collect(),
+        images: archive
+            .file_names()
+            .filter(|name| mime_type(name).is_some())
+            .map(str::to_string)
+            .collect(),
+        referenced_images: converted.images,
+        options: options.clone(),
+    })
+}
+
+impl EbookReader for OfficeDocument {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|(title, html)| {
+            Ok(Chapter {
+                title: title.clone(),
+                text: html_to_text(html, Path::new(""), &self.options, &self.notes)?,
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .filter_map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    fn images(&mut self, images_dir: &Path, options: &ImageOptions) -> Result<()> {
+        if options.mode == ImageMode::Skip {
+            return Ok(());
+        }
+        let mut archive = open_archive(&self.path)?;
+        for name in &self.images {
+            if options.mode == ImageMode::Referenced && !self.referenced_images.contains(name) {
+                continue;
+            }
+            let Some(mime) = mime_type(name) else {
+                continue;
+            };
+            let mut data = Vec::new();
+            archive.by_name(name)?.read_to_end(&mut data)?;
+            let Some(data) = images::optimize(data, mime, options) else {
+                continue;
+            };
+            let filename = format!("{}.{}", safe_file_name(name), images::extension(mime));
+            fs::write(images_dir.join(filename), data)?;
+        }
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+}
+
+fn open_archive(path: &Path) -> Result<zip::ZipArchive<BufReader<File>>> {
+    let file = File::open(path).context("could not open file")?;
+    zip::ZipArchive::new(BufReader::new(file)).map_err(|e| {
+        anyhow!(
+            "corrupted document, the file is not a valid ZIP container ({})",
+            e
+        )
+    })
+}
+
+fn read_entry(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    name: &str,
+) -> Result<Option<String>> {
+    let Ok(mut entry) = archive.by_name(name) else {
+        return Ok(None);
+    };
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .with_context(|| format!("{} is unreadable", name))?;
+    Ok(Some(content))
+}
+
+fn mime_type(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit('.').next()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+/// Title, author, language, description and date from `docProps/core.xml` or `meta.xml`
+fn core_metadata(xml: &str) -> BookMetadata {
+    let Ok(document) = Document::parse(xml) else {
+        return BookMetadata::default();
+    };
+    let field = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            document
+                .descendants()
+                .find(|n| n.tag_name().name() == *name)
+                .and_then(|n| n.text())
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+        })
+    };
+
+    BookMetadata {
+        title: field(&["title"]),
+        creators: field(&["creator", "initial-creator"]).into_iter().collect(),
+        language: field(&["language"]),
+        description: field(&["description", "subject"]),
+        date: field(&["created", "creation-date"]),
+        subjects: field(&["keywords", "keyword"])
+            .map(|keywords| {
+                keywords
+                    .split([',', ';'])
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+/// Splits the document at its highest heading level; text before the first heading is kept as
+/// an untitled chapter
+fn split_chapters(html: &str) -> Vec<(Option<String>, String)> {
+    let heading = Regex::new(r"(?s)<h([1-6])>(.*?)</h[1-6]>").unwrap();
+    let tags = Regex::new(r"<[^>]*>").unwrap();
+    let Some(top) = heading
+        .captures_iter(html)
+        .filter_map(|caps| caps[1].parse::<u8>().ok())
+        .min()
+    else {
+        return vec![(None, wrap_body(html))];
+    };
+
+    let mut chapters = Vec::new();
+    let mut start = 0;
+    let mut title = None;
+    for caps in heading.captures_iter(html) {
+        if caps[1].parse::<u8>().ok() != Some(top) {
+            continue;
+        }
+        let at = caps.get(0).unwrap().start();
+        let part = &html[start..at];
+        if !tags.replace_all(part, "").trim().is_empty() {
+            chapters.push((title.take(), wrap_body(part)));
+        }
+        let text = tags.replace_all(&caps[2], " ");
+        title = Some(text.split_whitespace().collect::<Vec<_>>().join(" "));
+        start = at;
+    }
+    chapters.push((title, wrap_body(&html[start..])));
+    chapters
+}
+
+fn wrap_body(html: &str) -> String {
+    format!("<html><body>{}</body></html>", html)
+}
+
+/// Value of an attribute by its local name, whatever its namespace prefix
+fn attr<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|attribute| attribute.name() == name)
+        .map(|attribute| attribute.value())
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+/// Word processing markup (`word/document.xml`)
+mod docx {
+    use super::{attr, child, Converted};
+    use crate::text::escape_html;
+    use anyhow::{anyhow, Result};
+    use roxmltree::{Document, Node};
+    use std::collections::HashMap;
+
+    pub fn convert(
+        document: &str,
+        styles: &str,
+        relationships: &str,
+        footnotes: &str,
+        endnotes: &str,
+    ) -> Result<Converted> {
+        let document =
+            Document::parse(document).map_err(|e| anyhow!("malformed DOCX document: {}", e))?;
+        let body = document
+            .descendants()
+            .find(|n| n.tag_name().name() == "body")
+            .ok_or_else(|| anyhow!("malformed DOCX, the document has no body"))?;
+
+        let mut converter = Converter {
+            heading_levels: heading_levels(styles),
+            targets: targets(relationships),
+            out: Converted::default(),
+        };
+        converter.out.notes = notes(footnotes, "footnote");
+        converter.out.notes.extend(notes(endnotes, "endnote"));
+        converter.blocks(body);
+        Ok(converter.out)
+    }
+
+    struct Converter {
+        /// Heading level of each paragraph style id
+        heading_levels: HashMap<String, u8>,
+        /// Relationship ids of the document and their targets (images, hyperlinks)
+        targets: HashMap<String, String>,
+        out: Converted,
+    }
+
+    impl Converter {
+        fn blocks(&mut self, parent: Node) {
+            let mut in_list = false;
+            for node in parent.children().filter(Node::is_element) {
+                let is_list_item = node.tag_name().name() == "p"
+                    && child(node, "pPr").and_then(|p| child(p, "numPr")).is_some();
+                if in_list && !is_list_item {
+                    self.out.html.push_str("</ul>");
+                    in_list = false;
+                }
+                match node.tag_name().name() {
+                    "p" if is_list_item => {
+                        if !in_list {
+                            self.out.html.push_str("<ul>");
+                            in_list = true;
+                        }
+                        self.out.html.push_str("<li>");
+                        self.runs(node);
+                        self.out.html.push_str("</li>");
+                    }
+                    "p" => self.paragraph(node),
+                    "tbl" => self.table(node),
+                    "sdt" | "sdtContent" => self.blocks(node),
+                    _ => (),
+                }
+            }
+            if in_list {
+                self.out.html.push_str("</ul>");
+            }
+        }
+
+        fn paragraph(&mut self, node: Node) {
+            let properties = child(node, "pPr");
+            let level = properties
+                .and_then(|p| child(p, "outlineLvl"))
+                .and_then(|l| attr(l, "val")?.parse::<u8>().ok())
+                .map(|level| level + 1)
+                .or_else(|| {
+                    let style = attr(child(properties?, "pStyle")?, "val")?;
+                    self.heading_levels.get(style).copied()
+                })
+                .filter(|level| *level <= 6);
+
+            let tag = match level {
+                Some(level) => format!("h{}", level),
+                None => "p".to_string(),
+            };
+            self.out.html.push_str(&format!("<{}>", tag));
+            self.runs(node);
+            self.out.html.push_str(&format!("</{}>", tag));
+        }
+
+        fn runs(&mut self, parent: Node) {
+            for node in parent.children().filter(Node::is_element) {
+                match node.tag_name().name() {
+                    "r" => self.run(node),
+                    "hyperlink" | "ins" | "smartTag" | "fldSimple" => self.runs(node),
+                    _ => (),
+                }
+            }
+        }
+
+        fn run(&mut self, node: Node) {
+            let properties = child(node, "rPr");
+            let on = |name: &str| {
+                properties
+                    .and_then(|p| child(p, name))
+                    .is_some_and(|flag| !matches!(attr(flag, "val"), Some("0" | "false")))
+            };
+            let (bold, italic) = (on("b"), on("i"));
+            if bold {
+                self.out.html.push_str("<strong>");
+            }
+            if italic {
+                self.out.html.push_str("<em>");
+            }
+            for part in node.children().filter(Node::is_element) {
+                match part.tag_name().name() {
+                    "t" => self
+                        .out
+                        .html
+                        .push_str(&escape_html(part.text().unwrap_or_default())),
+                    "tab" => self.out.html.push(' '),
+                    "br" | "cr" => self.out.html.push_str("<br/>"),
+                    name @ ("footnoteReference" | "endnoteReference") => {
+                        let kind = name.trim_end_matches("Reference");
+                        if let Some(id) = attr(part, "id") {
+                            self.out.html.push_str(&format!(
+                                r##"<a role="doc-noteref" href="#{}-{}">*</a>"##,
+                                kind,
+                                escape_html(id)
+                            ));
+                        }
+                    }
+                    "drawing" | "pict" => self.image(part),
+                    _ => (),
+                }
+            }
+            if italic {
+                self.out.html.push_str("</em>");
+            }
+            if bold {
+                self.out.html.push_str("</strong>");
+            }
+        }
+
+        /// `<a:blip r:embed="rId5">` with the picture's description as alt text
+        fn image(&mut self, drawing: Node) {
+            let Some(target) = drawing
+                .descendants()
+                .find_map(|n| match n.tag_name().name() {
+                    "blip" => attr(n, "embed"),
+                    "imagedata" => attr(n, "id"),
+                    _ => None,
+                })
+                .and_then(|id| self.targets.get(id))
+            else {
+                return;
+            };
+            let path = format!("word/{}", target.trim_start_matches('/'));
+            let alt = drawing
+                .descendants()
+                .find(|n| n.tag_name().name() == "docPr")
+                .and_then(|n| attr(n, "descr").or_else(|| attr(n, "title")))
+                .unwrap_or_default();
+            self.out.html.push_str(&format!(
+                r#"<img src="{}" alt="{}"/>"#,
+                escape_html(&path),
+                escape_html(alt)
+            ));
+            self.out.images.insert(path);
+        }
+
+        fn table(&mut self, table: Node) {
+            self.out.html.push_str("<table>");
+            for row in table.children().filter(|n| n.tag_name().name() == "tr") {
+                self.out.html.push_str("<tr>");
+                for cell in row.children().filter(|n| n.tag_name().name() == "tc") {
+                    self.out.html.push_str("<td>");
+                    for paragraph in cell.children().filter(|n| n.tag_name().name() == "p") {
+                        self.runs(paragraph);
+                        self.out.html.push(' ');
+                    }
+                    self.out.html.push_str("</td>");
+                }
+                self.out.html.push_str("</tr>");
+            }
+            self.out.html.push_str("</table>");
+        }
+    }
+
+    /// Heading level of every paragraph style, from its outline level or a "heading N" name
+    fn heading_levels(styles: &str) -> HashMap<String, u8> {
+        let Ok(document) = Document::parse(styles) else {
+            return HashMap::new();
+        };
+        document
+            .descendants()
+            .filter(|n| n.tag_name().name() == "style")
+            .filter_map(|style| {
+                let id = attr(style, "styleId")?;
+                let outline = child(style, "pPr")
+                    .and_then(|p| child(p, "outlineLvl"))
+                    .and_then(|l| attr(l, "val")?.parse::<u8>().ok())
+                    .map(|level| level + 1);
+                let named = child(style, "name")
+                    .and_then(|n| attr(n, "val"))
+                    .and_then(|name| name.to_lowercase().strip_prefix("heading ")?.parse().ok());
+                Some((id.to_string(), outline.or(named)?))
+            })
+            .collect()
+    }
+
+    fn targets(relationships: &str) -> HashMap<String, String> {
+        let Ok(document) = Document::parse(relationships) else {
+            return HashMap::new();
+        };
+        document
+            .descendants()
+            .filter(|n| n.tag_name().name() == "Relationship")
+            .filter_map(|r| Some((attr(r, "Id")?.to_string(), attr(r, "Target")?.to_string())))
+            .collect()
+    }
+
+    /// Text of every `footnote` or `endnote` element of `footnotes.xml` or `endnotes.xml`,
+    /// keyed as `footnote-{id}` or `endnote-{id}` like the references to them
+    fn notes(xml: &str, kind: &str) -> HashMap<String, String> {
+        let Ok(document) = Document::parse(xml) else {
+            return HashMap::new();
+        };
+        document
+            .descendants()
+            .filter(|n| n.tag_name().name() == kind)
+            .filter_map(|note| {
+                let text: String = note
+                    .descendants()
+                    .filter(|n| n.tag_name().name() == "t")
+                    .filter_map(|n| n.text())
+                    .collect();
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                let id = attr(note, "id")?;
+                (!text.is_empty()).then(|| (format!("{}-{}", kind, id), text))
+            })
+            .collect()
+    }
+}
+
+/// OpenDocument text markup (`content.xml`)
+mod odt {
+    use super::{attr, Converted};
+    use crate::text::escape_html;
+    use anyhow::{anyhow, Result};
+    use roxmltree::{Document, Node};
+
+    pub fn convert(content: &str) -> Result<Converted> {
+        let document =
+            Document::parse(content).map_err(|e| anyhow!("malformed ODT content: {}", e))?;
+        let text = document
+            .descendants()
+            .find(|n| {
+                n.tag_name().name() == "text"
+                    && n.parent_element()
+                        .is_some_and(|p| p.tag_name().name() == "body")
+            })
+            .ok_or_else(|| anyhow!("malformed ODT, the document has no text body"))?;
+
+        let mut out = Converted::default();
+        inline(text, &mut out);
+        Ok(out)
+    }
+
+    fn wrap(node: Node, tag: &str, out: &mut Converted) {
+        out.html.push_str(&format!("<{}>", tag));
+        inline(node, out);
+        out.html.push_str(&format!("</{}>", tag));
+    }
+
+    fn inline(parent: Node, out: &mut Converted) {
+        for node in parent.children() {
+            if node.is_text() {
+                out.html
+                    .push_str(&escape_html(node.text().unwrap_or_default()));
+                continue;
+            }
+            match node.tag_name().name() {
+                "h" => {
+                    let level = attr(node, "outline-level")
+                        .and_then(|l| l.parse::<u8>().ok())
+                        .unwrap_or(1)
+                        .clamp(1, 6);
+                    wrap(node, &format!("h{}", level), out);
+                }
+                "p" => wrap(node, "p", out),
+                "list" => wrap(node, "ul", out),
+                "list-item" => wrap(node, "li", out),
+                "table" => wrap(node, "table", out),
+                "table-row" => wrap(node, "tr", out),
+                "table-cell" => wrap(node, "td", out),
+                "s" | "tab" => out.html.push(' '),
+                "line-break" => out.html.push_str("<br/>"),
+                "note" => {
+                    let Some(id) = attr(node, "id") else {
+                        continue;
+                    };
+                    let body = node.children().find(|n| n.tag_name().name() == "note-body");
+                    let text: String = body
+                        .into_iter()
+                        .flat_map(|b| b.descendants())
+                        .filter(|n| n.is_text())
+                        .filter_map(|n| n.text())
+                        .collect();
+                    out.notes.insert(
+                        id.to_string(),
+                        text.split_whitespace().collect::<Vec<_>>().join(" "),
+                    );
+                    out.html.push_str(&format!(
+                        r##"<a role="doc-noteref" href="#{}">*</a>"##,
+                        escape_html(id)
+                    ));
+                }
+                "frame" => {
+                    let alt = node
+                        .children()
+                        .find(|n| matches!(n.tag_name().name(), "desc" | "title"))
+                        .and_then(|n| n.text())
+                        .unwrap_or_default();
+                    for image in node.children().filter(|n| n.tag_name().name() == "image") {
+                        if let Some(href) = attr(image, "href") {
+                            out.html.push_str(&format!(
+                                r#"<img src="{}" alt="{}"/>"#,
+                                escape_html(href),
+                                escape_html(alt)
+                            ));
+                            out.images.insert(href.to_string());
+                        }
+                    }
+                }
+                // Change-tracking deletions, annotations and table column definitions are not text
+                "tracked-changes" | "annotation" | "table-columns" | "table-column" => (),
+                _ => inline(node, out),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main""#;
+
+    #[test]
+    fn docx_headings_lists_and_notes() {
+        let document = format!(
+            r#"<w:document {W}><w:body>
+                <w:p><w:pPr><w:pStyle w:val="Title1"/></w:pPr><w:r><w:t>The Harvest</w:t></w:r></w:p>
+                <w:p><w:r><w:rPr><w:b/></w:rPr><w:t>Rain</w:t></w:r><w:r><w:t> came late.</w:t></w:r>
+                    <w:r><w:footnoteReference w:id="1"/></w:r><w:r><w:endnoteReference w:id="1"/></w:r></w:p>
+                <w:p><w:pPr><w:numPr/></w:pPr><w:r><w:t>Wheat &amp; rye</w:t></w:r></w:p>
+                <w:p><w:pPr><w:outlineLvl w:val="0"/></w:pPr><w:r><w:t>Winter</w:t></w:r></w:p>
+            </w:body></w:document>"#
+        );
+        let styles = format!(
+            r#"<w:styles {W}><w:style w:styleId="Title1"><w:name w:val="heading 1"/></w:style></w:styles>"#
+        );
+        let footnotes = format!(
+            r#"<w:footnotes {W}><w:footnote w:id="1"><w:p><w:r><w:t>A footnote.</w:t></w:r></w:p></w:footnote></w:footnotes>"#
+        );
+        let endnotes = format!(
+            r#"<w:endnotes {W}><w:endnote w:id="1"><w:p><w:r><w:t>An endnote.</w:t></w:r></w:p></w:endnote></w:endnotes>"#
+        );
+
+        let converted = docx::convert(&document, &styles, "", &footnotes, &endnotes).unwrap();
+        assert_eq!(
+            converted.html,
+            concat!(
+                "<h1>The Harvest</h1>",
+                "<p><strong>Rain</strong> came late.",
+                r##"<a role="doc-noteref" href="#footnote-1">*</a>"##,
+                r##"<a role="doc-noteref" href="#endnote-1">*</a></p>"##,
+                "<ul><li>Wheat &amp; rye</li></ul>",
+                "<h1>Winter</h1>"
+            )
+        );
+        assert_eq!(converted.notes["footnote-1"], "A footnote.");
+        assert_eq!(converted.notes["endnote-1"], "An endnote.");
+    }
+
+    #[test]
+    fn chapters_split_at_the_top_heading_level() {
+        let chapters = split_chapters(
+            "<p>Foreword</p><h2>One</h2><p>A</p><h3>One.1</h3><p>B</p><h2>Two <em>too</em></h2><p>C</p>",
+        );
+        let titles: Vec<Option<&str>> =
+            chapters.iter().map(|(title, _)| title.as_deref()).collect();
+        assert_eq!(titles, [None, Some("One"), Some("Two too")]);
+        assert!(chapters[1].1.contains("<h3>One.1</h3>"));
+        assert_eq!(split_chapters("<p>Only text</p>").len(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::output::{self, ChapterSummary};
+use crate::summarizer::Summarizer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single-page executive brief reduced from the full summary
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnePager {
+    pub thesis: String,
+    pub key_insights: Vec<String>,
+    pub action_items: Vec<String>,
+    pub audience: String,
+}
+
+/// Requests the brief for the whole book
+pub async fn generate(summarizer: &Summarizer, chapters: &[ChapterSummary]) -> Result<OnePager> {
+    let value = summarizer
+        .generate_one_pager(&output::full_summary(chapters))
+        .await?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Writes `one_pager.md`, returning its path
+pub fn write_one_pager(
+    output_dir: &Path,
+    title: &str,
+    author: Option<&str>,
+    brief: &OnePager,
+) -> Result<PathBuf> {
+    let mut doc = format!("# {}: One-Page Brief\n\n", title);
+    if let Some(author) = author {
+        doc.push_str(&format!("*{}*\n\n", author));
+    }
+    doc.push_str(&format!("## Thesis\n\n{}\n\n", brief.thesis));
+    doc.push_str("## Key Insights\n\n");
+    for (index, insight) in brief.key_insights.iter().enumerate() {
+        doc.push_str(&format!("{}. {}\n", index + 1, insight));
+    }
+    doc.push_str("\n## Action Items\n\n");
+    for item in &brief.action_items {
+        doc.push_str(&format!("- [ ] {}\n", item));
+    }
+    doc.push_str(&format!("\n## Who Should Read It\n\n{}\n", brief.audience));
+
+    let path = output_dir.join("one_pager.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
@@ -0,0 +1,638 @@
+use crate::epub_writer::{self, Edition};
+use crate::grounding::{self, KeyPoint};
+use crate::keywords;
+use crate::language;
+use crate::layers::SummaryLayer;
+use crate::markdown;
+use crate::math::{self, MathSpan};
+use crate::metadata::BookMetadata;
+use crate::sentiment::{self, ChapterMood};
+use crate::stats::ReadingStats;
+use crate::text::escape_html;
+use crate::vocabulary::VocabularyEntry;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Summary of a single chapter, merged from the summaries of its sections
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChapterSummary {
+    pub title: String,
+    pub summary: String,
+    /// Claims with their supporting quotes, when the summary was grounded
+    pub key_points: Vec<KeyPoint>,
+    pub keywords: Vec<String>,
+    pub glossary: Vec<String>,
+    pub references: Vec<String>,
+    pub additional_resources: Vec<String>,
+    pub vocabulary: Vec<VocabularyEntry>,
+    /// Passages of the chapter the reader highlighted, with their notes
+    pub highlights: Vec<String>,
+    /// Summaries of increasing depth shown instead of `summary`, when layers were requested
+    pub layers: Vec<SummaryLayer>,
+    /// Tone, sentiment and themes, when the chapter was analyzed
+    pub mood: Option<ChapterMood>,
+    /// Images of the source chapter that were extracted, shown after the summary
+    pub figures: Vec<Figure>,
+    /// Extracted text of the source chapter, kept only for the study edition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    /// 1-based numbers of the sections the model refused to summarize, shown as a notice
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub refused_sections: Vec<usize>,
+    /// Why the chapter could not be summarized, when `--on-chapter-error` skipped it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+/// A source book image in the `images/` directory of the output, with its alt text
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Figure {
+    pub file: String,
+    pub alt: String,
+}
+
+impl Figure {
+    /// Link to the image relative to the summary document, usable in Markdown, HTML and EPUB
+    pub fn href(&self) -> String {
+        format!(
+            "images/{}",
+            self.file
+                .replace('%', "%25")
+                .replace(' ', "%20")
+                .replace('#', "%23")
+                .replace('?', "%3F")
+                .replace('(', "%28")
+                .replace(')', "%29")
+        )
+    }
+}
+
+impl ChapterSummary {
+    /// Merges the JSON responses of every section of a chapter
+    pub fn from_sections(title: String, sections: &[Value]) -> Self {
+        let mut chapter = ChapterSummary {
+            title,
+            ..Default::default()
+        };
+        let mut paragraphs = Vec::new();
+
+        for (index, section) in sections.iter().enumerate() {
+            if section.get("refused").is_some() {
+                chapter.refused_sections.push(index + 1);
+            }
+            if let Some(summary) = section.get("summary").and_then(Value::as_str) {
+                paragraphs.push(summary.trim().to_string());
+            }
+            chapter
+                .key_points
+                .extend(grounding::parse_key_points(section));
+            extend_unique(&mut chapter.keywords, section.get("keywords"));
+            extend_unique(&mut chapter.glossary, section.get("glossary"));
+            extend_unique(&mut chapter.references, section.get("references"));
+            extend_unique(
+                &mut chapter.additional_resources,
+                section.get("additional_resources"),
+            );
+        }
+
+        chapter.summary = paragraphs.join("\n\n");
+        chapter
+    }
+
+    /// A chapter left out after its summary failed, whose text says so in place of a summary
+    pub fn skipped(title: String, reason: &str) -> Self {
+        let reason = reason.lines().next().unwrap_or_default();
+        ChapterSummary {
+            title,
+            summary: "*This chapter could not be summarized.*".to_string(),
+            skipped: Some(reason.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+fn extend_unique(target: &mut Vec<String>, values: Option<&Value>) {
+    let Some(Value::Array(items)) = values else {
+        return;
+    };
+    for item in items {
+        let text = match item {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if !target.contains(&text) {
+            target.push(text);
+        }
+    }
+}
+
+/// Joins every chapter summary under its title, used as input for book-level stages
+///
+/// Skipped chapters are left out, so book-level stages do not treat their notice as content.
+pub fn full_summary(chapters: &[ChapterSummary]) -> String {
+    chapters
+        .iter()
+        .filter(|c| c.skipped.is_none())
+        .map(|c| format!("## {}\n\n{}", c.title, c.summary))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Most frequent chapter keywords listed as `tags` in the Markdown frontmatter
+pub const FRONTMATTER_TAGS: usize = 10;
+/// Private-use characters marking highlighted keywords until the HTML is escaped
+const MARK_OPEN: &str = "\u{e000}";
+const MARK_CLOSE: &str = "\u{e001}";
+
+/// Settings of the assembled summary document
+#[derive(Debug, Clone, Default)]
+pub struct DocumentOptions {
+    /// Language the summary is written in, declared in EPUB output
+    pub language: String,
+    /// Emphasize the first mention of each chapter keyword in its summary
+    pub highlight_keywords: bool,
+    /// Append an index of keywords and the chapters that cover them
+    pub index: bool,
+    /// CSS inlined in HTML output and packaged with EPUB output
+    pub stylesheet: String,
+    /// File name of the font in `fonts/` of the output directory that the stylesheet embeds
+    pub font: Option<String>,
+    /// In the study edition, follow each summary with the passages its key points quote instead
+    /// of the whole chapter
+    pub study_excerpts: bool,
+    /// Compression and size settings of EPUB output
+    pub package: epub_writer::PackageOptions,
+}
+
+/// Writes the assembled summary document and returns its path
+pub fn write_document(
+    output_dir: &Path,
+    output_format: &str,
+    metadata: &BookMetadata,
+    stats: &ReadingStats,
+    chapters: &[ChapterSummary],
+    cover: Option<&str>,
+    options: &DocumentOptions,
+) -> Result<PathBuf> {
+    let (content, extension) = match output_format {
+        "epub" | "study-epub" => {
+            let edition = if output_format == "epub" {
+                Edition::Summary
+            } else {
+                Edition::Study
+            };
+            return epub_writer::write_epub(
+                output_dir, metadata, stats, chapters, cover, options, edition,
+            );
+        }
+        "html" => (
+            render_html(metadata, stats, chapters, cover, options),
+            "html",
+        ),
+        _ => (
+            render_markdown(metadata, stats, chapters, cover, options),
+            "md",
+        ),
+    };
+    let path = output_dir.join(format!("summary.{}", extension));
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+pub fn document_title(metadata: &BookMetadata) -> String {
+    metadata
+        .title
+        .clone()
+        .unwrap_or_else(|| "Summary".to_string())
+}
+
+/// The summary as normalized Markdown with YAML frontmatter
+pub fn render_markdown(
+    metadata: &BookMetadata,
+    stats: &ReadingStats,
+    chapters: &[ChapterSummary],
+    cover: Option<&str>,
+    options: &DocumentOptions,
+) -> String {
+    let mut doc = metadata.frontmatter(&keywords::book_tags(chapters, FRONTMATTER_TAGS));
+    if let Some(cover) = cover {
+        doc.push_str(&format!("![Cover]({})\n\n", cover));
+    }
+    doc.push_str(&format!("# {}\n\n", document_title(metadata)));
+    if let Some(author) = metadata.author() {
+        doc.push_str(&format!("*{}*\n\n", author));
+    }
+    doc.push_str(&format!("> {}\n\n", stats.summary_line()));
+
+    for chapter in chapters {
+        // Headings the model put in a summary stay below the heading it is placed under
+        let emphasize = |text: &str, min_level: usize| {
+            let text = markdown::demote_headings(text, min_level);
+            if options.highlight_keywords {
+                keywords::highlight(&text, &chapter.keywords, "**", "**")
+            } else {
+                text
+            }
+        };
+        if chapter.layers.is_empty() {
+            doc.push_str(&format!(
+                "## {}\n\n{}\n\n",
+                chapter.title,
+                emphasize(&chapter.summary, 3)
+            ));
+        } else {
+            doc.push_str(&format!("## {}\n\n", chapter.title));
+            for layer in &chapter.layers {
+                doc.push_str(&format!(
+                    "### {}\n\n{}\n\n",
+                    layer.label,
+                    emphasize(&layer.text, 4)
+                ));
+            }
+        }
+        if !chapter.figures.is_empty() {
+            doc.push_str("### Figures\n\n");
+            for figure in &chapter.figures {
+                doc.push_str(&format!(
+                    "![{}]({})\n\n",
+                    figure.alt.replace(['[', ']'], ""),
+                    figure.href()
+                ));
+            }
+        }
+        push_markdown_list(
+            &mut doc,
+            "Key Points",
+            &key_point_items(&chapter.key_points),
+        );
+        push_markdown_list(&mut doc, "Your Highlights", &chapter.highlights);
+        push_markdown_list(&mut doc, "Keywords", &chapter.keywords);
+        push_markdown_list(&mut doc, "Glossary", &chapter.glossary);
+        push_markdown_list(&mut doc, "References", &chapter.references);
+        push_markdown_list(
+            &mut doc,
+            "Additional Resources",
+            &chapter.additional_resources,
+        );
+    }
+
+    doc.push_str(&sentiment::markdown_section(chapters));
+
+    if chapters.iter().any(|c| !c.vocabulary.is_empty()) {
+        doc.push_str("## Vocabulary\n\n");
+        for chapter in chapters.iter().filter(|c| !c.vocabulary.is_empty()) {
+            doc.push_str(&format!("### {}\n\n", chapter.title));
+            doc.push_str("| Term | Translation | Example |\n|---|---|---|\n");
+            for entry in &chapter.vocabulary {
+                doc.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    markdown_cell(&entry.term),
+                    markdown_cell(&entry.translation),
+                    markdown_cell(&entry.example)
+                ));
+            }
+            doc.push('\n');
+        }
+    }
+
+    if options.index {
+        let index = keywords::index(chapters);
+        if !index.is_empty() {
+            doc.push_str("## Index\n\n");
+            for (keyword, found_in) in index {
+                let titles: Vec<&str> = found_in
+                    .iter()
+                    .map(|&number| chapters[number].title.as_str())
+                    .collect();
+                doc.push_str(&format!("- **{}**: {}\n", keyword, titles.join(", ")));
+            }
+            doc.push('\n');
+        }
+    }
+
+    let doc = markdown::normalize(&doc);
+    if language::is_right_to_left(&options.language) {
+        markdown::mark_right_to_left(&doc)
+    } else {
+        doc
+    }
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Formats key points as list items followed by their quote, marking unsupported ones
+fn key_point_items(key_points: &[KeyPoint]) -> Vec<String> {
+    key_points
+        .iter()
+        .map(|key_point| {
+            let marker = if key_point.supported {
+                ""
+            } else {
+                "⚠ Unsupported: "
+            };
+            format!("{}{} — “{}”", marker, key_point.point, key_point.quote)
+        })
+        .collect()
+}
+
+fn push_markdown_list(doc: &mut String, heading: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    doc.push_str(&format!("### {}\n\n", heading));
+    for item in items {
+        doc.push_str(&format!("- {}\n", item));
+    }
+    doc.push('\n');
+}
+
+fn render_html(
+    metadata: &BookMetadata,
+    stats: &ReadingStats,
+    chapters: &[ChapterSummary],
+    cover: Option<&str>,
+    options: &DocumentOptions,
+) -> String {
+    let title = escape_html(&document_title(metadata));
+    // A "</style>" in a custom stylesheet must not close the element early
+    let mut doc = format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\"{}>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<a class=\"skip-link\" href=\"#content\">Skip to content</a>\n<header>\n",
+        escape_html(&options.language),
+        direction(&options.language),
+        title,
+        options.stylesheet.replace("</", "<\\/")
+    );
+    if let Some(cover) = cover {
+        doc.push_str(&format!(
+            "<img class=\"cover\" src=\"{}\" alt=\"Cover of {}\">\n",
+            escape_html(cover),
+            title
+        ));
+    }
+    doc.push_str(&format!("<h1>{}</h1>\n", title));
+    if let Some(author) = metadata.author() {
+        doc.push_str(&format!("<p><em>{}</em></p>\n", escape_html(author)));
+    }
+    doc.push_str(&format!(
+        "<p class=\"reading-stats\">{}</p>\n</header>\n",
+        escape_html(&stats.summary_line())
+    ));
+
+    if chapters.len() > 1 {
+        doc.push_str(
+            "<nav aria-labelledby=\"contents\">\n<h2 id=\"contents\">Contents</h2>\n<ol>\n",
+        );
+        for (number, chapter) in chapters.iter().enumerate() {
+            doc.push_str(&format!(
+                "<li><a href=\"#chapter-{}\">{}</a></li>\n",
+                number + 1,
+                escape_html(&chapter.title)
+            ));
+        }
+        doc.push_str("</ol>\n</nav>\n");
+    }
+
+    doc.push_str("<main id=\"content\">\n");
+    for (number, chapter) in chapters.iter().enumerate() {
+        doc.push_str(&chapter_html(number, chapter, options));
+    }
+    doc.push_str(&sentiment::html_section(chapters));
+    doc.push_str(&vocabulary_html(chapters));
+    if options.index {
+        doc.push_str(&index_html(chapters, |number| {
+            format!("#chapter-{}", number + 1)
+        }));
+    }
+    doc.push_str("</main>\n");
+
+    doc.push_str("</body>\n</html>\n");
+    doc
+}
+
+/// The `dir` attribute of the root element for right-to-left languages, empty otherwise
+pub fn direction(language: &str) -> &'static str {
+    if language::is_right_to_left(language) {
+        " dir=\"rtl\""
+    } else {
+        ""
+    }
+}
+
+/// A chapter's section, labelled by its heading (with the `chapter-<n>` anchor the index links
+/// to), with its summary and lists; the markup is also valid XHTML for EPUB output
+pub fn chapter_html(number: usize, chapter: &ChapterSummary, options: &DocumentOptions) -> String {
+    let mut doc = format!(
+        "<section class=\"chapter\" aria-labelledby=\"chapter-{0}\">\n<h2 id=\"chapter-{0}\">{1}</h2>\n",
+        number + 1,
+        escape_html(&chapter.title)
+    );
+    let summary_to_html = |text: &str| {
+        if options.highlight_keywords {
+            // Marked before escaping so keywords match the raw text, swapped for tags after;
+            // stray markers in the model's text are removed so every tag is balanced
+            let text = text.replace(MARK_OPEN, "").replace(MARK_CLOSE, "");
+            summary_to_html(&keywords::highlight(
+                &text,
+                &chapter.keywords,
+                MARK_OPEN,
+                MARK_CLOSE,
+            ))
+            .replace(MARK_OPEN, "<strong class=\"keyword\">")
+            .replace(MARK_CLOSE, "</strong>")
+        } else {
+            summary_to_html(text)
+        }
+    };
+    if chapter.layers.is_empty() {
+        doc.push_str(&summary_to_html(&chapter.summary));
+    }
+    // Each layer collapses on its own; the shallowest one starts open
+    for (index, layer) in chapter.layers.iter().enumerate() {
+        let open = if index == 0 { " open=\"open\"" } else { "" };
+        doc.push_str(&format!(
+            "<details{}>\n<summary>{}</summary>\n{}</details>\n",
+            open,
+            escape_html(&layer.label),
+            summary_to_html(&layer.text)
+        ));
+    }
+    if !chapter.figures.is_empty() {
+        doc.push_str("<h3>Figures</h3>\n");
+        for figure in &chapter.figures {
+            doc.push_str(&format!(
+                "<figure><img src=\"{}\" alt=\"{}\"/></figure>\n",
+                escape_html(&figure.href()),
+                escape_html(&figure.alt)
+            ));
+        }
+    }
+    push_html_list(
+        &mut doc,
+        "Key Points",
+        &key_point_items(&chapter.key_points),
+    );
+    push_html_list(&mut doc, "Your Highlights", &chapter.highlights);
+    push_html_list(&mut doc, "Keywords", &chapter.keywords);
+    push_html_list(&mut doc, "Glossary", &chapter.glossary);
+    push_html_list(&mut doc, "References", &chapter.references);
+    push_html_list(
+        &mut doc,
+        "Additional Resources",
+        &chapter.additional_resources,
+    );
+    doc.push_str("</section>\n");
+    doc
+}
+
+/// Keywords in alphabetical order linked to the chapters that cover them, empty when there are
+/// none; `href` gives the link target of a chapter number
+pub fn index_html(chapters: &[ChapterSummary], href: impl Fn(usize) -> String) -> String {
+    let index = keywords::index(chapters);
+    if index.is_empty() {
+        return String::new();
+    }
+    let mut doc = String::from("<h2 id=\"index\">Index</h2>\n<ul class=\"index\">\n");
+    for (keyword, found_in) in index {
+        let links: Vec<String> = found_in
+            .iter()
+            .map(|&number| {
+                format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(&href(number)),
+                    escape_html(&chapters[number].title)
+                )
+            })
+            .collect();
+        doc.push_str(&format!(
+            "<li><strong>{}</strong>: {}</li>\n",
+            escape_html(&keyword),
+            links.join(", ")
+        ));
+    }
+    doc.push_str("</ul>\n");
+    doc
+}
+
+/// Vocabulary tables of every chapter that has entries, empty when none do
+pub fn vocabulary_html(chapters: &[ChapterSummary]) -> String {
+    let mut doc = String::new();
+    if chapters.iter().any(|c| !c.vocabulary.is_empty()) {
+        doc.push_str("<h2>Vocabulary</h2>\n");
+        for chapter in chapters.iter().filter(|c| !c.vocabulary.is_empty()) {
+            doc.push_str(&format!(
+                "<h3>{}</h3>\n<table>\n<tr><th>Term</th><th>Translation</th><th>Example</th></tr>\n",
+                escape_html(&chapter.title)
+            ));
+            for entry in &chapter.vocabulary {
+                doc.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&entry.term),
+                    escape_html(&entry.translation),
+                    escape_html(&entry.example)
+                ));
+            }
+            doc.push_str("</table>\n");
+        }
+    }
+    doc
+}
+
+/// Escapes summary text for HTML, rendering its LaTeX equations as MathML
+///
+/// Equations are rendered here rather than by a script, so they show in e-readers and offline.
+/// One outside what [`math::latex_to_mathml`] handles is left as its LaTeX source.
+fn text_html(text: &str) -> String {
+    math::split_math(text)
+        .into_iter()
+        .map(|span| match span {
+            MathSpan::Text(text) => escape_html(text),
+            MathSpan::Math { latex, display } => math::latex_to_mathml(latex, display)
+                .unwrap_or_else(|| {
+                    let delimiter = if display { "$$" } else { "$" };
+                    escape_html(&format!("{}{}{}", delimiter, latex, delimiter))
+                }),
+        })
+        .collect()
+}
+
+/// Renders a summary's paragraphs, code blocks and headings, the headings nested below the
+/// chapter's `<h2>` without skipping levels
+pub fn summary_to_html(summary: &str) -> String {
+    let summary = markdown::demote_headings(summary, 3);
+    let mut previous_level = 2;
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut code: Option<(String, Vec<&str>)> = None;
+
+    let flush_paragraph = |html: &mut String, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", text_html(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    };
+
+    for line in summary.lines() {
+        let trimmed = line.trim_start();
+        match code.as_mut() {
+            Some((language, lines)) if trimmed.starts_with("```") => {
+                // The info string is model output too; only a plain language name becomes a class
+                let language: String = language
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '+'))
+                    .collect();
+                let class = if language.is_empty() {
+                    String::new()
+                } else {
+                    format!(" class=\"language-{}\"", language)
+                };
+                html.push_str(&format!(
+                    "<pre><code{}>{}</code></pre>\n",
+                    class,
+                    escape_html(&lines.join("\n"))
+                ));
+                code = None;
+            }
+            Some((_, lines)) => lines.push(line),
+            None if trimmed.starts_with("```") => {
+                flush_paragraph(&mut html, &mut paragraph);
+                code = Some((
+                    trimmed.trim_start_matches('`').trim().to_string(),
+                    Vec::new(),
+                ));
+            }
+            None if line.trim().is_empty() => flush_paragraph(&mut html, &mut paragraph),
+            None => match markdown::heading(line) {
+                Some((level, title)) => {
+                    flush_paragraph(&mut html, &mut paragraph);
+                    let level = level.min(previous_level + 1);
+                    previous_level = level;
+                    html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, escape_html(&title)));
+                }
+                None => paragraph.push(line),
+            },
+        }
+    }
+    if let Some((_, lines)) = code {
+        paragraph.extend(lines);
+    }
+    flush_paragraph(&mut html, &mut paragraph);
+
+    html
+}
+
+fn push_html_list(doc: &mut String, heading: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    doc.push_str(&format!("<h3>{}</h3>\n<ul>\n", heading));
+    for item in items {
+        doc.push_str(&format!("<li>{}</li>\n", escape_html(item)));
+    }
+    doc.push_str("</ul>\n");
+}
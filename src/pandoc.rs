@@ -0,0 +1,169 @@
+use crate::language;
+use crate::metadata::{yaml_string, BookMetadata};
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Fails early, before any summarization work, when pandoc is not on the PATH
+pub fn check(format: &str) -> Result<()> {
+    if matches!(format, "mobi" | "azw3") {
+        bail!(
+            "pandoc cannot write {} (use --output-format epub and convert summary.epub with Calibre's ebook-convert)",
+            format
+        );
+    }
+    let output = Command::new("pandoc")
+        .arg("--version")
+        .output()
+        .context("pandoc is required for --via-pandoc (https://pandoc.org/installing.html)")?;
+    if !output.status.success() {
+        bail!("pandoc --version failed");
+    }
+    Ok(())
+}
+
+/// Metadata pandoc merges with the Markdown frontmatter: the summary language and direction, the
+/// book description and keywords, and the cover that e-book writers use
+pub fn metadata_block(
+    metadata: &BookMetadata,
+    language: &str,
+    tags: &[String],
+    cover: Option<&str>,
+) -> String {
+    let mut yaml = format!("lang: {}\nsubtitle: \"Summary\"\n", yaml_string(language));
+    if language::is_right_to_left(language) {
+        yaml.push_str("dir: rtl\n");
+    }
+    if let Some(description) = &metadata.description {
+        yaml.push_str(&format!("abstract: {}\n", yaml_string(description)));
+    }
+    if !tags.is_empty() {
+        yaml.push_str("keywords:\n");
+        for tag in tags {
+            yaml.push_str(&format!("  - {}\n", yaml_string(tag)));
+        }
+    }
+    if let Some(cover) = cover {
+        yaml.push_str(&format!("cover-image: {}\n", yaml_string(cover)));
+    }
+    yaml
+}
+
+/// Metadata putting `cover` on a page of its own ahead of a PDF's title page
+///
+/// Pandoc writes PDF through LaTeX, which ignores `cover-image` and cannot include SVG, so an
+/// SVG cover is first converted to `cover.pdf` with `rsvg-convert` (librsvg).
+pub fn pdf_cover_block(output_dir: &Path, cover: &str) -> Result<String> {
+    let cover = if cover.ends_with(".svg") {
+        let converted = "cover.pdf";
+        let status = Command::new("rsvg-convert")
+            .current_dir(output_dir)
+            .args(["--format", "pdf", "--output", converted, cover])
+            .status()
+            .context("rsvg-convert (librsvg) is required to put an SVG cover in a PDF")?;
+        if !status.success() {
+            bail!("rsvg-convert could not convert '{}'", cover);
+        }
+        converted
+    } else {
+        cover
+    };
+    let latex = |code: &str| format!("  ```{{=latex}}\n  {}\n  ```\n", code);
+    Ok(format!(
+        "header-includes: |\n{}include-before: |\n{}",
+        latex(r"\usepackage{graphicx}"),
+        latex(&format!(
+            r"\begin{{titlepage}}\centering\includegraphics[width=\textwidth,height=\textheight,keepaspectratio]{{{}}}\end{{titlepage}}",
+            cover
+        ))
+    ))
+}
+
+/// Converts the assembled Markdown summary to `format` with pandoc and returns the file's path
+///
+/// Pandoc reads the Markdown on stdin from inside `output_dir`, so the cover and images resolve.
+/// The file is `summary.<extension>`, or `summary.pandoc.<extension>` when that is `taken` by
+/// the main summary document. The metadata goes through a temporary file that is removed
+/// afterwards.
+pub fn convert(
+    output_dir: &Path,
+    format: &str,
+    markdown: &str,
+    metadata_yaml: &str,
+    taken: &Path,
+) -> Result<PathBuf> {
+    let mut metadata_file = tempfile::Builder::new()
+        .prefix("aibook-pandoc-")
+        .suffix(".yaml")
+        .tempfile()?;
+    metadata_file.write_all(metadata_yaml.as_bytes())?;
+
+    let mut file_name = format!("summary.{}", extension(format));
+    if output_dir.join(&file_name) == taken {
+        file_name = format!("summary.pandoc.{}", extension(format));
+    }
+    let mut command = Command::new("pandoc");
+    command
+        .current_dir(output_dir)
+        .args(["--from", "markdown", "--standalone"]);
+    // There is no pdf writer; pandoc picks a PDF engine from the output file's extension
+    if format != "pdf" {
+        command.args(["--to", format]);
+    }
+    let mut child = command
+        .arg("--metadata-file")
+        .arg(metadata_file.path())
+        .args(["--output", &file_name])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("pandoc is required for --via-pandoc")?;
+    // Written from a thread while stderr is read, so neither pipe can fill up and block pandoc
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("could not open pandoc's stdin")?;
+    let markdown = markdown.to_string();
+    let writer = thread::spawn(move || stdin.write_all(markdown.as_bytes()));
+    let result = child.wait_with_output()?;
+    let written = writer
+        .join()
+        .map_err(|_| anyhow!("writing to pandoc panicked"))?;
+    if !result.status.success() {
+        bail!(
+            "pandoc could not write {}: {}",
+            format,
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+    written.context("could not pass the summary to pandoc")?;
+    Ok(output_dir.join(file_name))
+}
+
+/// File extension for a pandoc output format, ignoring `+extension`/`-extension` suffixes
+fn extension(format: &str) -> String {
+    let name = format
+        .split(['+', '-'])
+        .next()
+        .unwrap_or(format)
+        .to_lowercase();
+    let extension = match name.as_str() {
+        "markdown" | "markdown_strict" | "markdown_phpextra" | "markdown_mmd" | "gfm"
+        | "commonmark" | "commonmark_x" => "md",
+        "latex" | "beamer" | "context" => "tex",
+        "html4" | "html5" | "revealjs" | "slidy" | "slideous" | "s5" | "dzslides" => "html",
+        "docbook" | "docbook4" | "docbook5" | "jats" | "tei" | "opendocument" => "xml",
+        "epub2" | "epub3" => "epub",
+        "plain" => "txt",
+        "asciidoc" | "asciidoctor" => "adoc",
+        "mediawiki" | "dokuwiki" | "xwiki" | "zimwiki" => "wiki",
+        "texinfo" => "texi",
+        "man" => "1",
+        "typst" => "typ",
+        _ => return name,
+    };
+    extension.to_string()
+}
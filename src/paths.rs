@@ -0,0 +1,206 @@
+use anyhow::Result;
+use directories::ProjectDirs;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Longest file name written, below the 255-byte limit of common filesystems with room for
+/// suffixes such as `.1` of rotated files
+const MAX_FILE_NAME_BYTES: usize = 200;
+/// Windows' classic path limit, beyond which paths need the `\\?\` prefix
+const MAX_PATH: usize = 260;
+/// Names Windows reserves for devices, whatever their extension
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "conin$", "conout$", "com0", "com1", "com2", "com3", "com4",
+    "com5", "com6", "com7", "com8", "com9", "com¹", "com²", "com³", "lpt0", "lpt1", "lpt2", "lpt3",
+    "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9", "lpt¹", "lpt²", "lpt³",
+];
+
+/// Where aibook keeps its configuration, outputs, logs and downloads
+///
+/// Defaults follow the platform conventions (XDG base directories on Linux, e.g.
+/// `~/.config/aibook`, `~/.local/share/aibook`, `~/.local/state/aibook` and `~/.cache/aibook`), so running aibook
+/// from any directory does not litter it. Each directory can be overridden from the command line.
+#[derive(Debug, Clone)]
+pub struct AppDirs {
+    /// `.env` file and prompt overrides in `prompts/`
+    pub config_dir: PathBuf,
+    /// Default parent of the per-book output directories
+    pub data_dir: PathBuf,
+    /// HTTP and LLM response logs
+    pub log_dir: PathBuf,
+    /// Downloaded books, safe to delete
+    pub cache_dir: PathBuf,
+}
+
+impl AppDirs {
+    pub fn resolve(
+        config_dir: Option<PathBuf>,
+        data_dir: Option<PathBuf>,
+        log_dir: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+    ) -> Self {
+        let project = ProjectDirs::from("", "", "aibook");
+        // Without a home directory there is nothing better than the working directory
+        let fallback = |name: &str| PathBuf::from(".aibook").join(name);
+
+        AppDirs {
+            config_dir: config_dir.unwrap_or_else(|| {
+                project
+                    .as_ref()
+                    .map(|p| p.config_dir().to_path_buf())
+                    .unwrap_or_else(|| fallback("config"))
+            }),
+            data_dir: data_dir.unwrap_or_else(|| {
+                project
+                    .as_ref()
+                    .map(|p| p.data_dir().to_path_buf())
+                    .unwrap_or_else(|| fallback("data"))
+            }),
+            log_dir: log_dir.unwrap_or_else(|| {
+                project
+                    .as_ref()
+                    .map(|p| {
+                        p.state_dir()
+                            .unwrap_or_else(|| p.data_local_dir())
+                            .join("logs")
+                    })
+                    .unwrap_or_else(|| fallback("logs"))
+            }),
+            cache_dir: cache_dir.unwrap_or_else(|| {
+                project
+                    .as_ref()
+                    .map(|p| p.cache_dir().to_path_buf())
+                    .unwrap_or_else(|| fallback("cache"))
+            }),
+        }
+    }
+
+    /// Default output directory when neither `--output-dir` nor `OUTPUT_DIR` is given
+    pub fn default_output_dir(&self) -> PathBuf {
+        self.data_dir.join("output")
+    }
+
+    /// Directories searched for a prompt template, most specific first
+    pub fn prompt_dirs(&self) -> Vec<PathBuf> {
+        vec![self.config_dir.join("prompts"), PathBuf::from("prompts")]
+    }
+
+    pub fn create_log_dir(&self) -> Result<()> {
+        fs::create_dir_all(&self.log_dir)?;
+        Ok(())
+    }
+}
+
+/// A file name derived from a title, URL or e-book resource that is valid on Windows as well
+/// as Unix, whatever the platform, so outputs can be copied between them
+///
+/// Beyond `sanitize_filename`, device names such as `CON` or `com1.txt` get a leading `_`,
+/// trailing dots and spaces are dropped, and long names are shortened keeping their extension.
+pub fn safe_file_name(name: &str) -> String {
+    let options = sanitize_filename::Options {
+        windows: false,
+        truncate: false,
+        replacement: "",
+    };
+    let mut name = sanitize_filename::sanitize_with_options(name, options)
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    if name.len() > MAX_FILE_NAME_BYTES {
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && extension.len() <= 16 => {
+                (stem, format!(".{}", extension))
+            }
+            _ => (name.as_str(), String::new()),
+        };
+        let mut end = MAX_FILE_NAME_BYTES - extension.len();
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = format!("{}{}", stem[..end].trim_end_matches(['.', ' ']), extension);
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.contains(&stem.to_lowercase().as_str()) {
+        name.insert(0, '_');
+    }
+    if name.is_empty() {
+        name.push('_');
+    }
+    name
+}
+
+/// On Windows, `path` in its `\\?\` form once files under it could pass the 260-character
+/// limit, so deeply nested output directories still work; elsewhere `path` unchanged
+pub fn long_path(path: &Path) -> PathBuf {
+    // Leaves room for the names written inside, such as `images/<resource name>`
+    if !cfg!(windows) || path.as_os_str().len() + 64 < MAX_PATH {
+        return path.to_path_buf();
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let text = absolute.to_string_lossy();
+    if text.starts_with(r"\\?\") {
+        absolute.clone()
+    } else if let Some(share) = text.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", share))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_are_valid_everywhere() {
+        for (name, expected) in [
+            ("Dune: Part One?", "Dune Part One"),
+            ("a/b\\c*d", "abcd"),
+            ("CON", "_CON"),
+            ("com1.txt", "_com1.txt"),
+            ("console.txt", "console.txt"),
+            ("notes. . ", "notes"),
+            ("...", "_"),
+            ("", "_"),
+        ] {
+            assert_eq!(safe_file_name(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn long_names_keep_their_extension_and_char_boundaries() {
+        let name = format!("{}.xhtml", "é".repeat(150));
+        let safe = safe_file_name(&name);
+        assert!(safe.len() <= MAX_FILE_NAME_BYTES, "{}", safe.len());
+        assert!(safe.ends_with("é.xhtml"));
+
+        let safe = safe_file_name(&"x".repeat(300));
+        assert_eq!(safe, "x".repeat(MAX_FILE_NAME_BYTES));
+    }
+
+    #[test]
+    fn short_paths_are_left_alone() {
+        let path = Path::new("output/dune");
+        assert_eq!(long_path(path), path);
+    }
+
+    #[test]
+    fn overridden_directories_are_used_as_given() {
+        let dirs = AppDirs::resolve(
+            Some(PathBuf::from("conf")),
+            Some(PathBuf::from("data")),
+            Some(PathBuf::from("logs")),
+            Some(PathBuf::from("cache")),
+        );
+        assert_eq!(dirs.default_output_dir(), PathBuf::from("data/output"));
+        assert_eq!(dirs.log_dir, PathBuf::from("logs"));
+        assert_eq!(dirs.cache_dir, PathBuf::from("cache"));
+        assert_eq!(
+            dirs.prompt_dirs(),
+            [PathBuf::from("conf/prompts"), PathBuf::from("prompts")]
+        );
+    }
+}
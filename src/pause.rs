@@ -0,0 +1,110 @@
+use std::io::{self, IsTerminal, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often a paused run checks whether it was resumed
+const RESUME_POLL: Duration = Duration::from_millis(200);
+
+/// Pausing a run from the keyboard: `p` pauses before the next section request, `r` resumes
+///
+/// Keys are read in the background while the terminal is switched to unbuffered input without
+/// echo; the previous mode comes back when this is dropped, or when Ctrl-C interrupts the run,
+/// which would otherwise leave the shell without echo.
+pub struct Pause {
+    paused: Arc<AtomicBool>,
+    #[cfg(unix)]
+    _terminal: unix::TerminalMode,
+}
+
+impl Pause {
+    /// Starts listening for keys, or returns `None` when stdin or stderr is not an interactive
+    /// terminal (or on platforms without support)
+    pub fn listen() -> Option<Self> {
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            return None;
+        }
+        #[cfg(unix)]
+        {
+            let terminal = unix::TerminalMode::unbuffered()?;
+            let original = terminal.original;
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    unix::restore(&original);
+                    // The exit status a shell reports for a run killed by SIGINT
+                    std::process::exit(130);
+                }
+            });
+            let paused = Arc::new(AtomicBool::new(false));
+            let listener = Arc::clone(&paused);
+            std::thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(b'p' | b'P') => listener.store(true, Ordering::SeqCst),
+                        Ok(b'r' | b'R') => listener.store(false, Ordering::SeqCst),
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                }
+            });
+            Some(Pause {
+                paused,
+                _terminal: terminal,
+            })
+        }
+        #[cfg(not(unix))]
+        None
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns once the user resumes the run
+    pub async fn resumed(&self) {
+        while self.is_paused() {
+            tokio::time::sleep(RESUME_POLL).await;
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::os::fd::AsRawFd;
+
+    /// Stdin without line buffering and echo, restored on drop
+    pub struct TerminalMode {
+        pub original: libc::termios,
+    }
+
+    impl TerminalMode {
+        pub fn unbuffered() -> Option<Self> {
+            let fd = std::io::stdin().as_raw_fd();
+            // SAFETY: termios is plain data filled in by tcgetattr before it is read
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+                return None;
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+                return None;
+            }
+            Some(TerminalMode { original })
+        }
+    }
+
+    impl Drop for TerminalMode {
+        fn drop(&mut self) {
+            restore(&self.original);
+        }
+    }
+
+    /// Puts stdin back in the mode it had before [`TerminalMode::unbuffered`]
+    pub fn restore(original: &libc::termios) {
+        let fd = std::io::stdin().as_raw_fd();
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, original) };
+    }
+}
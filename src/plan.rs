@@ -0,0 +1,286 @@
+use crate::summarizer::Summarizer;
+use crate::text::normalize_words;
+use anyhow::{bail, Result};
+use log::{info, warn};
+use serde_json::Value;
+
+/// Chapters listed on each side of the current one in a section prompt's book outline
+const OUTLINE_NEIGHBOURS: usize = 3;
+
+/// The plan for one chapter, as returned by the summary plan prompt
+#[derive(Debug, Clone, Default)]
+pub struct PlanSection {
+    pub title: String,
+    pub plan: String,
+    pub keywords: Vec<String>,
+}
+
+impl PlanSection {
+    /// Text passed to the section prompts as `{{plan}}`
+    fn render(&self) -> String {
+        let mut text = format!("## {}\n\n{}", self.title, self.plan);
+        if !self.keywords.is_empty() {
+            text.push_str(&format!("\n\nKeywords: {}", self.keywords.join(", ")));
+        }
+        text
+    }
+}
+
+/// A summary plan aligned with the table of contents, one entry per TOC title
+#[derive(Debug, Clone, Default)]
+pub struct SummaryPlan {
+    toc: Vec<String>,
+    sections: Vec<Option<PlanSection>>,
+}
+
+impl SummaryPlan {
+    /// Plan text for a chapter, looked up by its TOC position and then by title: the chapter's
+    /// own entry and a short outline of the chapters around it
+    ///
+    /// Only this much of the plan goes into each section prompt; the other chapters' entries
+    /// would cost their tokens on every request. Chapters that are not in the table of
+    /// contents (such as parts of a split spine document) get an empty plan.
+    pub fn for_chapter(&self, index: usize, title: &str) -> String {
+        match self.planned(index, title) {
+            Some((position, section)) => {
+                format!("{}\n\n{}", section.render(), self.outline(position))
+            }
+            None => String::new(),
+        }
+    }
+
+    /// The outline part of [`SummaryPlan::for_chapter`], which a prompt carrying only the
+    /// chapter's entry would not have
+    pub fn outline_for_chapter(&self, index: usize, title: &str) -> String {
+        self.planned(index, title)
+            .map(|(position, _)| self.outline(position))
+            .unwrap_or_default()
+    }
+
+    /// TOC position and plan entry of a chapter, if the plan has one for it
+    fn planned(&self, index: usize, title: &str) -> Option<(usize, &PlanSection)> {
+        let wanted = normalize_words(title);
+        let position = match self.toc.get(index) {
+            Some(toc_title) if normalize_words(toc_title) == wanted => Some(index),
+            _ => self
+                .toc
+                .iter()
+                .position(|toc_title| normalize_words(toc_title) == wanted),
+        }?;
+        Some((position, self.sections[position].as_ref()?))
+    }
+
+    /// Every chapter's entry, as a section prompt would carry the whole plan
+    pub fn full_text(&self) -> String {
+        self.sections
+            .iter()
+            .flatten()
+            .map(PlanSection::render)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// The titles of the chapters next to the one at `position`, so a section knows where it
+    /// stands in the book
+    fn outline(&self, position: usize) -> String {
+        let start = position.saturating_sub(OUTLINE_NEIGHBOURS);
+        let end = (position + OUTLINE_NEIGHBOURS + 1).min(self.toc.len());
+        let mut lines = vec![format!(
+            "Book outline (chapter {} of {}):",
+            position + 1,
+            self.toc.len()
+        )];
+        if start > 0 {
+            lines.push("- ...".to_string());
+        }
+        for (offset, title) in self.toc[start..end].iter().enumerate() {
+            if start + offset == position {
+                lines.push(format!("- {} (this chapter)", title));
+            } else {
+                lines.push(format!("- {}", title));
+            }
+        }
+        if end < self.toc.len() {
+            lines.push("- ...".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+/// Generates the summary plan and validates it against the table of contents
+///
+/// The plan must cover every TOC entry exactly once and nothing else. Otherwise the model is
+/// prompted again with the list of discrepancies, up to `attempts` times in total, before the
+/// book fails rather than being summarized against the wrong plan. A book without a table of
+/// contents gets an empty plan without asking the model.
+pub async fn generate(
+    summarizer: &Summarizer,
+    toc: &[String],
+    attempts: usize,
+) -> Result<SummaryPlan> {
+    if toc.is_empty() {
+        info!("No table of contents to plan against, summarizing without a plan");
+        return Ok(SummaryPlan::default());
+    }
+    let mut discrepancies = Vec::new();
+    for attempt in 1..=attempts.max(1) {
+        let value = summarizer
+            .generate_summary_plan(toc, &discrepancies)
+            .await?;
+        let sections = parse_sections(&value);
+        discrepancies = validate(toc, &sections);
+        if discrepancies.is_empty() {
+            return Ok(SummaryPlan {
+                toc: toc.to_vec(),
+                sections: align(toc, sections),
+            });
+        }
+        warn!(
+            "Summary plan attempt {} is invalid: {}",
+            attempt,
+            discrepancies.join("; ")
+        );
+    }
+
+    bail!(
+        "The summary plan could not be aligned with the table of contents: {}",
+        discrepancies.join("; ")
+    )
+}
+
+/// Lists every TOC entry the plan misses or repeats and every plan entry not in the TOC
+fn validate(toc: &[String], sections: &[PlanSection]) -> Vec<String> {
+    let count = |titles: &mut dyn Iterator<Item = &String>, wanted: &str| {
+        titles
+            .filter(|title| normalize_words(title) == wanted)
+            .count()
+    };
+
+    let mut discrepancies = Vec::new();
+    let mut seen = Vec::new();
+    for title in toc {
+        let wanted = normalize_words(title);
+        if seen.contains(&wanted) {
+            continue;
+        }
+        let expected = count(&mut toc.iter(), &wanted);
+        let planned = count(&mut sections.iter().map(|s| &s.title), &wanted);
+        if planned < expected {
+            discrepancies.push(format!("missing chapter \"{}\"", title));
+        } else if planned > expected {
+            discrepancies.push(format!("chapter \"{}\" is listed more than once", title));
+        }
+        seen.push(wanted);
+    }
+    for section in sections {
+        if !seen.contains(&normalize_words(&section.title)) {
+            discrepancies.push(format!(
+                "\"{}\" is not in the table of contents",
+                section.title
+            ));
+        }
+    }
+    discrepancies
+}
+
+fn parse_sections(value: &Value) -> Vec<PlanSection> {
+    let Some(chapters) = value.get("chapters").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    chapters
+        .iter()
+        .filter_map(|chapter| {
+            let field = |name: &str| {
+                chapter
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            };
+            let title = field("title");
+            if title.is_empty() {
+                return None;
+            }
+            Some(PlanSection {
+                title,
+                plan: field("plan"),
+                keywords: chapter
+                    .get("keywords")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Assigns each TOC entry the first unused plan section with the same title
+fn align(toc: &[String], sections: Vec<PlanSection>) -> Vec<Option<PlanSection>> {
+    let mut sections: Vec<Option<PlanSection>> = sections.into_iter().map(Some).collect();
+    toc.iter()
+        .map(|title| {
+            let wanted = normalize_words(title);
+            sections
+                .iter_mut()
+                .find(|section| {
+                    section
+                        .as_ref()
+                        .is_some_and(|section| normalize_words(&section.title) == wanted)
+                })
+                .and_then(Option::take)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(chapters: usize, planned: usize) -> SummaryPlan {
+        let toc: Vec<String> = (1..=chapters).map(|n| format!("Chapter {}", n)).collect();
+        let sections = toc
+            .iter()
+            .enumerate()
+            .map(|(index, title)| {
+                (index == planned).then(|| PlanSection {
+                    title: title.clone(),
+                    plan: "Cover the harvest.".to_string(),
+                    keywords: vec!["harvest".to_string()],
+                })
+            })
+            .collect();
+        SummaryPlan { toc, sections }
+    }
+
+    #[test]
+    fn outline_lists_the_neighbouring_chapters() {
+        let plan = plan(9, 4);
+        assert_eq!(
+            plan.outline(4),
+            "Book outline (chapter 5 of 9):\n- ...\n- Chapter 2\n- Chapter 3\n- Chapter 4\n\
+             - Chapter 5 (this chapter)\n- Chapter 6\n- Chapter 7\n- Chapter 8\n- ..."
+        );
+        assert_eq!(
+            plan.outline(0),
+            "Book outline (chapter 1 of 9):\n- Chapter 1 (this chapter)\n- Chapter 2\n\
+             - Chapter 3\n- Chapter 4\n- ..."
+        );
+    }
+
+    #[test]
+    fn chapters_are_found_by_position_then_title() {
+        let plan = plan(9, 4);
+        let text = plan.for_chapter(0, "chapter  5");
+        assert!(text.starts_with("## Chapter 5\n\nCover the harvest.\n\nKeywords: harvest"));
+        assert!(text.contains("- Chapter 5 (this chapter)"));
+        assert_eq!(plan.outline_for_chapter(0, "chapter  5"), plan.outline(4));
+        assert_eq!(plan.for_chapter(0, "Chapter 1"), "");
+        assert_eq!(plan.outline_for_chapter(0, "Chapter 1"), "");
+        assert_eq!(plan.for_chapter(3, "Appendix"), "");
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Sandboxed WASM modules adding stages to summarization, applied in the order given
+///
+/// A plugin exports its `memory`, an `alloc(len) -> ptr` function and any of the stages, each
+/// taking a UTF-8 buffer as `(ptr, len)` and returning the result buffer packed as
+/// `ptr << 32 | len`:
+///
+/// - `transform_chunk`: rewrites the text of a section before it is summarized
+/// - `post_process_summary`: rewrites a section summary, passed as JSON, after the model's reply
+///
+/// Plugins get no imports, so they cannot touch files, the network or the clock, and their
+/// memory and the work of each call are capped.
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        let plugins = paths
+            .iter()
+            .map(|path| Plugin::load(path))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Plugins { plugins })
+    }
+
+    /// Identifies the loaded modules, so cached summaries made without them are not reused
+    pub fn fingerprint(&self) -> String {
+        self.plugins
+            .iter()
+            .map(|plugin| plugin.sha256())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn transform_chunk(&self, mut text: String) -> Result<String> {
+        for plugin in &self.plugins {
+            if let Some(output) = plugin.call("transform_chunk", text.as_bytes())? {
+                text = String::from_utf8(output)?;
+            }
+        }
+        Ok(text)
+    }
+
+    pub fn post_process_summary(&self, mut summary: Value) -> Result<Value> {
+        for plugin in &self.plugins {
+            let input = serde_json::to_vec(&summary)?;
+            if let Some(output) = plugin.call("post_process_summary", &input)? {
+                summary = serde_json::from_slice(&output)?;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+use wasm::Plugin;
+
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use crate::manifest::sha256_hex;
+    use anyhow::{anyhow, bail, Context, Result};
+    use log::info;
+    use std::fs;
+    use std::path::Path;
+    use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    /// Memory a plugin instance may grow to
+    const MAX_MEMORY: usize = 256 * 1024 * 1024;
+    /// Work a single stage call may do, in wasmtime fuel units (roughly instructions)
+    const FUEL_PER_CALL: u64 = 5_000_000_000;
+
+    pub struct Plugin {
+        name: String,
+        sha256: String,
+        engine: Engine,
+        module: Module,
+    }
+
+    impl Plugin {
+        pub fn load(path: &Path) -> Result<Self> {
+            let bytes = fs::read(path)
+                .with_context(|| format!("could not read plugin '{}'", path.display()))?;
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config)?;
+            let module = Module::new(&engine, &bytes)
+                .with_context(|| format!("'{}' is not a valid WASM module", path.display()))?;
+            if let Some(import) = module.imports().next() {
+                bail!(
+                    "Plugin '{}' imports '{}::{}', but plugins run without imports",
+                    path.display(),
+                    import.module(),
+                    import.name()
+                );
+            }
+            let stages: Vec<&str> = ["transform_chunk", "post_process_summary"]
+                .into_iter()
+                .filter(|stage| module.get_export(stage).is_some())
+                .collect();
+            info!(
+                "Loaded plugin '{}' ({})",
+                path.display(),
+                if stages.is_empty() {
+                    "no stages".to_string()
+                } else {
+                    stages.join(", ")
+                }
+            );
+            Ok(Plugin {
+                name: path.display().to_string(),
+                sha256: sha256_hex(&bytes),
+                engine,
+                module,
+            })
+        }
+
+        pub fn sha256(&self) -> &str {
+            &self.sha256
+        }
+
+        /// Runs a stage on a fresh instance, or returns `None` when the plugin lacks it
+        pub fn call(&self, stage: &str, input: &[u8]) -> Result<Option<Vec<u8>>> {
+            if self.module.get_export(stage).is_none() {
+                return Ok(None);
+            }
+            self.run(stage, input)
+                .map(Some)
+                .with_context(|| format!("plugin '{}' failed in {}", self.name, stage))
+        }
+
+        fn run(&self, stage: &str, input: &[u8]) -> Result<Vec<u8>> {
+            let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+            let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(FUEL_PER_CALL)?;
+            let instance = Instance::new(&mut store, &self.module, &[])?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| anyhow!("the plugin does not export its memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+            let function = instance.get_typed_func::<(i32, i32), i64>(&mut store, stage)?;
+
+            let length = i32::try_from(input.len()).context("input too large for the plugin")?;
+            let pointer = alloc.call(&mut store, length)?;
+            memory.write(&mut store, pointer as u32 as usize, input)?;
+            let packed = function.call(&mut store, (pointer, length))? as u64;
+            let (output_pointer, output_length) = ((packed >> 32) as usize, packed as u32 as usize);
+            let mut output = vec![0; output_length];
+            memory.read(&store, output_pointer, &mut output)?;
+            Ok(output)
+        }
+    }
+}
+
+/// Stand-in when aibook is built without the `wasm-plugins` feature; it can never be loaded
+#[cfg(not(feature = "wasm-plugins"))]
+enum Plugin {}
+
+#[cfg(not(feature = "wasm-plugins"))]
+impl Plugin {
+    fn load(_path: &std::path::Path) -> Result<Self> {
+        anyhow::bail!(
+            "This aibook was built without WASM plugins (build it with --features wasm-plugins)"
+        )
+    }
+
+    fn sha256(&self) -> &str {
+        match *self {}
+    }
+
+    fn call(&self, _stage: &str, _input: &[u8]) -> Result<Option<Vec<u8>>> {
+        match *self {}
+    }
+}
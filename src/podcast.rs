@@ -0,0 +1,82 @@
+use crate::output::ChapterSummary;
+use crate::summarizer::Summarizer;
+use anyhow::Result;
+use log::warn;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Names used for the two podcast hosts
+pub const HOSTS: (&str, &str) = ("Alex", "Sam");
+
+/// A single spoken line of the script
+#[derive(Debug, Clone)]
+pub struct DialogueLine {
+    pub speaker: String,
+    pub line: String,
+}
+
+/// The dialogue for one chapter episode
+#[derive(Debug, Clone)]
+pub struct Episode {
+    pub title: String,
+    pub keywords: Vec<String>,
+    pub lines: Vec<DialogueLine>,
+}
+
+/// Generates one episode per chapter, skipping chapters whose dialogue could not be produced
+pub async fn generate(summarizer: &Summarizer, chapters: &[ChapterSummary]) -> Vec<Episode> {
+    let mut episodes = Vec::new();
+
+    for chapter in chapters.iter().filter(|c| !c.summary.trim().is_empty()) {
+        match summarizer
+            .generate_podcast_dialogue(&chapter.title, &chapter.summary, HOSTS)
+            .await
+        {
+            Ok(value) => episodes.push(Episode {
+                title: chapter.title.clone(),
+                keywords: chapter.keywords.clone(),
+                lines: parse_dialogue(&value),
+            }),
+            Err(e) => warn!("Podcast script failed for '{}': {}", chapter.title, e),
+        }
+    }
+
+    episodes
+}
+
+/// Parses the JSON array returned by the podcast prompt
+pub fn parse_dialogue(value: &Value) -> Vec<DialogueLine> {
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let speaker = item.get("speaker")?.as_str()?.trim().to_string();
+            let line = item.get("line")?.as_str()?.trim().to_string();
+            if line.is_empty() {
+                return None;
+            }
+            Some(DialogueLine { speaker, line })
+        })
+        .collect()
+}
+
+/// Writes the script as Markdown, one section per episode
+pub fn write_script(output_dir: &Path, title: &str, episodes: &[Episode]) -> Result<PathBuf> {
+    let mut doc = format!("# Podcast Script: {}\n\n", title);
+    doc.push_str(&format!("Hosts: {} and {}\n\n", HOSTS.0, HOSTS.1));
+
+    for (index, episode) in episodes.iter().enumerate() {
+        doc.push_str(&format!("## Episode {}: {}\n\n", index + 1, episode.title));
+        for line in &episode.lines {
+            doc.push_str(&format!("**{}:** {}\n\n", line.speaker, line.line));
+        }
+    }
+
+    let path = output_dir.join("podcast_script.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
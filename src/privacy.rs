@@ -0,0 +1,191 @@
+use crate::redact::EMAIL_PATTERN;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use http::header::CONTENT_LENGTH;
+use http::Extensions;
+use regex::{Captures, Regex};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Digit groups with an optional country code and area code in parentheses
+const PHONE_PATTERN: &str =
+    r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{1,4}\)[\s.-]?)?\d{2,4}(?:[\s.-]\d{2,4}){1,4}\b";
+/// Fewer digits are more likely dates, years or page ranges than phone numbers
+const MIN_PHONE_DIGITS: usize = 9;
+
+/// Masks personal data in every prompt before it leaves the machine and puts it back in the
+/// replies (`--privacy`)
+///
+/// Email addresses, phone numbers and the configured entities become placeholders such as
+/// `[EMAIL_1]` or `[ENTITY_2]`. The same value always gets the same placeholder, so the model
+/// can still tell people apart across sections, and the placeholders in a reply are restored
+/// before anything else sees it. Logs and recordings only hold the masked exchange.
+pub struct PrivacyFilter {
+    /// Placeholder kind and what it masks; entities go first, so a name is masked whole even
+    /// when it contains an address or number
+    patterns: Vec<(&'static str, Regex)>,
+    placeholder: Regex,
+    placeholders: Mutex<Placeholders>,
+}
+
+#[derive(Default)]
+struct Placeholders {
+    by_value: HashMap<String, String>,
+    values: HashMap<String, String>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl PrivacyFilter {
+    /// `entities` are names or terms masked wherever they appear, ignoring case
+    pub fn new(entities: &[String]) -> anyhow::Result<Self> {
+        let mut patterns = Vec::new();
+        for entity in entities.iter().map(|entity| entity.trim()) {
+            if entity.is_empty() {
+                continue;
+            }
+            let pattern = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(entity)))
+                .map_err(|e| anyhow!("Invalid private entity '{}': {}", entity, e))?;
+            patterns.push(("ENTITY", pattern));
+        }
+        patterns.push(("EMAIL", Regex::new(EMAIL_PATTERN)?));
+        patterns.push(("PHONE", Regex::new(PHONE_PATTERN)?));
+        Ok(PrivacyFilter {
+            patterns,
+            placeholder: Regex::new(r"\[(?:ENTITY|EMAIL|PHONE)_\d+\]")?,
+            placeholders: Mutex::new(Placeholders::default()),
+        })
+    }
+
+    fn mask(&self, text: &str) -> String {
+        let mut placeholders = self.placeholders.lock().unwrap();
+        let mut text = text.to_string();
+        for (kind, pattern) in &self.patterns {
+            text = pattern
+                .replace_all(&text, |captures: &Captures| {
+                    let value = &captures[0];
+                    if *kind == "PHONE"
+                        && value.chars().filter(char::is_ascii_digit).count() < MIN_PHONE_DIGITS
+                    {
+                        return value.to_string();
+                    }
+                    placeholders.placeholder(kind, value)
+                })
+                .into_owned();
+        }
+        text
+    }
+
+    fn restore(&self, text: &str) -> String {
+        let placeholders = self.placeholders.lock().unwrap();
+        self.placeholder
+            .replace_all(text, |captures: &Captures| {
+                let placeholder = &captures[0];
+                placeholders
+                    .values
+                    .get(placeholder)
+                    .cloned()
+                    .unwrap_or_else(|| placeholder.to_string())
+            })
+            .into_owned()
+    }
+}
+
+impl Placeholders {
+    fn placeholder(&mut self, kind: &'static str, value: &str) -> String {
+        if let Some(placeholder) = self.by_value.get(value) {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        let placeholder = format!("[{}_{}]", kind, count);
+        self.by_value.insert(value.to_string(), placeholder.clone());
+        self.values.insert(placeholder.clone(), value.to_string());
+        placeholder
+    }
+}
+
+#[async_trait]
+impl Middleware for PrivacyFilter {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let request = req
+            .body()
+            .and_then(|body| body.as_bytes())
+            .and_then(|bytes| serde_json::from_slice::<Value>(bytes).ok());
+        if let Some(mut request) = request {
+            if let Some(messages) = request.get_mut("messages") {
+                rewrite(messages, &|text| self.mask(text));
+            }
+            *req.body_mut() = Some(request.to_string().into());
+        }
+
+        let response = next.run(req, extensions).await?;
+
+        // The body can only be read once, so the response is rebuilt from the restored text
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        let version = response.version();
+        let bytes = response.bytes().await?;
+        let body = match serde_json::from_slice::<Value>(&bytes) {
+            Ok(mut value) => {
+                rewrite(&mut value, &|text| self.restore(text));
+                value.to_string()
+            }
+            Err(_) => self.restore(&String::from_utf8_lossy(&bytes)),
+        };
+
+        headers.remove(CONTENT_LENGTH);
+        let mut rebuilt = http::Response::builder().status(status).version(version);
+        if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+            *rebuilt_headers = headers;
+        }
+        let rebuilt = rebuilt.body(body).map_err(anyhow::Error::from)?;
+        Ok(Response::from(rebuilt))
+    }
+}
+
+/// Applies `change` to every string in a JSON value
+fn rewrite(value: &mut Value, change: &dyn Fn(&str) -> String) {
+    match value {
+        Value::String(text) => *text = change(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| rewrite(item, change)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| rewrite(field, change)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_and_restores_personal_data() {
+        let filter = PrivacyFilter::new(&["Ada Lovelace".to_string()]).unwrap();
+        let text = "Ada Lovelace wrote to ada@example.com and +44 20 7946 0958 in 1843; \
+                    ADA LOVELACE signed it.";
+        let masked = filter.mask(text);
+        assert_eq!(
+            masked,
+            "[ENTITY_1] wrote to [EMAIL_1] and [PHONE_1] in 1843; [ENTITY_2] signed it."
+        );
+        assert_eq!(filter.restore(&masked), text);
+        // The same value keeps its placeholder across requests
+        assert_eq!(
+            filter.mask("Write to ada@example.com"),
+            "Write to [EMAIL_1]"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        let filter = PrivacyFilter::new(&[]).unwrap();
+        assert_eq!(filter.restore("See [EMAIL_7]."), "See [EMAIL_7].");
+    }
+}
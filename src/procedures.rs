@@ -0,0 +1,187 @@
+use crate::schema;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One step of a procedure, with how long it takes when the book says so
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Step {
+    pub instruction: String,
+    pub duration: String,
+}
+
+/// A recipe or how-to procedure extracted from the text
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Procedure {
+    pub name: String,
+    pub kind: String,
+    pub yields: String,
+    pub total_time: String,
+    pub materials: Vec<String>,
+    pub steps: Vec<Step>,
+    pub notes: Vec<String>,
+}
+
+/// Procedures of one chapter, collected from all of its sections
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChapterProcedures {
+    pub chapter: String,
+    pub procedures: Vec<Procedure>,
+}
+
+impl ChapterProcedures {
+    pub fn new(chapter: String) -> Self {
+        ChapterProcedures {
+            chapter,
+            ..Default::default()
+        }
+    }
+
+    /// Adds one section's procedures; a procedure split across sections arrives in parts under
+    /// the same name and is joined back together
+    ///
+    /// Parts without steps are kept too, as the materials and name of a procedure often come a
+    /// section before its steps. Procedures that never get any steps are left out when written.
+    pub fn merge_section(&mut self, value: &Value) {
+        let Some(items) = value.get("procedures").and_then(Value::as_array) else {
+            return;
+        };
+        for item in items {
+            let Ok(procedure) = serde_json::from_value::<Procedure>(item.clone()) else {
+                continue;
+            };
+            if procedure.name.trim().is_empty() {
+                continue;
+            }
+            match self
+                .procedures
+                .iter_mut()
+                .find(|existing| existing.name.eq_ignore_ascii_case(procedure.name.trim()))
+            {
+                Some(existing) => existing.extend(procedure),
+                None => self.procedures.push(Procedure {
+                    name: procedure.name.trim().to_string(),
+                    ..procedure
+                }),
+            }
+        }
+    }
+}
+
+impl Procedure {
+    fn extend(&mut self, part: Procedure) {
+        for (field, value) in [
+            (&mut self.kind, part.kind),
+            (&mut self.yields, part.yields),
+            (&mut self.total_time, part.total_time),
+        ] {
+            if field.is_empty() {
+                *field = value;
+            }
+        }
+        for material in part.materials {
+            if !self.materials.contains(&material) {
+                self.materials.push(material);
+            }
+        }
+        for step in part.steps {
+            if !self.steps.contains(&step) {
+                self.steps.push(step);
+            }
+        }
+        for note in part.notes {
+            if !self.notes.contains(&note) {
+                self.notes.push(note);
+            }
+        }
+    }
+}
+
+/// Writes `procedures.json` for other tools and a printable `procedures.md`, returning the
+/// Markdown path
+pub fn write_procedures(
+    output_dir: &Path,
+    title: &str,
+    chapters: &[ChapterProcedures],
+) -> Result<PathBuf> {
+    let chapters: Vec<ChapterProcedures> = chapters
+        .iter()
+        .map(|chapter| ChapterProcedures {
+            chapter: chapter.chapter.clone(),
+            procedures: chapter
+                .procedures
+                .iter()
+                .filter(|procedure| !procedure.steps.is_empty())
+                .cloned()
+                .collect(),
+        })
+        .filter(|chapter| !chapter.procedures.is_empty())
+        .collect();
+    schema::write_json(
+        &output_dir.join("procedures.json"),
+        &json!({ "chapters": chapters }),
+    )?;
+
+    let mut doc = format!("# Procedures: {}\n\n", title);
+    if chapters.is_empty() {
+        doc.push_str("No recipes or procedures were found.\n");
+    }
+    for chapter in &chapters {
+        doc.push_str(&format!("## {}\n\n", chapter.chapter));
+        for procedure in &chapter.procedures {
+            doc.push_str(&format!("### {}\n\n", procedure.name));
+            let facts: Vec<String> = [
+                ("Yields", &procedure.yields),
+                ("Total time", &procedure.total_time),
+            ]
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(label, value)| format!("**{}:** {}", label, value))
+            .collect();
+            if !facts.is_empty() {
+                doc.push_str(&format!("{}\n\n", facts.join(" · ")));
+            }
+            if !procedure.materials.is_empty() {
+                let heading = if procedure.kind.eq_ignore_ascii_case("recipe") {
+                    "Ingredients"
+                } else {
+                    "Materials"
+                };
+                doc.push_str(&format!("**{}**\n\n", heading));
+                for material in &procedure.materials {
+                    doc.push_str(&format!("- [ ] {}\n", material));
+                }
+                doc.push('\n');
+            }
+            doc.push_str("**Steps**\n\n");
+            for (index, step) in procedure.steps.iter().enumerate() {
+                if step.duration.is_empty() {
+                    doc.push_str(&format!("{}. {}\n", index + 1, step.instruction));
+                } else {
+                    doc.push_str(&format!(
+                        "{}. {} *({})*\n",
+                        index + 1,
+                        step.instruction,
+                        step.duration
+                    ));
+                }
+            }
+            doc.push('\n');
+            if !procedure.notes.is_empty() {
+                doc.push_str("**Notes**\n\n");
+                for note in &procedure.notes {
+                    doc.push_str(&format!("- {}\n", note));
+                }
+                doc.push('\n');
+            }
+        }
+    }
+
+    let path = output_dir.join("procedures.md");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
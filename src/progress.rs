@@ -0,0 +1,290 @@
+use crate::pause::Pause;
+use crate::stats;
+use anyhow::Result;
+use indicatif::ProgressBar;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Where a run stands, kept up to date in the `--progress-file` for programs driving aibook
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProgressFile {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// 1-based position of the current book in the batch
+    pub book: usize,
+    pub books: usize,
+    pub input: String,
+    /// Output directory of the current book, where its summary is written
+    pub output_dir: String,
+    /// `planning`, `summarizing`, `writing`, `done` or `failed`
+    pub stage: &'static str,
+    pub chapters_done: u64,
+    pub chapters_total: u64,
+    /// Title of the last chapter summarized
+    pub chapter: String,
+}
+
+impl ProgressFile {
+    /// Tracks progress; nothing is written when `path` is `None`
+    pub fn new(path: Option<PathBuf>, books: usize) -> Self {
+        ProgressFile {
+            path,
+            books,
+            ..Default::default()
+        }
+    }
+
+    pub fn start_book(&mut self, input: &Path, output_dir: &Path, chapters_total: u64) {
+        self.book += 1;
+        self.input = input.display().to_string();
+        self.output_dir = output_dir.display().to_string();
+        self.stage = "planning";
+        self.chapters_done = 0;
+        self.chapters_total = chapters_total;
+        self.chapter.clear();
+        self.save();
+    }
+
+    pub fn finish_chapter(&mut self, title: &str, chapters_total: u64) {
+        self.chapters_done += 1;
+        self.chapters_total = chapters_total.max(self.chapters_done);
+        self.chapter = title.to_string();
+        self.save();
+    }
+
+    pub fn set_stage(&mut self, stage: &'static str) {
+        self.stage = stage;
+        self.save();
+    }
+
+    /// Replaces the file in one step, so a reader never sees half of it; a failed write only
+    /// costs the reader an update, so it is logged rather than stopping the run
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let temporary = path.with_extension("tmp");
+        let written = serde_json::to_string(self)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&temporary, json)?))
+            .and_then(|()| Ok(fs::rename(&temporary, path)?));
+        if let Err(e) = written {
+            warn!("Could not update progress file '{}': {}", path.display(), e);
+        }
+    }
+}
+
+/// Longest chapter title shown in the progress bar before it is cut short
+const MAX_TITLE_CHARS: usize = 50;
+/// Sections the rolling latency and speed are averaged over
+const ETA_WINDOW: usize = 20;
+
+/// Time remaining, projected from the latency of the last sections summarized
+///
+/// Chapters not read yet are assumed to need as many requests as the chapters so far, and
+/// books not started yet as long as the books so far (or the current one).
+#[derive(Debug)]
+pub struct Eta {
+    books_left: usize,
+    /// Seconds per summarized section, most recent last
+    latencies: VecDeque<f64>,
+    tokens_per_second: VecDeque<f64>,
+    book_started: Instant,
+    book_seconds: Vec<f64>,
+    /// Sections requested for each finished chapter of the current book
+    chapter_sections: Vec<usize>,
+    sections_in_chapter: usize,
+}
+
+impl Eta {
+    pub fn new(books: usize) -> Self {
+        Eta {
+            books_left: books,
+            latencies: VecDeque::new(),
+            tokens_per_second: VecDeque::new(),
+            book_started: Instant::now(),
+            book_seconds: Vec::new(),
+            chapter_sections: Vec::new(),
+            sections_in_chapter: 0,
+        }
+    }
+
+    pub fn start_book(&mut self) {
+        self.books_left = self.books_left.saturating_sub(1);
+        self.book_started = Instant::now();
+        self.chapter_sections.clear();
+        self.sections_in_chapter = 0;
+    }
+
+    pub fn finish_book(&mut self) {
+        self.book_seconds
+            .push(self.book_started.elapsed().as_secs_f64());
+    }
+
+    /// Records one summarized section and the completion tokens it produced
+    pub fn finish_section(&mut self, elapsed: Duration, completion_tokens: u64) {
+        let seconds = elapsed.as_secs_f64();
+        push_window(&mut self.latencies, seconds);
+        if completion_tokens > 0 && seconds > 0.0 {
+            push_window(
+                &mut self.tokens_per_second,
+                completion_tokens as f64 / seconds,
+            );
+        }
+        self.sections_in_chapter += 1;
+    }
+
+    pub fn finish_chapter(&mut self) {
+        self.chapter_sections.push(self.sections_in_chapter);
+        self.sections_in_chapter = 0;
+    }
+
+    /// Time left in the current book, given the sections still to request in this chapter and
+    /// the chapters after it; `None` until a section has been timed
+    pub fn book_remaining(&self, sections_left: usize, chapters_left: u64) -> Option<Duration> {
+        let latency = average(&self.latencies)?;
+        let sections_per_chapter = if self.chapter_sections.is_empty() {
+            // Only the current chapter to go by
+            (self.sections_in_chapter + sections_left) as f64
+        } else {
+            self.chapter_sections.iter().sum::<usize>() as f64 / self.chapter_sections.len() as f64
+        };
+        let sections = sections_left as f64 + sections_per_chapter * chapters_left as f64;
+        Some(Duration::from_secs_f64(sections * latency))
+    }
+
+    /// Time left in the whole batch, given the time left in the current book
+    pub fn batch_remaining(&self, book_remaining: Duration) -> Duration {
+        let book_seconds = average(&self.book_seconds).unwrap_or_else(|| {
+            self.book_started.elapsed().as_secs_f64() + book_remaining.as_secs_f64()
+        });
+        book_remaining + Duration::from_secs_f64(book_seconds * self.books_left as f64)
+    }
+
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        average(&self.tokens_per_second)
+    }
+
+    /// Whether more books follow the current one
+    pub fn has_more_books(&self) -> bool {
+        self.books_left > 0
+    }
+}
+
+fn push_window(window: &mut VecDeque<f64>, value: f64) {
+    if window.len() == ETA_WINDOW {
+        window.pop_front();
+    }
+    window.push_back(value);
+}
+
+fn average<'a>(values: impl IntoIterator<Item = &'a f64>) -> Option<f64> {
+    let (sum, count) = values
+        .into_iter()
+        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// "<1 min", "12 min" or "1 h 5 min"
+fn format_remaining(remaining: Duration) -> String {
+    if remaining < Duration::from_secs(60) {
+        "<1 min".to_string()
+    } else {
+        stats::format_duration(remaining.as_secs_f64() / 60.0)
+    }
+}
+
+/// Stores a chapter's finished section summaries before the run pauses
+pub type Checkpoint<'a> = dyn Fn(&[Value]) -> Result<()> + 'a;
+
+/// The progress bar while one chapter is summarized, naming the chapter and the section in
+/// progress with the time left: "3/18 — The Tyranny of Metrics §2/5 · ETA 12 min · 41 tok/s"
+///
+/// With a [`Pause`], the run waits between sections while the user has it paused, after
+/// handing the sections finished so far to the checkpoint so they are not lost if the run is
+/// then stopped.
+pub struct ChapterProgress<'a> {
+    pub bar: &'a ProgressBar,
+    pub eta: &'a mut Eta,
+    title: String,
+    pause: Option<&'a Pause>,
+    checkpoint: Option<&'a Checkpoint<'a>>,
+}
+
+impl<'a> ChapterProgress<'a> {
+    pub fn new(bar: &'a ProgressBar, eta: &'a mut Eta, title: &str) -> Self {
+        ChapterProgress {
+            bar,
+            eta,
+            title: stats::truncate(title, MAX_TITLE_CHARS),
+            pause: None,
+            checkpoint: None,
+        }
+    }
+
+    pub fn with_pause(mut self, pause: Option<&'a Pause>, checkpoint: &'a Checkpoint<'a>) -> Self {
+        self.pause = pause;
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Waits while the run is paused, saving the chapter's `finished` section summaries first
+    pub async fn pause_point(&self, finished: &[Value]) -> Result<()> {
+        let Some(pause) = self.pause.filter(|pause| pause.is_paused()) else {
+            return Ok(());
+        };
+        if let Some(checkpoint) = self.checkpoint {
+            checkpoint(finished)?;
+        }
+        info!(
+            "Paused after {} section(s) of '{}'",
+            finished.len(),
+            self.title
+        );
+        self.bar
+            .set_message(format!("— {} · paused, press r to resume", self.title));
+        pause.resumed().await;
+        info!("Resumed");
+        Ok(())
+    }
+
+    /// Shows the section being summarized and the time left, followed by the provider's
+    /// outage status if any; `sections_left` counts the sections still to request, this one
+    /// included
+    pub fn section(
+        &self,
+        index: usize,
+        total: usize,
+        sections_left: usize,
+        status: Option<String>,
+    ) {
+        let mut message = format!("— {} §{}/{}", self.title, index + 1, total);
+        // The bar counts finished chapters, so the current one is not among those left
+        let chapters_left = self
+            .bar
+            .length()
+            .unwrap_or_default()
+            .saturating_sub(self.bar.position() + 1);
+        if let Some(remaining) = self.eta.book_remaining(sections_left, chapters_left) {
+            message.push_str(&format!(" · ETA {}", format_remaining(remaining)));
+            if self.eta.has_more_books() {
+                message.push_str(&format!(
+                    " (batch {})",
+                    format_remaining(self.eta.batch_remaining(remaining))
+                ));
+            }
+        }
+        if let Some(speed) = self.eta.tokens_per_second() {
+            message.push_str(&format!(" · {:.0} tok/s", speed));
+        }
+        if let Some(status) = status {
+            message.push_str(&format!(" ({})", status));
+        }
+        self.bar.set_message(message);
+    }
+}
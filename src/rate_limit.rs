@@ -0,0 +1,240 @@
+use crate::summarizer;
+use async_trait::async_trait;
+use http::Extensions;
+use log::{debug, info};
+use reqwest::header::HeaderMap;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiktoken_rs::CoreBPE;
+
+/// Client-side requests-per-minute and tokens-per-minute limits for one provider
+///
+/// Both limits are token buckets refilled continuously; a limit of 0 disables it. When the
+/// provider reports an exhausted quota in its rate-limit headers, requests also wait for the
+/// advertised reset.
+pub struct RateLimiter {
+    bpe: Option<&'static CoreBPE>,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    /// Set from response headers when the provider says the quota is used up
+    blocked_until: Option<Instant>,
+}
+
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32) -> Option<Self> {
+        (limit > 0).then(|| Bucket {
+            capacity: limit as f64,
+            available: limit as f64,
+            per_second: limit as f64 / 60.0,
+            refilled_at: Instant::now(),
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Time until `amount` is available; requests larger than the bucket only wait for a full one
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_second)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        RateLimiter {
+            bpe: (tokens_per_minute > 0).then(|| &*summarizer::BPE),
+            state: Mutex::new(LimiterState {
+                requests: Bucket::per_minute(requests_per_minute),
+                tokens: Bucket::per_minute(tokens_per_minute),
+                blocked_until: None,
+            }),
+        }
+    }
+
+    /// Reserves one request and `tokens` tokens, or returns how long to wait before trying again
+    fn try_acquire(&self, tokens: f64) -> Duration {
+        let Ok(mut state) = self.state.lock() else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+
+        let mut wait = state
+            .blocked_until
+            .and_then(|until| until.checked_duration_since(now))
+            .unwrap_or_default();
+        if let Some(bucket) = state.requests.as_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(1.0));
+        }
+        if let Some(bucket) = state.tokens.as_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(tokens));
+        }
+
+        if wait.is_zero() {
+            state.blocked_until = None;
+            if let Some(bucket) = state.requests.as_mut() {
+                bucket.take(1.0);
+            }
+            if let Some(bucket) = state.tokens.as_mut() {
+                bucket.take(tokens);
+            }
+        }
+        wait
+    }
+
+    /// Honors `x-ratelimit-remaining-*`/`x-ratelimit-reset-*` headers advertised by the provider
+    fn observe(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let exhausted = [
+            (
+                "x-ratelimit-remaining-requests",
+                "x-ratelimit-reset-requests",
+            ),
+            ("x-ratelimit-remaining-tokens", "x-ratelimit-reset-tokens"),
+            ("x-ratelimit-remaining", "x-ratelimit-reset"),
+        ]
+        .iter()
+        .filter(|(remaining, _)| header(remaining).is_some_and(|v| v.trim() == "0"))
+        .filter_map(|(_, reset)| header(reset).and_then(parse_reset))
+        .max();
+
+        if let (Some(reset), Ok(mut state)) = (exhausted, self.state.lock()) {
+            info!(
+                "Provider rate limit reached, pausing requests for {}s",
+                reset.as_secs() + 1
+            );
+            state.blocked_until = Some(Instant::now() + reset);
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimiter {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let tokens = match (&self.bpe, req.body().and_then(|body| body.as_bytes())) {
+            (Some(bpe), Some(body)) => {
+                bpe.encode_ordinary(&String::from_utf8_lossy(body)).len() as f64
+            }
+            _ => 0.0,
+        };
+
+        loop {
+            let wait = self.try_acquire(tokens);
+            if wait.is_zero() {
+                break;
+            }
+            debug!("Rate limit: waiting {:.1}s", wait.as_secs_f64());
+            tokio::time::sleep(wait).await;
+        }
+
+        let response = next.run(req, extensions).await?;
+        self.observe(response.headers());
+        Ok(response)
+    }
+}
+
+/// Parses a reset header: Go-style durations ("1s", "6m0s", "20ms"), seconds, or epoch milliseconds
+fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(number) = value.parse::<u64>() {
+        // OpenRouter sends the reset time as a Unix timestamp in milliseconds
+        if number > 1_000_000_000_000 {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            return Some(Duration::from_millis(number).saturating_sub(now));
+        }
+        return Some(Duration::from_secs(number));
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = std::mem::take(&mut number).parse().ok()?;
+        total += match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+    }
+    if value.is_empty() || !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_times_are_parsed() {
+        for (value, expected) in [
+            ("1s", Some(Duration::from_secs(1))),
+            ("6m0s", Some(Duration::from_secs(360))),
+            ("200ms", Some(Duration::from_millis(200))),
+            ("1h2m3s", Some(Duration::from_secs(3723))),
+            ("1.5s", Some(Duration::from_millis(1500))),
+            (" 30 ", Some(Duration::from_secs(30))),
+            ("", None),
+            ("s", None),
+            ("10", Some(Duration::from_secs(10))),
+            ("1m30", None),
+            ("5x", None),
+            ("1.2.3s", None),
+            ("soon", None),
+            ("-1s", None),
+        ] {
+            assert_eq!(parse_reset(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn millisecond_timestamps_count_down_from_now() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let reset = (now + Duration::from_secs(30)).as_millis().to_string();
+        let wait = parse_reset(&reset).unwrap();
+        assert!(wait <= Duration::from_secs(30) && wait > Duration::from_secs(25));
+        assert_eq!(parse_reset("1000000000001"), Some(Duration::ZERO));
+    }
+}
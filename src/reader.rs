@@ -0,0 +1,164 @@
+use crate::audio::{self, TranscriptionOptions};
+use crate::djvu;
+use crate::ebook;
+use crate::extract::ExtractionOptions;
+use crate::fb2;
+use crate::images::{self, ImageOptions, ImageReference};
+use crate::metadata::BookMetadata;
+use crate::ocr::{self, OcrOptions};
+use crate::office;
+use crate::subtitles;
+use crate::web;
+use anyhow::Result;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A logical chapter of the e-book
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    /// Heading text, when the chapter was split out of a larger document
+    pub title: Option<String>,
+    pub text: String,
+    /// Images the chapter shows, in order, so the summary can carry them as figures
+    pub images: Vec<ImageReference>,
+}
+
+/// Lazily extracted chapters; a chapter that fails to convert yields an error and iteration goes on
+pub type ChapterIter<'a> = Box<dyn Iterator<Item = Result<Chapter>> + 'a>;
+
+/// An input format the summarization pipeline can read
+///
+/// EPUB is the first implementation; other formats (and test fakes) plug into the same pipeline
+/// by implementing this trait and adding a branch to [`open`].
+pub trait EbookReader {
+    /// Chapters in reading order, extracted as the iterator advances
+    fn chapters(&mut self) -> ChapterIter<'_>;
+
+    /// Entry titles of the table of contents
+    fn toc(&self) -> Vec<String>;
+
+    fn metadata(&self) -> &BookMetadata;
+
+    /// Saves the book's images to `images_dir`, applying the image filters
+    fn images(&mut self, images_dir: &Path, options: &ImageOptions) -> Result<()>;
+
+    /// Expected number of chapters, used to size the progress bar
+    fn chapter_count_hint(&self) -> usize;
+
+    /// Cover image data and its MIME type, if the book has one
+    fn cover(&mut self) -> Option<(Vec<u8>, String)> {
+        None
+    }
+
+    /// Plain text from the start of the book, for cheap checks such as language detection
+    fn text_sample(&mut self, max_chars: usize) -> String {
+        let mut sample = String::new();
+        for chapter in self.chapters().flatten() {
+            if sample.len() >= max_chars {
+                break;
+            }
+            sample.push_str(&chapter.text);
+            sample.push('\n');
+        }
+        sample
+    }
+
+    /// Whether the text was recognized from page scans and may contain OCR errors
+    fn is_ocr(&self) -> bool {
+        false
+    }
+
+    /// Markdown notes on extraction quality, written next to the summary when present
+    fn extraction_report(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Opens a book with the reader matching its file extension
+pub fn open(path: &Path, options: &ExtractionOptions) -> Result<Box<dyn EbookReader>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "epub" | "" => Ok(Box::new(ebook::open_ebook(path, options)?)),
+        "fb2" => Ok(Box::new(fb2::open_fb2(path, options)?)),
+        "zip" if path.to_string_lossy().to_lowercase().ends_with(".fb2.zip") => {
+            Ok(Box::new(fb2::open_fb2(path, options)?))
+        }
+        "docx" => Ok(Box::new(office::open_docx(path, options)?)),
+        "odt" => Ok(Box::new(office::open_odt(path, options)?)),
+        "djvu" | "djv" => Ok(Box::new(djvu::open_djvu(path)?)),
+        "srt" | "vtt" => Ok(Box::new(subtitles::open(path)?)),
+        // Unknown extensions (".kepub", ".book", a misnamed download) are recognized by their
+        // content, and read as EPUB as they always were when nothing else matches
+        _ => match sniff(path) {
+            Some("fb2") => Ok(Box::new(fb2::open_fb2(path, options)?)),
+            Some("docx") => Ok(Box::new(office::open_docx(path, options)?)),
+            Some("odt") => Ok(Box::new(office::open_odt(path, options)?)),
+            Some("djvu") => Ok(Box::new(djvu::open_djvu(path)?)),
+            _ => Ok(Box::new(ebook::open_ebook(path, options)?)),
+        },
+    }
+}
+
+/// The format of a file recognized from its first bytes, for files without a known extension
+fn sniff(path: &Path) -> Option<&'static str> {
+    let mut head = [0u8; 4096];
+    let read = fs::File::open(path).ok()?.read(&mut head).ok()?;
+    let head = &head[..read];
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|window| window == needle);
+
+    if head.starts_with(b"AT&TFORM") {
+        Some("djvu")
+    } else if head.starts_with(b"PK\x03\x04") {
+        // ZIP containers name their first entries in the local header
+        if contains(b"application/vnd.oasis.opendocument.text") {
+            Some("odt")
+        } else if contains(b"word/") || contains(b"[Content_Types].xml") {
+            Some("docx")
+        } else if contains(b".fb2") {
+            Some("fb2")
+        } else {
+            Some("epub")
+        }
+    } else if contains(b"<FictionBook") {
+        Some("fb2")
+    } else {
+        None
+    }
+}
+
+/// Opens a local book, fetches the article behind an http(s) URL, transcribes an audio file or
+/// recognizes a scanned PDF or comic archive
+pub async fn open_input(
+    input: &Path,
+    client: &reqwest::Client,
+    options: &ExtractionOptions,
+    transcription: &TranscriptionOptions,
+    ocr: &OcrOptions,
+) -> Result<Box<dyn EbookReader>> {
+    if let Some(url) = web::url(input) {
+        Ok(Box::new(web::fetch_article(url, client, options).await?))
+    } else if audio::is_audio(input) {
+        Ok(Box::new(audio::transcribe(input, transcription).await?))
+    } else if ocr::is_scanned(input) {
+        Ok(Box::new(ocr::open_scanned(input, ocr)?))
+    } else {
+        open(input, options)
+    }
+}
+
+/// Saves the book's cover image as `original_cover.<ext>`, returning its path if the book has one
+pub fn save_cover(reader: &mut dyn EbookReader, output_dir: &Path) -> Result<Option<PathBuf>> {
+    let Some((data, mime)) = reader.cover() else {
+        return Ok(None);
+    };
+
+    let cover_path = output_dir.join(format!("original_cover.{}", images::extension(&mime)));
+    fs::write(&cover_path, data)?;
+
+    Ok(Some(cover_path))
+}
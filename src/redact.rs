@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::Value;
+
+/// What matched text is replaced with, as for secret HTTP headers
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Shapes of API keys and tokens worth scrubbing even when aibook does not know their value
+const SECRET_PATTERNS: &[&str] = &[
+    r"\bsk-[A-Za-z0-9_-]{20,}",
+    r"\bgsk_[A-Za-z0-9]{20,}",
+    r"\bBearer\s+[A-Za-z0-9._~+/-]{16,}=*",
+];
+pub const EMAIL_PATTERN: &str =
+    r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b";
+
+/// Scrubs API keys, email addresses and user-configured patterns from what aibook keeps on
+/// disk about a run: logs, recordings, run manifests and run reports
+///
+/// Cached section summaries are left as written, since `--incremental` puts them back into the
+/// book; `--encrypt-cache` protects those instead.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    /// Exact secrets in use, such as the configured API keys
+    secrets: Vec<String>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Redactor::new(Vec::new(), &[]).expect("built-in redaction patterns are valid")
+    }
+}
+
+impl Redactor {
+    /// `secrets` are removed wherever they appear, `patterns` are regular expressions of
+    /// anything else that must not be kept
+    pub fn new(secrets: Vec<String>, patterns: &[String]) -> Result<Self> {
+        let mut compiled = SECRET_PATTERNS
+            .iter()
+            .chain([&EMAIL_PATTERN])
+            .map(|pattern| Regex::new(pattern).map_err(anyhow::Error::from))
+            .collect::<Result<Vec<_>>>()?;
+        for pattern in patterns {
+            compiled.push(
+                Regex::new(pattern)
+                    .map_err(|e| anyhow!("Invalid redaction pattern '{}': {}", pattern, e))?,
+            );
+        }
+        // Short values would blank out ordinary words, and longer ones go first so a key
+        // containing another is removed whole
+        let mut secrets: Vec<String> = secrets
+            .into_iter()
+            .filter(|secret| secret.trim().len() >= 8)
+            .collect();
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        Ok(Redactor {
+            patterns: compiled,
+            secrets,
+        })
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&text) {
+                text = pattern.replace_all(&text, REDACTED).into_owned();
+            }
+        }
+        text
+    }
+
+    /// Redacts a payload that is usually JSON, such as an HTTP body, keeping valid JSON valid
+    pub fn redact_payload(&self, text: &str) -> String {
+        match serde_json::from_str::<Value>(text) {
+            Ok(mut value) => {
+                self.redact_json(&mut value);
+                value.to_string()
+            }
+            Err(_) => self.redact(text),
+        }
+    }
+
+    /// Redacts every string in a JSON value, leaving its structure intact
+    pub fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_json(item)),
+            Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|field| self.redact_json(field)),
+            _ => {}
+        }
+    }
+}
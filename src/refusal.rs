@@ -0,0 +1,84 @@
+use regex::Regex;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// A summary field longer than this is taken as a summary even if it opens apologetically
+const MAX_REFUSAL_CHARS: usize = 500;
+
+/// Stands in for the summary of a section no model would summarize
+pub const NOTICE: &str = "*[A passage of this chapter was not summarized: the model declined it.]*";
+
+/// A first-person apology or "I can't ..." opening a reply; refusals say so up front
+static REFUSAL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^\W*(?:I['’]?m sorry|I am sorry|sorry, |I apologi[sz]e|I (?:can(?:no|['’])t|can not|won['’]?t|am unable to|['’]m unable to|am not able to) (?:help|assist|summari[sz]e|provide|comply|fulfil|process|continue|do that|engage))",
+    )
+    .unwrap()
+});
+
+/// The provider or the model declined to process the prompt (a safety block, moderation flag or
+/// "I can't help with that" reply) rather than failing
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Whether an error is a refusal, as opposed to a network, quota or parsing failure
+pub fn is_refused(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Refused>().is_some()
+}
+
+/// Whether a reply reads like a refusal: it opens with an apology or "I can't ..."
+///
+/// Only first-person wording at the start counts, so a summary of a book about AI, content
+/// moderation or safety policies, or one quoting a character who says "I can't help", is kept.
+pub fn looks_like_refusal(text: &str) -> bool {
+    REFUSAL.is_match(text.trim())
+}
+
+/// Whether a parsed section summary is a refusal dressed up as JSON: a short `summary` that
+/// opens with refusal wording
+pub fn is_refusal_summary(summary: &str) -> bool {
+    summary.chars().count() <= MAX_REFUSAL_CHARS && looks_like_refusal(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refusals_are_recognized() {
+        for text in [
+            "I'm sorry, but I can't help with that.",
+            "Sorry, I cannot summarize this passage.",
+            "I am unable to assist with this request.",
+            "**I can’t summarize this content.**",
+        ] {
+            assert!(looks_like_refusal(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn summaries_are_not_refusals() {
+        for text in [
+            "The narrator returns to the village and finds it empty.",
+            "The chapter explains how the author learned to cook.",
+            "The author compares the content policies of social networks.",
+            "As an AI researcher, the narrator doubts the safety policy.",
+            "Maria tells him: \"I can't help you any more.\"",
+        ] {
+            assert!(!looks_like_refusal(text), "{}", text);
+        }
+        let long = format!(
+            "I'm sorry to say the harvest failed. {}",
+            "The rains came late. ".repeat(40)
+        );
+        assert!(!is_refusal_summary(&long));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::redact::Redactor;
+use crate::schema;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// How long each chapter of a book took, for tuning chunk sizes against real timings
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    pub run_id: String,
+    /// Token limit sections were split at
+    pub section_tokens: usize,
+    /// Tokens of the whole summary plan; each section prompt only carries its chapter's entry
+    pub plan_tokens: usize,
+    /// Tokens the book outlines added to the section requests on top of their chapter's entry
+    pub outline_tokens: usize,
+    pub chapters: Vec<ChapterTiming>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterTiming {
+    pub title: String,
+    pub sections: usize,
+    /// Sections whose summary was reused from an earlier run instead of requested
+    pub cached_sections: usize,
+    pub started_at: String,
+    pub finished_at: String,
+    pub seconds: f64,
+    /// Times the chapter was summarized, more than 1 when `--on-chapter-error retry:N` retried,
+    /// and 0 when its text could not be extracted
+    pub attempts: usize,
+    /// Why the chapter was skipped, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunReport {
+    pub fn new(run_id: &str, section_tokens: usize) -> Self {
+        RunReport {
+            run_id: run_id.to_string(),
+            section_tokens,
+            plan_tokens: 0,
+            outline_tokens: 0,
+            chapters: Vec::new(),
+        }
+    }
+
+    /// Records a chapter started at `started_at` as finished now
+    pub fn finish_chapter(
+        &mut self,
+        title: &str,
+        started_at: DateTime<Utc>,
+        sections: usize,
+        cached_sections: usize,
+        attempts: usize,
+    ) {
+        let timing = ChapterTiming::until_now(title, started_at, sections, attempts);
+        self.chapters.push(ChapterTiming {
+            cached_sections,
+            ..timing
+        });
+    }
+
+    /// Records a chapter started at `started_at` as given up on now, after `attempts` failures
+    pub fn skip_chapter(
+        &mut self,
+        title: &str,
+        started_at: DateTime<Utc>,
+        sections: usize,
+        attempts: usize,
+        error: &str,
+    ) {
+        let timing = ChapterTiming::until_now(title, started_at, sections, attempts);
+        self.chapters.push(ChapterTiming {
+            error: Some(error.to_string()),
+            ..timing
+        });
+    }
+
+    /// Counts the outline tokens of `requests` section requests that each carried an outline of
+    /// `outline_tokens`
+    pub fn outline_sent(&mut self, outline_tokens: usize, requests: usize) {
+        self.outline_tokens += outline_tokens * requests;
+    }
+
+    /// Chapters skipped because their text could not be extracted or `--on-chapter-error` gave
+    /// up on their summary
+    pub fn skipped(&self) -> usize {
+        self.chapters
+            .iter()
+            .filter(|chapter| chapter.error.is_some())
+            .count()
+    }
+
+    /// Writes `run_report.json`, returning its path
+    ///
+    /// Titles and errors are redacted, as error messages can quote API keys, paths or the
+    /// provider's responses.
+    pub fn write(&self, output_dir: &Path, redactor: &Redactor) -> Result<PathBuf> {
+        let path = output_dir.join("run_report.json");
+        let mut report = serde_json::to_value(self)?;
+        redactor.redact_json(&mut report);
+        schema::write_json(&path, &report)?;
+        Ok(path)
+    }
+}
+
+impl ChapterTiming {
+    fn until_now(title: &str, started_at: DateTime<Utc>, sections: usize, attempts: usize) -> Self {
+        let finished_at = Utc::now();
+        ChapterTiming {
+            title: title.to_string(),
+            sections,
+            cached_sections: 0,
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            seconds: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            attempts,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_report_is_redacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let redactor = Redactor::new(vec!["sk-secret".to_string()], &["Jane".to_string()]).unwrap();
+        let mut report = RunReport::new("run", 2000);
+        report.skip_chapter(
+            "Letters to Jane",
+            Utc::now(),
+            1,
+            3,
+            "HTTP 401: invalid key sk-secret, contact ops@example.com",
+        );
+
+        let written =
+            std::fs::read_to_string(report.write(dir.path(), &redactor).unwrap()).unwrap();
+        for text in ["Jane", "sk-secret", "ops@example.com"] {
+            assert!(!written.contains(text), "{} in {}", text, written);
+        }
+        assert!(written.contains("Letters to [REDACTED]"));
+    }
+}
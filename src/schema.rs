@@ -0,0 +1,117 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version of the JSON files written to a book's output directory
+///
+/// - 0: files written before versioning, without a `schema_version` field
+/// - 1: every file starts with `schema_version`, and `argument_map.json` and `procedures.json`
+///   hold their chapters under `chapters` instead of being bare arrays
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// JSON files of a book directory that carry the schema version
+pub const VERSIONED_FILES: &[&str] = &[
+    "argument_map.json",
+    "content_warnings.json",
+    "evaluation.json",
+    "manifest.json",
+    "marketing.json",
+    "metadata.json",
+    "procedures.json",
+    "run_manifest.json",
+    "run_report.json",
+    "sentiment.json",
+    "state.json",
+    "summary.json",
+];
+
+#[derive(Serialize)]
+struct Versioned<'a, T: Serialize> {
+    schema_version: u64,
+    #[serde(flatten)]
+    content: &'a T,
+}
+
+/// Writes `value`, which must serialize as an object, with `schema_version` as its first field
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let versioned = Versioned {
+        schema_version: SCHEMA_VERSION,
+        content: value,
+    };
+    fs::write(path, serde_json::to_string_pretty(&versioned)?)?;
+    Ok(())
+}
+
+/// Schema version a file was written with; files from before versioning are version 0
+pub fn version(value: &Value) -> u64 {
+    value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Brings the content of `file_name` up to the current schema, step by step
+pub fn upgrade(file_name: &str, mut value: Value) -> Result<Value> {
+    let from = version(&value);
+    if from > SCHEMA_VERSION {
+        bail!(
+            "'{}' has schema version {}, newer than this aibook supports ({}); upgrade aibook",
+            file_name,
+            from,
+            SCHEMA_VERSION
+        );
+    }
+    if from < 1 {
+        if let Value::Array(chapters) = value {
+            value = Value::Object(Map::from_iter([(
+                "chapters".to_string(),
+                Value::Array(chapters),
+            )]));
+        }
+    }
+    match &mut value {
+        Value::Object(object) => {
+            object.remove("schema_version");
+        }
+        _ => bail!("'{}' is not a JSON object", file_name),
+    }
+    Ok(value)
+}
+
+/// Rewrites the versioned files of a book directory that are older than the current schema,
+/// returning their names
+pub fn migrate_book(book_dir: &Path) -> Result<Vec<String>> {
+    let mut migrated = Vec::new();
+    for &file_name in VERSIONED_FILES {
+        let path = book_dir.join(file_name);
+        if !path.is_file() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("could not parse '{}'", path.display()))?;
+        if version(&value) == SCHEMA_VERSION {
+            continue;
+        }
+        write_json(&path, &upgrade(file_name, value)?)?;
+        migrated.push(file_name.to_string());
+    }
+    Ok(migrated)
+}
+
+/// Book directories under `dir`: `dir` itself when it holds versioned files, otherwise its
+/// subdirectories that do
+pub fn book_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let is_book = |path: &Path| VERSIONED_FILES.iter().any(|name| path.join(name).is_file());
+    if is_book(dir) {
+        return Ok(vec![dir.to_path_buf()]);
+    }
+    let mut books: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("could not read '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir() && is_book(path))
+        .collect();
+    books.sort();
+    Ok(books)
+}
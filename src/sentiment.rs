@@ -0,0 +1,223 @@
+use crate::output::ChapterSummary;
+use crate::schema;
+use crate::text::escape_html;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Chart size in SVG user units
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 220.0;
+const CHART_MARGIN: f64 = 30.0;
+
+/// Emotional tone and themes of one chapter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChapterMood {
+    /// From -1.0 (bleak) to 1.0 (joyful)
+    pub sentiment: f64,
+    pub tone: String,
+    pub themes: Vec<String>,
+}
+
+/// Parses the sentiment prompt's response, clamping the score into range
+pub fn parse(value: &Value) -> Option<ChapterMood> {
+    let sentiment = value.get("sentiment")?.as_f64()?.clamp(-1.0, 1.0);
+    let themes = value
+        .get("themes")
+        .and_then(Value::as_array)
+        .map(|themes| {
+            themes
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|theme| theme.trim().to_string())
+                .filter(|theme| !theme.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ChapterMood {
+        sentiment,
+        tone: value
+            .get("tone")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        themes,
+    })
+}
+
+/// Writes `sentiment.json` and the `sentiment.svg` chart, returning the JSON path
+pub fn write_report(output_dir: &Path, chapters: &[ChapterSummary]) -> Result<PathBuf> {
+    let entries: Vec<Value> = chapters
+        .iter()
+        .filter_map(|c| Some(json!({ "chapter": c.title, "mood": c.mood.as_ref()? })))
+        .collect();
+    let report = json!({
+        "chapters": entries,
+        "recurring_themes": recurring_themes(chapters)
+            .into_iter()
+            .map(|(theme, count)| json!({ "theme": theme, "chapters": count }))
+            .collect::<Vec<_>>(),
+    });
+    fs::write(output_dir.join("sentiment.svg"), chart_svg(chapters))?;
+    let path = output_dir.join("sentiment.json");
+    schema::write_json(&path, &report)?;
+    Ok(path)
+}
+
+/// Themes named in more than one chapter, with how many chapters mention them, most frequent first
+fn recurring_themes(chapters: &[ChapterSummary]) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for mood in chapters.iter().filter_map(|c| c.mood.as_ref()) {
+        for theme in &mood.themes {
+            let theme = theme.to_lowercase();
+            match counts.iter_mut().find(|(name, _)| *name == theme) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((theme, 1)),
+            }
+        }
+    }
+    counts.retain(|(_, count)| *count > 1);
+    // Stable sort keeps first-appearance order among equally frequent themes
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+}
+
+/// "Themes and Sentiment" section with a per-chapter table, empty when no chapter was analyzed
+pub fn markdown_section(chapters: &[ChapterSummary]) -> String {
+    if chapters.iter().all(|c| c.mood.is_none()) {
+        return String::new();
+    }
+    let mut doc = String::from("## Themes and Sentiment\n\n");
+    doc.push_str("| Chapter | Sentiment | Tone | Themes |\n|---|---|---|---|\n");
+    for chapter in chapters {
+        if let Some(mood) = &chapter.mood {
+            doc.push_str(&format!(
+                "| {} | {:+.2} {} | {} | {} |\n",
+                chapter.title.replace('|', "\\|"),
+                mood.sentiment,
+                bar(mood.sentiment),
+                mood.tone.replace('|', "\\|"),
+                mood.themes.join(", ").replace('|', "\\|")
+            ));
+        }
+    }
+    let recurring = recurring_themes(chapters);
+    if !recurring.is_empty() {
+        doc.push_str("\nRecurring themes: ");
+        doc.push_str(
+            &recurring
+                .iter()
+                .map(|(theme, count)| format!("{} ({} chapters)", theme, count))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        doc.push('\n');
+    }
+    doc.push('\n');
+    doc
+}
+
+/// The same section for HTML, led by an inline SVG chart of the sentiment trajectory
+pub fn html_section(chapters: &[ChapterSummary]) -> String {
+    if chapters.iter().all(|c| c.mood.is_none()) {
+        return String::new();
+    }
+    let mut doc = String::from("<h2>Themes and Sentiment</h2>\n");
+    doc.push_str(&chart_svg(chapters));
+    doc.push_str(
+        "<table>\n<tr><th>Chapter</th><th>Sentiment</th><th>Tone</th><th>Themes</th></tr>\n",
+    );
+    for chapter in chapters {
+        if let Some(mood) = &chapter.mood {
+            doc.push_str(&format!(
+                "<tr><td>{}</td><td>{:+.2}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(&chapter.title),
+                mood.sentiment,
+                escape_html(&mood.tone),
+                escape_html(&mood.themes.join(", "))
+            ));
+        }
+    }
+    doc.push_str("</table>\n");
+    let recurring = recurring_themes(chapters);
+    if !recurring.is_empty() {
+        doc.push_str(&format!(
+            "<p>Recurring themes: {}</p>\n",
+            escape_html(
+                &recurring
+                    .iter()
+                    .map(|(theme, count)| format!("{} ({} chapters)", theme, count))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        ));
+    }
+    doc
+}
+
+/// Line chart of sentiment by chapter, from -1 at the bottom to 1 at the top
+pub fn chart_svg(chapters: &[ChapterSummary]) -> String {
+    let points: Vec<(&str, f64)> = chapters
+        .iter()
+        .filter_map(|c| Some((c.title.as_str(), c.mood.as_ref()?.sentiment)))
+        .collect();
+    let plot_width = CHART_WIDTH - 2.0 * CHART_MARGIN;
+    let plot_height = CHART_HEIGHT - 2.0 * CHART_MARGIN;
+    let x = |index: usize| {
+        if points.len() < 2 {
+            CHART_WIDTH / 2.0
+        } else {
+            CHART_MARGIN + plot_width * index as f64 / (points.len() - 1) as f64
+        }
+    };
+    let y = |sentiment: f64| CHART_MARGIN + plot_height * (1.0 - sentiment) / 2.0;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\" role=\"img\" aria-label=\"Sentiment by chapter\">\n",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+    for (label, value) in [("+1", 1.0), ("0", 0.0), ("-1", -1.0)] {
+        svg.push_str(&format!(
+            "<line x1=\"{m}\" y1=\"{y:.1}\" x2=\"{end}\" y2=\"{y:.1}\" stroke=\"#ccc\" stroke-dasharray=\"{dash}\"/>\n<text x=\"{lx}\" y=\"{ty:.1}\" font-size=\"10\" text-anchor=\"end\">{label}</text>\n",
+            m = CHART_MARGIN,
+            end = CHART_WIDTH - CHART_MARGIN,
+            y = y(value),
+            dash = if value == 0.0 { "none" } else { "4 4" },
+            lx = CHART_MARGIN - 6.0,
+            ty = y(value) + 3.0,
+            label = label
+        ));
+    }
+    let path: Vec<String> = points
+        .iter()
+        .enumerate()
+        .map(|(index, (_, sentiment))| format!("{:.1},{:.1}", x(index), y(*sentiment)))
+        .collect();
+    svg.push_str(&format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#3b6ea5\" stroke-width=\"2\"/>\n",
+        path.join(" ")
+    ));
+    for (index, (title, sentiment)) in points.iter().enumerate() {
+        svg.push_str(&format!(
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"{}\"><title>{}: {:+.2}</title></circle>\n",
+            x(index),
+            y(*sentiment),
+            if *sentiment < 0.0 { "#c0392b" } else { "#27ae60" },
+            escape_html(title),
+            sentiment
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// A block character whose height shows the score, for Markdown tables
+fn bar(sentiment: f64) -> &'static str {
+    const BLOCKS: [&str; 5] = ["▁", "▂", "▄", "▆", "█"];
+    let index = ((sentiment + 1.0) / 2.0 * (BLOCKS.len() - 1) as f64).round() as usize;
+    BLOCKS[index.min(BLOCKS.len() - 1)]
+}
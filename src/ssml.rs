@@ -0,0 +1,112 @@
+use crate::podcast::Episode;
+use crate::text::escape_html;
+use anyhow::Result;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Pause inserted between speaker turns
+const TURN_BREAK: &str = "<break time=\"400ms\"/>";
+/// Pause inserted between episodes
+const EPISODE_BREAK: &str = "<break time=\"1500ms\"/>";
+
+/// Writes the podcast script as a single SSML document for TTS engines
+///
+/// Each line becomes a paragraph of `<s>` sentences preceded by a `<mark>` naming the speaker,
+/// so pipelines can route turns to different voices. Chapter keywords are wrapped in `<emphasis>`.
+pub fn write_podcast_ssml(
+    output_dir: &Path,
+    language: &str,
+    episodes: &[Episode],
+) -> Result<PathBuf> {
+    let mut doc = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<speak version=\"1.1\" xmlns=\"http://www.w3.org/2001/10/synthesis\" xml:lang=\"{}\">\n",
+        escape_html(language)
+    );
+
+    for (episode_index, episode) in episodes.iter().enumerate() {
+        let emphasis = keyword_pattern(&episode.keywords);
+        doc.push_str(&format!(
+            "<p><s><emphasis level=\"strong\">{}</emphasis></s></p>\n",
+            escape_html(&episode.title)
+        ));
+
+        for (line_index, line) in episode.lines.iter().enumerate() {
+            doc.push_str(&format!(
+                "<mark name=\"ep{}-{}-{}\"/>\n<p>",
+                episode_index + 1,
+                line_index + 1,
+                mark_name(&line.speaker)
+            ));
+            for sentence in split_sentences(&line.line) {
+                let mut text = escape_html(sentence);
+                if let Some(pattern) = &emphasis {
+                    text = pattern
+                        .replace_all(&text, |captures: &Captures| {
+                            let found = &captures[0];
+                            if found.starts_with('&') {
+                                found.to_string()
+                            } else {
+                                format!("<emphasis level=\"moderate\">{}</emphasis>", found)
+                            }
+                        })
+                        .into_owned();
+                }
+                doc.push_str(&format!("<s>{}</s>", text));
+            }
+            doc.push_str(&format!("</p>\n{}\n", TURN_BREAK));
+        }
+        doc.push_str(EPISODE_BREAK);
+        doc.push('\n');
+    }
+
+    doc.push_str("</speak>\n");
+
+    let path = output_dir.join("podcast_script.ssml");
+    fs::write(&path, doc)?;
+    Ok(path)
+}
+
+/// Splits text after sentence-ending punctuation
+fn split_sentences(text: &str) -> Vec<&str> {
+    let boundary = Regex::new(r"[.!?…]+[\s]+").unwrap();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for found in boundary.find_iter(text) {
+        let sentence = text[start..found.end()].trim();
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+        start = found.end();
+    }
+    let rest = text[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+    sentences
+}
+
+/// Builds a case-insensitive whole-word pattern matching any of the (XML-escaped) keywords
+///
+/// Character entities are matched too, ahead of the keywords, so that a keyword such as "amp"
+/// or "quot" is never found inside the `&amp;` or `&quot;` that escaping produced.
+fn keyword_pattern(keywords: &[String]) -> Option<Regex> {
+    let alternatives: Vec<String> = keywords
+        .iter()
+        .map(|k| k.trim())
+        .filter(|k| k.chars().count() > 2)
+        .map(|k| regex::escape(&escape_html(k)))
+        .collect();
+    if alternatives.is_empty() {
+        return None;
+    }
+    Regex::new(&format!(r"&#?\w+;|(?i)\b(?:{})\b", alternatives.join("|"))).ok()
+}
+
+fn mark_name(speaker: &str) -> String {
+    speaker
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
@@ -0,0 +1,79 @@
+use crate::encryption::{self, CacheKey};
+use crate::schema;
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const STATE_FILE: &str = "state.json";
+
+/// A section summary together with the hashes of everything it was generated from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionEntry {
+    pub chapter: String,
+    pub source_sha256: String,
+    /// Hash of the prompt templates, chapter instructions, language and detail level
+    pub prompt_sha256: String,
+    pub model: String,
+    pub summary: Value,
+}
+
+/// Section summaries of the last run of a book, kept in `state.json` next to its outputs
+///
+/// `--incremental` reuses an entry when the section text, prompt and model all match, so only
+/// edited sections (or sections under an edited prompt or a new model) are summarized again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SectionState {
+    pub sections: Vec<SectionEntry>,
+}
+
+impl SectionState {
+    /// Reads the state of a previous run; a missing or unreadable file means starting fresh
+    ///
+    /// An encrypted state needs `key` to be read.
+    pub fn load(output_dir: &Path, key: Option<&CacheKey>) -> Self {
+        let path = output_dir.join(STATE_FILE);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return SectionState::default();
+        };
+        serde_json::from_str(&content)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| schema::upgrade(STATE_FILE, value))
+            .and_then(|value| match (encryption::sealed(&value), key) {
+                (Some(sealed), Some(key)) => key.open(&sealed),
+                (Some(_), None) => Err(anyhow!("it is encrypted, run with --encrypt-cache")),
+                (None, _) => Ok(serde_json::from_value(value)?),
+            })
+            .unwrap_or_else(|e| {
+                warn!("Ignoring unreadable state '{}': {}", path.display(), e);
+                SectionState::default()
+            })
+    }
+
+    pub fn get(&self, source_sha256: &str, prompt_sha256: &str, model: &str) -> Option<&Value> {
+        self.sections
+            .iter()
+            .find(|entry| {
+                entry.source_sha256 == source_sha256
+                    && entry.prompt_sha256 == prompt_sha256
+                    && entry.model == model
+            })
+            .map(|entry| &entry.summary)
+    }
+
+    pub fn push(&mut self, entry: SectionEntry) {
+        self.sections.push(entry);
+    }
+
+    /// Writes `state.json`, encrypted with `key` if given, returning its path
+    pub fn save(&self, output_dir: &Path, key: Option<&CacheKey>) -> Result<PathBuf> {
+        let path = output_dir.join(STATE_FILE);
+        match key {
+            Some(key) => schema::write_json(&path, &key.seal(self)?)?,
+            None => schema::write_json(&path, self)?,
+        }
+        Ok(path)
+    }
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Average silent reading speed used for reading time estimates (words per minute)
+const WORDS_PER_MINUTE: f64 = 238.0;
+
+/// Word counts for a single chapter before and after summarization
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterStats {
+    pub title: String,
+    pub original_words: usize,
+    pub summary_words: usize,
+}
+
+impl ChapterStats {
+    pub fn new(title: String, original: &str, summary: &str) -> Self {
+        ChapterStats {
+            title,
+            original_words: count_words(original),
+            summary_words: count_words(summary),
+        }
+    }
+}
+
+/// Reading statistics for a whole e-book
+#[derive(Debug, Clone, Default)]
+pub struct ReadingStats {
+    pub chapters: Vec<ChapterStats>,
+}
+
+impl ReadingStats {
+    pub fn push(&mut self, chapter: ChapterStats) {
+        self.chapters.push(chapter);
+    }
+
+    pub fn original_words(&self) -> usize {
+        self.chapters.iter().map(|c| c.original_words).sum()
+    }
+
+    pub fn summary_words(&self) -> usize {
+        self.chapters.iter().map(|c| c.summary_words).sum()
+    }
+
+    pub fn original_minutes(&self) -> f64 {
+        reading_minutes(self.original_words())
+    }
+
+    pub fn summary_minutes(&self) -> f64 {
+        reading_minutes(self.summary_words())
+    }
+
+    /// Ratio between original and summary length (e.g. 12.5 means 12.5x shorter)
+    pub fn compression_ratio(&self) -> f64 {
+        compression_ratio(self.original_words(), self.summary_words())
+    }
+
+    /// Renders a plain-text report for the terminal
+    pub fn terminal_report(&self) -> String {
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{:<40} {:>10} {:>10} {:>8}",
+            "Chapter", "Words", "Summary", "Ratio"
+        )
+        .unwrap();
+        for chapter in &self.chapters {
+            writeln!(
+                report,
+                "{:<40} {:>10} {:>10} {:>7.1}x",
+                truncate(&chapter.title, 40),
+                chapter.original_words,
+                chapter.summary_words,
+                compression_ratio(chapter.original_words, chapter.summary_words)
+            )
+            .unwrap();
+        }
+        writeln!(report).unwrap();
+        write!(report, "{}", self.summary_line()).unwrap();
+        report
+    }
+
+    /// One-line overview used in terminal output and document headers
+    pub fn summary_line(&self) -> String {
+        let original = self.original_minutes();
+        let summary = self.summary_minutes();
+        format!(
+            "Original reading time: {} | Summary reading time: {} | Compression: {:.1}x | Time saved: {}",
+            format_duration(original),
+            format_duration(summary),
+            self.compression_ratio(),
+            format_duration((original - summary).max(0.0))
+        )
+    }
+}
+
+/// Counts whitespace-separated words
+pub fn count_words(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+pub fn reading_minutes(words: usize) -> f64 {
+    words as f64 / WORDS_PER_MINUTE
+}
+
+fn compression_ratio(original: usize, summary: usize) -> f64 {
+    if summary == 0 {
+        0.0
+    } else {
+        original as f64 / summary as f64
+    }
+}
+
+/// Formats minutes as "9 h 12 min" or "7 min"
+pub fn format_duration(minutes: f64) -> String {
+    let total = minutes.round() as u64;
+    let (hours, mins) = (total / 60, total % 60);
+    if hours > 0 {
+        format!("{} h {} min", hours, mins)
+    } else {
+        format!("{} min", mins)
+    }
+}
+
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        let truncated: String = text.chars().take(max_chars - 1).collect();
+        format!("{}…", truncated)
+    }
+}
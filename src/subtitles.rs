@@ -0,0 +1,319 @@
+use crate::images::ImageOptions;
+use crate::metadata::BookMetadata;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// A pause in speech this long (in seconds) starts a new chapter when there are no chapter markers
+const CHAPTER_GAP_SECS: f64 = 8.0;
+/// Gap-detected chapters shorter than this are merged into the previous one
+const MIN_CHAPTER_SECS: f64 = 180.0;
+/// A pause this long ends the current paragraph
+const PARAGRAPH_GAP_SECS: f64 = 1.5;
+/// Paragraphs end at the first sentence boundary past this length
+const PARAGRAPH_CHARS: usize = 600;
+
+/// The `start --> end` line of an SRT or WebVTT cue
+static TIMING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*((?:\d+:)?\d{1,2}:\d{2}[.,]\d{1,3})\s*-->\s*((?:\d+:)?\d{1,2}:\d{2}[.,]\d{1,3})",
+    )
+    .unwrap()
+});
+/// Styling such as `<i>`, `<c.yellow>`, `<00:00:01.000>` karaoke timings and `{\an8}` positions
+static MARKUP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>|\{\\[^}]*\}").unwrap());
+
+/// One timed caption
+#[derive(Debug, Clone)]
+struct Cue {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// A course video, conference talk or other subtitle file, read as chapters of transcript
+pub struct Subtitles {
+    metadata: BookMetadata,
+    chapters: Vec<(String, String)>,
+}
+
+/// Reads an SRT or WebVTT file into chapters of timestamped paragraphs
+///
+/// Chapters come from a `<name>.chapters.vtt` (or `.srt`) file next to the subtitles, from
+/// `NOTE Chapter: <title>` blocks in WebVTT, or else from long pauses in speech. Every chapter
+/// title and paragraph starts with its `[hh:mm:ss]` timestamp so the summary can refer back to it.
+pub fn open(path: &Path) -> Result<Subtitles> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("cannot read '{}'", path.display()))?;
+    let (cues, notes) = parse(&content);
+    if cues.is_empty() {
+        bail!("no subtitle cues found in '{}'", path.display());
+    }
+
+    let markers = match chapters_file(path) {
+        Some(chapters_path) => {
+            let (chapter_cues, _) = parse(&fs::read_to_string(&chapters_path)?);
+            chapter_cues
+                .into_iter()
+                .map(|cue| (cue.start, cue.text))
+                .collect()
+        }
+        None => notes,
+    };
+    let segments = if markers.is_empty() {
+        gap_segments(&cues)
+    } else {
+        marker_segments(&cues, markers)
+    };
+
+    let chapters = segments
+        .into_iter()
+        .filter(|(_, cues)| !cues.is_empty())
+        .map(|(title, cues)| {
+            let title = format!("[{}] {}", timestamp(cues[0].start), title);
+            (title, paragraphs(cues))
+        })
+        .collect();
+
+    Ok(Subtitles {
+        metadata: BookMetadata {
+            title: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            ..Default::default()
+        },
+        chapters,
+    })
+}
+
+impl EbookReader for Subtitles {
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        Box::new(self.chapters.iter().map(|(title, text)| {
+            Ok(Chapter {
+                title: Some(title.clone()),
+                text: text.clone(),
+                images: Vec::new(),
+            })
+        }))
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Subtitles have no images
+    fn images(&mut self, _images_dir: &Path, _options: &ImageOptions) -> Result<()> {
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        self.chapters.len()
+    }
+}
+
+/// Cues in order, plus the chapter titles of WebVTT `NOTE Chapter:` blocks with the time they start
+fn parse(content: &str) -> (Vec<Cue>, Vec<(f64, String)>) {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues: Vec<Cue> = Vec::new();
+    let mut notes = Vec::new();
+
+    for block in content.split("\n\n") {
+        let lines: Vec<&str> = block.lines().filter(|l| !l.trim().is_empty()).collect();
+        if let Some(title) = lines
+            .first()
+            .and_then(|line| line.strip_prefix("NOTE"))
+            .map(str::trim)
+            .and_then(|note| note.strip_prefix("Chapter:"))
+        {
+            // The chapter starts with the next cue
+            let start = f64::MAX;
+            notes.push((start, title.trim().to_string()));
+            continue;
+        }
+        let Some(timing_index) = lines.iter().position(|line| TIMING.is_match(line)) else {
+            continue;
+        };
+        let captures = TIMING.captures(lines[timing_index]).unwrap();
+        let (Some(start), Some(end)) = (seconds(&captures[1]), seconds(&captures[2])) else {
+            continue;
+        };
+        let text = lines[timing_index + 1..]
+            .iter()
+            .map(|line| MARKUP.replace_all(line, "").trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if text.is_empty() {
+            continue;
+        }
+
+        for note in notes.iter_mut().filter(|(s, _)| *s == f64::MAX) {
+            note.0 = start;
+        }
+        // Auto-generated captions repeat the previous line as the next one scrolls in
+        let text = match cues.last() {
+            Some(last) if text.starts_with(&last.text) => {
+                text[last.text.len()..].trim().to_string()
+            }
+            _ => text,
+        };
+        if !text.is_empty() {
+            cues.push(Cue { start, end, text });
+        }
+    }
+    notes.retain(|(start, _)| *start != f64::MAX);
+    (cues, notes)
+}
+
+/// `hh:mm:ss.mmm`, `mm:ss.mmm` or the SRT `hh:mm:ss,mmm` in seconds
+fn seconds(time: &str) -> Option<f64> {
+    let time = time.replace(',', ".");
+    let mut total = 0.0;
+    for part in time.split(':') {
+        total = total * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(total)
+}
+
+fn timestamp(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// `talk.chapters.vtt` or `talk.chapters.srt` next to `talk.vtt`
+fn chapters_file(path: &Path) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_string_lossy();
+    ["vtt", "srt"]
+        .iter()
+        .map(|extension| path.with_file_name(format!("{}.chapters.{}", stem, extension)))
+        .find(|candidate| candidate.exists() && candidate != path)
+}
+
+fn marker_segments(cues: &[Cue], mut markers: Vec<(f64, String)>) -> Vec<(String, &[Cue])> {
+    markers.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut segments = Vec::new();
+
+    // Speech before the first marker is an untitled introduction
+    let first_start = markers[0].0;
+    let intro = cues.partition_point(|cue| cue.start < first_start);
+    if intro > 0 {
+        segments.push(("Introduction".to_string(), &cues[..intro]));
+    }
+    for (index, (start, title)) in markers.iter().enumerate() {
+        let from = cues.partition_point(|cue| cue.start < *start);
+        let to = match markers.get(index + 1) {
+            Some((next, _)) => cues.partition_point(|cue| cue.start < *next),
+            None => cues.len(),
+        };
+        segments.push((title.clone(), &cues[from..to.max(from)]));
+    }
+    segments
+}
+
+/// Splits at long pauses, merging chapters that would be too short
+fn gap_segments(cues: &[Cue]) -> Vec<(String, &[Cue])> {
+    let mut bounds: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0;
+    for index in 1..=cues.len() {
+        let at_end = index == cues.len();
+        if !at_end && cues[index].start - cues[index - 1].end < CHAPTER_GAP_SECS {
+            continue;
+        }
+        let length = cues[index - 1].end - cues[start].start;
+        match bounds.last_mut() {
+            Some(last) if length < MIN_CHAPTER_SECS && at_end => last.1 = index,
+            _ if length < MIN_CHAPTER_SECS && !at_end => continue,
+            _ => bounds.push((start, index)),
+        }
+        start = index;
+    }
+
+    bounds
+        .into_iter()
+        .enumerate()
+        .map(|(number, (from, to))| (format!("Part {}", number + 1), &cues[from..to]))
+        .collect()
+}
+
+/// Joins cues into paragraphs, each starting with its timestamp
+fn paragraphs(cues: &[Cue]) -> String {
+    let mut text = String::new();
+    let mut paragraph = String::new();
+    let mut previous_end = f64::MIN;
+
+    for cue in cues {
+        let sentence_ended = paragraph.ends_with(['.', '?', '!']);
+        let new_paragraph = paragraph.is_empty()
+            || cue.start - previous_end >= PARAGRAPH_GAP_SECS
+            || (paragraph.len() >= PARAGRAPH_CHARS && sentence_ended);
+        if new_paragraph {
+            if !paragraph.is_empty() {
+                text.push_str(&paragraph);
+                text.push_str("\n\n");
+            }
+            paragraph = format!("[{}] {}", timestamp(cue.start), cue.text);
+        } else {
+            paragraph.push(' ');
+            paragraph.push_str(&cue.text);
+        }
+        previous_end = cue.end;
+    }
+    text.push_str(&paragraph);
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srt_cues_are_parsed() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i> there\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\nGeneral Kenobi\r\n";
+        let (cues, notes) = parse(srt);
+        assert!(notes.is_empty());
+        assert_eq!(cues.len(), 2);
+        assert_eq!((cues[0].start, cues[0].end), (1.0, 2.5));
+        assert_eq!(cues[0].text, "Hello there");
+        assert_eq!(cues[1].text, "General Kenobi");
+    }
+
+    #[test]
+    fn vtt_cues_and_chapter_notes_are_parsed() {
+        let vtt = "WEBVTT\n\nNOTE Chapter: Opening\n\n\
+                   intro\n01:05.250 --> 01:07.000 align:start\n<c.yellow>Welcome</c> <00:01:06.000>all\n\n\
+                   1:00:00.000 --> 1:00:01.000\n- Bye\n";
+        let (cues, notes) = parse(vtt);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start, 65.25);
+        assert_eq!(cues[0].text, "Welcome all");
+        assert_eq!(cues[1].start, 3600.0);
+        assert_eq!(notes, vec![(65.25, "Opening".to_string())]);
+    }
+
+    #[test]
+    fn ass_override_tags_and_repeated_captions_are_dropped() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\n{\\an8}{\\i1}Top line{\\i0}\n\n\
+                   2\n00:00:02,000 --> 00:00:03,000\nTop line and more\n\n\
+                   3\n00:00:04,000 --> 00:00:05,000\n{\\pos(10,20)}\n";
+        let (cues, _) = parse(srt);
+        let texts: Vec<&str> = cues.iter().map(|cue| cue.text.as_str()).collect();
+        assert_eq!(texts, ["Top line", "and more"]);
+    }
+}
@@ -1,80 +1,364 @@
+use crate::language;
 use crate::llm::{ChatMessage, LLMClient};
+use crate::logs::LogPolicy;
+use crate::manifest::sha256_hex;
+use crate::metadata::BookMetadata;
+use crate::paths::AppDirs;
+use crate::redact::Redactor;
+use crate::refusal;
 use anyhow::{anyhow, Result};
 use chrono::Utc;
-use serde_json::Value;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use futures::future::join_all;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
 use std::path::PathBuf;
-use tiktoken_rs::cl100k_base;
+use std::sync::LazyLock;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+/// The tokenizer sections are measured with, loaded once as building it takes a while
+pub static BPE: LazyLock<CoreBPE> = LazyLock::new(|| cl100k_base().unwrap());
+
+/// Prompts compiled into the binary, used when no prompt directory has an override
+const BUILTIN_PROMPTS: &[(&str, &str)] = &[
+    ("argument_map", include_str!("../prompts/argument_map.md")),
+    ("chapter_title", include_str!("../prompts/chapter_title.md")),
+    (
+        "content_warnings",
+        include_str!("../prompts/content_warnings.md"),
+    ),
+    (
+        "detailed_summary",
+        include_str!("../prompts/detailed_summary.md"),
+    ),
+    (
+        "discussion_questions",
+        include_str!("../prompts/discussion_questions.md"),
+    ),
+    (
+        "ensemble_merge",
+        include_str!("../prompts/ensemble_merge.md"),
+    ),
+    ("evaluation", include_str!("../prompts/evaluation.md")),
+    (
+        "grounded_summary",
+        include_str!("../prompts/grounded_summary.md"),
+    ),
+    ("layers", include_str!("../prompts/layers.md")),
+    ("marketing", include_str!("../prompts/marketing.md")),
+    ("ocr_cleanup", include_str!("../prompts/ocr_cleanup.md")),
+    ("one_pager", include_str!("../prompts/one_pager.md")),
+    ("refusal_retry", include_str!("../prompts/refusal_retry.md")),
+    (
+        "podcast_script",
+        include_str!("../prompts/podcast_script.md"),
+    ),
+    ("procedures", include_str!("../prompts/procedures.md")),
+    ("sentiment", include_str!("../prompts/sentiment.md")),
+    ("summary_plan", include_str!("../prompts/summary_plan.md")),
+    ("vocabulary", include_str!("../prompts/vocabulary.md")),
+];
 
 #[derive(Clone)]
 pub struct Summarizer {
     pub llm_client: LLMClient,
+    /// Faster, cheaper client for the per-section (map) summaries; `llm_client` when unset
+    pub map_client: Option<LLMClient>,
+    /// Models that each draft every section summary; `llm_client` merges the drafts
+    pub ensemble: Vec<LLMClient>,
     pub output_language: String,
     pub detail_level: String,
     pub log_dir: PathBuf, // Directory for logs
+    pub log_policy: LogPolicy,
+    pub redactor: Redactor,
+    /// Directories searched for prompt templates, most specific first
+    pub prompt_dirs: Vec<PathBuf>,
+    pub metadata: BookMetadata,
+    pub source_language: String,
+    /// Asks for a supporting source quote with every key point
+    pub grounded: bool,
+    /// Model asked, with the softened prompt, for sections the section model refuses
+    pub refusal_client: Option<LLMClient>,
+}
+
+/// Where a prompt template in use comes from, for bug reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptVersion {
+    pub name: String,
+    /// The override file, or `built-in`
+    pub source: String,
+    pub sha256: String,
+}
+
+/// Every prompt template with the override that replaces the built-in one, if any
+pub fn prompt_versions(prompt_dirs: &[PathBuf]) -> Vec<PromptVersion> {
+    BUILTIN_PROMPTS
+        .iter()
+        .map(|(name, builtin)| {
+            let file = prompt_dirs
+                .iter()
+                .map(|dir| dir.join(format!("{}.md", name)))
+                .find(|path| path.is_file());
+            let (source, template) = match file.and_then(|path| {
+                let template = fs::read_to_string(&path).ok()?;
+                Some((path.display().to_string(), template))
+            }) {
+                Some(found) => found,
+                None => ("built-in".to_string(), builtin.to_string()),
+            };
+            PromptVersion {
+                name: name.to_string(),
+                source,
+                sha256: sha256_hex(template.as_bytes()),
+            }
+        })
+        .collect()
 }
 
 impl Summarizer {
     pub fn new(
-        api_key: String,
-        model_name: String,
+        llm_client: LLMClient,
         output_language: String,
         detail_level: String,
+        dirs: &AppDirs,
     ) -> Self {
-        let log_dir = PathBuf::from("logs"); // Create log directory
-        fs::create_dir_all(&log_dir).expect("Failed to create log directory");
-
         Summarizer {
-            llm_client: LLMClient::new(api_key, model_name),
+            llm_client,
+            map_client: None,
+            ensemble: Vec::new(),
             output_language,
             detail_level,
-            log_dir,
+            log_dir: dirs.log_dir.clone(),
+            log_policy: LogPolicy::default(),
+            redactor: Redactor::default(),
+            prompt_dirs: dirs.prompt_dirs(),
+            metadata: BookMetadata::default(),
+            source_language: String::new(),
+            grounded: false,
+            refusal_client: None,
         }
     }
 
-    pub async fn generate_summary_plan(&self, toc: &[String]) -> Result<String> {
-        let prompt_template = fs::read_to_string("prompts/summary_plan.md")?;
+    /// Makes the book's metadata available to prompt templates
+    pub fn with_metadata(mut self, metadata: BookMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 
-        let toc_text = toc.join("\n");
+    /// Uses a separate model for section summaries, keeping the main model for the plan and
+    /// the whole-book stages
+    pub fn with_map_client(mut self, map_client: Option<LLMClient>) -> Self {
+        self.map_client = map_client;
+        self
+    }
 
-        let prompt = prompt_template
-            .replace("{{language}}", &self.output_language)
-            .replace("{{toc}}", &toc_text);
+    /// Summarizes each section with several models and has the main model merge the drafts
+    pub fn with_ensemble(mut self, ensemble: Vec<LLMClient>) -> Self {
+        self.ensemble = ensemble;
+        self
+    }
 
-        let messages = vec![ChatMessage {
-            role: "user".to_string(),
-            content: prompt,
-        }];
+    /// Sets the language the book is written in, used for `{{source_language}}` in prompts
+    pub fn with_source_language(mut self, source_language: String) -> Self {
+        self.source_language = source_language;
+        self
+    }
 
-        let response = self.llm_client.send_request(messages, 0.7).await?;
+    /// Requires every key point to carry a quote from the source, for later verification
+    pub fn with_grounding(mut self, grounded: bool) -> Self {
+        self.grounded = grounded;
+        self
+    }
 
-        // Log raw response
-        self.log_llm_response(&response, "summary_plan", "received")
-            .await?;
+    /// Falls back to another model for sections the section model refuses to summarize
+    pub fn with_refusal_client(mut self, refusal_client: Option<LLMClient>) -> Self {
+        self.refusal_client = refusal_client;
+        self
+    }
 
-        if response.trim().is_empty() {
-            return Err(anyhow!("LLM returned an empty response."));
-        }
+    pub fn with_log_policy(mut self, log_policy: LogPolicy) -> Self {
+        self.log_policy = log_policy;
+        self
+    }
+
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    // Read a prompt template from the prompts directories and fill in the book metadata placeholders
+    fn load_prompt(&self, name: &str) -> Result<String> {
+        let file = format!("{}.md", name);
+        let template = match self
+            .prompt_dirs
+            .iter()
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+        {
+            Some(path) => fs::read_to_string(path)?,
+            None => BUILTIN_PROMPTS
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, template)| template.to_string())
+                .ok_or_else(|| anyhow!("prompt template '{}' not found", file))?,
+        };
+        let source_language = if self.source_language.is_empty() {
+            "an unspecified language"
+        } else {
+            &self.source_language
+        };
+        let language_name = language::display_name(&self.output_language);
+        Ok(self
+            .metadata
+            .fill_template(&template)
+            .replace("{{source_language}}", source_language)
+            .replace(
+                "{{language}}",
+                &format!("{} ({})", language_name, self.output_language),
+            )
+            .replace("{{language_name}}", &language_name)
+            .replace("{{language_code}}", &self.output_language))
+    }
+
+    /// Hash of everything besides the text that shapes a section summary, and the model(s) used
+    ///
+    /// `instructions` are the chapter's custom instructions, if any. The plan is left out since
+    /// it is regenerated on every run.
+    pub fn section_fingerprint(&self, instructions: &str) -> Result<(String, String)> {
+        let template = if self.grounded {
+            self.load_prompt("grounded_summary")?
+        } else {
+            self.load_prompt("detailed_summary")?
+        };
+        let mut fingerprint = vec![
+            template,
+            instructions.to_string(),
+            self.output_language.clone(),
+            self.detail_level.clone(),
+        ];
+
+        let model = if self.ensemble.is_empty() {
+            self.map_client
+                .as_ref()
+                .unwrap_or(&self.llm_client)
+                .model_name
+                .clone()
+        } else {
+            fingerprint.push(self.load_prompt("ensemble_merge")?);
+            let models: Vec<&str> = self
+                .ensemble
+                .iter()
+                .map(|client| client.model_name.as_str())
+                .collect();
+            format!(
+                "{} (judge: {})",
+                models.join(", "),
+                self.llm_client.model_name
+            )
+        };
+
+        Ok((sha256_hex(fingerprint.join("\n").as_bytes()), model))
+    }
 
-        Ok(response)
+    /// Custom instructions for one chapter from `prompts/chapters/<number>.md`, if present
+    pub fn chapter_instructions(&self, number: usize) -> Option<String> {
+        let instructions = self
+            .load_prompt(&format!("chapters/{}", number))
+            .ok()?
+            .trim()
+            .to_string();
+        (!instructions.is_empty()).then_some(instructions)
+    }
+
+    /// Asks for a per-chapter plan as JSON, with the table of contents given one title per line
+    ///
+    /// `issues` lists what was wrong with the previous attempt, if any.
+    pub async fn generate_summary_plan(&self, toc: &[String], issues: &[String]) -> Result<Value> {
+        let prompt_template = self.load_prompt("summary_plan")?;
+
+        let toc_text = toc.join("\n");
+        let issues_text = if issues.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "A previous plan had these problems, fix them:\n- {}\n",
+                issues.join("\n- ")
+            )
+        };
+
+        let prompt = prompt_template
+            .replace("{{plan_issues}}", &issues_text)
+            .replace("{{toc}}", &toc_text);
+
+        self.request_json(prompt, "summary_plan").await
     }
 
     pub async fn summarize_with_plan(&self, text: &str, plan: &str) -> Result<Value> {
-        let prompt_template = fs::read_to_string("prompts/detailed_summary.md")?;
+        let prompt_template = if self.grounded {
+            self.load_prompt("grounded_summary")?
+        } else {
+            self.load_prompt("detailed_summary")?
+        };
 
         let prompt = prompt_template
-            .replace("{{language}}", &self.output_language)
             .replace("{{detail_level}}", &self.detail_level)
             .replace("{{plan}}", plan)
             .replace("{{text}}", text);
 
+        if !self.ensemble.is_empty() {
+            return self.summarize_with_ensemble(text, prompt).await;
+        }
+
+        // A refusal is retried with a softened prompt, then with the refusal model if there is
+        // one; a section nobody will summarize is marked rather than failing the book
+        let section_client = self.map_client.as_ref().unwrap_or(&self.llm_client);
+        let refused = match self.summarize_section(section_client, prompt.clone()).await {
+            Err(e) if refusal::is_refused(&e) => e,
+            result => return result,
+        };
+        warn!(
+            "Section summary: {:#}; retrying with a softened prompt",
+            refused
+        );
+        let softened = format!(
+            "{}\n\n{}",
+            self.load_prompt("refusal_retry")?.trim(),
+            prompt
+        );
+        let mut refused = match self
+            .summarize_section(section_client, softened.clone())
+            .await
+        {
+            Err(e) if refusal::is_refused(&e) => e,
+            result => return result,
+        };
+        if let Some(refusal_client) = &self.refusal_client {
+            warn!(
+                "Section summary: {:#}; asking {}",
+                refused, refusal_client.model_name
+            );
+            refused = match self.summarize_section(refusal_client, softened).await {
+                Err(e) if refusal::is_refused(&e) => e,
+                result => return result,
+            };
+        }
+        warn!("Leaving a section unsummarized: {:#}", refused);
+        Ok(json!({
+            "summary": refusal::NOTICE,
+            "refused": format!("{:#}", refused)
+        }))
+    }
+
+    /// Requests one section summary, turning a reply that reads like a refusal into a
+    /// [`refusal::Refused`] error
+    async fn summarize_section(&self, client: &LLMClient, prompt: String) -> Result<Value> {
         let messages = vec![ChatMessage {
             role: "user".to_string(),
             content: prompt,
         }];
 
-        let response = self.llm_client.send_request(messages, 0.7).await?;
+        let response = client.send_request(messages, 0.7).await?;
 
         // Log raw response
         self.log_llm_response(&response, "detailed_summary", "received")
@@ -88,8 +372,18 @@ impl Summarizer {
             return Err(anyhow!("LLM returned an empty response."));
         }
 
-        // Try to parse the JSON and stop the program if parsing fails
+        // Try to parse the JSON, failing the section if it is not valid
         match serde_json::from_str::<Value>(&cleaned_response) {
+            Ok(parsed_response)
+                if parsed_response["summary"]
+                    .as_str()
+                    .is_some_and(refusal::is_refusal_summary) =>
+            {
+                Err(refusal::Refused(format!("{} declined the section", client.model_name)).into())
+            }
+            Err(_) if refusal::looks_like_refusal(&cleaned_response) => {
+                Err(refusal::Refused(format!("{} declined the section", client.model_name)).into())
+            }
             Ok(parsed_response) => {
                 // Log successful transformation
                 self.log_llm_response(&cleaned_response, "detailed_summary", "parsed")
@@ -100,11 +394,239 @@ impl Summarizer {
                 // Log the invalid JSON response
                 self.log_llm_response(&cleaned_response, "detailed_summary", "invalid_json")
                     .await?;
-                println!(
-                    "Critical error parsing LLM response: {}\nResponse: {}",
-                    e, cleaned_response
-                );
-                std::process::exit(1); // Stop the program immediately
+                // The chapter error policy decides whether to retry, skip or stop
+                Err(anyhow!(
+                    "Invalid JSON in {} section summary: {} (response logged to llm_detailed_summary.log)",
+                    client.model_name,
+                    e
+                ))
+            }
+        }
+    }
+
+    /// Drafts a section summary with every ensemble model, then asks the main model to merge them
+    async fn summarize_with_ensemble(&self, text: &str, prompt: String) -> Result<Value> {
+        let drafts = join_all(
+            self.ensemble
+                .iter()
+                .map(|client| self.request_json_with(client, prompt.clone(), "ensemble_draft")),
+        )
+        .await;
+
+        let mut successful = Vec::new();
+        for (client, draft) in self.ensemble.iter().zip(drafts) {
+            match draft {
+                Ok(draft) => successful.push((client.model_name.clone(), draft)),
+                Err(e) => warn!("Ensemble model {} failed: {}", client.model_name, e),
+            }
+        }
+        if successful.len() <= 1 {
+            return successful
+                .pop()
+                .map(|(_, draft)| draft)
+                .ok_or_else(|| anyhow!("All ensemble models failed to summarize the section"));
+        }
+
+        let drafts_text = successful
+            .iter()
+            .enumerate()
+            .map(|(index, (model, draft))| format!("Draft {} ({}):\n{}", index + 1, model, draft))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let prompt = self
+            .load_prompt("ensemble_merge")?
+            .replace("{{detail_level}}", &self.detail_level)
+            .replace("{{drafts}}", &drafts_text)
+            .replace("{{text}}", text);
+
+        self.request_json(prompt, "ensemble_merge").await
+    }
+
+    /// Extracts difficult words and idioms from the original text for language learners
+    pub async fn extract_vocabulary(&self, text: &str, max_terms: usize) -> Result<Value> {
+        let prompt_template = self.load_prompt("vocabulary")?;
+
+        let prompt = prompt_template
+            .replace("{{max_terms}}", &max_terms.to_string())
+            .replace("{{text}}", text);
+
+        self.request_json(prompt, "vocabulary").await
+    }
+
+    /// Generates open-ended book-club questions with talking points from a summary
+    pub async fn generate_discussion_questions(&self, summary: &str, count: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("discussion_questions")?;
+
+        let prompt = prompt_template
+            .replace("{{count}}", count)
+            .replace("{{summary}}", summary);
+
+        self.request_json(prompt, "discussion_questions").await
+    }
+
+    /// Condenses a chapter summary into the shorter layers: ELI5, one-liner and paragraph
+    pub async fn generate_layers(&self, chapter_title: &str, summary: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("layers")?;
+
+        let prompt = prompt_template
+            .replace("{{chapter_title}}", chapter_title)
+            .replace("{{summary}}", summary);
+
+        self.request_json(prompt, "layers").await
+    }
+
+    /// Generates back-cover blurb, pitch, subject categories and SEO keywords from a summary
+    pub async fn generate_marketing_copy(&self, summary: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("marketing")?;
+
+        let prompt = prompt_template.replace("{{summary}}", summary);
+
+        self.request_json(prompt, "marketing").await
+    }
+
+    /// Fixes misrecognized characters, broken words and stray page furniture in OCR text
+    pub async fn clean_ocr_text(&self, text: &str) -> Result<String> {
+        let prompt_template = self.load_prompt("ocr_cleanup")?;
+
+        let prompt = prompt_template.replace("{{text}}", text);
+
+        let response = self.request_json(prompt, "ocr_cleanup").await?;
+        response["text"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("OCR cleanup response has no 'text' field"))
+    }
+
+    /// Names a chapter from its opening, for books whose table of contents has no usable label
+    pub async fn generate_chapter_title(&self, text: &str) -> Result<String> {
+        let prompt_template = self.load_prompt("chapter_title")?;
+
+        let prompt = prompt_template.replace("{{text}}", text);
+
+        let response = self.request_json(prompt, "chapter_title").await?;
+        response["title"]
+            .as_str()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("chapter title response has no 'title' field"))
+    }
+
+    /// Reduces the full summary to a thesis, key insights, action items and intended audience
+    pub async fn generate_one_pager(&self, summary: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("one_pager")?;
+
+        let prompt = prompt_template.replace("{{summary}}", summary);
+
+        self.request_json(prompt, "one_pager").await
+    }
+
+    /// Rates a chapter's sentiment and names its tone and themes
+    pub async fn analyze_sentiment(
+        &self,
+        chapter_title: &str,
+        summary: &str,
+        text: &str,
+    ) -> Result<Value> {
+        let prompt_template = self.load_prompt("sentiment")?;
+
+        let prompt = prompt_template
+            .replace("{{chapter_title}}", chapter_title)
+            .replace("{{summary}}", summary)
+            .replace("{{text}}", text);
+
+        self.request_json(prompt, "sentiment").await
+    }
+
+    /// Maps the claims, evidence and counterarguments in the original text
+    pub async fn map_arguments(&self, text: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("argument_map")?;
+
+        let prompt = prompt_template.replace("{{text}}", text);
+
+        self.request_json(prompt, "argument_map").await
+    }
+
+    /// Extracts recipes and step-by-step procedures from the original text
+    pub async fn extract_procedures(&self, text: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("procedures")?;
+
+        let prompt = prompt_template.replace("{{text}}", text);
+
+        self.request_json(prompt, "procedures").await
+    }
+
+    /// Flags potentially sensitive content in the original text
+    pub async fn analyze_content_warnings(&self, text: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("content_warnings")?;
+
+        let prompt = prompt_template.replace("{{text}}", text);
+
+        self.request_json(prompt, "content_warnings").await
+    }
+
+    /// Scores a summary against its source text for coverage, faithfulness and clarity
+    pub async fn evaluate_summary(&self, text: &str, summary: &str) -> Result<Value> {
+        let prompt_template = self.load_prompt("evaluation")?;
+
+        let prompt = prompt_template
+            .replace("{{text}}", text)
+            .replace("{{summary}}", summary);
+
+        self.request_json(prompt, "evaluation").await
+    }
+
+    /// Turns a chapter summary into a two-host podcast dialogue
+    pub async fn generate_podcast_dialogue(
+        &self,
+        title: &str,
+        summary: &str,
+        hosts: (&str, &str),
+    ) -> Result<Value> {
+        let prompt_template = self.load_prompt("podcast_script")?;
+
+        let prompt = prompt_template
+            .replace("{{host_a}}", hosts.0)
+            .replace("{{host_b}}", hosts.1)
+            .replace("{{chapter_title}}", title)
+            .replace("{{summary}}", summary);
+
+        self.request_json(prompt, "podcast_script").await
+    }
+
+    // Send a single-prompt request and parse the response as JSON, returning an error on failure
+    async fn request_json(&self, prompt: String, context: &str) -> Result<Value> {
+        self.request_json_with(&self.llm_client, prompt, context)
+            .await
+    }
+
+    async fn request_json_with(
+        &self,
+        client: &LLMClient,
+        prompt: String,
+        context: &str,
+    ) -> Result<Value> {
+        let messages = vec![ChatMessage {
+            role: "user".to_string(),
+            content: prompt,
+        }];
+
+        let response = client.send_request(messages, 0.7).await?;
+        self.log_llm_response(&response, context, "received")
+            .await?;
+
+        let cleaned_response = self.clean_response(&response);
+        if cleaned_response.trim().is_empty() {
+            return Err(anyhow!("LLM returned an empty response."));
+        }
+
+        match serde_json::from_str::<Value>(&cleaned_response) {
+            Ok(parsed_response) => Ok(parsed_response),
+            Err(e) => {
+                self.log_llm_response(&cleaned_response, context, "invalid_json")
+                    .await?;
+                Err(anyhow!("Invalid JSON in {} response: {}", context, e))
             }
         }
     }
@@ -114,15 +636,15 @@ impl Summarizer {
         let timestamp = Utc::now().to_rfc3339();
         let log_file_path = self.log_dir.join(format!("llm_{}.log", context));
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_file_path)?;
-
-        writeln!(
-            file,
-            "[{}] Context: {}\nStatus: {}\nResponse:\n{}\n",
-            timestamp, context, status, response
+        self.log_policy.append(
+            &log_file_path,
+            &format!(
+                "[{}] Context: {}\nStatus: {}\nResponse:\n{}\n\n",
+                timestamp,
+                context,
+                status,
+                self.redactor.redact(response)
+            ),
         )?;
 
         Ok(())
@@ -138,21 +660,125 @@ impl Summarizer {
     }
 
     // Function to split text into sections based on token count
+    //
+    // Paragraphs are packed together up to the limit and fenced code blocks are never cut in
+    // half; only a single block larger than the limit is split on raw token boundaries.
     pub fn split_text_by_tokens(&self, text: &str, max_tokens: usize) -> Vec<String> {
-        let bpe = cl100k_base().unwrap();
-        let tokens = bpe.encode_with_special_tokens(text);
-
         let mut sections = Vec::new();
-        let mut start = 0;
-
-        while start < tokens.len() {
-            let end = usize::min(start + max_tokens, tokens.len());
-            let section_tokens = &tokens[start..end];
-            let section_text = bpe.decode(section_tokens.to_vec()).unwrap();
-            sections.push(section_text);
-            start = end;
+        let mut current = String::new();
+        let mut current_tokens = 0;
+
+        for block in text_blocks(text) {
+            let tokens = BPE.encode_with_special_tokens(&block);
+
+            if current_tokens + tokens.len() > max_tokens && !current.is_empty() {
+                sections.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+
+            if tokens.len() > max_tokens {
+                for chunk in tokens.chunks(max_tokens) {
+                    sections.push(BPE.decode(chunk.to_vec()).unwrap());
+                }
+                continue;
+            }
+
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&block);
+            current_tokens += tokens.len();
+        }
+
+        if !current.trim().is_empty() {
+            sections.push(current);
         }
 
         sections
     }
 }
+
+/// Tokens `text` takes up in a prompt, counted with the cl100k encoding used for splitting
+pub fn count_tokens(text: &str) -> usize {
+    BPE.encode_with_special_tokens(text).len()
+}
+
+/// Merges adjacent sections that fit in `max_tokens` together, saving a request each
+///
+/// Greedy packing leaves small sections next to oversized blocks and at the end of a chapter;
+/// one shorter than `min_tokens` is merged into its neighbour even when that takes the merged
+/// section up to `min_tokens` over the limit.
+pub fn merge_small_sections(
+    sections: Vec<String>,
+    max_tokens: usize,
+    min_tokens: usize,
+) -> Vec<String> {
+    let mut merged: Vec<(String, usize)> = Vec::new();
+    for section in sections {
+        let tokens = count_tokens(&section);
+        if let Some((previous, previous_tokens)) = merged.last_mut() {
+            let combined = *previous_tokens + tokens;
+            let small = tokens.min(*previous_tokens) < min_tokens;
+            if combined <= max_tokens || (small && combined <= max_tokens + min_tokens) {
+                previous.push_str("\n\n");
+                previous.push_str(&section);
+                *previous_tokens = combined;
+                continue;
+            }
+        }
+        merged.push((section, tokens));
+    }
+
+    merged.into_iter().map(|(section, _)| section).collect()
+}
+
+/// Splits text into blank-line separated blocks, keeping fenced code blocks whole
+pub fn text_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if line.trim().is_empty() && !in_fence {
+            if !current.is_empty() {
+                blocks.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+        current.push(line);
+    }
+    if !current.is_empty() {
+        blocks.push(current.join("\n"));
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_sections_are_merged_into_their_neighbours() {
+        let word = |count: usize| vec!["harvest"; count].join(" ");
+        let tokens = count_tokens(&word(10));
+        let sections = vec![word(10), word(10), word(30), word(2)];
+
+        let merged = merge_small_sections(sections.clone(), tokens * 2, 0);
+        assert_eq!(
+            merged,
+            [format!("{}\n\n{}", word(10), word(10)), word(30), word(2)]
+        );
+
+        // A small last section joins a full one, going over the limit by at most `min_tokens`
+        let sections = vec![word(20), word(2)];
+        let full = count_tokens(&word(20));
+        assert_eq!(merge_small_sections(sections.clone(), full, 0), sections);
+        let merged = merge_small_sections(sections, full, count_tokens(&word(2)) + 1);
+        assert_eq!(merged, [format!("{}\n\n{}", word(20), word(2))]);
+    }
+}
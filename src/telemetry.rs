@@ -0,0 +1,85 @@
+use anyhow::Result;
+use log::{info, warn};
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, ContextGuard, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::env;
+
+/// Exports spans over OTLP/HTTP for the rest of the run, flushing the last ones when dropped
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    /// Starts exporting to `endpoint` (the collector's base URL, e.g. `http://localhost:4318`),
+    /// or to the one in the standard `OTEL_EXPORTER_OTLP_*` variables; without either, spans
+    /// are dropped and `None` is returned
+    pub fn init(endpoint: Option<&str>) -> Result<Option<Self>> {
+        let configured = [
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+        ]
+        .iter()
+        .any(|name| env::var(name).is_ok_and(|value| !value.is_empty()));
+        if endpoint.is_none() && !configured {
+            return Ok(None);
+        }
+        let mut exporter = SpanExporter::builder().with_http();
+        if let Some(endpoint) = endpoint {
+            let endpoint = endpoint.trim_end_matches('/');
+            exporter = exporter.with_endpoint(if endpoint.ends_with("/v1/traces") {
+                endpoint.to_string()
+            } else {
+                format!("{}/v1/traces", endpoint)
+            });
+        }
+        let mut resource = Resource::builder();
+        if env::var("OTEL_SERVICE_NAME").is_err() {
+            resource = resource.with_service_name("aibook");
+        }
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter.build()?)
+            .with_resource(resource.build())
+            .build();
+        global::set_tracer_provider(provider.clone());
+        info!("Exporting traces over OTLP");
+        Ok(Some(Telemetry { provider }))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("Could not export the last traces: {}", e);
+        }
+    }
+}
+
+/// Starts a span under the current one, e.g. for work running concurrently with its siblings
+pub fn span(name: &'static str, attributes: Vec<KeyValue>) -> global::BoxedSpan {
+    let tracer = global::tracer("aibook");
+    tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&tracer)
+}
+
+/// Starts a span under the current one and makes it current until the guard is dropped
+///
+/// The context lives in a thread local, so this is only for the sequential stages of the
+/// pipeline, which run on `main`'s task and so on one thread across their awaits.
+pub fn enter(name: &'static str, attributes: Vec<KeyValue>) -> ContextGuard {
+    Context::current_with_span(span(name, attributes)).attach()
+}
+
+/// Adds attributes to the current span
+pub fn record(attributes: Vec<KeyValue>) {
+    Context::current().span().set_attributes(attributes);
+}
+
+/// Marks a span as failed with the error's message
+pub fn fail(span: &mut impl Span, error: &anyhow::Error) {
+    span.set_status(Status::error(format!("{:#}", error)));
+}
@@ -0,0 +1,116 @@
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+static ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+
+/// Escapes text for HTML and XML content and attribute values
+///
+/// Every LLM-derived string goes through here before it reaches markup, so a summary cannot
+/// inject tags or scripts. Control characters, which XML forbids and which would make EPUB
+/// chapters unreadable, are dropped. Entities the model already escaped are kept as they are,
+/// as long as XML knows them, so `&amp;` does not come out as `&amp;amp;`.
+pub fn escape_html(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect();
+    let mut escaped = String::with_capacity(text.len());
+    for (index, c) in text.char_indices() {
+        match c {
+            '&' if is_xml_entity(&text[index..]) => escaped.push('&'),
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether the text starts with one of the five predefined XML entities or a character reference
+/// to a character XML allows
+fn is_xml_entity(text: &str) -> bool {
+    let Some(end) = text.find(';').filter(|&end| end <= 10) else {
+        return false;
+    };
+    let entity = &text[..=end];
+    match &entity[1..end] {
+        "amp" | "lt" | "gt" | "quot" | "apos" => true,
+        name if name.starts_with('#') => {
+            let decoded = decode_entities(entity);
+            decoded != entity
+                && !decoded
+                    .chars()
+                    .any(|c| c.is_control() && !matches!(c, '\n' | '\t' | '\r'))
+        }
+        _ => false,
+    }
+}
+
+/// Decodes the XML entities and numeric character references left in raw markup text
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    ENTITY
+        .replace_all(text, |caps: &Captures| {
+            let name = &caps[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            decoded.map_or_else(|| caps[0].to_string(), |c| c.to_string())
+        })
+        .into_owned()
+}
+
+/// Lowercase words without punctuation, separated by single spaces, so texts and titles compare
+/// equal whatever their case, numbering punctuation and spacing
+pub fn normalize_words(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_characters_are_escaped() {
+        assert_eq!(
+            escape_html("<script>alert('x' & \"y\")</script>"),
+            "&lt;script&gt;alert(&#39;x&#39; &amp; &quot;y&quot;)&lt;/script&gt;"
+        );
+        assert_eq!(escape_html("AT&T;\u{0}\tok"), "AT&amp;T;\tok");
+    }
+
+    #[test]
+    fn escaped_entities_are_left_alone() {
+        assert_eq!(
+            escape_html("Tom &amp; Jerry &lt;3 &#8212; &#x2014; &quot;hi&quot;"),
+            "Tom &amp; Jerry &lt;3 &#8212; &#x2014; &quot;hi&quot;"
+        );
+        // Entities XML does not know, or references to characters it forbids, are escaped
+        assert_eq!(
+            escape_html("&nbsp;&#0;&#xZZ;"),
+            "&amp;nbsp;&amp;#0;&amp;#xZZ;"
+        );
+    }
+}
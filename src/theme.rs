@@ -0,0 +1,102 @@
+use crate::paths;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+const BASE_CSS: &str = include_str!("../themes/base.css");
+
+/// A built-in stylesheet for HTML and EPUB output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Light,
+    Dark,
+    Print,
+}
+
+impl Theme {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            "print" => Ok(Theme::Print),
+            other => Err(anyhow!(
+                "Unknown theme '{}' (expected light, dark or print)",
+                other
+            )),
+        }
+    }
+
+    fn css(self) -> &'static str {
+        match self {
+            Theme::Light => include_str!("../themes/light.css"),
+            Theme::Dark => include_str!("../themes/dark.css"),
+            Theme::Print => include_str!("../themes/print.css"),
+        }
+    }
+}
+
+/// The shared typography and the theme's colours, the embedded font if any, then the user's
+/// stylesheet so its rules win
+pub fn stylesheet(theme: Theme, custom: Option<&Path>, font: Option<&Path>) -> Result<String> {
+    let mut css = format!("{}\n{}", BASE_CSS, theme.css());
+    if let Some(font) = font {
+        css.push('\n');
+        css.push_str(&font_face(&font_file_name(font)?));
+    }
+    if let Some(path) = custom {
+        let custom = fs::read_to_string(path)
+            .with_context(|| format!("could not read theme CSS '{}'", path.display()))?;
+        css.push('\n');
+        css.push_str(&custom);
+    }
+    Ok(css)
+}
+
+/// File name of a font to embed, checking that it is a format e-readers and browsers load
+pub fn font_file_name(path: &Path) -> Result<String> {
+    if !path.is_file() {
+        bail!("font '{}' does not exist", path.display());
+    }
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if font_media_type(&extension).is_none() {
+        bail!(
+            "Unsupported font '{}' (expected a .ttf, .otf, .woff or .woff2 file)",
+            path.display()
+        );
+    }
+    Ok(paths::safe_file_name(
+        &path.file_name().unwrap_or_default().to_string_lossy(),
+    ))
+}
+
+/// Copies the font into `fonts/` of the output directory, where the stylesheet refers to it
+pub fn install_font(path: &Path, output_dir: &Path) -> Result<String> {
+    let file_name = font_file_name(path)?;
+    let fonts_dir = output_dir.join("fonts");
+    fs::create_dir_all(&fonts_dir)?;
+    fs::copy(path, fonts_dir.join(&file_name))
+        .with_context(|| format!("could not copy font '{}'", path.display()))?;
+    Ok(file_name)
+}
+
+pub fn font_media_type(extension: &str) -> Option<&'static str> {
+    match extension {
+        "ttf" => Some("font/ttf"),
+        "otf" => Some("font/otf"),
+        "woff" => Some("font/woff"),
+        "woff2" => Some("font/woff2"),
+        _ => None,
+    }
+}
+
+/// Declares the embedded font and puts it first in the text and heading font stacks; the
+/// theme's fonts remain the fallback for characters it lacks
+fn font_face(file_name: &str) -> String {
+    format!(
+        "@font-face {{\n  font-family: \"Embedded\";\n  src: url(\"fonts/{}\");\n}}\n\nbody {{\n  font-family: \"Embedded\", Charter, Georgia, serif;\n}}\n\nh1, h2, h3, h4, h5, h6 {{\n  font-family: \"Embedded\", system-ui, sans-serif;\n}}\n",
+        file_name.replace('"', "")
+    )
+}
@@ -0,0 +1,190 @@
+use crate::reader::EbookReader;
+use crate::summarizer::Summarizer;
+use crate::unicode::{self, Normalization};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Characters of a chapter's opening the title prompt is given
+const OPENING_CHARS: usize = 3000;
+/// Longer lines are prose rather than a heading
+const MAX_TITLE_CHARS: usize = 100;
+
+/// A file name with a markup extension, or a generated id such as `part0005` or
+/// `index_split_003`
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:[\w./-]+\.(?:x?html?|xml)|(?:part|section|index_split|ch)[_-]?\d+(?:_split_\d+)?)$")
+        .unwrap()
+});
+/// Markdown emphasis markers around a heading's text
+static EMPHASIS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[*_`]+").unwrap());
+
+/// How table of contents labels that are file names or ids get a readable title
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleMode {
+    /// The chapter's first heading, else a title written by the model
+    Auto,
+    /// The chapter's first heading only; chapters without one become "Chapter N"
+    Heading,
+    /// Labels are used as they are
+    Off,
+}
+
+impl TitleMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "auto" => Ok(TitleMode::Auto),
+            "heading" => Ok(TitleMode::Heading),
+            "off" => Ok(TitleMode::Off),
+            other => Err(anyhow!(
+                "Unknown chapter title mode '{}' (expected auto, heading or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// Whether a TOC label names a file or an id instead of the chapter: "Section0012.xhtml",
+/// "part0005", "index_split_003", "ch01" or an empty label
+///
+/// Only those shapes count: labels with spaces ("Chapter 3"), bare numbers and titles that
+/// merely end in digits ("Apollo13", "Catch22") are how books really title chapters, so they
+/// are kept.
+pub fn is_placeholder(title: &str) -> bool {
+    let title = title.trim();
+    title.is_empty() || PLACEHOLDER.is_match(title)
+}
+
+/// The first heading of a chapter: a Markdown heading among its opening lines or, in plain
+/// text, a short opening line that does not read like a sentence
+pub fn first_heading(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("!["))
+        .take(5)
+        .collect();
+
+    let heading = match lines.iter().find(|line| line.starts_with('#')) {
+        Some(line) => line.trim_start_matches('#'),
+        None => lines
+            .first()
+            .filter(|line| !line.ends_with(['.', ',', ';', ':']))?,
+    };
+    let heading = EMPHASIS.replace_all(heading, "").trim().to_string();
+    (!heading.is_empty() && heading.chars().count() <= MAX_TITLE_CHARS && !is_placeholder(&heading))
+        .then_some(heading)
+}
+
+/// Replaces placeholder labels in `toc` with the heading of the chapter at the same position
+/// or, in [`TitleMode::Auto`], a title the model writes from the chapter's opening
+///
+/// Chapters are only read again when the table of contents has placeholders.
+pub async fn replace_placeholders(
+    book: &mut dyn EbookReader,
+    toc: &mut [String],
+    summarizer: &Summarizer,
+    mode: TitleMode,
+    normalization: Normalization,
+) {
+    let placeholders: Vec<usize> = (0..toc.len())
+        .filter(|&index| is_placeholder(&toc[index]))
+        .collect();
+    let Some(&last) = placeholders.last() else {
+        return;
+    };
+    if mode == TitleMode::Off {
+        return;
+    }
+
+    let mut openings = Vec::new();
+    for (index, chapter) in book.chapters().enumerate().take(last + 1) {
+        if !placeholders.contains(&index) {
+            continue;
+        }
+        // A chapter that fails to extract is reported by the summarization pass
+        let Ok(chapter) = chapter else {
+            openings.push((index, String::new()));
+            continue;
+        };
+        let text = unicode::clean(&chapter.text, normalization);
+        let heading = chapter
+            .title
+            .map(|title| unicode::clean(&title, normalization))
+            .filter(|title| !is_placeholder(title))
+            .or_else(|| first_heading(&text));
+        match heading {
+            Some(heading) => toc[index] = heading,
+            None => openings.push((index, text.chars().take(OPENING_CHARS).collect::<String>())),
+        }
+    }
+
+    for (index, opening) in openings {
+        let generated = match mode {
+            TitleMode::Auto if !opening.trim().is_empty() => {
+                match summarizer.generate_chapter_title(&opening).await {
+                    Ok(title) => Some(title).filter(|title| !is_placeholder(title)),
+                    Err(e) => {
+                        warn!("Could not title chapter {}: {:#}", index + 1, e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        toc[index] = generated.unwrap_or_else(|| format!("Chapter {}", index + 1));
+    }
+    info!(
+        "Gave {} chapter(s) a title in place of their table of contents label",
+        placeholders.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_and_ids_are_placeholders() {
+        for title in [
+            "",
+            "  ",
+            "Section0012.xhtml",
+            "text/chapter-3.html",
+            "part0005",
+            "index_split_003",
+            "ch01",
+            "CH_12",
+            "part0002_split_001",
+        ] {
+            assert!(is_placeholder(title), "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn real_titles_are_kept() {
+        for title in [
+            "Chapter 3",
+            "12",
+            "Epilogue",
+            "The Tyranny of Metrics",
+            "Apollo13",
+            "Catch22",
+            "Y2K",
+            "Partition7",
+        ] {
+            assert!(!is_placeholder(title), "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn first_heading_skips_prose_and_placeholders() {
+        assert_eq!(
+            first_heading("# **The Beginning**\n\nIt was a dark night.").as_deref(),
+            Some("The Beginning")
+        );
+        assert_eq!(first_heading("It was a dark night, and cold."), None);
+        assert_eq!(first_heading("# part0005\n\nText."), None);
+    }
+}
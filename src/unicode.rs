@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use icu_normalizer::ComposingNormalizerBorrowed;
+
+/// Unicode normalization applied to extracted text before it is counted and summarized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition, e.g. `e` + combining acute becomes `é`
+    Nfc,
+    /// Compatibility composition as well: ligatures, full-width forms and the like are folded
+    /// to their plain letters
+    Nfkc,
+    /// The text as extracted
+    Off,
+}
+
+impl Normalization {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfkc" => Ok(Normalization::Nfkc),
+            "off" => Ok(Normalization::Off),
+            other => Err(anyhow!(
+                "Unknown Unicode normalization '{}' (expected nfc, nfkc or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// Normalizes `text` and removes the encoding junk sloppy e-books carry: control characters,
+/// soft hyphens, zero-width spaces and stray joiners, exotic spaces and line separators, and
+/// typographic quotes, which cost extra tokens without changing the meaning
+pub fn clean(text: &str, normalization: Normalization) -> String {
+    let text = match normalization {
+        Normalization::Nfc => ComposingNormalizerBorrowed::new_nfc().normalize(text),
+        Normalization::Nfkc => ComposingNormalizerBorrowed::new_nfkc().normalize(text),
+        Normalization::Off => return text.to_string(),
+    };
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut cleaned = String::with_capacity(text.len());
+    for (index, &c) in chars.iter().enumerate() {
+        match c {
+            '\r' if chars.get(index + 1) == Some(&'\n') => {}
+            '\r' | '\u{2028}' | '\u{2029}' => cleaned.push('\n'),
+            '\n' | '\t' => cleaned.push(c),
+            '\u{ad}' | '\u{200b}' | '\u{2060}' | '\u{feff}' => {}
+            // Joiners shape Arabic, Indic and emoji sequences but are noise between Latin letters
+            '\u{200c}' | '\u{200d}' => {
+                let previous = index.checked_sub(1).and_then(|i| chars.get(i));
+                if previous.is_some_and(|p| !p.is_ascii())
+                    && chars.get(index + 1).is_some_and(|n| !n.is_ascii())
+                {
+                    cleaned.push(c);
+                }
+            }
+            '\u{a0}' | '\u{2000}'..='\u{200a}' | '\u{202f}' | '\u{205f}' | '\u{3000}' => {
+                cleaned.push(' ')
+            }
+            '‘' | '’' | '‚' | '‛' => cleaned.push('\''),
+            '“' | '”' | '„' | '‟' => cleaned.push('"'),
+            c if c.is_control() => {}
+            c => cleaned.push(c),
+        }
+    }
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_junk_is_removed() {
+        let text =
+            "Soft\u{ad}ware\u{200b} “quoted” ‘word’\u{a0}and\u{3000}more\r\nnext\u{2028}line\u{7}";
+        assert_eq!(
+            clean(text, Normalization::Nfc),
+            "Software \"quoted\" 'word' and more\nnext\nline"
+        );
+    }
+
+    #[test]
+    fn joiners_are_kept_only_between_non_ascii_letters() {
+        assert_eq!(clean("a\u{200d}b", Normalization::Nfc), "ab");
+        let persian = "می\u{200c}خواهم";
+        assert_eq!(clean(persian, Normalization::Nfc), persian);
+    }
+
+    #[test]
+    fn normalization_forms_differ_in_compatibility_characters() {
+        let text = "cafe\u{301} ﬁle ＡＢ";
+        assert_eq!(clean(text, Normalization::Nfc), "café ﬁle ＡＢ");
+        assert_eq!(clean(text, Normalization::Nfkc), "café file AB");
+        assert_eq!(clean(text, Normalization::Off), text);
+    }
+
+    #[test]
+    fn unknown_normalizations_are_rejected() {
+        assert_eq!(Normalization::parse("nfkc").unwrap(), Normalization::Nfkc);
+        let error = Normalization::parse("nfd").unwrap_err().to_string();
+        assert!(error.starts_with("Unknown Unicode normalization 'nfd'"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::language;
+use crate::output::ChapterSummary;
+use crate::text::escape_html;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A difficult word or idiom with its translation and an example sentence
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VocabularyEntry {
+    pub term: String,
+    pub translation: String,
+    pub example: String,
+}
+
+/// Parses the JSON array returned by the vocabulary prompt, skipping malformed entries
+pub fn parse_entries(value: &Value) -> Vec<VocabularyEntry> {
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let field = |name: &str| {
+                item.get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string()
+            };
+            let term = field("term");
+            if term.is_empty() {
+                return None;
+            }
+            Some(VocabularyEntry {
+                term,
+                translation: field("translation"),
+                example: field("example"),
+            })
+        })
+        .collect()
+}
+
+/// Adds the entries whose term is not in `entries` yet (ignoring case), up to `max_terms` in all
+pub fn merge_entries(
+    entries: &mut Vec<VocabularyEntry>,
+    new: Vec<VocabularyEntry>,
+    max_terms: usize,
+) {
+    for entry in new {
+        if entries.len() >= max_terms {
+            break;
+        }
+        let term = entry.term.to_lowercase();
+        if !entries
+            .iter()
+            .any(|known| known.term.to_lowercase() == term)
+        {
+            entries.push(entry);
+        }
+    }
+}
+
+/// Whether a language code names no particular language: nothing was declared or detected, or
+/// it is ISO 639's "undetermined", "multiple languages" or "no linguistic content"
+pub fn is_undetermined(code: &str) -> bool {
+    matches!(
+        language::primary_subtag(code).as_str(),
+        "" | "und" | "mul" | "zxx"
+    )
+}
+
+/// Compares the primary subtags of two language codes ("en-US" and "en" are the same language)
+pub fn languages_differ(book_language: &str, output_language: &str) -> bool {
+    !is_undetermined(book_language)
+        && !is_undetermined(output_language)
+        && language::primary_subtag(book_language) != language::primary_subtag(output_language)
+}
+
+/// Writes a tab-separated file that Anki can import as a deck (front, back, tags)
+pub fn write_anki_deck(output_dir: &Path, chapters: &[ChapterSummary]) -> Result<PathBuf> {
+    let mut deck = String::from("#separator:tab\n#html:true\n#tags column:3\n");
+
+    for (index, chapter) in chapters.iter().enumerate() {
+        let tag = format!("aibook::chapter_{:02}", index + 1);
+        for entry in &chapter.vocabulary {
+            // The deck is imported as HTML, so the model's text is escaped around our markup
+            let back = if entry.example.is_empty() {
+                escape_html(&entry.translation)
+            } else {
+                format!(
+                    "{}<br><i>{}</i>",
+                    escape_html(&entry.translation),
+                    escape_html(&entry.example)
+                )
+            };
+            deck.push_str(&format!(
+                "{}\t{}\t{}\n",
+                anki_field(&escape_html(&entry.term)),
+                anki_field(&back),
+                tag
+            ));
+        }
+    }
+
+    let path = output_dir.join("vocabulary_anki.txt");
+    fs::write(&path, deck)?;
+    Ok(path)
+}
+
+fn anki_field(text: &str) -> String {
+    text.replace(['\t', '\n'], " ")
+}
@@ -0,0 +1,317 @@
+use crate::ebook;
+use crate::extract::{self, ExtractionOptions};
+use crate::images::ImageOptions;
+use crate::metadata::BookMetadata;
+use crate::paths;
+use crate::reader::{Chapter, ChapterIter, EbookReader};
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Paragraphs shorter than this are navigation, captions or bylines rather than article text
+const MIN_PARAGRAPH_CHARS: usize = 25;
+/// Elements whose class or id says they are not part of the article
+const NEGATIVE_HINTS: [&str; 14] = [
+    "aside",
+    "comment",
+    "footer",
+    "header",
+    "sidebar",
+    "nav",
+    "menu",
+    "promo",
+    "share",
+    "related",
+    "advert",
+    "cookie",
+    "newsletter",
+    "social",
+];
+/// Class or id hints for the element that holds the article
+const POSITIVE_HINTS: [&str; 7] = [
+    "article", "content", "entry", "main", "post", "story", "text",
+];
+/// Page furniture removed from the chosen element before extraction
+const REMOVED_TAGS: &str = "script, style, noscript, nav, aside, form, footer, iframe, button";
+
+/// The input as a URL if it is an http(s) address rather than a file path
+pub fn url(input: &Path) -> Option<&str> {
+    let input = input.to_str()?;
+    (input.starts_with("https://") || input.starts_with("http://")).then_some(input)
+}
+
+/// Directory name for a URL's outputs: its last meaningful path segment or its host
+pub fn slug(url: &str) -> String {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split("://")
+        .nth(1)
+        .unwrap_or_default();
+    let segment = path
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("article");
+    let segment = segment
+        .rsplit_once('.')
+        .filter(|(_, ext)| matches!(*ext, "html" | "htm" | "php" | "aspx"))
+        .map_or(segment, |(stem, _)| stem);
+    paths::safe_file_name(segment)
+}
+
+/// A web article fetched from a URL and reduced to its main content
+pub struct WebArticle {
+    metadata: BookMetadata,
+    /// The article's main content, as HTML
+    content: String,
+    options: ExtractionOptions,
+}
+
+/// Downloads a page and extracts its article with a readability-style content score
+pub async fn fetch_article(
+    url: &str,
+    client: &reqwest::Client,
+    options: &ExtractionOptions,
+) -> Result<WebArticle> {
+    let response = client
+        .get(url)
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("fetching '{}' failed with HTTP {}", url, response.status());
+    }
+    let html = response.text().await?;
+    info!("Fetched {} bytes from '{}'", html.len(), url);
+
+    let document = Html::parse_document(&html);
+    let content =
+        main_content(&document).ok_or_else(|| anyhow!("no article text found at '{}'", url))?;
+
+    Ok(WebArticle {
+        metadata: page_metadata(&document, url),
+        content,
+        options: options.clone(),
+    })
+}
+
+impl EbookReader for WebArticle {
+    /// The article as one chapter, or several when it is long enough to split at its headings
+    fn chapters(&mut self) -> ChapterIter<'_> {
+        let chapters: Vec<Result<Chapter>> = ebook::split_by_headings(&self.content)
+            .into_iter()
+            .map(|(title, html)| {
+                ebook::html_to_text(&html, Path::new(""), &self.options, &extract::Notes::new())
+                    .map(|text| Chapter {
+                        title: title.or_else(|| self.metadata.title.clone()),
+                        text,
+                        images: Vec::new(),
+                    })
+            })
+            .collect();
+        Box::new(chapters.into_iter())
+    }
+
+    fn toc(&self) -> Vec<String> {
+        self.metadata.title.clone().into_iter().collect()
+    }
+
+    fn metadata(&self) -> &BookMetadata {
+        &self.metadata
+    }
+
+    /// Images of web pages are not downloaded
+    fn images(&mut self, _images_dir: &Path, _options: &ImageOptions) -> Result<()> {
+        Ok(())
+    }
+
+    fn chapter_count_hint(&self) -> usize {
+        1
+    }
+}
+
+/// Picks the element whose paragraphs score highest and returns its cleaned HTML
+///
+/// Each paragraph adds to its parent (and half as much to its grandparent) a score that grows
+/// with its length and number of commas; class and id hints raise or lower the final score.
+/// Paragraphs inside navigation, sidebars, footers and comments are not counted at all.
+fn main_content(document: &Html) -> Option<String> {
+    let paragraphs = Selector::parse("p, pre, blockquote").ok()?;
+    let mut scores: HashMap<_, (ElementRef, f64)> = HashMap::new();
+
+    for paragraph in document.select(&paragraphs) {
+        let text: String = paragraph.text().collect();
+        let length = text.trim().chars().count();
+        if length < MIN_PARAGRAPH_CHARS || in_furniture(&paragraph) {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (length as f64 / 100.0).min(3.0);
+
+        let mut ancestors = paragraph.ancestors().filter_map(ElementRef::wrap);
+        for weight in [1.0, 0.5] {
+            let Some(ancestor) = ancestors.next() else {
+                break;
+            };
+            scores.entry(ancestor.id()).or_insert((ancestor, 0.0)).1 += score * weight;
+        }
+    }
+
+    let (best, _) = scores
+        .into_values()
+        .map(|(element, score)| (element, score * hint_factor(&element)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    Some(strip_furniture(&best.html()))
+}
+
+/// Whether a paragraph sits in navigation, a sidebar, a footer or the like
+///
+/// Only the elements between the paragraph and its article root (the nearest `article`, `main`
+/// or `body`) are checked, so a page-wide wrapper or `<body>` class does not discard every
+/// paragraph on the page.
+fn in_furniture(paragraph: &ElementRef) -> bool {
+    for ancestor in paragraph.ancestors().filter_map(ElementRef::wrap) {
+        if matches!(
+            ancestor.value().name(),
+            "article" | "main" | "body" | "html"
+        ) {
+            return false;
+        }
+        if hint_factor(&ancestor) < 1.0 {
+            return true;
+        }
+    }
+    false
+}
+
+fn hint_factor(element: &ElementRef) -> f64 {
+    let value = element.value();
+    let tokens = format!(
+        "{} {} {}",
+        value.name(),
+        value.attr("class").unwrap_or_default(),
+        value.attr("id").unwrap_or_default()
+    )
+    .to_lowercase();
+    let has_hint = |hints: &[&str]| {
+        tokens
+            .split_whitespace()
+            .any(|token| hints.iter().any(|hint| is_hint_token(token, hint)))
+    };
+
+    if has_hint(&NEGATIVE_HINTS) {
+        0.25
+    } else if has_hint(&POSITIVE_HINTS) {
+        1.25
+    } else {
+        1.0
+    }
+}
+
+/// Whether a class or id token is the hint itself or starts or ends with it as a separate word,
+/// as in `sidebar-left` or `site_footer`, but not `navigation` for `nav`
+fn is_hint_token(token: &str, hint: &str) -> bool {
+    token == hint
+        || token
+            .strip_prefix(hint)
+            .is_some_and(|rest| rest.starts_with(['-', '_']))
+        || token
+            .strip_suffix(hint)
+            .is_some_and(|rest| rest.ends_with(['-', '_']))
+}
+
+/// Removes scripts, navigation, forms and similar elements from the chosen content
+fn strip_furniture(html: &str) -> String {
+    let handlers = vec![lol_html::element!(REMOVED_TAGS, |el| {
+        el.remove();
+        Ok(())
+    })];
+    lol_html::rewrite_str(
+        html,
+        lol_html::RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..lol_html::RewriteStrSettings::new()
+        },
+    )
+    .unwrap_or_else(|e| {
+        warn!("Could not clean the article HTML: {}", e);
+        html.to_string()
+    })
+}
+
+/// Title, author, language and description from the page's meta tags
+fn page_metadata(document: &Html, url: &str) -> BookMetadata {
+    let first = |selector: &str, attribute: Option<&str>| -> Option<String> {
+        let selector = Selector::parse(selector).ok()?;
+        let element = document.select(&selector).next()?;
+        let value = match attribute {
+            Some(attribute) => element.value().attr(attribute)?.to_string(),
+            None => element.text().collect(),
+        };
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!value.is_empty()).then_some(value)
+    };
+
+    BookMetadata {
+        title: first("meta[property='og:title']", Some("content"))
+            .or_else(|| first("title", None))
+            .or_else(|| first("h1", None)),
+        creators: first("meta[name='author']", Some("content"))
+            .or_else(|| first("meta[property='article:author']", Some("content")))
+            .into_iter()
+            .collect(),
+        language: first("html", Some("lang")),
+        publisher: first("meta[property='og:site_name']", Some("content")),
+        description: first("meta[name='description']", Some("content"))
+            .or_else(|| first("meta[property='og:description']", Some("content"))),
+        date: first("meta[property='article:published_time']", Some("content")),
+        source: Some(url.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn article_text_is_chosen_over_page_furniture() {
+        let page = r#"<html><body class="layout-main">
+            <nav><p>Home, News, Sport, Weather, Culture, Travel and more sections</p></nav>
+            <div class="sidebar-left"><p>Read this too, and this, and this other story here</p></div>
+            <article><div class="story-body">
+                <p>The harvest failed this year, farmers say, after the rains came late.</p>
+                <p>Wheat, rye and barley were all affected, and prices have risen since.</p>
+                <script>track()</script>
+            </div></article>
+        </body></html>"#;
+        let content = main_content(&Html::parse_document(page)).unwrap();
+        assert!(content.contains("The harvest failed"));
+        assert!(content.contains("Wheat, rye and barley"));
+        assert!(!content.contains("Sport"));
+        assert!(!content.contains("other story"));
+        assert!(!content.contains("track()"));
+    }
+
+    #[test]
+    fn hints_match_whole_words() {
+        assert!(is_hint_token("nav", "nav"));
+        assert!(is_hint_token("sidebar-left", "sidebar"));
+        assert!(is_hint_token("site_footer", "footer"));
+        assert!(!is_hint_token("navigation", "nav"));
+        assert!(!is_hint_token("canvas", "nav"));
+    }
+
+    #[test]
+    fn slugs_name_the_article() {
+        assert_eq!(
+            slug("https://example.com/news/the-harvest.html?ref=1"),
+            "the-harvest"
+        );
+        assert_eq!(slug("https://example.com/"), "example.com");
+    }
+}
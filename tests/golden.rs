@@ -0,0 +1,284 @@
+//! End-to-end runs of the CLI over `tests/fixtures/tiny_book.epub` with `--mock-provider`, so
+//! extraction, chunking, summarization, assembly and the EPUB build are exercised offline
+//!
+//! The Markdown summary is compared with `tests/golden/tiny_book.md`; run with
+//! `UPDATE_GOLDEN=1` to accept a deliberate change to the output. The property tests check what
+//! must hold for every combination of options rather than one exact output.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const CHAPTERS: [&str; 3] = ["The Lighthouse Keeper", "The Storm", "The Ledger"];
+
+/// Options whose values are parsed into a fixed set, each with one value it accepts
+const ENUM_OPTIONS: [(&str, &str); 15] = [
+    ("--provider", "openrouter"),
+    ("--strategy", "llm"),
+    ("--links", "footnote"),
+    ("--lists", "markdown"),
+    ("--footnotes", "collect"),
+    ("--unicode-normalization", "nfc"),
+    ("--chapter-titles", "auto"),
+    ("--low-content", "merge"),
+    ("--cover", "original"),
+    ("--on-chapter-error", "retry:2"),
+    ("--grounding", "flag"),
+    ("--transcriber", "openai"),
+    ("--theme", "light"),
+    ("--images", "referenced"),
+    ("--key-rotation", "round-robin"),
+];
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// A fresh directory serving as HOME and working directory, so no user configuration, cache
+/// or `.env` file leaks into the run
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aibook-golden-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the CLI on the fixture book in a scratch HOME, returning that directory and the output
+fn invoke(name: &str, args: &[&str]) -> (PathBuf, Output) {
+    let home = scratch_dir(name);
+    let output = Command::new(env!("CARGO_BIN_EXE_aibook-cli"))
+        .env_clear()
+        .env("HOME", &home)
+        .current_dir(&home)
+        .arg("--mock-provider")
+        .arg("--input")
+        .arg(manifest_dir().join("tests/fixtures/tiny_book.epub"))
+        .arg("--output-dir")
+        .arg(home.join("output"))
+        .args(args)
+        .output()
+        .unwrap();
+    (home, output)
+}
+
+/// Runs the CLI on the fixture book and returns the book's output directory
+fn run(name: &str, args: &[&str]) -> PathBuf {
+    let (home, output) = invoke(name, args);
+    assert!(
+        output.status.success(),
+        "aibook-cli {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    home.join("output/tiny_book")
+}
+
+fn assert_golden(actual: &str, golden: &Path) {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(golden, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(golden).unwrap_or_default();
+    assert!(
+        actual == expected,
+        "output differs from '{}' (rerun with UPDATE_GOLDEN=1 if the change is intended):\n{}",
+        golden.display(),
+        actual
+    );
+}
+
+/// Byte offsets of each title in `text`, panicking when one is missing
+fn positions(text: &str, titles: &[&str]) -> Vec<usize> {
+    titles
+        .iter()
+        .map(|title| {
+            text.find(title)
+                .unwrap_or_else(|| panic!("'{}' missing from:\n{}", title, text))
+        })
+        .collect()
+}
+
+#[test]
+fn markdown_summary_matches_golden() {
+    let book = run("markdown", &[]);
+    let summary = fs::read_to_string(book.join("summary.md")).unwrap();
+
+    let offsets = positions(&summary, &CHAPTERS);
+    assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(summary.contains("title: \"The Keeper of Gull Point\""));
+    assert!(book.join("manifest.json").is_file());
+    assert!(book.join("metadata.json").is_file());
+
+    assert_golden(&summary, &manifest_dir().join("tests/golden/tiny_book.md"));
+}
+
+#[test]
+fn summaries_are_reproducible() {
+    let first = fs::read_to_string(run("repeat-a", &[]).join("summary.md")).unwrap();
+    let second = fs::read_to_string(run("repeat-b", &[]).join("summary.md")).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn epub_output_is_a_well_formed_package() {
+    let book = run("epub", &["--output-format", "epub", "--grounding", "flag"]);
+    let file = fs::File::open(book.join("summary.epub")).unwrap();
+    let mut archive = zip::ZipArchive::new(file).unwrap();
+
+    {
+        let mut mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        let mut content = String::new();
+        mimetype.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "application/epub+zip");
+    }
+    assert!(archive.by_name("META-INF/container.xml").is_ok());
+
+    let mut xhtml = String::new();
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    for name in names.iter().filter(|name| name.ends_with(".xhtml")) {
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut xhtml)
+            .unwrap();
+    }
+    positions(&xhtml, &CHAPTERS);
+    // The mock quotes each section's first sentence, so grounding keeps every key point
+    assert!(xhtml.contains("Martha Quill kept the lighthouse at Gull Point for thirty years."));
+    assert!(!xhtml.contains("Unsupported"));
+}
+
+/// Deterministic pseudo-random option values that no parser accepts: lowercase words, digits,
+/// separators and non-ASCII letters, all starting with `zz`
+fn invalid_values(count: usize) -> Vec<String> {
+    const ALPHABET: &[char] = &['a', 'k', 'q', 'z', '0', '7', '-', '_', ':', 'é', 'ж'];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..count)
+        .map(|_| {
+            let mut value = String::from("zz");
+            for _ in 0..1 + state % 6 {
+                // xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                value.push(ALPHABET[(state % ALPHABET.len() as u64) as usize]);
+            }
+            value
+        })
+        .collect()
+}
+
+/// Every enumerated option rejects values it does not know with an error naming the value and
+/// the accepted ones, instead of panicking or falling back to a default
+#[test]
+fn option_parsers_reject_unknown_values() {
+    let values = invalid_values(4);
+    for (option, _) in ENUM_OPTIONS {
+        for value in &values {
+            let (_, output) = invoke("invalid", &[option, value]);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(
+                !output.status.success(),
+                "{} {} was accepted",
+                option,
+                value
+            );
+            assert!(
+                !stderr.contains("panicked"),
+                "{} {} panicked:\n{}",
+                option,
+                value,
+                stderr
+            );
+            assert!(
+                stderr.contains(&format!("'{}'", value)) && stderr.contains("expected"),
+                "{} {} gave an unhelpful error:\n{}",
+                option,
+                value,
+                stderr
+            );
+        }
+    }
+}
+
+/// Whatever the detail level and format, the output covers the table of contents in order and
+/// the manifest lists every file with its hash
+#[test]
+fn every_output_covers_the_book() {
+    let combinations = [
+        ("short", "markdown", "summary.md"),
+        ("long", "html", "summary.html"),
+        ("medium", "json", "summary.json"),
+    ];
+    let accepted: Vec<&str> = ENUM_OPTIONS
+        .iter()
+        .flat_map(|(option, value)| [*option, *value])
+        .filter(|arg| {
+            !matches!(
+                *arg,
+                "--provider" | "openrouter" | "--transcriber" | "openai"
+            )
+        })
+        .collect();
+    for (index, (detail, format, file)) in combinations.into_iter().enumerate() {
+        let mut args = vec!["--detail-level", detail, "--output-format", format];
+        // Every accepted value of the enumerated options works together in some run
+        if index == 0 {
+            args.extend(&accepted);
+        }
+        let book = run(&format!("cover-{}", format), &args);
+
+        let summary = fs::read_to_string(book.join(file)).unwrap();
+        let offsets = positions(&summary, &CHAPTERS);
+        assert!(
+            offsets.windows(2).all(|pair| pair[0] < pair[1]),
+            "{} {}: chapters out of order",
+            detail,
+            format
+        );
+
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(book.join("manifest.json")).unwrap()).unwrap();
+        let mut listed: Vec<String> = manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let path = entry["path"].as_str().unwrap();
+                let bytes = fs::read(book.join(path)).unwrap();
+                let hash: String = Sha256::digest(&bytes)
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                assert_eq!(entry["sha256"].as_str(), Some(hash.as_str()), "{}", path);
+                assert_eq!(entry["size"].as_u64(), Some(bytes.len() as u64), "{}", path);
+                path.to_string()
+            })
+            .collect();
+        listed.sort();
+        let mut written = files(&book, &book);
+        written.retain(|path| path != "manifest.json");
+        written.sort();
+        assert_eq!(listed, written, "{} {}", detail, format);
+    }
+}
+
+/// Paths of the files under `dir`, relative to `root` with `/` separators
+fn files(root: &Path, dir: &Path) -> Vec<String> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            paths.extend(files(root, &path));
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            paths.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    paths
+}